@@ -0,0 +1,373 @@
+// The check phase as JSON for dashboards: every registry value, boot setting and
+// audio property becomes one entry with the same fields.
+
+use crate::bcd::{self, BcdCheck};
+use crate::cli::Format;
+use crate::error;
+use crate::registry::report::{CheckOutcome, CheckResult, Report};
+use crate::selection::{Category, Selection};
+#[cfg(windows)]
+use crate::sound::{self, AudioEndpoint};
+
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Correct,
+    Mismatch,
+    // Exists with another registry type
+    #[serde(rename = "wrong_type")]
+    WrongType,
+    Missing,
+    // Should not exist but does
+    Present,
+    Written,
+    // Written over a value of another registry type
+    Retyped,
+    Deleted,
+    Failed,
+    // Reported for reference, there is nothing to compare with
+    #[cfg(windows)]
+    Info,
+}
+
+#[derive(Serialize)]
+pub struct AuditEntry {
+    // The tweak id, several entries share it when a tweak covers more than one value
+    pub id: String,
+    pub category: Option<Category>,
+    // Registry path, boot element or audio property
+    pub path: String,
+    pub current: Option<String>,
+    pub desired: Option<String>,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// Collects the entries of all areas, NDJSON is written as soon as an area is added
+pub struct Audit {
+    format: Format,
+    entries: Vec<AuditEntry>,
+}
+
+impl Audit {
+    pub fn new(format: Format) -> Audit {
+        Audit {
+            format,
+            entries: Vec::new(),
+        }
+    }
+
+    // Entries of tweaks outside the selection are dropped
+    pub fn extend(&mut self, mut entries: Vec<AuditEntry>, selection: &Selection) {
+        entries.retain(|e| selection.includes(&e.id, e.category));
+        if self.format == Format::Ndjson {
+            for entry in entries.iter() {
+                println!("{}", serde_json::to_string(entry).unwrap_or_default());
+            }
+        }
+        self.entries.extend(entries);
+    }
+
+    pub fn drift(&self) -> usize {
+        self.count(|s| matches!(s, Status::Mismatch | Status::WrongType | Status::Missing | Status::Present))
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(|s| s == Status::Failed)
+    }
+
+    fn count(&self, filter: impl Fn(Status) -> bool) -> usize {
+        self.entries.iter().filter(|e| filter(e.status)).count()
+    }
+
+    // Writes the JSON array, text and NDJSON are already on the console
+    #[cfg(windows)]
+    pub fn finish(&self) {
+        if self.format == Format::Json {
+            println!("{}", serde_json::to_string_pretty(&self.entries).unwrap_or_default());
+        }
+    }
+}
+
+// `tweak` names the id and category a result belongs to
+pub fn from_report(report: &Report, tweak: impl Fn(&CheckResult) -> (String, Option<Category>)) -> Vec<AuditEntry> {
+    report
+        .results
+        .iter()
+        .map(|result| {
+            let desired = result.value.as_ref().map(|v| result.describe(v));
+            let (status, current, error) = match &result.outcome {
+                CheckOutcome::Correct => (Status::Correct, desired.clone(), None),
+                CheckOutcome::Mismatch { current, .. } => (Status::Mismatch, Some(result.describe(current)), None),
+                CheckOutcome::WrongType { current, .. } => (Status::WrongType, Some(result.describe(current)), None),
+                CheckOutcome::Missing => (Status::Missing, None, None),
+                CheckOutcome::Present => (Status::Present, None, None),
+                CheckOutcome::Written => (Status::Written, desired.clone(), None),
+                CheckOutcome::Retyped { .. } => (Status::Retyped, desired.clone(), None),
+                CheckOutcome::Deleted | CheckOutcome::DeletedKey => (Status::Deleted, None, None),
+                CheckOutcome::Failed(e) => (Status::Failed, None, Some(e.to_string())),
+            };
+            let (id, category) = tweak(result);
+            AuditEntry {
+                id,
+                category,
+                path: result.full_path(),
+                current,
+                desired,
+                status,
+                error,
+            }
+        })
+        .collect()
+}
+
+pub fn from_bcd(checks: &error::Result<Vec<BcdCheck>>) -> Vec<AuditEntry> {
+    let label = |value: bool| String::from(if value { "Yes" } else { "No" });
+    match checks {
+        Ok(checks) => checks
+            .iter()
+            .map(|check| AuditEntry {
+                id: bcd::bcd_id(check.setting),
+                category: Some(Category::Boot),
+                path: format!("BCD\\{{current}}\\{}", check.setting),
+                current: check.current.map(label),
+                desired: Some(label(check.desired)),
+                status: match check.current {
+                    None => Status::Missing,
+                    Some(_) if check.in_sync() => Status::Correct,
+                    Some(_) => Status::Mismatch,
+                },
+                error: None,
+            })
+            .collect(),
+        Err(e) => bcd::bcd_settings()
+            .into_iter()
+            .map(|setting| AuditEntry {
+                id: bcd::bcd_id(setting),
+                category: Some(Category::Boot),
+                path: format!("BCD\\{{current}}\\{}", setting),
+                current: None,
+                desired: None,
+                status: Status::Failed,
+                error: Some(e.to_string()),
+            })
+            .collect(),
+    }
+}
+
+#[cfg(windows)]
+pub fn from_audio(endpoint: &error::Result<AudioEndpoint>) -> Vec<AuditEntry> {
+    let endpoint = match endpoint {
+        Ok(endpoint) => endpoint,
+        Err(e) => {
+            return vec![AuditEntry {
+                id: sound::AUDIO_FULL_RANGE.to_string(),
+                category: Some(Category::Audio),
+                path: String::from("Audio\\FullRangeSpeakers"),
+                current: None,
+                desired: None,
+                status: Status::Failed,
+                error: Some(e.to_string()),
+            }]
+        }
+    };
+    let path = |property: &str| format!("Audio\\{}\\{}", endpoint.friendly_name, property);
+    let info = |property: &str, value: String| AuditEntry {
+        id: sound::AUDIO_FULL_RANGE.to_string(),
+        category: Some(Category::Audio),
+        path: path(property),
+        current: Some(value),
+        desired: None,
+        status: Status::Info,
+        error: None,
+    };
+    vec![
+        AuditEntry {
+            id: sound::AUDIO_FULL_RANGE.to_string(),
+            category: Some(Category::Audio),
+            path: path("FullRangeSpeakers"),
+            current: Some(format!("0x{:x}", endpoint.full_range_speakers)),
+            desired: Some(format!("0x{:x}", endpoint.physical_speakers)),
+            status: if endpoint.in_sync() {
+                Status::Correct
+            } else if endpoint.written {
+                Status::Written
+            } else {
+                Status::Mismatch
+            },
+            error: None,
+        },
+        info("Channels", endpoint.format.channels.to_string()),
+        info("BitsPerSample", endpoint.format.bits_per_sample.to_string()),
+        info("SamplesPerSec", endpoint.format.samples_per_sec.to_string()),
+        info("AvgBytesPerSec", endpoint.format.avg_bytes_per_sec.to_string()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::registry::backend::{Hive, RegValue};
+    use serde_json::json;
+
+    fn result(name: &str, value: Option<RegValue>, outcome: CheckOutcome) -> CheckResult {
+        CheckResult {
+            hive: Hive::LocalMachine,
+            path: String::from("Software\\Test"),
+            name: name.to_string(),
+            value,
+            outcome,
+        }
+    }
+
+    fn entries(outcomes: Vec<(Option<RegValue>, CheckOutcome)>) -> Vec<serde_json::Value> {
+        let mut report = Report::default();
+        for (value, outcome) in outcomes {
+            report.push(result("Speed", value, outcome));
+        }
+        from_report(&report, |r| (format!("{}-id", r.name.to_lowercase()), Some(Category::Input)))
+            .iter()
+            .map(|entry| serde_json::to_value(entry).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn every_outcome_is_one_entry() {
+        let one = || Some(RegValue::dword(1));
+        let entries = entries(vec![
+            (one(), CheckOutcome::Correct),
+            (
+                one(),
+                CheckOutcome::Mismatch {
+                    current: RegValue::dword(0),
+                    desired: RegValue::dword(1),
+                },
+            ),
+            (
+                one(),
+                CheckOutcome::WrongType {
+                    current: RegValue::sz("1"),
+                    desired: RegValue::dword(1),
+                },
+            ),
+            (one(), CheckOutcome::Missing),
+            (None, CheckOutcome::Present),
+            (one(), CheckOutcome::Written),
+            (
+                one(),
+                CheckOutcome::Retyped {
+                    previous: RegValue::sz("1"),
+                },
+            ),
+            (None, CheckOutcome::Deleted),
+            (None, CheckOutcome::DeletedKey),
+            (one(), CheckOutcome::Failed(Error::AccessDenied)),
+        ]);
+        let path = "HKEY_LOCAL_MACHINE\\Software\\Test\\Speed";
+        let entry = |current: Option<&str>, desired: Option<&str>, status: &str| {
+            json!({
+                "id": "speed-id",
+                "category": "input",
+                "path": path,
+                "current": current,
+                "desired": desired,
+                "status": status,
+            })
+        };
+        let mut failed = entry(None, Some("dword:1"), "failed");
+        failed["error"] = json!(Error::AccessDenied.to_string());
+        assert_eq!(
+            entries,
+            vec![
+                entry(Some("dword:1"), Some("dword:1"), "correct"),
+                entry(Some("dword:0"), Some("dword:1"), "mismatch"),
+                entry(Some("sz:1"), Some("dword:1"), "wrong_type"),
+                entry(None, Some("dword:1"), "missing"),
+                entry(None, None, "present"),
+                entry(Some("dword:1"), Some("dword:1"), "written"),
+                entry(Some("dword:1"), Some("dword:1"), "retyped"),
+                entry(None, None, "deleted"),
+                entry(None, None, "deleted"),
+                failed,
+            ]
+        );
+    }
+
+    #[test]
+    fn boot_settings_are_entries_of_their_own() {
+        let checks = vec![
+            BcdCheck {
+                setting: "useplatformclock",
+                current: Some(false),
+                desired: false,
+            },
+            BcdCheck {
+                setting: "disabledynamictick",
+                current: None,
+                desired: true,
+            },
+        ];
+        let entries: Vec<serde_json::Value> =
+            from_bcd(&Ok(checks)).iter().map(|e| serde_json::to_value(e).unwrap()).collect();
+        assert_eq!(
+            entries,
+            vec![
+                json!({
+                    "id": "bcd-useplatformclock",
+                    "category": "boot",
+                    "path": "BCD\\{current}\\useplatformclock",
+                    "current": "No",
+                    "desired": "No",
+                    "status": "correct",
+                }),
+                json!({
+                    "id": "bcd-disabledynamictick",
+                    "category": "boot",
+                    "path": "BCD\\{current}\\disabledynamictick",
+                    "current": null,
+                    "desired": "Yes",
+                    "status": "missing",
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn an_unreadable_boot_store_fails_every_boot_setting() {
+        let entries = from_bcd(&Err(Error::Bcd(String::from("access denied"))));
+        let ids: Vec<&str> = entries.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ids, vec!["bcd-useplatformclock", "bcd-disabledynamictick", "bcd-useplatformtick"]);
+        for entry in entries.iter() {
+            assert_eq!(entry.status, Status::Failed);
+            assert_eq!(entry.error.as_deref(), Some("bcdedit failed: access denied"));
+            assert_eq!((entry.current.as_ref(), entry.desired.as_ref()), (None, None));
+        }
+    }
+
+    #[test]
+    fn entries_outside_the_selection_are_dropped() {
+        let mut report = Report::default();
+        report.push(result("Speed", Some(RegValue::dword(1)), CheckOutcome::Correct));
+        report.push(result("Extra", None, CheckOutcome::Present));
+        let tweak = |r: &CheckResult| match r.name.as_str() {
+            "Speed" => (String::from("speed"), Some(Category::Input)),
+            _ => (r.full_path(), None),
+        };
+
+        let mut audit = Audit::new(Format::Json);
+        audit.extend(from_report(&report, tweak), &Selection::new(vec![String::from("input")], Vec::new()));
+        audit.extend(from_bcd(&Err(Error::Missing)), &Selection::default().without(vec![String::from("boot")]));
+        let ids: Vec<&str> = audit.entries.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ids, vec!["speed"]);
+        assert_eq!((audit.drift(), audit.failed()), (0, 0));
+
+        let mut audit = Audit::new(Format::Json);
+        audit.extend(from_report(&report, tweak), &Selection::default());
+        assert_eq!(audit.entries.len(), 2);
+        assert_eq!(audit.drift(), 1);
+    }
+}
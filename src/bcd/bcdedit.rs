@@ -0,0 +1,272 @@
+// bcdedit.exe as the BcdStore: `/enum {current} /v` is parsed, `/set` and
+// `/deletevalue` write. The names of the elements we read are the same in every
+// language (identifier is not), Yes and No are translated.
+
+use super::{BcdElement, BcdStore, BcdValue, ValueKind};
+use crate::error::{self, Error};
+
+use std::process::Command;
+
+// The boot entry of the running Windows
+const ENTRY: &str = "{current}";
+
+pub struct Bcdedit;
+
+impl BcdStore for Bcdedit {
+    fn read(&self, elements: &[&BcdElement]) -> error::Result<Vec<Option<BcdValue>>> {
+        read_elements(&bcdedit(&["/enum", ENTRY, "/v"])?, elements)
+    }
+
+    fn set(&self, element: &BcdElement, value: &BcdValue) -> error::Result<()> {
+        let text = match value {
+            BcdValue::Boolean(true) => String::from("yes"),
+            BcdValue::Boolean(false) => String::from("no"),
+            BcdValue::Integer(n) => n.to_string(),
+            BcdValue::String(s) | BcdValue::Object(s) => s.clone(),
+        };
+        bcdedit(&["/set", ENTRY, &element.bcdedit_name(), &text]).map(|_| ())
+    }
+
+    fn delete(&self, element: &BcdElement) -> error::Result<()> {
+        bcdedit(&["/deletevalue", ENTRY, &element.bcdedit_name()]).map(|_| ())
+    }
+}
+
+// The values of `elements` in the first object of the output
+fn read_elements(output: &str, elements: &[&BcdElement]) -> error::Result<Vec<Option<BcdValue>>> {
+    let entries = parse_enum(output).map_err(Error::Bcd)?;
+    let entry = entries
+        .first()
+        .ok_or_else(|| Error::Bcd(format!("no boot entry {} in the output", ENTRY)))?;
+    elements
+        .iter()
+        .map(|element| match entry.get(element) {
+            Some(text) => element.parse(text).map(Some).map_err(Error::Bcd),
+            None => Ok(None),
+        })
+        .collect()
+}
+
+// One object of `bcdedit /enum`, its elements as bcdedit prints them
+#[derive(Clone, Debug, PartialEq)]
+pub struct BcdEntry {
+    // The line above the dashes, e.g. Windows Boot Loader
+    pub title: String,
+    pub elements: Vec<(String, String)>,
+}
+
+impl BcdEntry {
+    // An element without a name of its own is printed as custom:<type id>
+    pub fn get(&self, element: &BcdElement) -> Option<&str> {
+        let custom = format!("custom:{:08x}", element.type_id);
+        self.elements
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(element.name) || name.eq_ignore_ascii_case(&custom))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// The output of `bcdedit /enum`:
+//
+// Windows Boot Loader
+// -------------------
+// identifier              {b2721d73-1db4-11e6-8a2c-b4fdea2a1b31}
+// device                  partition=C:
+// useplatformclock        No
+//
+// Objects are separated by empty lines. A value that goes on in the next line
+// (lists like displayorder) is indented, its lines are joined with a space.
+pub fn parse_enum(text: &str) -> Result<Vec<BcdEntry>, String> {
+    let mut entries: Vec<BcdEntry> = Vec::new();
+    let mut lines = text.lines().enumerate().peekable();
+    while let Some((n, line)) = lines.next() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        // A title needs its line of dashes, anything else is the heading of the store
+        match lines.peek() {
+            Some((_, next)) if is_rule(next) => {
+                lines.next();
+            }
+            _ if entries.is_empty() => continue,
+            _ => return Err(format!("line {}: `{}` is not part of an object", n + 1, line.trim())),
+        }
+        let mut entry = BcdEntry {
+            title: line.trim().to_string(),
+            elements: Vec::new(),
+        };
+        while let Some((n, line)) = lines.next_if(|(_, line)| !line.trim().is_empty()) {
+            if line.starts_with(char::is_whitespace) {
+                match entry.elements.last_mut() {
+                    Some((_, value)) => {
+                        value.push(' ');
+                        value.push_str(line.trim());
+                    }
+                    None => return Err(format!("line {}: a continued value without an element", n + 1)),
+                }
+                continue;
+            }
+            let line = line.trim_end();
+            let (name, value) = match line.find(char::is_whitespace) {
+                Some(index) => (&line[..index], line[index..].trim()),
+                None => (line, ""),
+            };
+            entry.elements.push((name.to_string(), value.to_string()));
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn is_rule(line: &str) -> bool {
+    let line = line.trim();
+    !line.is_empty() && line.chars().all(|c| c == '-')
+}
+
+// bcdedit prints Yes and No in the language of Windows
+const YES_NO: &[(&str, &str)] = &[
+    ("Yes", "No"),
+    ("Ja", "Nein"),
+    ("Oui", "Non"),
+    ("Sí", "No"),
+    ("Si", "No"),
+    ("Sim", "Não"),
+    ("Tak", "Nie"),
+    ("Да", "Нет"),
+    ("Igen", "Nem"),
+    ("Ano", "Ne"),
+    ("Evet", "Hayır"),
+];
+
+pub fn parse_bool(text: &str) -> Option<bool> {
+    YES_NO.iter().find_map(|(yes, no)| {
+        if text.eq_ignore_ascii_case(yes) {
+            Some(true)
+        } else if text.eq_ignore_ascii_case(no) {
+            Some(false)
+        } else {
+            None
+        }
+    })
+}
+
+impl BcdElement {
+    // What bcdedit calls the element, also for /set and /deletevalue
+    fn bcdedit_name(&self) -> String {
+        match self.name.is_empty() {
+            true => format!("custom:{:08x}", self.type_id),
+            false => self.name.to_string(),
+        }
+    }
+
+    // The value of the element as bcdedit prints it
+    pub fn parse(&self, text: &str) -> Result<BcdValue, String> {
+        let invalid = || format!("{}: `{}` is not a {:?} value", self.name, text, self.kind());
+        match self.kind() {
+            ValueKind::Boolean => parse_bool(text).map(BcdValue::Boolean).ok_or_else(invalid),
+            ValueKind::Integer => {
+                let number = match text.strip_prefix("0x") {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => text.parse::<u64>(),
+                };
+                number.map(BcdValue::Integer).map_err(|_| invalid())
+            }
+            ValueKind::String => Ok(BcdValue::String(text.to_string())),
+            ValueKind::Object => Ok(BcdValue::Object(text.to_string())),
+        }
+    }
+}
+
+// The output, or the error bcdedit printed
+fn bcdedit(args: &[&str]) -> error::Result<String> {
+    let output = Command::new("bcdedit").args(args).output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    if output.status.success() {
+        Ok(stdout)
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = if stderr.trim().is_empty() { stdout.trim() } else { stderr.trim() };
+        Err(Error::Bcd(format!("bcdedit {}: {}", args.join(" "), message)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bcd::{DISABLE_DYNAMIC_TICK, USE_PLATFORM_CLOCK, USE_PLATFORM_TICK};
+
+    // Captured from bcdedit with CRLF line ends
+    fn fixture(name: &str) -> String {
+        let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn current_entry_in_german() {
+        let output = fixture("bcdedit-enum-current-de.txt");
+        let entries = parse_enum(&output).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].title, "Windows-Startladeprogramm");
+        assert_eq!(entries[0].elements[0], ("Bezeichner".to_string(), "{b2721d73-1db4-11e6-8a2c-b4fdea2a1b31}".to_string()));
+        // Printed by its type id only
+        assert_eq!(entries[0].get(&DISABLE_DYNAMIC_TICK), Some("Ja"));
+        assert_eq!(entries[0].get(&USE_PLATFORM_CLOCK), Some("Nein"));
+
+        let values = read_elements(&output, &[&USE_PLATFORM_CLOCK, &USE_PLATFORM_TICK, &DISABLE_DYNAMIC_TICK]).unwrap();
+        assert_eq!(
+            values,
+            vec![Some(BcdValue::Boolean(false)), Some(BcdValue::Boolean(true)), Some(BcdValue::Boolean(true))]
+        );
+    }
+
+    #[test]
+    fn a_wrapped_value_is_joined() {
+        let entries = parse_enum(&fixture("bcdedit-enum-en.txt")).unwrap();
+        let titles: Vec<&str> = entries.iter().map(|e| e.title.as_str()).collect();
+        assert_eq!(titles, vec!["Windows Boot Manager", "Windows Boot Loader"]);
+        let manager = &entries[0];
+        let order = manager.elements.iter().find(|(name, _)| name == "displayorder").unwrap();
+        assert_eq!(order.1, "{b2721d73-1db4-11e6-8a2c-b4fdea2a1b31} {b2721d77-1db4-11e6-8a2c-b4fdea2a1b31}");
+        // The line after the continuation is an element of its own again
+        assert_eq!(manager.elements[9].0, "toolsdisplayorder");
+        assert_eq!(manager.elements.len(), 11);
+        assert_eq!(entries[1].get(&USE_PLATFORM_CLOCK), Some("No"));
+        assert_eq!(entries[1].get(&USE_PLATFORM_TICK), None);
+    }
+
+    #[test]
+    fn an_element_outside_of_an_object_is_refused() {
+        let mut output = fixture("bcdedit-enum-en.txt");
+        output.push_str("\r\nuseplatformtick         Yes\r\n");
+        assert_eq!(parse_enum(&output).unwrap_err(), "line 36: `useplatformtick         Yes` is not part of an object");
+        let wrapped = "Windows Boot Loader\n-------------------\n    {b2721d73-1db4-11e6-8a2c-b4fdea2a1b31}\n";
+        assert_eq!(parse_enum(wrapped).unwrap_err(), "line 3: a continued value without an element");
+    }
+
+    #[test]
+    fn yes_and_no_in_other_languages() {
+        assert_eq!(parse_bool("Ja"), Some(true));
+        assert_eq!(parse_bool("nein"), Some(false));
+        assert_eq!(parse_bool("Oui"), Some(true));
+        assert_eq!(parse_bool("Да"), Some(true));
+        assert_eq!(parse_bool("Hayır"), Some(false));
+        assert_eq!(parse_bool("0x1"), None);
+        assert_eq!(
+            USE_PLATFORM_CLOCK.parse("Vielleicht").unwrap_err(),
+            "useplatformclock: `Vielleicht` is not a Boolean value"
+        );
+    }
+
+    #[test]
+    fn an_unknown_element_goes_by_its_type_id() {
+        let element = BcdElement {
+            type_id: 0x2500_00c2,
+            name: "",
+        };
+        assert_eq!(element.kind(), ValueKind::Integer);
+        assert_eq!(element.bcdedit_name(), "custom:250000c2");
+        let entries = parse_enum("Windows Boot Loader\n---\ncustom:250000C2         0x1\n").unwrap();
+        assert_eq!(entries[0].get(&element), Some("0x1"));
+        assert_eq!(element.parse("0x1"), Ok(BcdValue::Integer(1)));
+    }
+}
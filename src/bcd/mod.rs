@@ -0,0 +1,382 @@
+// The boot configuration data (BCD) elements of the {current} boot entry. An
+// element is known by its type id, bcdedit also has a name for most of them.
+// The store is behind a trait, so the checks work on made-up values and
+// bcdedit is the only place that talks to Windows.
+
+mod bcdedit;
+
+pub use bcdedit::Bcdedit;
+
+use crate::error::{self, Error};
+use crate::registry::snapshot::{SavedBcd, Snapshot};
+use crate::selection::{Category, Risk, Selection, TweakInfo};
+
+use std::fmt;
+
+// The format of the element, bits 24 to 27 of its type id (0x26 is a boolean of
+// an OS loader). Only booleans are tweaked so far.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ValueKind {
+    Object,
+    String,
+    Integer,
+    Boolean,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BcdElement {
+    pub type_id: u32,
+    // As bcdedit calls it, empty for an element it only knows as custom:<type id>
+    pub name: &'static str,
+}
+
+impl BcdElement {
+    // Devices and lists of integers are kept as the text bcdedit prints
+    pub fn kind(&self) -> ValueKind {
+        match (self.type_id >> 24) & 0xf {
+            3 | 4 => ValueKind::Object,
+            5 => ValueKind::Integer,
+            6 => ValueKind::Boolean,
+            _ => ValueKind::String,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BcdValue {
+    Object(String),
+    String(String),
+    Integer(u64),
+    Boolean(bool),
+}
+
+impl BcdValue {
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            BcdValue::Boolean(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BcdValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BcdValue::Boolean(value) => f.write_str(yes_no(*value)),
+            BcdValue::Integer(value) => write!(f, "{}", value),
+            BcdValue::String(value) | BcdValue::Object(value) => f.write_str(value),
+        }
+    }
+}
+
+pub const USE_PLATFORM_CLOCK: BcdElement = BcdElement {
+    type_id: 0x2600_00a2,
+    name: "useplatformclock",
+};
+
+pub const USE_PLATFORM_TICK: BcdElement = BcdElement {
+    type_id: 0x2600_00a4,
+    name: "useplatformtick",
+};
+
+pub const DISABLE_DYNAMIC_TICK: BcdElement = BcdElement {
+    type_id: 0x2600_00a5,
+    name: "disabledynamictick",
+};
+
+// The boolean elements we touch on the {current} boot entry and the value we want
+const BCD_ELEMENTS: [(BcdElement, bool); 3] = [
+    (USE_PLATFORM_CLOCK, false),
+    (DISABLE_DYNAMIC_TICK, true),
+    (USE_PLATFORM_TICK, true),
+];
+
+// Where the elements are read and written, a trait so the checks can run
+// without a boot store
+pub trait BcdStore {
+    // The values in the order of `elements`, None when an element is not set
+    fn read(&self, elements: &[&BcdElement]) -> error::Result<Vec<Option<BcdValue>>>;
+    fn set(&self, element: &BcdElement, value: &BcdValue) -> error::Result<()>;
+    // Back to the Windows default
+    fn delete(&self, element: &BcdElement) -> error::Result<()>;
+}
+
+// An element of BCD_ELEMENTS compared with the value the tweak wants
+pub struct BcdCheck {
+    pub setting: &'static str,
+    pub current: Option<bool>,
+    pub desired: bool,
+}
+
+impl BcdCheck {
+    pub fn in_sync(&self) -> bool {
+        self.current == Some(self.desired)
+    }
+}
+
+pub fn check_bcd_store<S: BcdStore>(store: &S) -> error::Result<Vec<BcdCheck>> {
+    let states = read_bcd_store(store)?;
+    Ok(BCD_ELEMENTS
+        .iter()
+        .zip(states)
+        .map(|((element, desired), (_, current))| BcdCheck {
+            setting: element.name,
+            current,
+            desired: *desired,
+        })
+        .collect())
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "Yes"
+    } else {
+        "No"
+    }
+}
+
+#[cfg(windows)]
+pub fn print_bcd_checks(checks: &[BcdCheck]) {
+    for check in checks.iter() {
+        match check.current {
+            Some(current) if check.in_sync() => println!("correct setting: {} {}", check.setting, yes_no(current)),
+            Some(current) => println!("wrong setting: \x1b[0;93m{} {}\x1b[0m", check.setting, yes_no(current)),
+            None => println!("setting missing: \x1b[0;93m{} None\x1b[0m", check.setting),
+        }
+    }
+}
+
+// TODO: aber tscsyncpolicy Legacy kann ich dir auf jedenfall empfehlen
+
+pub fn bcd_id(setting: &str) -> String {
+    format!("bcd-{}", setting)
+}
+
+pub fn bcd_settings() -> Vec<&'static str> {
+    BCD_ELEMENTS.iter().map(|(element, _)| element.name).collect()
+}
+
+pub fn bcd_tweaks() -> Vec<TweakInfo> {
+    BCD_ELEMENTS
+        .iter()
+        .map(|(element, desired)| TweakInfo {
+            id: bcd_id(element.name),
+            category: Some(Category::Boot),
+            risk: Risk::Medium,
+            description: format!("{} = {}", element.name, yes_no(*desired)),
+        })
+        .collect()
+}
+
+fn selected(selection: &Selection, setting: &str) -> bool {
+    selection.includes(&bcd_id(setting), Some(Category::Boot))
+}
+
+fn element(setting: &str) -> error::Result<&'static BcdElement> {
+    BCD_ELEMENTS
+        .iter()
+        .map(|(element, _)| element)
+        .find(|element| element.name == setting)
+        .ok_or_else(|| Error::Bcd(format!("unknown BcdStore setting {}", setting)))
+}
+
+// Deletes the selected elements to get the Windows default back
+pub fn reset_bcd_store<S: BcdStore>(store: &S, selection: &Selection, snapshot: Option<&Snapshot>) {
+    if !BCD_ELEMENTS.iter().any(|(element, _)| selected(selection, element.name)) {
+        return;
+    }
+    let states = match read_bcd_store(store) {
+        Ok(states) => states,
+        Err(e) => {
+            println!("\x1b[0;91mcould not read the BcdStore, nothing changed: {}\x1b[0m", e);
+            return;
+        }
+    };
+    let states: Vec<(String, Option<bool>)> = states
+        .into_iter()
+        .filter(|(setting, _)| selected(selection, setting))
+        .collect();
+    if let Some(snapshot) = snapshot {
+        let saved = states
+            .iter()
+            .try_for_each(|(setting, state)| snapshot.record_bcd(setting, *state));
+        if let Err(e) = saved {
+            println!("\x1b[0;91mcould not save the BcdStore, nothing changed: {}\x1b[0m", e);
+            return;
+        }
+    }
+    for (setting, state) in states.iter() {
+        if state.is_none() {
+            continue;
+        }
+        match element(setting).and_then(|element| store.delete(element)) {
+            Ok(()) => println!("delete setting: {}", setting),
+            Err(e) => println!("\x1b[0;93m{}\x1b[0m ", e),
+        }
+    }
+}
+
+// Writes one element of BCD_ELEMENTS, used by the plan executor
+pub fn set_bcd_element<S: BcdStore>(store: &S, setting: &str, value: bool) -> error::Result<()> {
+    store.set(element(setting)?, &BcdValue::Boolean(value))
+}
+
+// Current state of the elements in BCD_ELEMENTS, None when an element is not set
+pub fn read_bcd_store<S: BcdStore>(store: &S) -> error::Result<Vec<(String, Option<bool>)>> {
+    let elements: Vec<&BcdElement> = BCD_ELEMENTS.iter().map(|(element, _)| element).collect();
+    let values = store.read(&elements)?;
+    if values.len() != elements.len() {
+        return Err(Error::Bcd(format!("read {} of {} elements", values.len(), elements.len())));
+    }
+    elements
+        .iter()
+        .zip(values)
+        .map(|(element, value)| {
+            let state = match value {
+                Some(value) => Some(
+                    value
+                        .as_bool()
+                        .ok_or_else(|| Error::Bcd(format!("{} is {}, not Yes or No", element.name, value)))?,
+                ),
+                None => None,
+            };
+            Ok((element.name.to_string(), state))
+        })
+        .collect()
+}
+
+// Puts the elements back the way a snapshot saw them
+pub fn restore_bcd_store<S: BcdStore>(store: &S, saved: &[SavedBcd]) {
+    if saved.is_empty() {
+        return;
+    }
+    // bcdedit fails to delete an element that is not set
+    let states = match read_bcd_store(store) {
+        Ok(states) => states,
+        Err(e) => {
+            println!("\x1b[0;93m{}\x1b[0m ", e);
+            return;
+        }
+    };
+    for entry in saved.iter() {
+        let now = states.iter().find(|(setting, _)| *setting == entry.setting).and_then(|(_, state)| *state);
+        if now == entry.previous {
+            continue;
+        }
+        let restored = element(&entry.setting).and_then(|element| match entry.previous {
+            Some(value) => store.set(element, &BcdValue::Boolean(value)),
+            None => store.delete(element),
+        });
+        match (restored, entry.previous) {
+            (Ok(()), Some(value)) => println!("write setting: {} = {}", entry.setting, yes_no(value)),
+            (Ok(()), None) => println!("delete setting: {}", entry.setting),
+            (Err(e), _) => println!("\x1b[0;93m{}\x1b[0m ", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    // The elements that are set, by type id
+    #[derive(Default)]
+    struct FakeStore {
+        elements: RefCell<Vec<(u32, BcdValue)>>,
+        deleted: RefCell<Vec<&'static str>>,
+    }
+
+    impl FakeStore {
+        fn with(elements: &[(BcdElement, BcdValue)]) -> FakeStore {
+            FakeStore {
+                elements: RefCell::new(elements.iter().map(|(e, v)| (e.type_id, v.clone())).collect()),
+                ..FakeStore::default()
+            }
+        }
+
+        fn get(&self, element: &BcdElement) -> Option<BcdValue> {
+            self.elements.borrow().iter().find(|(id, _)| *id == element.type_id).map(|(_, v)| v.clone())
+        }
+    }
+
+    impl BcdStore for FakeStore {
+        fn read(&self, elements: &[&BcdElement]) -> error::Result<Vec<Option<BcdValue>>> {
+            Ok(elements.iter().map(|element| self.get(element)).collect())
+        }
+
+        fn set(&self, element: &BcdElement, value: &BcdValue) -> error::Result<()> {
+            self.elements.borrow_mut().retain(|(id, _)| *id != element.type_id);
+            self.elements.borrow_mut().push((element.type_id, value.clone()));
+            Ok(())
+        }
+
+        fn delete(&self, element: &BcdElement) -> error::Result<()> {
+            if self.get(element).is_none() {
+                return Err(Error::Bcd(format!("{} is not set", element.name)));
+            }
+            self.elements.borrow_mut().retain(|(id, _)| *id != element.type_id);
+            self.deleted.borrow_mut().push(element.name);
+            Ok(())
+        }
+    }
+
+    fn states(checks: &[BcdCheck]) -> Vec<(&str, Option<bool>, bool)> {
+        checks.iter().map(|c| (c.setting, c.current, c.in_sync())).collect()
+    }
+
+    #[test]
+    fn check_against_the_store() {
+        let store = FakeStore::with(&[
+            (USE_PLATFORM_CLOCK, BcdValue::Boolean(true)),
+            (DISABLE_DYNAMIC_TICK, BcdValue::Boolean(true)),
+        ]);
+        let checks = check_bcd_store(&store).unwrap();
+        assert_eq!(
+            states(&checks),
+            vec![
+                ("useplatformclock", Some(true), false),
+                ("disabledynamictick", Some(true), true),
+                ("useplatformtick", None, false),
+            ]
+        );
+        set_bcd_element(&store, "useplatformtick", true).unwrap();
+        set_bcd_element(&store, "useplatformclock", false).unwrap();
+        assert!(check_bcd_store(&store).unwrap().iter().all(BcdCheck::in_sync));
+        assert!(set_bcd_element(&store, "tscsyncpolicy", true).is_err());
+    }
+
+    #[test]
+    fn a_value_that_is_not_yes_or_no_fails_the_check() {
+        let store = FakeStore::with(&[(USE_PLATFORM_TICK, BcdValue::Integer(1))]);
+        match check_bcd_store(&store) {
+            Err(Error::Bcd(message)) => assert_eq!(message, "useplatformtick is 1, not Yes or No"),
+            _ => panic!("expected a bcd error"),
+        }
+    }
+
+    #[test]
+    fn reset_and_restore() {
+        let store = FakeStore::with(&[(USE_PLATFORM_CLOCK, BcdValue::Boolean(false))]);
+        reset_bcd_store(&store, &Selection::default(), None);
+        // The elements that are not set are left alone
+        assert_eq!(*store.deleted.borrow(), vec!["useplatformclock"]);
+        assert!(store.elements.borrow().is_empty());
+
+        let saved = [
+            SavedBcd {
+                setting: "useplatformclock".to_string(),
+                previous: Some(false),
+            },
+            SavedBcd {
+                setting: "useplatformtick".to_string(),
+                previous: None,
+            },
+        ];
+        restore_bcd_store(&store, &saved);
+        assert_eq!(store.get(&USE_PLATFORM_CLOCK), Some(BcdValue::Boolean(false)));
+        assert_eq!(store.get(&USE_PLATFORM_TICK), None);
+        assert_eq!(store.deleted.borrow().len(), 1);
+    }
+}
@@ -0,0 +1,572 @@
+// Command line of gaming-optimizer. Without a subcommand the interactive menu
+// is shown like before, the subcommands are meant for scripts and scheduled tasks.
+
+use crate::registry::backend::HiveMount;
+use crate::selection::{category_names, Selection};
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+// Exit codes
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_ERROR: i32 = 1;
+// `check` found settings that differ from the tweaks
+pub const EXIT_DRIFT: i32 = 2;
+
+// A failure wins over settings that differ
+pub fn exit_code(failed: usize, drift: usize) -> i32 {
+    if failed > 0 {
+        EXIT_ERROR
+    } else if drift > 0 {
+        EXIT_DRIFT
+    } else {
+        EXIT_OK
+    }
+}
+
+// How `check` and `plan` print their results
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Text,
+    // One JSON array once everything is checked
+    Json,
+    // One JSON object per line, written as soon as an area is checked
+    Ndjson,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportKind {
+    Desired,
+    Defaults,
+    Current,
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Interactive,
+    Check,
+    // Prints the changes an apply would make, or saves them to a file
+    Plan { output: Option<PathBuf> },
+    // None plans the changes first, otherwise a saved plan is applied
+    Apply { plan: Option<PathBuf> },
+    // None restores the Windows defaults, otherwise a snapshot file or "latest"
+    Restore { snapshot: Option<String> },
+    Export { kind: ExportKind, output: Option<PathBuf> },
+    List { snapshots: bool },
+    Profile(ProfileCommand),
+}
+
+#[derive(Debug)]
+pub enum ProfileCommand {
+    List,
+    // Every PerfOptions entry on the machine, also those of other tools
+    Scan,
+    Add { process: String, profile: String },
+    Remove { process: String },
+    Check { name: String },
+    Apply { name: String },
+    // Removes the IFEO entries of the profile
+    Clear { name: String },
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub command: Command,
+    pub catalog: Option<PathBuf>,
+    pub reg: Option<PathBuf>,
+    pub profiles: Option<PathBuf>,
+    // Hive files to work on instead of the live registry
+    pub hives: Vec<HiveMount>,
+    // The HKCU tweaks go to these users instead of the one running the tool
+    pub users: Vec<String>,
+    pub load_profiles: bool,
+    // Show the hidden power settings of the gaming scheme in the control panel
+    pub unhide_power_settings: bool,
+    pub ping: Option<String>,
+    pub mtu: Option<u32>,
+    pub yes: bool,
+    pub format: Format,
+    pub selection: Selection,
+}
+
+fn selection_help() -> String {
+    format!(
+        "Comma separated categories ({}) or tweak ids, `list` shows the ids",
+        category_names()
+    )
+}
+
+fn format_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("format")
+        .long("format")
+        .value_name("FORMAT")
+        .possible_values(&["text", "json", "ndjson"])
+        .default_value("text")
+        .help("Output for people, one JSON array, or one JSON object per line")
+}
+
+fn profile_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("name").value_name("PROFILE").required(true).help("Profile name, `profile list` shows them")
+}
+
+fn app<'a, 'b>(selection_help: &'b str) -> App<'a, 'b> {
+    App::new("gaming-optimizer")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Checks and applies Windows tweaks for gaming")
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(
+            Arg::with_name("catalog")
+                .long("catalog")
+                .value_name("FILE")
+                .global(true)
+                .conflicts_with("reg")
+                .help("Tweak catalog (TOML or JSON) to use instead of the built-in one"),
+        )
+        .arg(
+            Arg::with_name("reg")
+                .long("reg")
+                .value_name("FILE")
+                .global(true)
+                .help(".reg file to use as the tweak set instead of the catalog"),
+        )
+        .arg(
+            Arg::with_name("profiles")
+                .long("profiles")
+                .value_name("FILE")
+                .global(true)
+                .help("Process profiles (TOML or JSON) to use instead of the saved or built-in ones"),
+        )
+        .arg(
+            Arg::with_name("hive")
+                .long("hive")
+                .value_name("[KEY=]FILE")
+                .global(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Work on a hive file instead of the live registry, e.g. HKLM\\SOFTWARE=D:\\Windows\\System32\\config\\SOFTWARE"),
+        )
+        .arg(
+            Arg::with_name("users")
+                .long("users")
+                .value_name("USERS")
+                .global(true)
+                .use_delimiter(true)
+                .help("Check and write the HKCU tweaks for these users (names or SIDs) or `all` instead of yourself"),
+        )
+        .arg(
+            Arg::with_name("load-profiles")
+                .long("load-profiles")
+                .global(true)
+                .requires("users")
+                .conflicts_with("hive")
+                .help("Load the NTUSER.DAT of picked users who are not signed in"),
+        )
+        .arg(
+            Arg::with_name("unhide-power-settings")
+                .long("unhide-power-settings")
+                .global(true)
+                .help("Also show the hidden power settings of the gaming plan in the power options"),
+        )
+        .arg(
+            Arg::with_name("ping")
+                .long("ping")
+                .value_name("IP")
+                .global(true)
+                .help("Address used to find the MTU [default: 1.1.1.1]"),
+        )
+        .arg(
+            Arg::with_name("mtu")
+                .long("mtu")
+                .value_name("BYTES")
+                .global(true)
+                .conflicts_with("ping")
+                .help("Use this MTU instead of measuring it with ping"),
+        )
+        .arg(
+            Arg::with_name("only")
+                .long("only")
+                .value_name("TWEAKS")
+                .global(true)
+                .use_delimiter(true)
+                .help(selection_help),
+        )
+        .arg(
+            Arg::with_name("skip")
+                .long("skip")
+                .value_name("TWEAKS")
+                .global(true)
+                .use_delimiter(true)
+                .help(selection_help),
+        )
+        .arg(
+            Arg::with_name("yes")
+                .long("yes")
+                .short("y")
+                .global(true)
+                .help("Do not ask before changing anything"),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Compares the machine with the tweaks, exits with 2 if something differs")
+                .arg(format_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("plan")
+                .about("Shows the changes an apply would make without writing anything")
+                .arg(format_arg())
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .value_name("FILE")
+                        .help("Save the plan as JSON for `apply --plan`"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("apply")
+                .about("Writes the picked tweaks, the previous state is saved to a snapshot")
+                .arg(
+                    Arg::with_name("plan")
+                        .long("plan")
+                        .value_name("FILE")
+                        .help("Apply a plan saved by `plan -o` instead of planning again"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("Restores the Windows defaults or a snapshot")
+                .arg(
+                    Arg::with_name("snapshot")
+                        .long("snapshot")
+                        .value_name("FILE")
+                        .help("Snapshot file to restore, or `latest`"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Writes the tweaks as a .reg file")
+                .arg(
+                    Arg::with_name("kind")
+                        .possible_values(&["desired", "restore", "current"])
+                        .default_value("desired")
+                        .help("The tweaked values, the Windows defaults or the values on this machine"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .value_name("FILE")
+                        .help("Write to a file instead of the console"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("Lists the tweaks")
+                .arg(Arg::with_name("snapshots").long("snapshots").help("List the saved snapshots instead")),
+        )
+        .subcommand(
+            SubCommand::with_name("profile")
+                .about("Manages the process profiles and their Image File Execution Options")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("list").about("Lists the profiles and their processes"))
+                .subcommand(
+                    SubCommand::with_name("scan")
+                        .about("Lists every PerfOptions entry on this machine, also those written by other tools"),
+                )
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Adds a game to a profile")
+                        .arg(Arg::with_name("process").value_name("EXE").required(true).help("Executable name, e.g. game.exe"))
+                        .arg(
+                            Arg::with_name("to")
+                                .long("to")
+                                .value_name("PROFILE")
+                                .default_value("games")
+                                .help("Profile to add the game to"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Removes a game from its profile, `profile clear` removes the IFEO entries")
+                        .arg(Arg::with_name("process").value_name("EXE").required(true).help("Executable name, e.g. game.exe")),
+                )
+                .subcommand(
+                    SubCommand::with_name("check")
+                        .about("Compares the IFEO entries of a profile, exits with 2 if something differs")
+                        .arg(profile_arg())
+                        .arg(format_arg()),
+                )
+                .subcommand(SubCommand::with_name("apply").about("Writes the IFEO entries of a profile").arg(profile_arg()))
+                .subcommand(SubCommand::with_name("clear").about("Removes the IFEO entries of a profile").arg(profile_arg())),
+        )
+}
+
+#[cfg(windows)]
+pub fn parse() -> Options {
+    let help = selection_help();
+    let matches = app(&help).get_matches();
+    match options(&matches) {
+        Ok(options) => options,
+        Err(message) => clap::Error::with_description(&message, clap::ErrorKind::InvalidValue).exit(),
+    }
+}
+
+fn options(matches: &ArgMatches) -> Result<Options, String> {
+    let format_of = |sub: &ArgMatches| match sub.value_of("format") {
+        Some("json") => Format::Json,
+        Some("ndjson") => Format::Ndjson,
+        _ => Format::Text,
+    };
+    let format = match matches.subcommand() {
+        ("check", Some(sub)) | ("plan", Some(sub)) => format_of(sub),
+        ("profile", Some(sub)) => match sub.subcommand() {
+            ("check", Some(sub)) => format_of(sub),
+            _ => Format::Text,
+        },
+        _ => Format::Text,
+    };
+
+    let command = match matches.subcommand() {
+        ("check", _) => Command::Check,
+        ("plan", Some(sub)) => Command::Plan {
+            output: sub.value_of("output").map(PathBuf::from),
+        },
+        ("apply", Some(sub)) => Command::Apply {
+            plan: sub.value_of("plan").map(PathBuf::from),
+        },
+        ("restore", Some(sub)) => Command::Restore {
+            snapshot: sub.value_of("snapshot").map(String::from),
+        },
+        ("export", Some(sub)) => Command::Export {
+            kind: match sub.value_of("kind") {
+                Some("restore") => ExportKind::Defaults,
+                Some("current") => ExportKind::Current,
+                _ => ExportKind::Desired,
+            },
+            output: sub.value_of("output").map(PathBuf::from),
+        },
+        ("list", Some(sub)) => Command::List {
+            snapshots: sub.is_present("snapshots"),
+        },
+        ("profile", Some(sub)) => {
+            let value = |sub: &ArgMatches, name: &str| sub.value_of(name).unwrap_or_default().to_string();
+            Command::Profile(match sub.subcommand() {
+                ("add", Some(sub)) => ProfileCommand::Add {
+                    process: value(sub, "process"),
+                    profile: value(sub, "to"),
+                },
+                ("remove", Some(sub)) => ProfileCommand::Remove {
+                    process: value(sub, "process"),
+                },
+                ("check", Some(sub)) => ProfileCommand::Check { name: value(sub, "name") },
+                ("apply", Some(sub)) => ProfileCommand::Apply { name: value(sub, "name") },
+                ("clear", Some(sub)) => ProfileCommand::Clear { name: value(sub, "name") },
+                ("scan", _) => ProfileCommand::Scan,
+                _ => ProfileCommand::List,
+            })
+        }
+        _ => Command::Interactive,
+    };
+
+    let names = |name: &str| -> Vec<String> {
+        matches
+            .values_of(name)
+            .into_iter()
+            .flatten()
+            .map(|n| n.trim().to_string())
+            .filter(|n| !n.is_empty())
+            .collect()
+    };
+    let selection = Selection::new(names("only"), names("skip"));
+
+    let mtu = match matches.value_of("mtu") {
+        Some(mtu) => Some(mtu.parse::<u32>().map_err(|_| format!("`{}` is not a valid MTU", mtu))?),
+        None => None,
+    };
+
+    let hives = matches
+        .values_of("hive")
+        .into_iter()
+        .flatten()
+        .map(str::parse::<HiveMount>)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if let Some(ip) = matches.value_of("ping") {
+        ip.parse::<IpAddr>().map_err(|_| format!("`{}` is not an IP address", ip))?;
+    }
+
+    Ok(Options {
+        command,
+        catalog: matches.value_of("catalog").map(PathBuf::from),
+        reg: matches.value_of("reg").map(PathBuf::from),
+        profiles: matches.value_of("profiles").map(PathBuf::from),
+        hives,
+        users: names("users"),
+        load_profiles: matches.is_present("load-profiles"),
+        unhide_power_settings: matches.is_present("unhide-power-settings"),
+        ping: matches.value_of("ping").map(String::from),
+        mtu,
+        yes: matches.is_present("yes"),
+        format,
+        selection,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selection::Category;
+
+    fn parse_from(args: &[&str]) -> Result<Options, String> {
+        let help = selection_help();
+        let args = std::iter::once("gaming-optimizer").chain(args.iter().copied());
+        let matches = app(&help).get_matches_from_safe(args).map_err(|e| e.message)?;
+        options(&matches)
+    }
+
+    #[test]
+    fn only_and_skip_take_lists_before_or_after_the_subcommand() {
+        let options = parse_from(&["--only", "input, bcd-useplatformclock,", "check", "--skip", "mouse-acceleration"]).unwrap();
+        let selection = &options.selection;
+        assert!(selection.includes("keyboard-delay", Some(Category::Input)));
+        assert!(selection.includes("bcd-useplatformclock", Some(Category::Boot)));
+        assert!(!selection.includes("bcd-disabledynamictick", Some(Category::Boot)));
+        assert!(!selection.includes("mouse-acceleration", Some(Category::Input)));
+
+        let options = parse_from(&["restore", "--skip", "boot,power"]).unwrap();
+        assert!(!options.selection.includes("bcd-useplatformclock", Some(Category::Boot)));
+        assert!(options.selection.includes("tcp-mtu", Some(Category::Network)));
+        assert!(parse_from(&["restore"]).unwrap().selection.includes("tcp-mtu", Some(Category::Network)));
+    }
+
+    #[test]
+    fn check_with_a_selection_as_json() {
+        let options = parse_from(&["check", "--only", "tcp-mtu", "--format", "json"]).unwrap();
+        assert!(matches!(options.command, Command::Check));
+        assert_eq!(options.format, Format::Json);
+        assert!(options.selection.includes("tcp-mtu", Some(Category::Network)));
+        assert!(!options.selection.includes("tcp-ack-frequency", Some(Category::Network)));
+        assert_eq!(parse_from(&["check"]).unwrap().format, Format::Text);
+    }
+
+    #[test]
+    fn apply_restore_and_plan() {
+        assert!(matches!(parse_from(&["apply"]).unwrap().command, Command::Apply { plan: None }));
+        match parse_from(&["apply", "--plan", "plan.json", "--yes"]).unwrap() {
+            Options {
+                command: Command::Apply { plan: Some(plan) },
+                yes: true,
+                ..
+            } => assert_eq!(plan, PathBuf::from("plan.json")),
+            options => panic!("{:?}", options),
+        }
+
+        assert!(matches!(parse_from(&["restore"]).unwrap().command, Command::Restore { snapshot: None }));
+        match parse_from(&["restore", "--snapshot", "latest"]).unwrap().command {
+            Command::Restore { snapshot } => assert_eq!(snapshot.as_deref(), Some("latest")),
+            command => panic!("{:?}", command),
+        }
+
+        let options = parse_from(&["plan"]).unwrap();
+        assert!(matches!(options.command, Command::Plan { output: None }));
+        assert_eq!(options.format, Format::Text);
+        let options = parse_from(&["plan", "--format", "ndjson", "-o", "plan.json"]).unwrap();
+        assert!(matches!(options.command, Command::Plan { output: Some(_) }));
+        assert_eq!(options.format, Format::Ndjson);
+        assert!(parse_from(&["plan", "--format", "xml"]).is_err());
+    }
+
+    #[test]
+    fn export_list_and_profile() {
+        match parse_from(&["export", "restore", "-o", "defaults.reg"]).unwrap().command {
+            Command::Export { kind, output } => {
+                assert_eq!(kind, ExportKind::Defaults);
+                assert_eq!(output, Some(PathBuf::from("defaults.reg")));
+            }
+            command => panic!("{:?}", command),
+        }
+        assert!(matches!(
+            parse_from(&["export"]).unwrap().command,
+            Command::Export {
+                kind: ExportKind::Desired,
+                output: None
+            }
+        ));
+        assert!(matches!(parse_from(&["list", "--snapshots"]).unwrap().command, Command::List { snapshots: true }));
+        assert!(matches!(parse_from(&["list"]).unwrap().command, Command::List { snapshots: false }));
+
+        let profile = |args: &[&str]| match parse_from(args).unwrap().command {
+            Command::Profile(command) => command,
+            command => panic!("{:?}", command),
+        };
+        match profile(&["profile", "add", "game.exe"]) {
+            ProfileCommand::Add { process, profile } => {
+                assert_eq!((process.as_str(), profile.as_str()), ("game.exe", "games"))
+            }
+            command => panic!("{:?}", command),
+        }
+        match profile(&["profile", "add", "game.exe", "--to", "streaming"]) {
+            ProfileCommand::Add { profile, .. } => assert_eq!(profile, "streaming"),
+            command => panic!("{:?}", command),
+        }
+        match profile(&["profile", "remove", "game.exe"]) {
+            ProfileCommand::Remove { process } => assert_eq!(process, "game.exe"),
+            command => panic!("{:?}", command),
+        }
+        for action in ["check", "apply", "clear"] {
+            match profile(&["profile", action, "games"]) {
+                ProfileCommand::Check { name } | ProfileCommand::Apply { name } | ProfileCommand::Clear { name } => {
+                    assert_eq!(name, "games")
+                }
+                command => panic!("{:?}", command),
+            }
+        }
+        assert!(matches!(profile(&["profile", "scan"]), ProfileCommand::Scan));
+        assert!(parse_from(&["profile"]).is_err());
+    }
+
+    #[test]
+    fn global_options_anywhere() {
+        let options = parse_from(&[
+            "--catalog",
+            "tweaks.toml",
+            "check",
+            "--users",
+            "gamer,S-1-5-21-1001",
+            "--load-profiles",
+            "--mtu",
+            "1472",
+            "--unhide-power-settings",
+            "--profiles",
+            "profiles.toml",
+            "--yes",
+        ])
+        .unwrap();
+        assert_eq!(options.catalog, Some(PathBuf::from("tweaks.toml")));
+        assert_eq!(options.reg, None);
+        assert_eq!(options.profiles, Some(PathBuf::from("profiles.toml")));
+        assert_eq!(options.users, vec!["gamer", "S-1-5-21-1001"]);
+        assert!(options.load_profiles && options.unhide_power_settings && options.yes);
+        assert_eq!((options.mtu, options.ping), (Some(1472), None));
+        assert!(options.hives.is_empty());
+
+        let args = ["--hive", "HKLM\\SOFTWARE=SOFTWARE.hiv", "--reg", "tweaks.reg", "--ping", "9.9.9.9"];
+        let options = parse_from(&args).unwrap();
+        assert_eq!(options.hives[0].file, PathBuf::from("SOFTWARE.hiv"));
+        assert_eq!(options.reg, Some(PathBuf::from("tweaks.reg")));
+        assert_eq!(options.ping.as_deref(), Some("9.9.9.9"));
+        assert_eq!(parse_from(&["--mtu", "big"]).err().unwrap(), "`big` is not a valid MTU");
+        assert_eq!(parse_from(&["--ping", "router"]).err().unwrap(), "`router` is not an IP address");
+        assert!(parse_from(&["--catalog", "tweaks.toml", "--reg", "tweaks.reg"]).is_err());
+        assert!(parse_from(&["--load-profiles"]).is_err());
+    }
+
+    #[test]
+    fn failures_win_over_drift() {
+        assert_eq!(exit_code(0, 0), EXIT_OK);
+        assert_eq!(exit_code(0, 3), EXIT_DRIFT);
+        assert_eq!(exit_code(1, 3), EXIT_ERROR);
+        assert_eq!(exit_code(1, 0), EXIT_ERROR);
+    }
+}
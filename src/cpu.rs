@@ -0,0 +1,293 @@
+// The cores of the CPU and their efficiency class. Hybrid CPUs (P-cores and
+// E-cores) have more than one class, the highest one is the fastest. The power
+// plan only writes the settings of Processor Power Efficiency Class 1 on them
+// and RSS is kept off the E-cores.
+
+#[cfg(windows)]
+use std::io;
+use std::mem;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Processor {
+    pub group: u16,
+    // Within its group
+    pub number: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Core {
+    pub efficiency_class: u8,
+    // More than one with SMT
+    pub processors: Vec<Processor>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CpuTopology {
+    pub cores: Vec<Core>,
+}
+
+// Where the topology comes from, a trait so it can be made up without the hardware
+#[cfg(windows)]
+pub trait TopologySource {
+    fn topology(&self) -> io::Result<CpuTopology>;
+}
+
+// GetLogicalProcessorInformationEx of the running system
+#[cfg(windows)]
+pub struct SystemTopology;
+
+#[cfg(windows)]
+impl TopologySource for SystemTopology {
+    fn topology(&self) -> io::Result<CpuTopology> {
+        use winapi::shared::minwindef::DWORD;
+        use winapi::um::sysinfoapi::GetLogicalProcessorInformationEx;
+        use winapi::um::winnt::RelationProcessorCore;
+
+        // The first call only tells the size of the buffer
+        let mut length: DWORD = 0;
+        unsafe { GetLogicalProcessorInformationEx(RelationProcessorCore, std::ptr::null_mut(), &mut length) };
+        if length == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buffer = vec![0u8; length as usize];
+        let ok = unsafe { GetLogicalProcessorInformationEx(RelationProcessorCore, buffer.as_mut_ptr() as _, &mut length) };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        buffer.truncate(length as usize);
+        parse_processor_cores(&buffer).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+const RELATION_PROCESSOR_CORE: u32 = 0;
+
+// The SYSTEM_LOGICAL_PROCESSOR_INFORMATION_EX records of RelationProcessorCore:
+//
+// Relationship u32, Size u32, then the PROCESSOR_RELATIONSHIP: Flags u8,
+// EfficiencyClass u8, 20 reserved bytes, GroupCount u16 and GroupCount
+// GROUP_AFFINITY { Mask: usize, Group: u16, 3 reserved u16 }
+pub fn parse_processor_cores(buffer: &[u8]) -> Result<CpuTopology, String> {
+    let mask_size = mem::size_of::<usize>();
+    let affinity_size = mask_size + 8;
+    let u16_at = |bytes: &[u8], offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+    let u32_at = |bytes: &[u8], offset: usize| {
+        u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+    };
+
+    let mut topology = CpuTopology::default();
+    let mut offset = 0;
+    while offset < buffer.len() {
+        if offset + 8 > buffer.len() {
+            return Err(format!("record at byte {} is cut off", offset));
+        }
+        let relationship = u32_at(buffer, offset);
+        let size = u32_at(buffer, offset + 4) as usize;
+        if size < 32 || offset + size > buffer.len() {
+            return Err(format!("record at byte {} has a size of {}", offset, size));
+        }
+        let record = &buffer[offset..offset + size];
+        offset += size;
+        if relationship != RELATION_PROCESSOR_CORE {
+            continue;
+        }
+
+        let group_count = u16_at(record, 30) as usize;
+        if 32 + group_count * affinity_size > record.len() {
+            return Err(format!("record at byte {} is too short for {} groups", offset - size, group_count));
+        }
+        let mut processors = Vec::new();
+        for affinity in record[32..].chunks_exact(affinity_size).take(group_count) {
+            let mut mask = [0u8; 8];
+            mask[..mask_size].copy_from_slice(&affinity[..mask_size]);
+            let mask = u64::from_le_bytes(mask);
+            let group = u16_at(affinity, mask_size);
+            processors.extend((0..64).filter(|bit| mask & (1 << bit) != 0).map(|number| Processor { group, number }));
+        }
+        topology.cores.push(Core {
+            efficiency_class: record[9],
+            processors,
+        });
+    }
+    Ok(topology)
+}
+
+impl CpuTopology {
+    // P-cores and E-cores
+    pub fn is_hybrid(&self) -> bool {
+        self.cores.iter().any(|core| core.efficiency_class != self.cores[0].efficiency_class)
+    }
+
+    fn performance_class(&self) -> u8 {
+        self.cores.iter().map(|core| core.efficiency_class).max().unwrap_or(0)
+    }
+
+    // The cores of processor group 0 in the order of their numbers, RssBaseCpu
+    // and MaxNumRssCpus only count in that group
+    fn group_zero(&self) -> Vec<(u32, u32, u8)> {
+        let mut cores: Vec<(u32, u32, u8)> = self
+            .cores
+            .iter()
+            .filter_map(|core| {
+                let numbers = core.processors.iter().filter(|p| p.group == 0).map(|p| p.number);
+                Some((numbers.clone().min()?, numbers.max()?, core.efficiency_class))
+            })
+            .collect();
+        cores.sort_unstable();
+        cores
+    }
+
+    // RSS starts at the processor `base` and spreads over `count` cores in the
+    // order of their numbers. On a hybrid CPU a base on an E-core moves to the
+    // next P-core, the second one when there is none after it (the first one
+    // takes most interrupts), and the count ends before the first E-core.
+    pub fn rss_cpus(&self, base: u32, count: u32) -> (u32, u32) {
+        if !self.is_hybrid() {
+            return (base, count);
+        }
+        let performance = self.performance_class();
+        let cores = self.group_zero();
+        let p_cores: Vec<&(u32, u32, u8)> = cores.iter().filter(|core| core.2 == performance).collect();
+        let base = match p_cores.iter().find(|core| core.1 >= base) {
+            Some(core) if core.0 <= base => base,
+            Some(core) => core.0,
+            None => match p_cores.get(1).or_else(|| p_cores.first()) {
+                Some(core) => core.0,
+                None => return (base, count),
+            },
+        };
+        let available = cores
+            .iter()
+            .skip_while(|core| core.1 < base)
+            .take_while(|core| core.2 == performance)
+            .count() as u32;
+        (base, count.min(available))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn core(efficiency_class: u8, numbers: &[u32]) -> Core {
+        Core {
+            efficiency_class,
+            processors: numbers.iter().map(|&number| Processor { group: 0, number }).collect(),
+        }
+    }
+
+    // 8 P-cores with two threads each and 16 E-cores, numbered as Windows does
+    // with the P-cores first or, on some boards, the E-cores first
+    fn hybrid(e_cores_first: bool) -> CpuTopology {
+        let (p_start, e_start) = if e_cores_first { (16, 0) } else { (0, 16) };
+        let p_cores = (0..8).map(|i| core(1, &[p_start + 2 * i, p_start + 2 * i + 1]));
+        let e_cores = (0..16).map(|i| core(0, &[e_start + i]));
+        CpuTopology {
+            cores: p_cores.chain(e_cores).collect(),
+        }
+    }
+
+    #[test]
+    fn rss_on_p_cores_numbered_first() {
+        let cpu = hybrid(false);
+        assert!(cpu.is_hybrid());
+        // Already on a P-core, also on its second thread
+        assert_eq!(cpu.rss_cpus(2, 4), (2, 4));
+        assert_eq!(cpu.rss_cpus(3, 4), (3, 4));
+        // No P-core after an E-core, the second P-core takes it
+        assert_eq!(cpu.rss_cpus(20, 4), (2, 4));
+        // The count ends before the first E-core
+        assert_eq!(cpu.rss_cpus(0, 16), (0, 8));
+        assert_eq!(cpu.rss_cpus(12, 4), (12, 2));
+        assert_eq!(cpu.rss_cpus(20, 16), (2, 7));
+    }
+
+    #[test]
+    fn rss_on_e_cores_numbered_first() {
+        let cpu = hybrid(true);
+        // The base moves to the next P-core
+        assert_eq!(cpu.rss_cpus(0, 4), (16, 4));
+        assert_eq!(cpu.rss_cpus(15, 16), (16, 8));
+        assert_eq!(cpu.rss_cpus(18, 16), (18, 7));
+        // After the last P-core there is nothing, back to the second one
+        assert_eq!(cpu.rss_cpus(32, 4), (18, 4));
+    }
+
+    #[test]
+    fn rss_is_left_alone_without_e_cores() {
+        let cpu = CpuTopology {
+            cores: (0..8).map(|i| core(0, &[2 * i, 2 * i + 1])).collect(),
+        };
+        assert!(!cpu.is_hybrid());
+        assert_eq!(cpu.rss_cpus(2, 16), (2, 16));
+        assert_eq!(CpuTopology::default().rss_cpus(2, 4), (2, 4));
+    }
+
+    #[test]
+    fn only_group_zero_counts_for_rss() {
+        // A second group with more P-cores does not stretch the count
+        let mut cpu = hybrid(false);
+        cpu.cores.push(Core {
+            efficiency_class: 1,
+            processors: vec![Processor { group: 1, number: 0 }],
+        });
+        assert_eq!(cpu.rss_cpus(0, 16), (0, 8));
+    }
+
+    // One SYSTEM_LOGICAL_PROCESSOR_INFORMATION_EX record
+    fn record(relationship: u32, efficiency_class: u8, groups: &[(u64, u16)]) -> Vec<u8> {
+        let mask_size = mem::size_of::<usize>();
+        let size = 32 + groups.len() * (mask_size + 8);
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&relationship.to_le_bytes());
+        bytes.extend_from_slice(&(size as u32).to_le_bytes());
+        bytes.push(1);
+        bytes.push(efficiency_class);
+        bytes.extend_from_slice(&[0; 20]);
+        bytes.extend_from_slice(&(groups.len() as u16).to_le_bytes());
+        for (mask, group) in groups.iter() {
+            bytes.extend_from_slice(&mask.to_le_bytes()[..mask_size]);
+            bytes.extend_from_slice(&group.to_le_bytes());
+            bytes.extend_from_slice(&[0; 6]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn processor_cores_from_a_buffer() {
+        let mut buffer = record(RELATION_PROCESSOR_CORE, 1, &[(0b11, 0)]);
+        // A cache record in between is skipped
+        buffer.extend(record(2, 0, &[(0xff, 0)]));
+        buffer.extend(record(RELATION_PROCESSOR_CORE, 0, &[(1 << 4, 0)]));
+        buffer.extend(record(RELATION_PROCESSOR_CORE, 0, &[(1 << 5, 1)]));
+        let cpu = parse_processor_cores(&buffer).unwrap();
+        assert_eq!(
+            cpu.cores,
+            vec![
+                core(1, &[0, 1]),
+                core(0, &[4]),
+                Core {
+                    efficiency_class: 0,
+                    processors: vec![Processor { group: 1, number: 5 }],
+                },
+            ]
+        );
+        assert!(cpu.is_hybrid());
+    }
+
+    #[test]
+    fn a_broken_buffer_is_refused() {
+        let buffer = record(RELATION_PROCESSOR_CORE, 1, &[(0b11, 0)]);
+        let size = buffer.len();
+        assert_eq!(
+            parse_processor_cores(&buffer[..size - 1]).unwrap_err(),
+            format!("record at byte 0 has a size of {}", size)
+        );
+        let mut two = buffer.clone();
+        two.extend_from_slice(&buffer[..4]);
+        assert_eq!(parse_processor_cores(&two).unwrap_err(), format!("record at byte {} is cut off", size));
+        // The group count says two, there is room for one
+        let mut short = buffer;
+        short[30] = 2;
+        assert_eq!(parse_processor_cores(&short).unwrap_err(), "record at byte 0 is too short for 2 groups");
+    }
+}
@@ -0,0 +1,95 @@
+// The errors a run can hit. They are recorded with the value, setting or step
+// they belong to, so one failure does not stop the rest of the run.
+//
+// The registry backends stay close to the OS and return io::Error, converting
+// one sorts it by its kind. A type mismatch travels inside the io::Error and
+// comes back out as Error::TypeMismatch.
+
+use crate::registry::backend::RegType;
+use crate::registry::catalog::CatalogError;
+
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    // The registry refused the access, most writes need administrator rights
+    AccessDenied,
+    // The key or the value does not exist
+    Missing,
+    TypeMismatch { expected: RegType, found: RegType },
+    // Windows does not allow the index for the power setting
+    OutOfRange { setting: String, index: u32 },
+    // bcdedit failed or printed something unexpected
+    Bcd(String),
+    // A COM call of the audio endpoint failed
+    #[cfg(windows)]
+    Com { call: &'static str, hresult: i32 },
+    #[cfg(windows)]
+    Ping(String),
+    // No interface has AddressType 0, the TCP tweaks have nothing to go to
+    NoNetworkInterface,
+    // The setting is no longer what a saved plan expects
+    Changed,
+    // A snapshot of other hives or users than the run writes to
+    WrongTarget { snapshot: String, run: String },
+    Catalog(CatalogError),
+    Io(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    // For the backends, which only return io::Error
+    pub fn type_mismatch(expected: RegType, found: RegType) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, Error::TypeMismatch { expected, found })
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::AccessDenied => f.write_str("access denied, run as administrator"),
+            Error::Missing => f.write_str("does not exist"),
+            Error::TypeMismatch { expected, found } => write!(f, "expected {:?}, found {:?}", expected, found),
+            Error::OutOfRange { setting, index } => {
+                write!(f, "{} is not a possible index of {} on this system", index, setting)
+            }
+            Error::Bcd(message) => write!(f, "bcdedit failed: {}", message),
+            #[cfg(windows)]
+            Error::Com { call, hresult } => write!(f, "{} failed: hr = 0x{:08x}", call, hresult),
+            #[cfg(windows)]
+            Error::Ping(message) => write!(f, "ping failed: {}", message),
+            Error::NoNetworkInterface => f.write_str("could not find your current network interface"),
+            Error::Changed => f.write_str("changed since the plan was made, make a new plan"),
+            Error::WrongTarget { snapshot, run } => {
+                write!(f, "the snapshot was taken of {}, this run targets {}", snapshot, run)
+            }
+            Error::Catalog(e) => e.fmt(f),
+            Error::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        match e.kind() {
+            io::ErrorKind::PermissionDenied => Error::AccessDenied,
+            io::ErrorKind::NotFound => Error::Missing,
+            _ if e.get_ref().is_none() => Error::Io(e),
+            kind => match e.into_inner().map(|inner| inner.downcast::<Error>()) {
+                Some(Ok(error)) => *error,
+                Some(Err(inner)) => Error::Io(io::Error::new(kind, inner)),
+                None => Error::Io(kind.into()),
+            },
+        }
+    }
+}
+
+impl From<CatalogError> for Error {
+    fn from(e: CatalogError) -> Error {
+        Error::Catalog(e)
+    }
+}
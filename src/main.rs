@@ -1,220 +1,1112 @@
-use dialoguer::{theme::ColorfulTheme, Select};
+#[cfg(windows)]
+use audit::Audit;
+#[cfg(windows)]
+use cli::{Command, ProfileCommand, ExportKind, Format, Options, EXIT_ERROR, EXIT_OK};
+#[cfg(windows)]
+use cpu::{CpuTopology, SystemTopology, TopologySource};
+#[cfg(windows)]
+use dialoguer::{theme::ColorfulTheme, Confirm, MultiSelect, Select};
+#[cfg(windows)]
+use plan::ChangePlan;
+#[cfg(windows)]
+use bcd::BcdCheck;
+#[cfg(windows)]
+use registry::backend::{Hive, HiveBackend, HiveMount, NativeBackend, RegPath, RegistryBackend};
+#[cfg(windows)]
+use registry::catalog::Catalog;
+#[cfg(windows)]
+use registry::perf_options;
+#[cfg(windows)]
+use registry::profile::{self, Profile, ProfileEdit, Profiles};
+#[cfg(windows)]
+use registry::report::{CheckResult, Report};
+#[cfg(windows)]
+use registry::snapshot::{SavedPowerSetting, Snapshot, SnapshotBackend, SnapshotTarget};
+#[cfg(windows)]
+use registry::users::{self, LoadedHive};
+#[cfg(windows)]
+use registry::{PowerPlan, Powercfg, Settings};
+#[cfg(windows)]
+use selection::{Category, Risk, Selection, TweakInfo};
+#[cfg(windows)]
+use sound::AudioEndpoint;
+#[cfg(windows)]
+use std::path::{Path, PathBuf};
 
+// Only the Windows build drives the tweaks, elsewhere the modules are compiled
+// for the tests, which run the engine against the in-memory backend
+#[cfg(any(windows, test))]
+mod audit;
+#[cfg(any(windows, test))]
+mod bcd;
+#[cfg(any(windows, test))]
+mod cli;
+#[cfg(any(windows, test))]
+mod cpu;
+#[cfg(any(windows, test))]
+mod error;
+#[cfg(windows)]
 mod ping;
-mod powershell;
+#[cfg(any(windows, test))]
+mod plan;
+#[cfg(any(windows, test))]
 mod registry;
+#[cfg(any(windows, test))]
+mod selection;
+#[cfg(windows)]
 mod sound;
 
 // TODO! Performance Tuning Network Adapters https://docs.microsoft.com/en-us/windows-server/networking/technologies/network-subsystem/net-sub-performance-tuning-nics
 
+// Where the registry tweaks come from
 #[cfg(windows)]
-fn main() {
-    let game_launcher_bloat = vec![
-        // Game launcher
-        String::from("EpicWebHelper.exe"),
-        String::from("GameOverlayUI.exe"),
-        String::from("OriginWebHelperService.exe"),
-        String::from("QtWebEngineProcess.exe"),
-        String::from("RiotClientCrashHandler.exe"),
-        String::from("RiotClientUx.exe"),
-        String::from("RiotClientUxRender.exe"),
-        String::from("vgtray.exe"),
-        String::from("SocialClubHelper.exe"),
-        String::from("SteamService.exe"),
-        String::from("steamwebhelper.exe"),
-        String::from("UplayWebCore.exe"),
-        
-        // Windows
-        String::from("CompatTelRunner.exe"),
-
-        // Xbox Microsoft.XboxGamingOverlay_5.721.10202.0_x64__8wekyb3d8bbwe
-        String::from("GameBar.exe"),
-        String::from("GameBarFTServer.exe"),
-    ];
-
-    let games = vec![String::from("r5apex.exe"), String::from("csgo.exe")];
-    
-    let system_process_high = vec![
-        String::from("audiodg.exe"),
-        String::from("csrss.exe"),
-        String::from("ntoskrnl.exe"),
-    ];
-
-    let system_process_below_normal = vec![
-        // String::from("fontdrvhost.exe"), // Usermode Font Driver Host
-        // String::from("lsass.exe"), // Local Security Authority Process
-        // String::from("sihost.exe"), // Shell Infrastructure Host
-        // String::from("sppsvc.exe"), // Microsoft Software Protection Platform Service
-        // String::from("spoolsv.exe"), // Print Spooler
-        String::from("SearchIndexer.exe"), // Microsoft Windows Search Indexer
-        String::from("svchost.exe"),
-    ];
-    println!("\n# Check Registry");
-    let mtu = ping::ping();
-    let dpi = registry::apply_get_dpi();
-    let reg_settings = registry::factory_settings(&dpi);
-    registry::apply_reg_tweaks(&reg_settings, false);
-    registry::apply_tcp_tweaks(&mtu, false, false);
-
-    for process in game_launcher_bloat.iter() {
-        registry::set_cpu_priority(
-            registry::CpuPriority {
-                process: process.to_string(),
-                cpu_priority_class: Some(5u32), // 5 = Below Normal
-                io_priority: Some(1u32),        // 1 = Low
-                page_priority: None,
-                working_set_limit_in_kb: None,
+enum Source {
+    Catalog(Catalog),
+    Reg(Settings),
+}
+
+// Every tweak a run knows about: the registry values of the source followed by
+// the built-in TCP tweaks, the process profiles and the power, boot and audio tweaks
+#[cfg(windows)]
+struct Tweaks {
+    source: Source,
+    profiles: Profiles,
+    // Catalog and profile values can depend on the display scaling
+    dpi: u32,
+    all: Vec<TweakInfo>,
+    // SIDs of the users picked with --users, their HKU key takes the HKCU tweaks
+    users: Vec<String>,
+    // The hive files of --hive, empty for the live registry
+    hives: Vec<HiveMount>,
+    // The power tweak also shows the hidden settings it writes
+    unhide_power_settings: bool,
+    // None for hive files, which may belong to another machine
+    cpu: Option<CpuTopology>,
+}
+
+#[cfg(windows)]
+impl Tweaks {
+    fn load<B: RegistryBackend>(backend: &B, options: &Options) -> error::Result<Tweaks> {
+        let source = match (&options.reg, &options.catalog) {
+            (Some(file), _) => Source::Reg(registry::regfile::import_reg_file(file)?),
+            (None, Some(file)) => Source::Catalog(Catalog::from_file(file)?),
+            (None, None) => Source::Catalog(Catalog::builtin()),
+        };
+        let profiles = Profiles::load(options.profiles.as_deref())?;
+        let mut all = match &source {
+            Source::Catalog(catalog) => catalog.tweaks.iter().map(|t| t.info()).collect(),
+            Source::Reg(settings) => settings.tweaks(),
+        };
+        all.extend(registry::tcp_tweaks());
+        all.extend(profiles.profiles.iter().map(Profile::info));
+        all.extend(registry::powerplan_tweaks());
+        all.extend(bcd::bcd_tweaks());
+        all.extend(sound::audio_tweaks());
+        let cpu = match options.hives.is_empty() {
+            true => match SystemTopology.topology() {
+                Ok(cpu) => Some(cpu),
+                Err(e) => {
+                    eprintln!("\x1b[0;93mCould not read the CPU topology: {}\x1b[0m", e);
+                    None
+                }
             },
-            false,
-        );
+            false => None,
+        };
+        Ok(Tweaks {
+            source,
+            profiles,
+            dpi: registry::apply_get_dpi(backend),
+            all,
+            users: Vec::new(),
+            hives: options.hives.clone(),
+            unhide_power_settings: options.unhide_power_settings,
+            cpu,
+        })
     }
 
-    for process in games.iter() {
-        registry::set_cpu_priority(
-            registry::CpuPriority {
-                process: process.to_string(),
-                cpu_priority_class: Some(3u32), // 3 = High
-                io_priority: Some(3u32),        // 3 = High
-                page_priority: None,
-                working_set_limit_in_kb: None,
-            },
-            false,
-        );
+    // The registry values of the selected tweaks
+    fn settings(&self, selection: &Selection) -> Settings {
+        let settings = match &self.source {
+            Source::Catalog(catalog) => {
+                catalog.settings(self.dpi, self.cpu.as_ref(), |t| selection.includes(&t.id, Some(t.category)))
+            }
+            Source::Reg(settings) => {
+                let mut settings = settings.clone();
+                settings.retain(|id| selection.includes(id, None));
+                settings
+            }
+        };
+        match self.users.is_empty() {
+            true => settings,
+            false => settings.for_users(&self.users),
+        }
     }
 
-    for process in system_process_high.iter() {
-        registry::set_cpu_priority(
-            registry::CpuPriority {
-                process: process.to_string(),
-                cpu_priority_class: Some(3u32), // 3 = High
-                io_priority: Some(3u32),        // 3 = High
-                page_priority: None,
-                working_set_limit_in_kb: None,
-            },
-            false,
-        );
+    // What a snapshot of this run is taken of
+    fn target(&self) -> SnapshotTarget {
+        SnapshotTarget::new(&self.hives, &self.users)
+    }
+
+    // P-cores and E-cores, the power plan gets the settings of both
+    fn hybrid(&self) -> bool {
+        self.cpu.as_ref().is_some_and(CpuTopology::is_hybrid)
+    }
+
+    // What the user should know before the tweaks `ids` are written
+    fn warnings(&self, ids: &[&str]) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if let Source::Catalog(catalog) = &self.source {
+            for tweak in catalog.tweaks.iter().filter(|t| ids.contains(&t.id.as_str())) {
+                warnings.extend(tweak.warnings());
+                warnings.extend(tweak.dpi_warning(self.dpi));
+            }
+        }
+        for profile in self.profiles.profiles.iter() {
+            if ids.contains(&profile.id().as_str()) {
+                warnings.extend(profile.warnings());
+                warnings.extend(profile.tweaks.iter().filter_map(|t| t.dpi_warning(self.dpi)));
+            }
+        }
+        warnings
+    }
+
+    // Where the catalog tweak `id` is documented
+    fn sources(&self, id: &str) -> &[String] {
+        match &self.source {
+            Source::Catalog(catalog) => catalog.tweaks.iter().find(|t| t.id == id).map_or(&[], |t| &t.sources),
+            Source::Reg(_) => &[],
+        }
     }
 
-    for process in system_process_below_normal.iter() {
-        registry::set_cpu_priority(
-            registry::CpuPriority {
-                process: process.to_string(),
-                cpu_priority_class: Some(5u32), // 5 = Below Normal
-                io_priority: Some(1u32),        // 1 = Low
-                page_priority: None,
-                working_set_limit_in_kb: None,
+    // The tweak a result of `part` belongs to, imported values go by their path
+    fn tweak_of(&self, part: &Part<'_>, result: &CheckResult) -> (String, Option<Category>) {
+        match part {
+            Part::Tcp if result.name == "MTU" => (registry::TCP_MTU.to_string(), Some(Category::Network)),
+            Part::Tcp => (registry::TCP_ACK_FREQUENCY.to_string(), Some(Category::Network)),
+            Part::Profile(profile) => (profile.id(), Some(Category::Scheduler)),
+            Part::Registry => match &self.source {
+                Source::Catalog(catalog) => {
+                    let key = self.user_path(result);
+                    catalog
+                        .tweaks
+                        .iter()
+                        .find(|t| t.key == key && t.name.eq_ignore_ascii_case(&result.name))
+                        .map(|t| (t.id.clone(), Some(t.category)))
+                        .unwrap_or_else(|| (result.full_path(), None))
+                }
+                Source::Reg(_) => (result.full_path(), None),
             },
-            false,
-        );
+        }
     }
 
-    // println!("\n# Check PowerPlan");
-    // let powerplan = registry::factory_powerplan();
-    // registry::check_powerplan(&powerplan, false);
+    // HKU\<SID>\... of a picked user is where a HKCU tweak went
+    fn user_path(&self, result: &CheckResult) -> RegPath {
+        if result.hive == Hive::Users {
+            if let Some((sid, path)) = result.path.split_once('\\') {
+                if self.users.iter().any(|u| u.eq_ignore_ascii_case(sid)) {
+                    return RegPath::new(Hive::CurrentUser, path);
+                }
+            }
+        }
+        RegPath::new(result.hive, &result.path)
+    }
+}
+
+// Which tweaks a report of check_tweaks comes from
+#[cfg(windows)]
+enum Part<'a> {
+    Registry,
+    Tcp,
+    Profile(&'a Profile),
+}
+
+#[cfg(windows)]
+fn main() {
+    let mut options = cli::parse();
+    if !options.hives.is_empty() {
+        options.selection = std::mem::take(&mut options.selection).without(live_only());
+    }
+    std::process::exit(run(&options));
+}
 
-    println!("\n# Check BcdStore");
-    powershell::check_bcd_store();
+// The power plan, the boot store, the audio device and the network interface
+// belong to the running system, a hive file has none of them
+#[cfg(windows)]
+fn live_only() -> Vec<String> {
+    let mut ids: Vec<String> = [registry::TCP_ACK_FREQUENCY, registry::TCP_MTU, registry::POWERPLAN, sound::AUDIO_FULL_RANGE]
+        .iter()
+        .map(|id| id.to_string())
+        .collect();
+    ids.extend(bcd::bcd_tweaks().into_iter().map(|t| t.id));
+    ids
+}
 
-    sound::apply_audio_settings(false, false);
-    // TODO: Bitrate und kHz ausgeben
-    // TODO: Latency anzeige/prüfen und wenn es möglich ist REAL empfehlen
+#[cfg(windows)]
+fn run(options: &Options) -> i32 {
+    if options.hives.is_empty() {
+        return run_with(&NativeBackend, options);
+    }
+    match HiveBackend::open(&options.hives) {
+        Ok(backend) => run_with(&backend, options),
+        Err(e) => {
+            eprintln!("\x1b[0;91m{}\x1b[0m", e);
+            EXIT_ERROR
+        }
+    }
+}
 
-    // registry::apply_audio_settings(false)?;
+#[cfg(windows)]
+fn run_with<B: RegistryBackend>(backend: &B, options: &Options) -> i32 {
+    let mut tweaks = match Tweaks::load(backend, options) {
+        Ok(tweaks) => tweaks,
+        Err(e) => {
+            eprintln!("\x1b[0;91m{}\x1b[0m", e);
+            return EXIT_ERROR;
+        }
+    };
+    // Unloaded again when the run is over
+    let loaded = match pick_users(backend, options) {
+        Ok((sids, loaded)) => {
+            tweaks.users = sids;
+            loaded
+        }
+        Err(e) => {
+            eprintln!("\x1b[0;91m{}\x1b[0m", e);
+            return EXIT_ERROR;
+        }
+    };
+    let unknown = options.selection.unknown(&tweaks.all);
+    if !unknown.is_empty() {
+        for name in unknown {
+            eprintln!("\x1b[0;91munknown tweak or category `{}`\x1b[0m", name);
+        }
+        eprintln!("categories are {}, `list` shows the tweak ids", selection::category_names());
+        return EXIT_ERROR;
+    }
+    let selection = &options.selection;
+
+    let mut status = match &options.command {
+        Command::Interactive => interactive(backend, &tweaks, options),
+        Command::Check => check(backend, &tweaks, tcp_mtu(options, selection), selection, options.format),
+        Command::Plan { output } => plan_command(backend, &tweaks, output.as_deref(), options),
+        Command::Apply { plan: Some(file) } => apply_plan_file(backend, &tweaks, file, options),
+        Command::Apply { plan: None } => apply(backend, &tweaks, options),
+        Command::Restore { snapshot: None } => {
+            if !confirm(options, "Restore the Windows default settings?") {
+                return EXIT_ERROR;
+            }
+            restore_defaults(backend, &tweaks, selection)
+        }
+        Command::Restore { snapshot: Some(file) } => restore_snapshot_file(backend, &tweaks, file, options),
+        Command::Export { kind, output } => export(backend, &tweaks.settings(selection), *kind, output.as_deref()),
+        Command::List { snapshots: false } => {
+            list_tweaks(&tweaks, selection);
+            EXIT_OK
+        }
+        Command::List { snapshots: true } => list_snapshots(),
+        Command::Profile(command) => profile_command(backend, &tweaks, command, options),
+    };
+    for hive in loaded {
+        if let Err(e) = hive.unload() {
+            eprintln!("\x1b[0;91m{}\x1b[0m", e);
+            status = EXIT_ERROR;
+        }
+    }
+    status
+}
+
+// The users picked with --users, the hive of a user who is not signed in is
+// loaded with --load-profiles and the user is skipped otherwise
+#[cfg(windows)]
+fn pick_users<B: RegistryBackend>(backend: &B, options: &Options) -> Result<(Vec<String>, Vec<LoadedHive>), String> {
+    if options.users.is_empty() {
+        return Ok((Vec::new(), Vec::new()));
+    }
+    let profiles = users::user_profiles(backend).map_err(|e| e.to_string())?;
+    let mut sids = Vec::new();
+    let mut loaded = Vec::new();
+    for user in users::pick_users(profiles, &options.users)? {
+        if !user.loaded {
+            if !options.load_profiles {
+                eprintln!("\x1b[0;93m{} is not signed in, --load-profiles loads their NTUSER.DAT\x1b[0m", user.name);
+                continue;
+            }
+            let hive = LoadedHive::load(&user).map_err(|e| format!("Could not load the profile of {}: {}", user.name, e))?;
+            loaded.push(hive);
+        }
+        if options.format == Format::Text {
+            println!("HKCU tweaks for {} go to HKEY_USERS\\{}", user.name, user.sid);
+        }
+        sids.push(user.sid);
+    }
+    if sids.is_empty() {
+        return Err(String::from("None of the picked users is signed in, --load-profiles loads their NTUSER.DAT"));
+    }
+    Ok((sids, loaded))
+}
+
+// Pinging takes a while, so it only happens when the MTU tweak is selected
+#[cfg(windows)]
+fn tcp_mtu(options: &Options, selection: &Selection) -> Option<error::Result<u32>> {
+    if !selection.includes(registry::TCP_MTU, Some(Category::Network)) {
+        return None;
+    }
+    Some(options.mtu.map_or_else(|| ping::ping(options.ping.as_deref()), Ok))
+}
+
+#[cfg(windows)]
+fn tcp_selected(selection: &Selection) -> bool {
+    [registry::TCP_ACK_FREQUENCY, registry::TCP_MTU]
+        .iter()
+        .any(|id| selection.includes(id, Some(Category::Network)))
+}
+
+// Compares the selected registry, TCP and profile tweaks
+#[cfg(windows)]
+fn check_tweaks<'a, B: RegistryBackend>(
+    backend: &B,
+    tweaks: &'a Tweaks,
+    mtu: Option<error::Result<u32>>,
+    selection: &Selection,
+) -> Vec<(Part<'a>, Report)> {
+    let mut reports = vec![(Part::Registry, registry::check_reg_tweaks(backend, &tweaks.settings(selection)))];
+    if tcp_selected(selection) {
+        let mtu = mtu.unwrap_or(Ok(0));
+        reports.push((Part::Tcp, registry::check_tcp_tweaks(backend, selection, mtu)));
+    }
+    for profile in tweaks.profiles.profiles.iter() {
+        if selection.includes(&profile.id(), Some(Category::Scheduler)) {
+            reports.push((Part::Profile(profile), profile::check_profile(backend, profile, tweaks.dpi)));
+        }
+    }
+    reports
+}
+
+// What the check found in every area, the planner turns it into the changes of an apply
+#[cfg(windows)]
+struct Inspection<'a> {
+    audit: Audit,
+    registry: Vec<(Part<'a>, Report)>,
+    power: Option<(PowerPlan, Report)>,
+    bcd: Option<error::Result<Vec<BcdCheck>>>,
+    audio: Option<error::Result<AudioEndpoint>>,
+}
+
+// Checks every selected tweak, text and NDJSON are printed as each area is checked
+#[cfg(windows)]
+fn inspect<'a, B: RegistryBackend>(
+    backend: &B,
+    tweaks: &'a Tweaks,
+    mtu: Option<error::Result<u32>>,
+    selection: &Selection,
+    format: Format,
+) -> Inspection<'a> {
+    let text = format == Format::Text;
+    let mut audit = Audit::new(format);
+
+    let registry = check_tweaks(backend, tweaks, mtu, selection);
+    for (part, report) in registry.iter() {
+        audit.extend(audit::from_report(report, |r| tweaks.tweak_of(part, r)), selection);
+    }
+    if text {
+        println!("\n# Check Registry");
+        let reports: Vec<&Report> = registry.iter().map(|(_, report)| report).collect();
+        for report in reports.iter() {
+            registry::report::print_console(report);
+        }
+        registry::report::print_totals(&reports);
+    }
+
+    let mut power = None;
+    if selection.includes(registry::POWERPLAN, Some(Category::Power)) {
+        let powerplan = registry::factory_powerplan(tweaks.hybrid());
+        let mut report = registry::check_powerplan(&Powercfg, &powerplan);
+        if tweaks.unhide_power_settings {
+            report.extend(registry::check_power_attributes(backend, &powerplan));
+        }
+        if text {
+            println!("\n# Check PowerPlan");
+            registry::report::print_console(&report);
+        }
+        let tweak = |_: &CheckResult| (registry::POWERPLAN.to_string(), Some(Category::Power));
+        audit.extend(audit::from_report(&report, tweak), selection);
+        power = Some((powerplan, report));
+    }
+
+    let mut bcd = None;
+    if bcd::bcd_tweaks().iter().any(|t| selection.selects(t)) {
+        let checks = bcd::check_bcd_store(&bcd::Bcdedit);
+        if text {
+            println!("\n# Check BcdStore");
+            match &checks {
+                Ok(checks) => bcd::print_bcd_checks(checks),
+                Err(e) => println!("\x1b[0;93m{}\x1b[0m", e),
+            }
+        }
+        audit.extend(audit::from_bcd(&checks), selection);
+        bcd = Some(checks);
+    }
+
+    let mut audio = None;
+    if selection.includes(sound::AUDIO_FULL_RANGE, Some(Category::Audio)) {
+        let endpoint = sound::apply_audio_settings(false, false);
+        if text {
+            println!("\n# Check Audio");
+            print_audio(&endpoint);
+        }
+        audit.extend(audit::from_audio(&endpoint), selection);
+        audio = Some(endpoint);
+        // TODO: Bitrate und kHz ausgeben
+        // TODO: Latency anzeige/prüfen und wenn es möglich ist REAL empfehlen
+    }
 
     // netsh interface tcp show global
     // https://stackoverflow.com/questions/26943777/netsh-result-to-a-powershell-object
     // https://docs.microsoft.com/en-us/powershell/module/netadapter/get-netadapter?view=windowsserver2019-ps#inputs
 
-    // registry::apply_reg_tweaks(true)?;
-    // crate::registry::check_gaming_tweaks()?;
-    // crate::registry::check_fse_fix()?;
-    // crate::registry::check_tcp_tweaks()?;
+    Inspection {
+        audit,
+        registry,
+        power,
+        bcd,
+        audio,
+    }
+}
+
+#[cfg(windows)]
+fn exit_code(audit: &Audit) -> i32 {
+    cli::exit_code(audit.failed(), audit.drift())
+}
+
+// Prints the state of every selected tweak and returns the exit code for it
+#[cfg(windows)]
+fn check<B: RegistryBackend>(
+    backend: &B,
+    tweaks: &Tweaks,
+    mtu: Option<error::Result<u32>>,
+    selection: &Selection,
+    format: Format,
+) -> i32 {
+    let inspection = inspect(backend, tweaks, mtu, selection, format);
+    inspection.audit.finish();
+    exit_code(&inspection.audit)
+}
+
+// The changes that bring the selected tweaks in line with what the check found
+#[cfg(windows)]
+fn plan_of<B: RegistryBackend>(backend: &B, tweaks: &Tweaks, inspection: &Inspection, selection: &Selection) -> ChangePlan {
+    let mut plan = ChangePlan::new();
+    for (part, report) in inspection.registry.iter() {
+        plan.add_report(backend, report, |r| tweaks.tweak_of(part, r));
+    }
+    if let Some((powerplan, report)) = &inspection.power {
+        plan.add_powerplan(powerplan, report);
+    }
+    if let Some(Ok(checks)) = &inspection.bcd {
+        plan.add_bcd(checks);
+    }
+    if let Some(Ok(endpoint)) = &inspection.audio {
+        plan.add_audio(endpoint);
+    }
+    plan.retain(selection);
+    plan
+}
+
+// Checks, then plans the changes, an error of the check is the status when there is nothing to do
+#[cfg(windows)]
+fn plan_changes<B: RegistryBackend>(
+    backend: &B,
+    tweaks: &Tweaks,
+    mtu: Option<error::Result<u32>>,
+    selection: &Selection,
+) -> (ChangePlan, i32) {
+    let inspection = inspect(backend, tweaks, mtu, selection, Format::Text);
+    let status = if inspection.audit.failed() > 0 { EXIT_ERROR } else { EXIT_OK };
+    (plan_of(backend, tweaks, &inspection, selection), status)
+}
+
+// The diff goes to the console, JSON to the console or the output file
+#[cfg(windows)]
+fn plan_command<B: RegistryBackend>(backend: &B, tweaks: &Tweaks, output: Option<&Path>, options: &Options) -> i32 {
+    let selection = &options.selection;
+    let mtu = tcp_mtu(options, selection);
+    // Only the plan is printed as JSON, not the check results
+    let format = match options.format {
+        Format::Text => Format::Text,
+        _ => Format::Json,
+    };
+    let inspection = inspect(backend, tweaks, mtu, selection, format);
+    let plan = plan_of(backend, tweaks, &inspection, selection);
+    match options.format {
+        Format::Text => {
+            println!("\n# Plan");
+            if plan.is_empty() {
+                println!("Nothing to apply");
+            } else {
+                plan.print_diff();
+            }
+        }
+        _ if output.is_some() => {}
+        Format::Json => println!("{}", plan.to_json()),
+        Format::Ndjson => {
+            for step in plan.steps.iter() {
+                println!("{}", serde_json::to_string(step).unwrap_or_default());
+            }
+        }
+    }
+    if let Some(file) = output {
+        if let Err(e) = plan.save(file) {
+            eprintln!("\x1b[0;91m{}: {}\x1b[0m", file.display(), e);
+            return EXIT_ERROR;
+        }
+        println!("plan saved to \x1b[0;92m{}\x1b[0m", file.display());
+    }
+    cli::exit_code(inspection.audit.failed(), plan.steps.len())
+}
+
+#[cfg(windows)]
+fn apply<B: RegistryBackend>(backend: &B, tweaks: &Tweaks, options: &Options) -> i32 {
+    let mtu = tcp_mtu(options, &options.selection);
+    let (plan, status) = plan_changes(backend, tweaks, mtu, &options.selection);
+    approve(backend, tweaks, plan, options, status)
+}
+
+// Shows the plan and lets the user pick from its tweaks unless --yes is given,
+// exactly the picked steps are executed
+#[cfg(windows)]
+fn approve<B: RegistryBackend>(backend: &B, tweaks: &Tweaks, mut plan: ChangePlan, options: &Options, status: i32) -> i32 {
+    if plan.is_empty() {
+        println!("\nNothing to apply");
+        return status;
+    }
+    println!("\n# Plan");
+    plan.print_diff();
+    if !options.yes {
+        match pick(tweaks, &plan) {
+            Some(picked) => plan.retain(&picked),
+            None => return EXIT_ERROR,
+        }
+    }
+    execute_plan(backend, tweaks, &plan)
+}
+
+// A plan saved by `plan -o`, the executor refuses steps whose setting changed since
+#[cfg(windows)]
+fn apply_plan_file<B: RegistryBackend>(backend: &B, tweaks: &Tweaks, file: &Path, options: &Options) -> i32 {
+    let plan = match ChangePlan::load(file) {
+        Ok(plan) => plan,
+        Err(e) => {
+            eprintln!("\x1b[0;91m{}: {}\x1b[0m", file.display(), e);
+            return EXIT_ERROR;
+        }
+    };
+    if plan.is_empty() {
+        println!("Nothing to apply");
+        return EXIT_OK;
+    }
+    println!("# Plan {}", file.display());
+    plan.print_diff();
+    if !confirm(options, "Apply this plan?") {
+        return EXIT_ERROR;
+    }
+    execute_plan(backend, tweaks, &plan)
+}
+
+// Lets the user pick from the tweaks of the plan, high risk ones start unchecked
+#[cfg(windows)]
+fn pick(tweaks: &Tweaks, plan: &ChangePlan) -> Option<Selection> {
+    let ids = plan.ids();
+    let candidates: Vec<&TweakInfo> = tweaks.all.iter().filter(|t| ids.contains(&t.id.as_str())).collect();
+    let items: Vec<String> = candidates.iter().map(|t| tweak_line(t)).collect();
+    let defaults: Vec<bool> = candidates.iter().map(|t| t.risk < Risk::High).collect();
+    match MultiSelect::with_theme(&ColorfulTheme::default())
+        .with_prompt("Tweaks to apply (space toggles, enter applies)")
+        .items(&items)
+        .defaults(&defaults)
+        .interact()
+    {
+        Ok(picked) if picked.is_empty() => {
+            println!("Nothing picked");
+            None
+        }
+        Ok(picked) => Some(Selection::ids(picked.iter().map(|&i| candidates[i].id.clone()).collect())),
+        Err(_) => {
+            eprintln!("\x1b[0;91mCould not ask which tweaks to apply, use --yes to apply the selected ones\x1b[0m");
+            None
+        }
+    }
+}
+
+// Writes the steps of the plan, the previous state goes to a new snapshot
+#[cfg(windows)]
+fn execute_plan<B: RegistryBackend>(backend: &B, tweaks: &Tweaks, plan: &ChangePlan) -> i32 {
+    for warning in tweaks.warnings(&plan.ids()) {
+        eprintln!("\x1b[0;93m{}\x1b[0m", warning);
+    }
+    let snapshot = Snapshot::create(&registry::snapshot::snapshot_dir(), tweaks.target());
+    let tracked = SnapshotBackend::new(backend, &snapshot);
+    println!();
+    let execution = plan::execute(&tracked, plan, Some(&snapshot));
+    plan::print_results(&execution);
+    if execution.rollback.is_some() {
+        if let Err(e) = snapshot.discard() {
+            eprintln!("\x1b[0;91m{}: {}\x1b[0m", snapshot.path().display(), e);
+        }
+    }
+    print_snapshot(&snapshot);
+
+    if execution.failed() {
+        EXIT_ERROR
+    } else {
+        EXIT_OK
+    }
+}
+
+#[cfg(windows)]
+fn restore_defaults<B: RegistryBackend>(backend: &B, tweaks: &Tweaks, selection: &Selection) -> i32 {
+    let snapshot = Snapshot::create(&registry::snapshot::snapshot_dir(), tweaks.target());
+    let tracked = SnapshotBackend::new(backend, &snapshot);
+    let mut status = EXIT_OK;
+    let mut report = Report::default();
+    report.extend(registry::restore_default_reg(&tracked, &tweaks.settings(selection)));
+    for profile in tweaks.profiles.profiles.iter() {
+        if selection.includes(&profile.id(), Some(Category::Scheduler)) {
+            report.extend(profile::remove_profile(&tracked, profile, tweaks.dpi));
+        }
+    }
+    if tcp_selected(selection) {
+        // The default deletes the MTU, so there is no need to measure it
+        report.extend(registry::restore_tcp_defaults(&tracked, selection));
+    }
+    registry::report::print_console(&report);
+    registry::report::print_summary(&report);
+
+    bcd::reset_bcd_store(&bcd::Bcdedit, selection, Some(&snapshot));
+    print_snapshot(&snapshot);
+
+    if selection.includes(registry::POWERPLAN, Some(Category::Power)) {
+        let previous = registry::snapshot::last_power_scheme(&registry::snapshot::snapshot_dir());
+        if !remove_gaming_scheme(previous.as_deref()) {
+            status = EXIT_ERROR;
+        }
+        let hidden = registry::hide_power_settings(backend, &registry::factory_powerplan(tweaks.hybrid()));
+        registry::report::print_console(&hidden);
+        if hidden.failed() > 0 {
+            status = EXIT_ERROR;
+        }
+    }
+
+    if selection.includes(sound::AUDIO_FULL_RANGE, Some(Category::Audio)) {
+        let endpoint = sound::apply_audio_settings(true, true);
+        print_audio(&endpoint);
+        if endpoint.is_err() {
+            status = EXIT_ERROR;
+        }
+    }
+
+    if report.failed() > 0 {
+        EXIT_ERROR
+    } else {
+        status
+    }
+}
+
+#[cfg(windows)]
+fn print_audio(endpoint: &error::Result<AudioEndpoint>) {
+    match endpoint {
+        Ok(endpoint) => sound::print_audio_endpoint(endpoint),
+        Err(e) => println!("\x1b[0;91m{}\x1b[0m", e),
+    }
+}
+
+#[cfg(windows)]
+fn restore_snapshot_file<B: RegistryBackend>(backend: &B, tweaks: &Tweaks, file: &str, options: &Options) -> i32 {
+    let dir = registry::snapshot::snapshot_dir();
+    let path = if file == "latest" {
+        match Snapshot::list(&dir).ok().and_then(|paths| paths.into_iter().next()) {
+            Some(path) => path,
+            None => {
+                eprintln!("\x1b[0;91mNo snapshots found in {}\x1b[0m", dir.display());
+                return EXIT_ERROR;
+            }
+        }
+    } else {
+        PathBuf::from(file)
+    };
+    let snapshot = match Snapshot::load(&path) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            eprintln!("\x1b[0;91m{}: {}\x1b[0m", path.display(), e);
+            return EXIT_ERROR;
+        }
+    };
+    if !confirm(options, &format!("Restore the state from {}?", snapshot.created())) {
+        return EXIT_ERROR;
+    }
+    restore_snapshot(backend, tweaks, &snapshot, options)
+}
+
+#[cfg(windows)]
+fn restore_snapshot<B: RegistryBackend>(backend: &B, tweaks: &Tweaks, snapshot: &Snapshot, options: &Options) -> i32 {
+    let status = match registry::snapshot::restore_snapshot(backend, snapshot, &tweaks.target()) {
+        Ok(report) => {
+            registry::report::print_console(&report);
+            registry::report::print_summary(&report);
+            if report.failed() > 0 {
+                EXIT_ERROR
+            } else {
+                EXIT_OK
+            }
+        }
+        // Neither is the boot store or the power plan
+        Err(e @ error::Error::WrongTarget { .. }) => {
+            println!("\x1b[0;91m{}\x1b[0m", e);
+            return EXIT_ERROR;
+        }
+        Err(e) => {
+            println!("\x1b[0;91m{}\x1b[0m", e);
+            EXIT_ERROR
+        }
+    };
+    if options.hives.is_empty() {
+        bcd::restore_bcd_store(&bcd::Bcdedit, &snapshot.bcd());
+        if let Some(previous) = snapshot.power_scheme() {
+            if !remove_gaming_scheme(Some(&previous)) {
+                return EXIT_ERROR;
+            }
+        }
+        if !hide_power_settings(&snapshot.shown_power_settings()) {
+            return EXIT_ERROR;
+        }
+    }
+    status
+}
+
+// Back to the scheme active before the gaming one, which is then deleted
+#[cfg(windows)]
+fn remove_gaming_scheme(previous: Option<&str>) -> bool {
+    match registry::remove_gaming_scheme(&Powercfg, previous) {
+        Ok(true) => {
+            println!("deleted the {} power plan", registry::GAMING_SCHEME_NAME);
+            true
+        }
+        Ok(false) => true,
+        Err(e) => {
+            println!("\x1b[0;91m{} power plan: {}\x1b[0m", registry::GAMING_SCHEME_NAME, e);
+            false
+        }
+    }
+}
+
+// Hides the power settings an apply showed again
+#[cfg(windows)]
+fn hide_power_settings(shown: &[SavedPowerSetting]) -> bool {
+    let mut ok = true;
+    for saved in shown.iter() {
+        match registry::set_power_hidden(&saved.subgroup, &saved.setting, true) {
+            Ok(()) => println!("hid the power setting {}\\{}", saved.subgroup, saved.setting),
+            Err(e) => {
+                println!("\x1b[0;91mpower setting {}\\{}: {}\x1b[0m", saved.subgroup, saved.setting, e);
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+#[cfg(windows)]
+fn profile_command<B: RegistryBackend>(backend: &B, tweaks: &Tweaks, command: &ProfileCommand, options: &Options) -> i32 {
+    let name = match command {
+        ProfileCommand::List => {
+            list_profiles(&tweaks.profiles);
+            return EXIT_OK;
+        }
+        ProfileCommand::Scan => return scan_perf_options(backend, &tweaks.profiles),
+        ProfileCommand::Add { process, profile } => {
+            return edit_profiles(options, ProfileEdit::Add { process, profile }, "added", "to");
+        }
+        ProfileCommand::Remove { process } => {
+            return edit_profiles(options, ProfileEdit::Remove { process }, "removed", "from");
+        }
+        ProfileCommand::Check { name } | ProfileCommand::Apply { name } | ProfileCommand::Clear { name } => name,
+    };
+    let profile = match tweaks.profiles.find(name) {
+        Some(profile) => profile,
+        None => {
+            eprintln!("\x1b[0;91munknown profile `{}`, `profile list` shows them\x1b[0m", name);
+            return EXIT_ERROR;
+        }
+    };
+    let selection = Selection::ids(vec![profile.id()]);
+    match command {
+        ProfileCommand::Apply { .. } => {
+            let (plan, status) = plan_changes(backend, tweaks, None, &selection);
+            if plan.is_empty() {
+                println!("\nNothing to apply");
+                return status;
+            }
+            println!("\n# Plan");
+            plan.print_diff();
+            if !confirm(options, &format!("Write the IFEO entries of {}?", profile.name)) {
+                return EXIT_ERROR;
+            }
+            execute_plan(backend, tweaks, &plan)
+        }
+        ProfileCommand::Clear { .. } => {
+            if !confirm(options, &format!("Remove the IFEO entries of {}?", profile.name)) {
+                return EXIT_ERROR;
+            }
+            clear_profile(backend, tweaks, profile)
+        }
+        _ => check(backend, tweaks, None, &selection, options.format),
+    }
+}
+
+#[cfg(windows)]
+fn clear_profile<B: RegistryBackend>(backend: &B, tweaks: &Tweaks, profile: &Profile) -> i32 {
+    let snapshot = Snapshot::create(&registry::snapshot::snapshot_dir(), tweaks.target());
+    let tracked = SnapshotBackend::new(backend, &snapshot);
+    let report = profile::remove_profile(&tracked, profile, tweaks.dpi);
+    registry::report::print_console(&report);
+    registry::report::print_summary(&report);
+    let status = if report.failed() > 0 { EXIT_ERROR } else { EXIT_OK };
+    print_snapshot(&snapshot);
+    status
+}
+
+// --profiles or the user's copy of the profiles
+#[cfg(windows)]
+fn edit_profiles(options: &Options, edit: ProfileEdit, done: &str, preposition: &str) -> i32 {
+    let file = options.profiles.clone().unwrap_or_else(profile::profiles_file);
+    let process = match &edit {
+        ProfileEdit::Add { process, .. } | ProfileEdit::Remove { process } => process.to_string(),
+    };
+    match profile::edit_profiles(&file, edit) {
+        Ok(edited) => {
+            println!(
+                "{} {} {} profile {} in \x1b[0;92m{}\x1b[0m",
+                done,
+                process,
+                preposition,
+                edited.profile,
+                file.display()
+            );
+            if edited.dropped_comments {
+                eprintln!("\x1b[0;93mThe comments of {} were not kept\x1b[0m", file.display());
+            }
+            EXIT_OK
+        }
+        Err(e) => {
+            eprintln!("\x1b[0;91m{}\x1b[0m", e);
+            EXIT_ERROR
+        }
+    }
+}
+
+// Every PerfOptions entry on the machine and the profile it belongs to
+#[cfg(windows)]
+fn scan_perf_options<B: RegistryBackend>(backend: &B, profiles: &Profiles) -> i32 {
+    let entries = match perf_options::scan(backend) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("\x1b[0;91m{}\x1b[0m", e);
+            return EXIT_ERROR;
+        }
+    };
+    for entry in entries.iter() {
+        match profiles.profiles.iter().find(|p| p.owns(&entry.process)) {
+            Some(profile) => println!("{} (profile {})", entry.process, profile.name),
+            None => println!("\x1b[0;93m{}\x1b[0m (not in a profile)", entry.process),
+        }
+        for (name, value) in entry.values.iter() {
+            println!("    {} = {}", name, perf_options::describe(name, value));
+        }
+    }
+    EXIT_OK
+}
+
+#[cfg(windows)]
+fn list_profiles(profiles: &Profiles) {
+    fn value<T: std::fmt::Display>(value: Option<T>) -> String {
+        value.map_or(String::from("-"), |v| v.to_string())
+    }
+    for profile in profiles.profiles.iter() {
+        println!("{}", tweak_line(&profile.info()));
+        for warning in profile.warnings() {
+            println!("    \x1b[0;93m{}\x1b[0m", warning);
+        }
+        println!(
+            "    cpu {}  io {}  page {}  working set {} KB",
+            value(profile.cpu_priority_class),
+            value(profile.io_priority),
+            value(profile.page_priority),
+            value(profile.working_set_limit_in_kb)
+        );
+        println!("    {}", profile.processes.join(", "));
+        for tweak in profile.tweaks.iter() {
+            println!("    {}\\{}", tweak.key, tweak.name);
+        }
+    }
+}
+
+// Without an output file the .reg content goes to the console
+#[cfg(windows)]
+fn export<B: RegistryBackend>(backend: &B, reg_settings: &Settings, kind: ExportKind, output: Option<&Path>) -> i32 {
+    let content = match kind {
+        ExportKind::Desired => registry::regfile::export_desired(reg_settings),
+        ExportKind::Defaults => registry::regfile::export_defaults(reg_settings),
+        ExportKind::Current => registry::regfile::export_current(backend, reg_settings),
+    };
+    match output {
+        Some(file) => match registry::regfile::write_reg_file(file, &content) {
+            Ok(()) => {
+                println!("exported \x1b[0;92m{}\x1b[0m", file.display());
+                EXIT_OK
+            }
+            Err(e) => {
+                eprintln!("\x1b[0;91m{}: {}\x1b[0m", file.display(), e);
+                EXIT_ERROR
+            }
+        },
+        None => {
+            print!("{}", content);
+            EXIT_OK
+        }
+    }
+}
+
+#[cfg(windows)]
+fn tweak_line(tweak: &TweakInfo) -> String {
+    let category = tweak.category.map(|c| c.name()).unwrap_or("-");
+    format!("{:<32} {:<9} {:<6} {}", tweak.id, category, tweak.risk, tweak.description)
+}
+
+#[cfg(windows)]
+fn list_tweaks(tweaks: &Tweaks, selection: &Selection) {
+    for tweak in tweaks.all.iter().filter(|t| selection.selects(t)) {
+        println!("{}", tweak_line(tweak));
+        for warning in tweaks.warnings(&[&tweak.id]) {
+            println!("    \x1b[0;93m{}\x1b[0m", warning);
+        }
+        for source in tweaks.sources(&tweak.id) {
+            println!("    {}", source);
+        }
+        if tweak.id == registry::POWERPLAN {
+            for setting in registry::PowerCatalog::builtin().describe_plan(tweaks.hybrid()) {
+                println!("    {}", setting);
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+fn list_snapshots() -> i32 {
+    let dir = registry::snapshot::snapshot_dir();
+    let paths = Snapshot::list(&dir).unwrap_or_default();
+    if paths.is_empty() {
+        println!("No snapshots found in {}", dir.display());
+    }
+    for path in paths.iter() {
+        match Snapshot::load(path) {
+            Ok(snapshot) => println!("{}  {}  {}", snapshot.created(), path.display(), snapshot.target()),
+            Err(e) => println!("\x1b[0;91m{}: {}\x1b[0m", path.display(), e),
+        }
+    }
+    EXIT_OK
+}
+
+// The menu of the previous versions, used when no subcommand is given
+#[cfg(windows)]
+fn interactive<B: RegistryBackend>(backend: &B, tweaks: &Tweaks, options: &Options) -> i32 {
+    let selection = &options.selection;
+    let mtu = tcp_mtu(options, selection);
+    let inspection = inspect(backend, tweaks, mtu, selection, Format::Text);
+    let mut status = exit_code(&inspection.audit);
 
     if let Ok(select) = Select::with_theme(&ColorfulTheme::default())
-        .items(&vec![
+        .items(&[
             "Apply fixes",
             "Restore Windows Default Settings",
+            "Restore previous state",
+            "Export .reg files",
             "Exit",
         ])
         .default(0)
         .interact()
     {
-        match select {
-            0 => {
-                // Apply fixes
-                // registry::check_powerplan(&powerplan, true);
-                registry::apply_reg_tweaks(&reg_settings, true);
-                registry::apply_tcp_tweaks(&mtu, true, false);
-
-                powershell::set_bcd_store(false);
-
-                for process in game_launcher_bloat.iter() {
-                    registry::set_cpu_priority(
-                        registry::CpuPriority {
-                            process: process.to_string(),
-                            cpu_priority_class: Some(5u32), // 5 = Below Normal
-                            io_priority: Some(1u32),        // 1 = Low
-                            page_priority: None,
-                            working_set_limit_in_kb: None,
-                        },
-                        true,
-                    );
+        status = match select {
+            0 => approve(backend, tweaks, plan_of(backend, tweaks, &inspection, selection), options, status),
+            1 => restore_defaults(backend, tweaks, selection),
+            2 => {
+                let snapshots: Vec<Snapshot> = Snapshot::list(&registry::snapshot::snapshot_dir())
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|path| Snapshot::load(path).ok())
+                    .collect();
+                if snapshots.is_empty() {
+                    println!("\x1b[0;93mNo snapshots found in {}\x1b[0m", registry::snapshot::snapshot_dir().display());
+                    status
+                } else {
+                    let items: Vec<String> = snapshots.iter().map(|s| format!("{}, {}", s.created(), s.target())).collect();
+                    match Select::with_theme(&ColorfulTheme::default())
+                        .items(&items)
+                        .default(0)
+                        .interact()
+                    {
+                        Ok(index) => restore_snapshot(backend, tweaks, &snapshots[index], options),
+                        Err(_) => status,
+                    }
                 }
-
-                for process in games.iter() {
-                    registry::set_cpu_priority(
-                        registry::CpuPriority {
-                            process: process.to_string(),
-                            cpu_priority_class: Some(3u32), // 3 = High
-                            io_priority: Some(3u32),        // 3 = High
-                            page_priority: None,
-                            working_set_limit_in_kb: None,
-                        },
-                        true,
-                    );
-                }
-
-                for process in system_process_high.iter() {
-                    registry::set_cpu_priority(
-                        registry::CpuPriority {
-                            process: process.to_string(),
-                            cpu_priority_class: Some(3u32), // 3 = High
-                            io_priority: Some(3u32),        // 3 = High
-                            page_priority: None,
-                            working_set_limit_in_kb: None,
-                        },
-                        true,
-                    );
-                }
-
-                for process in system_process_below_normal.iter() {
-                    registry::set_cpu_priority(
-                        registry::CpuPriority {
-                            process: process.to_string(),
-                            cpu_priority_class: Some(5u32), // 5 = Below Normal
-                            io_priority: Some(1u32),        // 1 = Low
-                            page_priority: None,
-                            working_set_limit_in_kb: None,
-                        },
-                        true,
-                    );
-                }
-
-                sound::apply_audio_settings(true, false);
             }
-            1 => {
-                // Restore Windows Default Settings
-                // registry::default_powerplan();
-                registry::restore_default_reg(&reg_settings).unwrap();
-                registry::apply_tcp_tweaks(&mtu, true, true);
+            3 => {
+                let files = [
+                    ("gaming-optimizer-desired.reg", ExportKind::Desired),
+                    ("gaming-optimizer-restore.reg", ExportKind::Defaults),
+                    ("gaming-optimizer-current.reg", ExportKind::Current),
+                ];
+                let reg_settings = tweaks.settings(selection);
+                files
+                    .iter()
+                    .map(|(file, kind)| export(backend, &reg_settings, *kind, Some(Path::new(file))))
+                    .max()
+                    .unwrap_or(EXIT_OK)
+            }
+            _ => return status,
+        };
+    }
 
-                powershell::set_bcd_store(true);
+    let _ = dialoguer::Input::<String>::new().allow_empty(true).interact_text();
+    status
+}
 
-                sound::apply_audio_settings(true, true);
-            }
-            _ => std::process::exit(0),
+// --yes answers for the user, without a terminal there is nobody to ask
+#[cfg(windows)]
+fn confirm(options: &Options, prompt: &str) -> bool {
+    if options.yes {
+        return true;
+    }
+    match Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .default(false)
+        .interact()
+    {
+        Ok(answer) => answer,
+        Err(_) => {
+            eprintln!("\x1b[0;91mCould not ask for confirmation, use --yes to run without asking\x1b[0m");
+            false
         }
-    };
+    }
+}
 
-    dialoguer::Input::<String>::new().interact_text().unwrap();
+#[cfg(windows)]
+fn print_snapshot(snapshot: &Snapshot) {
+    if !snapshot.is_empty() {
+        println!("previous state saved to \x1b[0;92m{}\x1b[0m", snapshot.path().display());
+    }
+}
+
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("gaming-optimizer only runs on Windows");
+    std::process::exit(1);
 }
@@ -1,14 +1,15 @@
+use crate::error::{self, Error};
+
 use std::net::IpAddr;
 use winping::{Buffer, Pinger};
 
-pub fn ping() -> u32 {
-    let dst = std::env::args()
-        .nth(1)
-        .unwrap_or(String::from("1.1.1.1"))
+pub fn ping(target: Option<&str>) -> error::Result<u32> {
+    let target = target.unwrap_or("1.1.1.1");
+    let dst = target
         .parse::<IpAddr>()
-        .expect("Could not parse IP Address");
+        .map_err(|_| Error::Ping(format!("`{}` is not an IP address", target)))?;
 
-    let mut pinger = Pinger::new().unwrap();
+    let mut pinger = Pinger::new().map_err(|e| Error::Ping(e.to_string()))?;
     pinger.set_ttl(64);
     pinger.set_df(true);
 
@@ -29,5 +30,5 @@ pub fn ping() -> u32 {
             Err(_) => max_mtu -= 1,
         }
     }
-    max_mtu + 28 // Header 28 bit
+    Ok(max_mtu + 28) // Header 28 bit
 }
@@ -0,0 +1,1182 @@
+// The changes an apply makes, worked out before anything is written. The
+// planner turns the check results into an ordered list of steps, which is shown
+// as a diff, can be saved as JSON and is then handed to the executor, so only
+// the steps the user approved are written.
+
+use crate::bcd::{self, BcdCheck, BcdStore, Bcdedit};
+use crate::error::{self, Error};
+use crate::registry::{self, PowerConfig, PowerPlan, PowerSource, Powercfg};
+use crate::registry::backend::{Hive, RegValue, RegistryBackend};
+use crate::registry::perf_options;
+use crate::registry::report::{CheckOutcome, CheckResult, Report};
+use crate::registry::snapshot::{SavedData, Snapshot};
+use crate::selection::{Category, Selection};
+#[cfg(windows)]
+use crate::sound::{self, AudioEndpoint};
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const VERSION: u32 = 1;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Step {
+    // The tweak the change belongs to, several steps share it when a tweak covers more than one value
+    pub id: String,
+    pub category: Option<Category>,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+// `current` is the state the plan was made against, the executor refuses to
+// write when it is no longer there
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Change {
+    CreateKey {
+        hive: String,
+        path: String,
+    },
+    SetValue {
+        hive: String,
+        path: String,
+        name: String,
+        value: SavedData,
+        current: Option<SavedData>,
+    },
+    DeleteValue {
+        hive: String,
+        path: String,
+        name: String,
+        current: SavedData,
+    },
+    // The key and everything below it
+    DeleteKey {
+        hive: String,
+        path: String,
+    },
+    SetBcdElement {
+        setting: String,
+        value: bool,
+        current: Option<bool>,
+    },
+    // A copy of the `base` power scheme the power steps write to
+    CreatePowerScheme {
+        scheme: String,
+        base: String,
+        name: String,
+    },
+    // The AC or DC index of a power setting, written through powercfg
+    SetPowerIndex {
+        scheme: String,
+        subgroup: String,
+        setting: String,
+        #[serde(default)]
+        source: PowerSource,
+        value: u32,
+        current: Option<u32>,
+    },
+    SetActivePowerScheme {
+        scheme: String,
+        current: Option<String>,
+    },
+    // Clears the hide bit of the Attributes of a setting, written through powercfg
+    ShowPowerSetting {
+        subgroup: String,
+        setting: String,
+        current: u32,
+    },
+    SetAudioProperty {
+        device: String,
+        property: String,
+        value: i32,
+        current: i32,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangePlan {
+    pub version: u32,
+    pub steps: Vec<Step>,
+}
+
+impl ChangePlan {
+    pub fn new() -> ChangePlan {
+        ChangePlan {
+            version: VERSION,
+            steps: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<ChangePlan> {
+        let text = fs::read_to_string(path)?;
+        let plan: ChangePlan =
+            serde_json::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if plan.version != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported plan version {}", plan.version),
+            ));
+        }
+        Ok(plan)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_json() + "\n")
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    // The tweaks with at least one step, in plan order
+    pub fn ids(&self) -> Vec<&str> {
+        let mut ids: Vec<&str> = Vec::new();
+        for step in self.steps.iter() {
+            if !ids.contains(&step.id.as_str()) {
+                ids.push(&step.id);
+            }
+        }
+        ids
+    }
+
+    fn push(&mut self, (id, category): (String, Option<Category>), change: Change) {
+        self.steps.push(Step { id, category, change });
+    }
+
+    // Registry results that differ from the tweak, a key that does not exist is
+    // created before its first value
+    pub fn add_report<B: RegistryBackend>(
+        &mut self,
+        backend: &B,
+        report: &Report,
+        tweak: impl Fn(&CheckResult) -> (String, Option<Category>),
+    ) {
+        for result in report.results.iter() {
+            let hive = result.hive.short_name().to_string();
+            let root = backend.predef(result.hive);
+            let current = backend
+                .open_subkey(&root, &result.path)
+                .and_then(|key| backend.get_raw_value(&key, &result.name))
+                .ok();
+            let change = match &result.outcome {
+                CheckOutcome::Mismatch { current: seen, desired }
+                | CheckOutcome::WrongType { current: seen, desired } => Change::SetValue {
+                    hive,
+                    path: result.path.clone(),
+                    name: result.name.clone(),
+                    value: SavedData::from_value(desired),
+                    current: Some(SavedData::from_value(current.as_ref().unwrap_or(seen))),
+                },
+                CheckOutcome::Missing => {
+                    let value = match &result.value {
+                        Some(value) => value,
+                        None => continue,
+                    };
+                    if backend.open_subkey(&root, &result.path).is_err() && !self.creates(&hive, &result.path) {
+                        let change = Change::CreateKey {
+                            hive: hive.clone(),
+                            path: result.path.clone(),
+                        };
+                        self.push(tweak(result), change);
+                    }
+                    Change::SetValue {
+                        hive,
+                        path: result.path.clone(),
+                        name: result.name.clone(),
+                        value: SavedData::from_value(value),
+                        current: None,
+                    }
+                }
+                CheckOutcome::Present if result.name.is_empty() => Change::DeleteKey {
+                    hive,
+                    path: result.path.clone(),
+                },
+                CheckOutcome::Present => match current {
+                    Some(current) => Change::DeleteValue {
+                        hive,
+                        path: result.path.clone(),
+                        name: result.name.clone(),
+                        current: SavedData::from_value(&current),
+                    },
+                    None => continue,
+                },
+                _ => continue,
+            };
+            self.push(tweak(result), change);
+        }
+    }
+
+    fn creates(&self, hive: &str, path: &str) -> bool {
+        self.steps.iter().any(|step| match &step.change {
+            Change::CreateKey { hive: h, path: p } => h == hive && p.eq_ignore_ascii_case(path),
+            _ => false,
+        })
+    }
+
+    // The results of check_powerplan: the scheme to create, the indices, whose
+    // path ends in scheme\\subgroup\\setting, and the active scheme. Those of
+    // check_power_attributes end in subgroup\\setting.
+    pub fn add_powerplan(&mut self, powerplan: &PowerPlan, report: &Report) {
+        let id = || (registry::POWERPLAN.to_string(), Some(Category::Power));
+        for result in report.results.iter() {
+            if result.name.is_empty() {
+                if let CheckOutcome::Missing = result.outcome {
+                    let change = Change::CreatePowerScheme {
+                        scheme: registry::GAMING_SCHEME.to_string(),
+                        base: powerplan.base().to_string(),
+                        name: registry::GAMING_SCHEME_NAME.to_string(),
+                    };
+                    self.push(id(), change);
+                }
+                continue;
+            }
+            if result.name == "ActivePowerScheme" {
+                let current = match &result.outcome {
+                    CheckOutcome::Mismatch { current, .. } => current.as_string().ok(),
+                    CheckOutcome::Missing => None,
+                    _ => continue,
+                };
+                let change = Change::SetActivePowerScheme {
+                    scheme: registry::GAMING_SCHEME.to_string(),
+                    current,
+                };
+                self.push(id(), change);
+                continue;
+            }
+            if result.name == "Attributes" {
+                let current = match &result.outcome {
+                    CheckOutcome::Mismatch { current, .. } => current.as_u32().ok(),
+                    _ => None,
+                };
+                let guids: Vec<&str> = result.path.rsplitn(3, '\\').collect();
+                if let (Some(current), [setting, subgroup, _]) = (current, &guids[..]) {
+                    let change = Change::ShowPowerSetting {
+                        subgroup: subgroup.to_string(),
+                        setting: setting.to_string(),
+                        current,
+                    };
+                    self.push(id(), change);
+                }
+                continue;
+            }
+            let source = match result.name.as_str() {
+                "DCSettingIndex" => PowerSource::Dc,
+                _ => PowerSource::Ac,
+            };
+            let current = match &result.outcome {
+                CheckOutcome::Mismatch { current, .. } => current.as_u32().ok(),
+                CheckOutcome::Missing => None,
+                _ => continue,
+            };
+            let value = match result.value.as_ref().and_then(|v| v.as_u32().ok()) {
+                Some(value) => value,
+                None => continue,
+            };
+            let guids: Vec<&str> = result.path.rsplitn(4, '\\').collect();
+            if let [setting, subgroup, scheme, _] = guids[..] {
+                let change = Change::SetPowerIndex {
+                    scheme: scheme.to_string(),
+                    subgroup: subgroup.to_string(),
+                    setting: setting.to_string(),
+                    source,
+                    value,
+                    current,
+                };
+                self.push(id(), change);
+            }
+        }
+    }
+
+    pub fn add_bcd(&mut self, checks: &[BcdCheck]) {
+        for check in checks.iter().filter(|check| !check.in_sync()) {
+            let change = Change::SetBcdElement {
+                setting: check.setting.to_string(),
+                value: check.desired,
+                current: check.current,
+            };
+            self.push((bcd::bcd_id(check.setting), Some(Category::Boot)), change);
+        }
+    }
+
+    #[cfg(windows)]
+    pub fn add_audio(&mut self, endpoint: &AudioEndpoint) {
+        if endpoint.in_sync() {
+            return;
+        }
+        let change = Change::SetAudioProperty {
+            device: endpoint.friendly_name.clone(),
+            property: String::from("FullRangeSpeakers"),
+            value: endpoint.physical_speakers,
+            current: endpoint.full_range_speakers,
+        };
+        self.push((sound::AUDIO_FULL_RANGE.to_string(), Some(Category::Audio)), change);
+    }
+
+    // Drops the steps of tweaks outside the selection, a created key stays as
+    // long as a value is still planned in it
+    pub fn retain(&mut self, selection: &Selection) {
+        self.steps.retain(|step| {
+            matches!(step.change, Change::CreateKey { .. }) || selection.includes(&step.id, step.category)
+        });
+        let needed: Vec<(String, String)> = self
+            .steps
+            .iter()
+            .filter_map(|step| match &step.change {
+                Change::SetValue { hive, path, .. } => Some((hive.clone(), path.to_lowercase())),
+                _ => None,
+            })
+            .collect();
+        self.steps.retain(|step| match &step.change {
+            Change::CreateKey { hive, path } => needed.contains(&(hive.clone(), path.to_lowercase())),
+            _ => true,
+        });
+    }
+
+    // One block per tweak, + adds, ~ changes and - removes
+    #[cfg(windows)]
+    pub fn print_diff(&self) {
+        let mut last: Option<&str> = None;
+        for step in self.steps.iter() {
+            if last != Some(&step.id) {
+                println!("{}", step.id);
+                last = Some(&step.id);
+            }
+            println!("    {}", step.change.diff_line());
+        }
+        println!("\n{} changes", self.steps.len());
+    }
+}
+
+impl Change {
+    // What the step touches, e.g. HKLM\...\Name or BCD\{current}\useplatformtick
+    pub fn target(&self) -> String {
+        match self {
+            Change::CreateKey { hive, path } | Change::DeleteKey { hive, path } => format!("{}\\{}", hive, path),
+            Change::SetValue { hive, path, name, .. } | Change::DeleteValue { hive, path, name, .. } => {
+                format!("{}\\{}\\{}", hive, path, name)
+            }
+            Change::SetBcdElement { setting, .. } => format!("BCD\\{{current}}\\{}", setting),
+            Change::CreatePowerScheme { scheme, .. } => format!("Power\\{}", scheme),
+            Change::SetPowerIndex {
+                scheme,
+                subgroup,
+                setting,
+                source,
+                ..
+            } => format!("Power\\{}\\{}\\{}\\{}", scheme, subgroup, setting, source.name()),
+            Change::SetActivePowerScheme { .. } => String::from("Power\\ActivePowerScheme"),
+            Change::ShowPowerSetting { subgroup, setting, .. } => format!("Power\\{}\\{}\\Attributes", subgroup, setting),
+            Change::SetAudioProperty { device, property, .. } => format!("Audio\\{}\\{}", device, property),
+        }
+    }
+
+    pub fn diff_line(&self) -> String {
+        let target = self.target();
+        let added = |value: String| format!("\x1b[0;92m+ {} = {}\x1b[0m", target, value);
+        let changed = |current: String, value: String| format!("\x1b[0;93m~ {} = {} -> {}\x1b[0m", target, current, value);
+        let yes_no = |value: bool| String::from(if value { "Yes" } else { "No" });
+        match self {
+            Change::CreateKey { .. } => format!("\x1b[0;92m+ {}\x1b[0m", target),
+            Change::DeleteKey { .. } => format!("\x1b[0;91m- {}\x1b[0m", target),
+            Change::SetValue {
+                path,
+                name,
+                value,
+                current: None,
+                ..
+            } => added(show(path, name, value)),
+            Change::SetValue {
+                path,
+                name,
+                value,
+                current: Some(current),
+                ..
+            } => changed(show(path, name, current), show(path, name, value)),
+            Change::DeleteValue { path, name, current, .. } => {
+                format!("\x1b[0;91m- {} = {}\x1b[0m", target, show(path, name, current))
+            }
+            Change::SetBcdElement { value, current: None, .. } => added(yes_no(*value)),
+            Change::SetBcdElement {
+                value,
+                current: Some(current),
+                ..
+            } => changed(yes_no(*current), yes_no(*value)),
+            Change::CreatePowerScheme { base, name, .. } => added(format!("{} (copy of {})", name, base)),
+            Change::SetPowerIndex { value, current: None, .. } => added(value.to_string()),
+            Change::SetPowerIndex {
+                value,
+                current: Some(current),
+                ..
+            } => changed(current.to_string(), value.to_string()),
+            Change::SetActivePowerScheme { scheme, current: None } => added(scheme.clone()),
+            Change::SetActivePowerScheme {
+                scheme,
+                current: Some(current),
+            } => changed(current.clone(), scheme.clone()),
+            Change::ShowPowerSetting { .. } => changed(String::from("hidden"), String::from("shown")),
+            Change::SetAudioProperty { value, current, .. } => {
+                changed(format!("0x{:x}", current), format!("0x{:x}", value))
+            }
+        }
+    }
+}
+
+// PerfOptions values get their meaning appended like in the check
+fn show(path: &str, name: &str, data: &SavedData) -> String {
+    match data.to_value() {
+        Ok(value) if perf_options::is_perf_options(path) => perf_options::describe(name, &value),
+        Ok(value) => value.to_string(),
+        Err(_) => format!("hex({}):{}", data.vtype, data.data),
+    }
+}
+
+pub enum StepOutcome {
+    Done,
+    Failed(Error),
+    // Written, then undone with the rest of the registry steps
+    RolledBack,
+    // Not run because the registry steps were rolled back
+    Skipped,
+}
+
+pub struct StepResult<'a> {
+    pub step: &'a Step,
+    pub outcome: StepOutcome,
+}
+
+pub struct Execution<'a> {
+    pub results: Vec<StepResult<'a>>,
+    // Why nothing was written, None when the registry steps were committed
+    pub rollback: Option<String>,
+}
+
+impl Execution<'_> {
+    pub fn failed(&self) -> bool {
+        self.rollback.is_some() || self.results.iter().any(|r| matches!(r.outcome, StepOutcome::Failed(_)))
+    }
+}
+
+impl Change {
+    fn is_registry(&self) -> bool {
+        matches!(
+            self,
+            Change::CreateKey { .. } | Change::SetValue { .. } | Change::DeleteValue { .. } | Change::DeleteKey { .. }
+        )
+    }
+}
+
+// All registry steps share one transaction, which is only committed when every
+// one of them succeeded. Otherwise it is rolled back and nothing else runs.
+// Boot, power and audio steps cannot be undone, they run after the commit and
+// a failed one does not stop the ones after it. Registry writes go through
+// `backend`, boot settings are recorded in `snapshot`.
+pub fn execute<'a, B: RegistryBackend>(
+    backend: &B,
+    plan: &'a ChangePlan,
+    snapshot: Option<&Snapshot>,
+) -> Execution<'a> {
+    execute_with(backend, &Powercfg, &Bcdedit, plan, snapshot)
+}
+
+// `execute` with the power schemes and the boot store it writes to
+pub fn execute_with<'a, B: RegistryBackend, P: PowerConfig, S: BcdStore>(
+    backend: &B,
+    power: &P,
+    store: &S,
+    plan: &'a ChangePlan,
+    snapshot: Option<&Snapshot>,
+) -> Execution<'a> {
+    let mut results: Vec<StepResult> = plan
+        .steps
+        .iter()
+        .map(|step| StepResult {
+            step,
+            outcome: StepOutcome::Skipped,
+        })
+        .collect();
+    let rollback = write_registry(backend, &mut results);
+    if rollback.is_none() {
+        // Reading the BcdStore runs bcdedit, so it happens once
+        let mut bcd = None;
+        for result in results.iter_mut().filter(|r| !r.step.change.is_registry()) {
+            result.outcome = match execute_step(backend, power, store, &result.step.change, snapshot, &mut bcd) {
+                Ok(()) => StepOutcome::Done,
+                Err(e) => StepOutcome::Failed(e),
+            };
+        }
+    }
+    Execution { results, rollback }
+}
+
+// Returns why the transaction was rolled back
+fn write_registry<B: RegistryBackend>(backend: &B, results: &mut [StepResult]) -> Option<String> {
+    if !results.iter().any(|r| r.step.change.is_registry()) {
+        return None;
+    }
+    let transaction = match backend.transaction() {
+        Ok(transaction) => transaction,
+        Err(e) => return Some(format!("could not start a registry transaction: {}", e)),
+    };
+    let mut failure = None;
+    for (i, result) in results.iter_mut().enumerate().filter(|(_, r)| r.step.change.is_registry()) {
+        match write_step(backend, &result.step.change, &transaction) {
+            Ok(()) => result.outcome = StepOutcome::Done,
+            Err(e) => {
+                failure = Some(format!("step {} failed: {}: {}", i + 1, result.step.change.target(), e));
+                result.outcome = StepOutcome::Failed(e);
+                break;
+            }
+        }
+    }
+    let failure = match failure {
+        None => match backend.commit(&transaction) {
+            Ok(()) => return None,
+            Err(e) => format!("the commit failed: {}", e),
+        },
+        Some(failure) => match backend.rollback(&transaction) {
+            Ok(()) => failure,
+            Err(e) => format!("{}, the rollback failed too: {}", failure, e),
+        },
+    };
+    for result in results.iter_mut() {
+        if let StepOutcome::Done = result.outcome {
+            result.outcome = StepOutcome::RolledBack;
+        }
+    }
+    Some(failure)
+}
+
+fn write_step<B: RegistryBackend>(backend: &B, change: &Change, transaction: &B::Transaction) -> error::Result<()> {
+    match change {
+        Change::CreateKey { hive, path } => {
+            backend.create_subkey_transacted(&root(backend, hive)?, path, transaction)?;
+            Ok(())
+        }
+        Change::SetValue {
+            hive,
+            path,
+            name,
+            value,
+            current,
+        } => {
+            let root = root(backend, hive)?;
+            unchanged(read_value(backend, &root, path, name, transaction)?, current.as_ref())?;
+            let key = backend.create_subkey_transacted(&root, path, transaction)?;
+            Ok(backend.set_raw_value(&key, name, &value.to_value()?)?)
+        }
+        Change::DeleteValue {
+            hive,
+            path,
+            name,
+            current,
+        } => {
+            let root = root(backend, hive)?;
+            unchanged(read_value(backend, &root, path, name, transaction)?, Some(current))?;
+            Ok(backend.delete_value(&backend.open_subkey_transacted(&root, path, transaction)?, name)?)
+        }
+        Change::DeleteKey { hive, path } => {
+            Ok(registry::delete_tree_transacted(backend, &root(backend, hive)?, path, transaction)?)
+        }
+        _ => Err(Error::Io(io::Error::other("not a registry change"))),
+    }
+}
+
+fn execute_step<B: RegistryBackend, P: PowerConfig, S: BcdStore>(
+    backend: &B,
+    power: &P,
+    store: &S,
+    change: &Change,
+    snapshot: Option<&Snapshot>,
+    bcd: &mut Option<Vec<(String, Option<bool>)>>,
+) -> error::Result<()> {
+    match change {
+        Change::SetBcdElement { setting, value, current } => {
+            if bcd.is_none() {
+                *bcd = Some(bcd::read_bcd_store(store)?);
+            }
+            let now = bcd
+                .iter()
+                .flatten()
+                .find(|(name, _)| name == setting)
+                .and_then(|(_, state)| *state);
+            if now != *current {
+                return Err(Error::Changed);
+            }
+            if let Some(snapshot) = snapshot {
+                snapshot.record_bcd(setting, now)?;
+            }
+            bcd::set_bcd_element(store, setting, *value)
+        }
+        Change::CreatePowerScheme { scheme, base, name } => {
+            if registry::power_scheme_exists(power, scheme)? {
+                return Err(Error::Changed);
+            }
+            registry::create_power_scheme(power, base, scheme, name)
+        }
+        Change::SetPowerIndex {
+            scheme,
+            subgroup,
+            setting,
+            source,
+            value,
+            current,
+        } => {
+            if registry::power_index(power, scheme, subgroup, setting, *source)? != *current {
+                return Err(Error::Changed);
+            }
+            registry::set_power_index(power, scheme, subgroup, setting, *source, *value)
+        }
+        Change::SetActivePowerScheme { scheme, current } => {
+            let now = registry::active_power_scheme(power)?;
+            let same = match (&now, current) {
+                (Some(now), Some(current)) => now.eq_ignore_ascii_case(current),
+                (None, None) => true,
+                _ => false,
+            };
+            if !same {
+                return Err(Error::Changed);
+            }
+            // What a restore switches back to before it deletes the scheme
+            if let (Some(snapshot), Some(now)) = (snapshot, &now) {
+                snapshot.record_power_scheme(now)?;
+            }
+            registry::activate_power_scheme(power, scheme)
+        }
+        Change::ShowPowerSetting {
+            subgroup,
+            setting,
+            current,
+        } => {
+            if registry::power_attributes(backend, subgroup, setting)?.unwrap_or(0) != *current {
+                return Err(Error::Changed);
+            }
+            // What a restore hides again
+            if let Some(snapshot) = snapshot {
+                snapshot.record_shown_power_setting(subgroup, setting)?;
+            }
+            registry::set_power_hidden(subgroup, setting, false)
+        }
+        Change::SetAudioProperty {
+            device, value, current, ..
+        } => set_audio_property(device, *value, *current),
+        _ => Err(Error::Io(io::Error::other("a registry change outside the transaction"))),
+    }
+}
+
+fn root<B: RegistryBackend>(backend: &B, hive: &str) -> error::Result<B::Key> {
+    let hive = hive.parse::<Hive>().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(backend.predef(hive))
+}
+
+// None when the key or the value does not exist, changes made earlier in the transaction count
+fn read_value<B: RegistryBackend>(
+    backend: &B,
+    root: &B::Key,
+    path: &str,
+    name: &str,
+    transaction: &B::Transaction,
+) -> error::Result<Option<RegValue>> {
+    let value = backend
+        .open_subkey_transacted(root, path, transaction)
+        .and_then(|key| backend.get_raw_value(&key, name));
+    match value {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// A value that now has another type counts as changed too
+fn unchanged(now: Option<RegValue>, planned: Option<&SavedData>) -> error::Result<()> {
+    let planned = match planned {
+        Some(planned) => Some(planned.to_value()?),
+        None => None,
+    };
+    let same = match (&now, &planned) {
+        (Some(now), Some(planned)) => now.same_data(planned),
+        (None, None) => true,
+        _ => false,
+    };
+    if same {
+        Ok(())
+    } else {
+        Err(Error::Changed)
+    }
+}
+
+#[cfg(windows)]
+fn set_audio_property(device: &str, value: i32, current: i32) -> error::Result<()> {
+    let endpoint = sound::apply_audio_settings(false, false)?;
+    if endpoint.friendly_name != device || endpoint.full_range_speakers != current || endpoint.physical_speakers != value {
+        return Err(Error::Changed);
+    }
+    if sound::apply_audio_settings(true, false)?.written {
+        Ok(())
+    } else {
+        Err(Error::Io(io::Error::other("the speakers were not written")))
+    }
+}
+
+#[cfg(not(windows))]
+fn set_audio_property(_device: &str, _value: i32, _current: i32) -> error::Result<()> {
+    Err(Error::Io(io::Error::other("audio properties can only be set on Windows")))
+}
+
+#[cfg(windows)]
+pub fn print_results(execution: &Execution) {
+    for result in execution.results.iter() {
+        let target = result.step.change.target();
+        match &result.outcome {
+            StepOutcome::Done => println!("done: {}", result.step.change.diff_line()),
+            StepOutcome::Failed(e) => println!("\x1b[0;91mfailed: {}: {}\x1b[0m", target, e),
+            StepOutcome::RolledBack => println!("rolled back: \x1b[0;93m{}\x1b[0m", target),
+            StepOutcome::Skipped => println!("skipped: \x1b[0;93m{}\x1b[0m", target),
+        }
+    }
+    let count = |filter: fn(&StepOutcome) -> bool| execution.results.iter().filter(|r| filter(&r.outcome)).count();
+    println!(
+        "\n\x1b[0;92m{} written\x1b[0m, \x1b[0;91m{} failed\x1b[0m",
+        count(|o| matches!(o, StepOutcome::Done)),
+        count(|o| matches!(o, StepOutcome::Failed(_)))
+    );
+    if let Some(reason) = &execution.rollback {
+        println!("\x1b[0;91mNothing was changed, the registry transaction was rolled back: {}\x1b[0m", reason);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bcd::{BcdElement, BcdValue};
+    use crate::registry::backend::{Hive, MemoryBackend, RegistryBackend};
+    use crate::registry::catalog::Catalog;
+    use crate::registry::report::CheckOutcome;
+    use crate::registry::powerplan::{PossibleValues, PowerScheme, PowerSetting, SchemeEntry};
+    use crate::registry::Settings;
+
+    const CATALOG: &str = r#"
+version = 1
+
+[[tweak]]
+id = "speed"
+category = "input"
+path = 'HKLM\SOFTWARE\Test'
+name = "Speed"
+type = "dword"
+value = 1
+default = 10
+
+[[tweak]]
+id = "user-name"
+category = "input"
+path = 'HKCU\Software\Test\Deep'
+name = "Name"
+type = "sz"
+value = "gaming"
+"#;
+
+    fn settings() -> Settings {
+        Catalog::from_toml("test.toml", CATALOG).unwrap().settings(96, None, |_| true)
+    }
+
+    fn plan_of(backend: &MemoryBackend) -> ChangePlan {
+        let report = registry::check_reg_tweaks(backend, &settings());
+        let mut plan = ChangePlan::new();
+        plan.add_report(backend, &report, |r| (r.name.to_lowercase(), Some(Category::Input)));
+        plan
+    }
+
+    fn speed(backend: &MemoryBackend) -> Option<u32> {
+        let key = backend.open_subkey(&backend.predef(Hive::LocalMachine), "SOFTWARE\\Test").ok()?;
+        backend.get_u32(&key, "Speed").ok()
+    }
+
+    fn set_speed(backend: &MemoryBackend, value: u32) {
+        let transaction = backend.transaction().unwrap();
+        let key = backend
+            .create_subkey_transacted(&backend.predef(Hive::LocalMachine), "SOFTWARE\\Test", &transaction)
+            .unwrap();
+        backend.set_raw_value(&key, "Speed", &RegValue::dword(value)).unwrap();
+        backend.commit(&transaction).unwrap();
+    }
+
+    #[test]
+    fn check_apply_and_restore() {
+        let backend = MemoryBackend::new();
+        set_speed(&backend, 5);
+        let report = registry::check_reg_tweaks(&backend, &settings());
+        assert_eq!(report.drift(), 2);
+        assert!(matches!(report.results[0].outcome, CheckOutcome::Mismatch { .. }));
+        assert!(matches!(report.results[1].outcome, CheckOutcome::Missing));
+
+        let plan = plan_of(&backend);
+        let targets: Vec<String> = plan.steps.iter().map(|s| s.change.target()).collect();
+        assert_eq!(
+            targets,
+            vec![
+                "HKLM\\SOFTWARE\\Test\\Speed",
+                "HKCU\\Software\\Test\\Deep",
+                "HKCU\\Software\\Test\\Deep\\Name",
+            ]
+        );
+        let execution = execute(&backend, &plan, None);
+        assert!(!execution.failed());
+        assert!(execution.results.iter().all(|r| matches!(r.outcome, StepOutcome::Done)));
+        assert_eq!(speed(&backend), Some(1));
+        assert_eq!(registry::check_reg_tweaks(&backend, &settings()).drift(), 0);
+
+        let report = registry::restore_default_reg(&backend, &settings());
+        assert_eq!(report.failed(), 0);
+        assert_eq!(speed(&backend), Some(10));
+        let deep = backend
+            .open_subkey(&backend.predef(Hive::CurrentUser), "Software\\Test\\Deep")
+            .unwrap();
+        assert!(backend.get_raw_value(&deep, "Name").is_err());
+    }
+
+    #[test]
+    fn a_changed_value_rolls_back_every_registry_step() {
+        let backend = MemoryBackend::new();
+        set_speed(&backend, 5);
+        let plan = plan_of(&backend);
+        // Someone else writes the value between the plan and the apply
+        set_speed(&backend, 7);
+
+        let execution = execute(&backend, &plan, None);
+        assert!(execution.failed());
+        assert!(execution.rollback.as_deref().unwrap_or_default().contains("step 1 failed"));
+        assert!(matches!(execution.results[0].outcome, StepOutcome::Failed(Error::Changed)));
+        assert!(execution.results[1..].iter().all(|r| matches!(r.outcome, StepOutcome::Skipped)));
+        assert_eq!(speed(&backend), Some(7));
+        assert!(backend
+            .open_subkey(&backend.predef(Hive::CurrentUser), "Software\\Test")
+            .is_err());
+    }
+
+    #[test]
+    fn a_late_failure_undoes_the_earlier_steps() {
+        let backend = MemoryBackend::new();
+        let mut plan = plan_of(&backend);
+        // The last step expects a value that is not there
+        if let Change::SetValue { current, .. } = &mut plan.steps.last_mut().unwrap().change {
+            *current = Some(SavedData::from_value(&RegValue::sz("elsewhere")));
+        }
+        let execution = execute(&backend, &plan, None);
+        assert!(execution.failed());
+        let rolled_back = execution
+            .results
+            .iter()
+            .filter(|r| matches!(r.outcome, StepOutcome::RolledBack))
+            .count();
+        assert_eq!(rolled_back, plan.steps.len() - 1);
+        assert_eq!(speed(&backend), None);
+        assert!(backend
+            .open_subkey(&backend.predef(Hive::CurrentUser), "Software\\Test")
+            .is_err());
+    }
+
+    const HIGH_PERFORMANCE: &str = "8c5e7fda-e8bf-4a96-9a85-a6e23a8c635c";
+    const BALANCED: &str = "381b4222-f694-41f0-9685-ff5bb260df2e";
+    const SUB_PROCESSOR: &str = "54533251-82be-4824-96c1-47b60b740d00";
+    const PROCTHROTTLEMIN: &str = "893dee8e-2bef-41e0-89c6-b55d0929964c";
+
+    // A machine that moved on since the plan was made: the gaming scheme exists,
+    // High performance is active, every index is 100 and every boot element is
+    // set. A stale step must not write anything.
+    struct Moved;
+
+    impl PowerConfig for Moved {
+        fn list(&self) -> io::Result<Vec<SchemeEntry>> {
+            let entry = |guid: &str, active| SchemeEntry {
+                guid: guid.to_string(),
+                active,
+            };
+            Ok(vec![entry(registry::GAMING_SCHEME, false), entry(HIGH_PERFORMANCE, true)])
+        }
+
+        fn query(&self, _scheme: &str) -> io::Result<PowerScheme> {
+            panic!("the executor queries single settings")
+        }
+
+        fn query_setting(&self, _scheme: &str, _subgroup: &str, setting: &str) -> io::Result<Option<PowerSetting>> {
+            Ok(Some(PowerSetting {
+                guid: setting.to_string(),
+                alias: None,
+                values: PossibleValues::Unknown,
+                ac: Some(100),
+                dc: Some(100),
+            }))
+        }
+
+        fn duplicate(&self, _base: &str, scheme: &str, _name: &str) -> io::Result<()> {
+            panic!("{} was created by a stale plan", scheme)
+        }
+
+        fn set(&self, _scheme: &str, _subgroup: &str, setting: &str, _: PowerSource, _: u32) -> io::Result<()> {
+            panic!("{} was written by a stale plan", setting)
+        }
+
+        fn activate(&self, scheme: &str) -> io::Result<()> {
+            panic!("{} was activated by a stale plan", scheme)
+        }
+
+        fn delete(&self, scheme: &str) -> io::Result<()> {
+            panic!("{} was deleted by a stale plan", scheme)
+        }
+    }
+
+    impl BcdStore for Moved {
+        fn read(&self, elements: &[&BcdElement]) -> error::Result<Vec<Option<BcdValue>>> {
+            Ok(elements.iter().map(|_| Some(BcdValue::Boolean(true))).collect())
+        }
+
+        fn set(&self, element: &BcdElement, _value: &BcdValue) -> error::Result<()> {
+            panic!("{} was written by a stale plan", element.name)
+        }
+
+        fn delete(&self, element: &BcdElement) -> error::Result<()> {
+            panic!("{} was deleted by a stale plan", element.name)
+        }
+    }
+
+    fn step(change: Change) -> Step {
+        Step {
+            id: String::from("test"),
+            category: None,
+            change,
+        }
+    }
+
+    fn dword(value: u32) -> SavedData {
+        SavedData::from_value(&RegValue::dword(value))
+    }
+
+    // One step of every kind
+    fn every_change() -> Vec<Change> {
+        let hive = || String::from("HKLM");
+        let path = || String::from("SOFTWARE\\Test");
+        vec![
+            Change::CreateKey { hive: hive(), path: path() },
+            Change::SetValue {
+                hive: hive(),
+                path: path(),
+                name: String::from("Speed"),
+                value: dword(1),
+                current: Some(dword(5)),
+            },
+            Change::DeleteValue {
+                hive: hive(),
+                path: path(),
+                name: String::from("Speed"),
+                current: dword(5),
+            },
+            Change::DeleteKey { hive: hive(), path: path() },
+            Change::SetBcdElement {
+                setting: String::from("useplatformtick"),
+                value: true,
+                current: None,
+            },
+            Change::CreatePowerScheme {
+                scheme: registry::GAMING_SCHEME.to_string(),
+                base: HIGH_PERFORMANCE.to_string(),
+                name: registry::GAMING_SCHEME_NAME.to_string(),
+            },
+            Change::SetPowerIndex {
+                scheme: registry::GAMING_SCHEME.to_string(),
+                subgroup: SUB_PROCESSOR.to_string(),
+                setting: PROCTHROTTLEMIN.to_string(),
+                source: PowerSource::Dc,
+                value: 100,
+                current: Some(5),
+            },
+            Change::SetActivePowerScheme {
+                scheme: registry::GAMING_SCHEME.to_string(),
+                current: Some(BALANCED.to_string()),
+            },
+            Change::ShowPowerSetting {
+                subgroup: SUB_PROCESSOR.to_string(),
+                setting: PROCTHROTTLEMIN.to_string(),
+                current: 1,
+            },
+            Change::SetAudioProperty {
+                device: String::from("Speakers"),
+                property: String::from("FullRangeSpeakers"),
+                value: 0x3f,
+                current: 0x3,
+            },
+        ]
+    }
+
+    #[test]
+    fn a_saved_plan_loads_the_same() {
+        let mut plan = ChangePlan::new();
+        plan.steps = every_change().into_iter().map(step).collect();
+        let file = std::env::temp_dir().join(format!("gaming-optimizer-plan-{}.json", std::process::id()));
+        plan.save(&file).unwrap();
+        let loaded = ChangePlan::load(&file);
+        fs::remove_file(&file).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.to_json(), plan.to_json());
+        let json: serde_json::Value = serde_json::from_str(&plan.to_json()).unwrap();
+        let actions: Vec<&str> = json["steps"].as_array().unwrap().iter().map(|s| s["action"].as_str().unwrap()).collect();
+        assert_eq!(
+            actions,
+            vec![
+                "create_key",
+                "set_value",
+                "delete_value",
+                "delete_key",
+                "set_bcd_element",
+                "create_power_scheme",
+                "set_power_index",
+                "set_active_power_scheme",
+                "show_power_setting",
+                "set_audio_property",
+            ]
+        );
+        let targets = |plan: &ChangePlan| plan.steps.iter().map(|s| s.change.target()).collect::<Vec<_>>();
+        assert_eq!(targets(&loaded), targets(&plan));
+    }
+
+    #[test]
+    fn a_plan_of_another_version_is_refused() {
+        let file = std::env::temp_dir().join(format!("gaming-optimizer-version-{}.json", std::process::id()));
+        fs::write(&file, "{\"version\": 2, \"steps\": []}").unwrap();
+        let error = ChangePlan::load(&file).err().unwrap();
+        fs::remove_file(&file).unwrap();
+        assert_eq!(error.to_string(), "unsupported plan version 2");
+    }
+
+    #[test]
+    fn every_step_refuses_a_machine_that_moved_on() {
+        let backend = MemoryBackend::new();
+        set_speed(&backend, 7);
+        // Shown already, the plan expected it hidden
+        let transaction = backend.transaction().unwrap();
+        let attributes = format!("{}\\{}\\{}", registry::POWER_SETTINGS, SUB_PROCESSOR, PROCTHROTTLEMIN);
+        let key = backend
+            .create_subkey_transacted(&backend.predef(Hive::LocalMachine), &attributes, &transaction)
+            .unwrap();
+        backend.set_raw_value(&key, "Attributes", &RegValue::dword(0)).unwrap();
+        backend.commit(&transaction).unwrap();
+
+        let mut stale = every_change();
+        // Planned before Speed was written at all
+        stale.push(Change::SetValue {
+            hive: String::from("HKLM"),
+            path: String::from("SOFTWARE\\Test"),
+            name: String::from("Speed"),
+            value: dword(1),
+            current: None,
+        });
+        for change in stale {
+            // Creating or deleting a key has nothing to compare, the audio device is only read on Windows
+            if let Change::CreateKey { .. } | Change::DeleteKey { .. } | Change::SetAudioProperty { .. } = change {
+                continue;
+            }
+            let plan = ChangePlan {
+                version: VERSION,
+                steps: vec![step(change)],
+            };
+            let execution = execute_with(&backend, &Moved, &Moved, &plan, None);
+            let result = &execution.results[0];
+            assert!(
+                matches!(result.outcome, StepOutcome::Failed(Error::Changed)),
+                "{}",
+                result.step.change.target()
+            );
+        }
+        assert_eq!(speed(&backend), Some(7));
+    }
+
+    #[test]
+    fn a_failed_registry_write_rolls_back_and_skips_the_rest() {
+        let backend = MemoryBackend::new();
+        let mut plan = plan_of(&backend);
+        // The registry steps come first, the others run after the commit
+        let later: Vec<Step> = every_change().into_iter().filter(|c| !c.is_registry()).map(step).collect();
+        plan.steps.extend(later);
+        backend.deny(Hive::CurrentUser, "Software\\Test\\Deep");
+
+        let execution = execute_with(&backend, &Moved, &Moved, &plan, None);
+        assert!(execution.failed());
+        let outcomes: Vec<(String, &str)> = execution
+            .results
+            .iter()
+            .map(|r| {
+                let outcome = match r.outcome {
+                    StepOutcome::Done => "done",
+                    StepOutcome::Failed(_) => "failed",
+                    StepOutcome::RolledBack => "rolled back",
+                    StepOutcome::Skipped => "skipped",
+                };
+                (r.step.change.target(), outcome)
+            })
+            .collect();
+        assert_eq!(
+            outcomes[..3],
+            [
+                (String::from("HKLM\\SOFTWARE\\Test"), "rolled back"),
+                (String::from("HKLM\\SOFTWARE\\Test\\Speed"), "rolled back"),
+                (String::from("HKCU\\Software\\Test\\Deep"), "failed"),
+            ]
+        );
+        // The value after the failed step is not written, the boot, power and audio steps do not run
+        assert!(outcomes[3..].iter().all(|(_, outcome)| *outcome == "skipped"), "{:?}", outcomes);
+        assert_eq!(outcomes.len(), 10);
+        let rollback = execution.rollback.as_deref().unwrap();
+        assert!(rollback.starts_with("step 3 failed: HKCU\\Software\\Test\\Deep: "), "{}", rollback);
+        assert_eq!(speed(&backend), None);
+        assert!(backend.open_subkey(&backend.predef(Hive::LocalMachine), "SOFTWARE\\Test").is_err());
+    }
+
+    #[test]
+    fn the_diff_and_a_narrower_selection() {
+        let backend = MemoryBackend::new();
+        let mut plan = plan_of(&backend);
+        let useplatformtick = BcdCheck {
+            setting: "useplatformtick",
+            current: None,
+            desired: true,
+        };
+        let disabledynamictick = BcdCheck {
+            setting: "disabledynamictick",
+            current: Some(true),
+            desired: true,
+        };
+        plan.add_bcd(&[useplatformtick, disabledynamictick]);
+        plan.push(
+            (String::from("profile-games"), Some(Category::Scheduler)),
+            Change::SetValue {
+                hive: String::from("HKLM"),
+                path: perf_options::perf_options_path("game.exe"),
+                name: String::from(perf_options::CPU_PRIORITY_CLASS),
+                value: dword(3),
+                current: Some(dword(2)),
+            },
+        );
+        assert_eq!(plan.ids(), vec!["speed", "name", "bcd-useplatformtick", "profile-games"]);
+        let lines: Vec<String> = plan.steps.iter().map(|s| s.change.diff_line()).collect();
+        assert_eq!(lines[0], "\x1b[0;92m+ HKLM\\SOFTWARE\\Test\x1b[0m");
+        assert_eq!(lines[1], "\x1b[0;92m+ HKLM\\SOFTWARE\\Test\\Speed = dword:1\x1b[0m");
+        assert_eq!(lines[4], "\x1b[0;92m+ BCD\\{current}\\useplatformtick = Yes\x1b[0m");
+        // PerfOptions values are named like in the check
+        let priority = "\\game.exe\\PerfOptions\\CpuPriorityClass = dword:2 (Normal) -> dword:3 (High)\x1b[0m";
+        assert!(lines[5].ends_with(priority), "{}", lines[5]);
+
+        // The created key goes with the value that needed it
+        plan.retain(&Selection::new(vec![String::from("name"), String::from("boot")], Vec::new()));
+        let targets: Vec<String> = plan.steps.iter().map(|s| s.change.target()).collect();
+        assert_eq!(
+            targets,
+            vec!["HKCU\\Software\\Test\\Deep", "HKCU\\Software\\Test\\Deep\\Name", "BCD\\{current}\\useplatformtick"]
+        );
+    }
+}
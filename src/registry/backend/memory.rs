@@ -0,0 +1,446 @@
+use super::regf::KeyMeta;
+use super::{Hive, RegValue, RegistryBackend};
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io;
+
+// Key and value names are case-insensitive like in the real registry, the
+// original spelling is kept for enumeration.
+#[derive(Clone, Default)]
+pub(super) struct Node {
+    pub(super) name: String,
+    pub(super) values: Vec<(String, RegValue)>,
+    pub(super) subkeys: BTreeMap<String, Node>,
+    // What a hive file stores besides the values, kept so saving it loses nothing
+    pub(super) meta: KeyMeta,
+}
+
+impl Node {
+    fn find(&self, path: &[String]) -> Option<&Node> {
+        match path.split_first() {
+            None => Some(self),
+            Some((first, rest)) => self.subkeys.get(&first.to_lowercase())?.find(rest),
+        }
+    }
+
+    fn find_mut(&mut self, path: &[String]) -> Option<&mut Node> {
+        match path.split_first() {
+            None => Some(self),
+            Some((first, rest)) => self.subkeys.get_mut(&first.to_lowercase())?.find_mut(rest),
+        }
+    }
+
+    fn create(&mut self, path: &[String]) -> &mut Node {
+        match path.split_first() {
+            None => self,
+            Some((first, rest)) => self
+                .subkeys
+                .entry(first.to_lowercase())
+                .or_insert_with(|| Node {
+                    name: first.clone(),
+                    ..Node::default()
+                })
+                .create(rest),
+        }
+    }
+
+    fn value_index(&self, name: &str) -> Option<usize> {
+        self.values
+            .iter()
+            .position(|(n, _)| n.eq_ignore_ascii_case(name))
+    }
+}
+
+type Tree = BTreeMap<Hive, Node>;
+
+enum Op {
+    CreateKey(Hive, Vec<String>),
+    SetValue(Hive, Vec<String>, String, RegValue),
+    DeleteValue(Hive, Vec<String>, String),
+    DeleteKey(Hive, Vec<String>),
+}
+
+impl Op {
+    fn replay(self, tree: &mut Tree) {
+        match self {
+            Op::CreateKey(hive, path) => {
+                tree.entry(hive).or_default().create(&path);
+            }
+            Op::SetValue(hive, path, name, value) => {
+                set_value(tree.entry(hive).or_default().create(&path), &name, value)
+            }
+            Op::DeleteValue(hive, path, name) => {
+                if let Some(node) = tree.entry(hive).or_default().find_mut(&path) {
+                    remove_value(node, &name);
+                }
+            }
+            Op::DeleteKey(hive, path) => {
+                let _ = remove_key(tree.entry(hive).or_default(), &path);
+            }
+        }
+    }
+}
+
+// A transaction works on its own copy of the hives and records what it did,
+// the log is replayed onto the shared hives on commit.
+struct Pending {
+    tree: Tree,
+    log: Vec<Op>,
+}
+
+#[derive(Clone)]
+pub struct MemoryKey {
+    pub(super) hive: Hive,
+    pub(super) path: Vec<String>,
+    pub(super) transaction: Option<usize>,
+}
+
+pub struct MemoryTransaction(pub(super) usize);
+
+#[derive(Default)]
+pub struct MemoryBackend {
+    hives: RefCell<Tree>,
+    transactions: RefCell<Vec<Option<Pending>>>,
+    // Keys that can be read but not written, with everything below them
+    denied: RefCell<Vec<(Hive, Vec<String>)>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> MemoryBackend {
+        MemoryBackend::default()
+    }
+
+    // Makes writes below `path` fail like a key without write access
+    #[cfg(test)]
+    pub fn deny(&self, hive: Hive, path: &str) {
+        let path = path.split('\\').filter(|p| !p.is_empty()).map(str::to_lowercase).collect();
+        self.denied.borrow_mut().push((hive, path));
+    }
+
+    // The native backend opens keys outside of a transaction for reading only
+    fn takes_values(&self, key: &MemoryKey) -> io::Result<()> {
+        match key.transaction {
+            Some(_) => self.writable(key),
+            None => Err(access_denied()),
+        }
+    }
+
+    fn writable(&self, key: &MemoryKey) -> io::Result<()> {
+        let denied = self.denied.borrow().iter().any(|(hive, path)| {
+            *hive == key.hive
+                && key.path.len() >= path.len()
+                && key.path.iter().zip(path.iter()).all(|(a, b)| a.to_lowercase() == *b)
+        });
+        match denied {
+            true => Err(access_denied()),
+            false => Ok(()),
+        }
+    }
+
+    // Puts a whole key, e.g. the root of a hive file, at `path`
+    pub(super) fn mount(&self, hive: Hive, path: &[String], node: Node) {
+        let mut hives = self.hives.borrow_mut();
+        match path.split_last() {
+            Some((last, parent)) => {
+                let parent = hives.entry(hive).or_default().create(parent);
+                parent.subkeys.insert(last.to_lowercase(), node);
+            }
+            None => {
+                hives.insert(hive, node);
+            }
+        }
+    }
+
+    pub(super) fn with_node<R>(&self, hive: Hive, path: &[String], f: impl FnOnce(&Node) -> R) -> Option<R> {
+        self.hives.borrow().get(&hive)?.find(path).map(f)
+    }
+
+    fn with_tree<R>(&self, key: &MemoryKey, f: impl FnOnce(&mut Tree) -> R) -> io::Result<R> {
+        match key.transaction {
+            None => Ok(f(&mut self.hives.borrow_mut())),
+            Some(id) => match self.transactions.borrow_mut().get_mut(id) {
+                Some(Some(pending)) => Ok(f(&mut pending.tree)),
+                _ => Err(finished()),
+            },
+        }
+    }
+
+    fn log(&self, key: &MemoryKey, op: Op) {
+        if let Some(id) = key.transaction {
+            if let Some(Some(pending)) = self.transactions.borrow_mut().get_mut(id) {
+                pending.log.push(op);
+            }
+        }
+    }
+
+    fn finish(&self, transaction: &MemoryTransaction) -> io::Result<Pending> {
+        self.transactions
+            .borrow_mut()
+            .get_mut(transaction.0)
+            .and_then(Option::take)
+            .ok_or_else(finished)
+    }
+}
+
+impl RegistryBackend for MemoryBackend {
+    type Key = MemoryKey;
+    type Transaction = MemoryTransaction;
+
+    fn predef(&self, hive: Hive) -> MemoryKey {
+        MemoryKey {
+            hive,
+            path: Vec::new(),
+            transaction: None,
+        }
+    }
+
+    fn open_subkey(&self, key: &MemoryKey, path: &str) -> io::Result<MemoryKey> {
+        let sub = join(key, path);
+        let exists = self.with_tree(key, |tree| {
+            tree.entry(key.hive).or_default().find(&sub.path).is_some()
+        })?;
+        if exists {
+            Ok(sub)
+        } else {
+            Err(not_found())
+        }
+    }
+
+    fn create_subkey_transacted(
+        &self,
+        key: &MemoryKey,
+        path: &str,
+        transaction: &MemoryTransaction,
+    ) -> io::Result<MemoryKey> {
+        let mut sub = join(key, path);
+        sub.transaction = Some(transaction.0);
+        self.writable(&sub)?;
+        self.with_tree(&sub, |tree| {
+            tree.entry(sub.hive).or_default().create(&sub.path);
+        })?;
+        self.log(&sub, Op::CreateKey(sub.hive, sub.path.clone()));
+        Ok(sub)
+    }
+
+    fn open_subkey_transacted(
+        &self,
+        key: &MemoryKey,
+        path: &str,
+        transaction: &MemoryTransaction,
+    ) -> io::Result<MemoryKey> {
+        let mut key = key.clone();
+        key.transaction = Some(transaction.0);
+        self.open_subkey(&key, path)
+    }
+
+    fn delete_subkey(&self, key: &MemoryKey, path: &str) -> io::Result<()> {
+        let sub = join(key, path);
+        self.writable(&sub)?;
+        self.with_tree(key, |tree| remove_key(tree.entry(key.hive).or_default(), &sub.path))??;
+        self.log(key, Op::DeleteKey(sub.hive, sub.path));
+        Ok(())
+    }
+
+    fn delete_subkey_transacted(&self, key: &MemoryKey, path: &str, transaction: &MemoryTransaction) -> io::Result<()> {
+        let mut key = key.clone();
+        key.transaction = Some(transaction.0);
+        self.delete_subkey(&key, path)
+    }
+
+    fn get_raw_value(&self, key: &MemoryKey, name: &str) -> io::Result<RegValue> {
+        self.with_tree(key, |tree| {
+            let node = tree.entry(key.hive).or_default().find(&key.path)?;
+            node.value_index(name).map(|i| node.values[i].1.clone())
+        })?
+        .ok_or_else(not_found)
+    }
+
+    fn set_raw_value(&self, key: &MemoryKey, name: &str, value: &RegValue) -> io::Result<()> {
+        self.takes_values(key)?;
+        self.with_tree(key, |tree| {
+            tree.entry(key.hive)
+                .or_default()
+                .find_mut(&key.path)
+                .map(|node| set_value(node, name, value.clone()))
+        })?
+        .ok_or_else(not_found)?;
+        self.log(
+            key,
+            Op::SetValue(key.hive, key.path.clone(), name.to_string(), value.clone()),
+        );
+        Ok(())
+    }
+
+    fn delete_value(&self, key: &MemoryKey, name: &str) -> io::Result<()> {
+        self.takes_values(key)?;
+        self.with_tree(key, |tree| {
+            let node = tree.entry(key.hive).or_default().find_mut(&key.path)?;
+            remove_value(node, name)
+        })?
+        .ok_or_else(not_found)?;
+        self.log(
+            key,
+            Op::DeleteValue(key.hive, key.path.clone(), name.to_string()),
+        );
+        Ok(())
+    }
+
+    fn enum_keys(&self, key: &MemoryKey) -> io::Result<Vec<String>> {
+        self.with_tree(key, |tree| {
+            let node = tree.entry(key.hive).or_default().find(&key.path)?;
+            Some(node.subkeys.values().map(|n| n.name.clone()).collect())
+        })?
+        .ok_or_else(not_found)
+    }
+
+    fn enum_values(&self, key: &MemoryKey) -> io::Result<Vec<(String, RegValue)>> {
+        self.with_tree(key, |tree| {
+            let node = tree.entry(key.hive).or_default().find(&key.path)?;
+            Some(node.values.clone())
+        })?
+        .ok_or_else(not_found)
+    }
+
+    fn transaction(&self) -> io::Result<MemoryTransaction> {
+        let mut transactions = self.transactions.borrow_mut();
+        transactions.push(Some(Pending {
+            tree: self.hives.borrow().clone(),
+            log: Vec::new(),
+        }));
+        Ok(MemoryTransaction(transactions.len() - 1))
+    }
+
+    fn commit(&self, transaction: &MemoryTransaction) -> io::Result<()> {
+        let pending = self.finish(transaction)?;
+        let mut hives = self.hives.borrow_mut();
+        for op in pending.log {
+            op.replay(&mut hives);
+        }
+        Ok(())
+    }
+
+    fn rollback(&self, transaction: &MemoryTransaction) -> io::Result<()> {
+        self.finish(transaction).map(|_| ())
+    }
+}
+
+fn set_value(node: &mut Node, name: &str, value: RegValue) {
+    match node.value_index(name) {
+        Some(index) => node.values[index].1 = value,
+        None => node.values.push((name.to_string(), value)),
+    }
+    node.meta.touch();
+}
+
+fn remove_value(node: &mut Node, name: &str) -> Option<()> {
+    let index = node.value_index(name)?;
+    node.values.remove(index);
+    node.meta.touch();
+    Some(())
+}
+
+fn remove_key(root: &mut Node, path: &[String]) -> io::Result<()> {
+    let (last, parent) = path.split_last().ok_or_else(not_found)?;
+    let parent = root.find_mut(parent).ok_or_else(not_found)?;
+    match parent.subkeys.get(&last.to_lowercase()) {
+        None => Err(not_found()),
+        Some(node) if !node.subkeys.is_empty() => Err(access_denied()),
+        Some(_) => {
+            parent.subkeys.remove(&last.to_lowercase());
+            Ok(())
+        }
+    }
+}
+
+fn join(key: &MemoryKey, path: &str) -> MemoryKey {
+    let mut joined = key.clone();
+    joined
+        .path
+        .extend(path.split('\\').filter(|p| !p.is_empty()).map(String::from));
+    joined
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "The system cannot find the file specified.")
+}
+
+fn access_denied() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "Access is denied.")
+}
+
+fn finished() -> io::Error {
+    io::Error::other("transaction already committed or rolled back")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_transaction_is_only_seen_after_the_commit() {
+        let backend = MemoryBackend::new();
+        let hklm = backend.predef(Hive::LocalMachine);
+        let transaction = backend.transaction().unwrap();
+        let key = backend.create_subkey_transacted(&hklm, "SOFTWARE\\Test", &transaction).unwrap();
+        backend.set_raw_value(&key, "Value", &RegValue::dword(1)).unwrap();
+        assert_eq!(backend.get_u32(&key, "value").unwrap(), 1);
+        assert!(backend.open_subkey(&hklm, "SOFTWARE\\Test").is_err());
+
+        backend.commit(&transaction).unwrap();
+        let key = backend.open_subkey(&hklm, "software\\test").unwrap();
+        assert_eq!(backend.get_u32(&key, "Value").unwrap(), 1);
+        assert_eq!(backend.enum_keys(&backend.open_subkey(&hklm, "SOFTWARE").unwrap()).unwrap(), vec!["Test"]);
+        assert!(backend.commit(&transaction).is_err());
+    }
+
+    #[test]
+    fn a_rolled_back_transaction_changes_nothing() {
+        let backend = MemoryBackend::new();
+        let hklm = backend.predef(Hive::LocalMachine);
+        let setup = backend.transaction().unwrap();
+        let key = backend.create_subkey_transacted(&hklm, "SOFTWARE\\Test", &setup).unwrap();
+        backend.set_raw_value(&key, "Kept", &RegValue::sz("old")).unwrap();
+        backend.commit(&setup).unwrap();
+
+        let transaction = backend.transaction().unwrap();
+        let key = backend.open_subkey_transacted(&hklm, "SOFTWARE\\Test", &transaction).unwrap();
+        backend.delete_value(&key, "Kept").unwrap();
+        backend.create_subkey_transacted(&hklm, "SOFTWARE\\Test\\New", &transaction).unwrap();
+        backend.rollback(&transaction).unwrap();
+
+        let key = backend.open_subkey(&hklm, "SOFTWARE\\Test").unwrap();
+        assert_eq!(backend.get_string(&key, "Kept").unwrap(), "old");
+        assert!(backend.enum_keys(&key).unwrap().is_empty());
+    }
+
+    #[test]
+    fn only_keys_of_a_transaction_take_values() {
+        let backend = MemoryBackend::new();
+        let hklm = backend.predef(Hive::LocalMachine);
+        let transaction = backend.transaction().unwrap();
+        let key = backend.create_subkey_transacted(&hklm, "SOFTWARE\\Test", &transaction).unwrap();
+        backend.set_raw_value(&key, "Value", &RegValue::dword(1)).unwrap();
+        backend.commit(&transaction).unwrap();
+
+        let key = backend.open_subkey(&hklm, "SOFTWARE\\Test").unwrap();
+        let error = backend.set_raw_value(&key, "Value", &RegValue::dword(2)).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        let error = backend.delete_value(&key, "Value").err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(backend.get_u32(&key, "Value").unwrap(), 1);
+    }
+
+    #[test]
+    fn a_key_with_subkeys_is_not_deleted() {
+        let backend = MemoryBackend::new();
+        let hklm = backend.predef(Hive::LocalMachine);
+        let transaction = backend.transaction().unwrap();
+        backend.create_subkey_transacted(&hklm, "SOFTWARE\\Test\\Sub", &transaction).unwrap();
+        backend.commit(&transaction).unwrap();
+        let error = backend.delete_subkey(&hklm, "SOFTWARE\\Test").err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        backend.delete_subkey(&hklm, "SOFTWARE\\Test\\Sub").unwrap();
+        backend.delete_subkey(&hklm, "SOFTWARE\\Test").unwrap();
+    }
+}
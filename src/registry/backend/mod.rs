@@ -0,0 +1,418 @@
+// Everything that touches the registry goes through `RegistryBackend`, so the
+// check/apply/restore logic can run against the live registry on Windows, a
+// hive file, or an in-memory hive everywhere else.
+
+// The Windows binary talks to the live registry or to hive files, a hive file
+// is read into the in-memory hive, which the tests also use on their own.
+mod memory;
+#[cfg(windows)]
+mod native;
+mod regf;
+
+#[cfg(test)]
+pub use memory::MemoryBackend;
+#[cfg(windows)]
+pub use native::NativeBackend;
+#[cfg(windows)]
+pub use regf::HiveBackend;
+pub use regf::HiveMount;
+
+use crate::error::Error;
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Hive {
+    LocalMachine,
+    CurrentUser,
+    Users,
+}
+
+impl Hive {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hive::LocalMachine => "HKEY_LOCAL_MACHINE",
+            Hive::CurrentUser => "HKEY_CURRENT_USER",
+            Hive::Users => "HKEY_USERS",
+        }
+    }
+
+    pub fn short_name(&self) -> &'static str {
+        match self {
+            Hive::LocalMachine => "HKLM",
+            Hive::CurrentUser => "HKCU",
+            Hive::Users => "HKU",
+        }
+    }
+}
+
+impl fmt::Display for Hive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// Accepts the short (HKLM) and the long (HKEY_LOCAL_MACHINE) spelling
+impl FromStr for Hive {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Hive, String> {
+        match s.to_ascii_uppercase().as_str() {
+            "HKLM" | "HKEY_LOCAL_MACHINE" => Ok(Hive::LocalMachine),
+            "HKCU" | "HKEY_CURRENT_USER" => Ok(Hive::CurrentUser),
+            "HKU" | "HKEY_USERS" => Ok(Hive::Users),
+            _ => Err(format!("unknown hive `{}`, expected HKLM, HKCU or HKU", s)),
+        }
+    }
+}
+
+// A key below one of the root keys, e.g. HKLM\SYSTEM\CurrentControlSet.
+// Key names are case-insensitive, so are the comparisons.
+#[derive(Clone, Debug)]
+pub struct RegPath {
+    pub hive: Hive,
+    pub subkey: String,
+}
+
+impl RegPath {
+    pub fn new(hive: Hive, subkey: &str) -> RegPath {
+        RegPath {
+            hive,
+            subkey: subkey.trim_matches('\\').to_string(),
+        }
+    }
+
+    // A key below this one
+    pub fn join(&self, subkey: &str) -> RegPath {
+        RegPath::new(self.hive, &format!("{}\\{}", self.subkey, subkey.trim_matches('\\')))
+    }
+
+    // The second part of HKLM\SYSTEM\<control set>\..., if the path has one
+    fn control_set(&self) -> Option<&str> {
+        let mut parts = self.subkey.split('\\');
+        match (self.hive, parts.next()) {
+            (Hive::LocalMachine, Some(system)) if system.eq_ignore_ascii_case("SYSTEM") => parts.next(),
+            _ => None,
+        }
+    }
+
+    // The NNN of a path that names SYSTEM\ControlSetNNN instead of CurrentControlSet.
+    // Windows boots from one of several control sets, a pinned one may not be in use.
+    pub fn pinned_control_set(&self) -> Option<u32> {
+        let set = self.control_set()?;
+        match set.get(..10) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("ControlSet") => set[10..].parse().ok(),
+            _ => None,
+        }
+    }
+
+    // CurrentControlSet is a link the kernel creates at boot to the control set
+    // SYSTEM\Select\Current names, a SYSTEM hive file only has the ControlSetNNN keys
+    pub fn resolve_control_set(&self, current: u32) -> RegPath {
+        match self.control_set() {
+            Some(set) if set.eq_ignore_ascii_case(CURRENT_CONTROL_SET) => {
+                let rest = &self.subkey["SYSTEM\\".len() + set.len()..];
+                RegPath::new(self.hive, &format!("SYSTEM\\ControlSet{:03}{}", current, rest))
+            }
+            _ => self.clone(),
+        }
+    }
+}
+
+const CURRENT_CONTROL_SET: &str = "CurrentControlSet";
+
+// The control set CurrentControlSet links to, from HKLM\SYSTEM\Select
+pub fn current_control_set<B: RegistryBackend>(backend: &B) -> io::Result<u32> {
+    let select = backend.open_subkey(&backend.predef(Hive::LocalMachine), "SYSTEM\\Select")?;
+    backend.get_u32(&select, "Current")
+}
+
+impl PartialEq for RegPath {
+    fn eq(&self, other: &RegPath) -> bool {
+        self.hive == other.hive && self.subkey.eq_ignore_ascii_case(&other.subkey)
+    }
+}
+
+impl fmt::Display for RegPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}\\{}", self.hive, self.subkey)
+    }
+}
+
+// HKLM\SYSTEM\... or HKEY_LOCAL_MACHINE\SYSTEM\..., a root key alone is not a path
+impl FromStr for RegPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<RegPath, String> {
+        let s = s.trim_matches('\\');
+        match s.split_once('\\') {
+            Some((hive, subkey)) if !subkey.trim_matches('\\').is_empty() => Ok(RegPath::new(hive.parse()?, subkey)),
+            _ => Err(format!("`{}` is a root key, a subkey is needed", s)),
+        }
+    }
+}
+
+// Same names and numbering as winreg::enums::RegType
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegType {
+    REG_NONE = 0,
+    REG_SZ = 1,
+    REG_EXPAND_SZ = 2,
+    REG_BINARY = 3,
+    REG_DWORD = 4,
+    REG_DWORD_BIG_ENDIAN = 5,
+    REG_LINK = 6,
+    REG_MULTI_SZ = 7,
+    REG_RESOURCE_LIST = 8,
+    REG_FULL_RESOURCE_DESCRIPTOR = 9,
+    REG_RESOURCE_REQUIREMENTS_LIST = 10,
+    REG_QWORD = 11,
+}
+
+impl RegType {
+    pub fn from_u32(vtype: u32) -> Option<RegType> {
+        Some(match vtype {
+            0 => RegType::REG_NONE,
+            1 => RegType::REG_SZ,
+            2 => RegType::REG_EXPAND_SZ,
+            3 => RegType::REG_BINARY,
+            4 => RegType::REG_DWORD,
+            5 => RegType::REG_DWORD_BIG_ENDIAN,
+            6 => RegType::REG_LINK,
+            7 => RegType::REG_MULTI_SZ,
+            8 => RegType::REG_RESOURCE_LIST,
+            9 => RegType::REG_FULL_RESOURCE_DESCRIPTOR,
+            10 => RegType::REG_RESOURCE_REQUIREMENTS_LIST,
+            11 => RegType::REG_QWORD,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegValue {
+    pub bytes: Vec<u8>,
+    pub vtype: RegType,
+}
+
+impl RegValue {
+    pub fn dword(value: u32) -> RegValue {
+        RegValue {
+            bytes: value.to_le_bytes().to_vec(),
+            vtype: RegType::REG_DWORD,
+        }
+    }
+
+    pub fn qword(value: u64) -> RegValue {
+        RegValue {
+            bytes: value.to_le_bytes().to_vec(),
+            vtype: RegType::REG_QWORD,
+        }
+    }
+
+    pub fn sz(value: &str) -> RegValue {
+        RegValue {
+            bytes: utf16(&[value]),
+            vtype: RegType::REG_SZ,
+        }
+    }
+
+    pub fn expand_sz(value: &str) -> RegValue {
+        RegValue {
+            bytes: utf16(&[value]),
+            vtype: RegType::REG_EXPAND_SZ,
+        }
+    }
+
+    // Every string ends with a NUL, the list with one more
+    pub fn multi_sz(values: &[String]) -> RegValue {
+        let mut strings: Vec<&str> = values.iter().map(String::as_str).collect();
+        strings.push("");
+        RegValue {
+            bytes: utf16(&strings),
+            vtype: RegType::REG_MULTI_SZ,
+        }
+    }
+
+    pub fn binary(value: Vec<u8>) -> RegValue {
+        RegValue {
+            bytes: value,
+            vtype: RegType::REG_BINARY,
+        }
+    }
+
+    pub fn as_u32(&self) -> io::Result<u32> {
+        match self.vtype {
+            RegType::REG_DWORD if self.bytes.len() == 4 => {
+                let mut raw = [0u8; 4];
+                raw.copy_from_slice(&self.bytes);
+                Ok(u32::from_le_bytes(raw))
+            }
+            _ => Err(Error::type_mismatch(RegType::REG_DWORD, self.vtype)),
+        }
+    }
+
+    pub fn as_u64(&self) -> io::Result<u64> {
+        match self.vtype {
+            RegType::REG_QWORD if self.bytes.len() == 8 => {
+                let mut raw = [0u8; 8];
+                raw.copy_from_slice(&self.bytes);
+                Ok(u64::from_le_bytes(raw))
+            }
+            _ => Err(Error::type_mismatch(RegType::REG_QWORD, self.vtype)),
+        }
+    }
+
+    pub fn as_string(&self) -> io::Result<String> {
+        match self.vtype {
+            RegType::REG_SZ | RegType::REG_EXPAND_SZ => {
+                let mut s = self.utf16_lossy();
+                while s.ends_with('\u{0}') {
+                    s.pop();
+                }
+                Ok(s)
+            }
+            _ => Err(Error::type_mismatch(RegType::REG_SZ, self.vtype)),
+        }
+    }
+
+    // The strings up to the empty one that ends the list
+    pub fn as_multi_string(&self) -> io::Result<Vec<String>> {
+        match self.vtype {
+            RegType::REG_MULTI_SZ => Ok(self
+                .utf16_lossy()
+                .split('\u{0}')
+                .take_while(|s| !s.is_empty())
+                .map(String::from)
+                .collect()),
+            _ => Err(Error::type_mismatch(RegType::REG_MULTI_SZ, self.vtype)),
+        }
+    }
+
+    // Strings are compared without the NULs they end with, which not every
+    // program writes, everything else byte by byte
+    pub fn same_data(&self, other: &RegValue) -> bool {
+        if self.vtype != other.vtype {
+            return false;
+        }
+        match self.vtype {
+            RegType::REG_SZ | RegType::REG_EXPAND_SZ => self.as_string().ok() == other.as_string().ok(),
+            RegType::REG_MULTI_SZ => self.as_multi_string().ok() == other.as_multi_string().ok(),
+            _ => self.bytes == other.bytes,
+        }
+    }
+
+    fn utf16_lossy(&self) -> String {
+        let words: Vec<u16> = self
+            .bytes
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        String::from_utf16_lossy(&words)
+    }
+}
+
+// UTF-16LE with a NUL after every string
+fn utf16(strings: &[&str]) -> Vec<u8> {
+    strings
+        .iter()
+        .flat_map(|s| s.encode_utf16().chain(Some(0)))
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect()
+}
+
+impl fmt::Display for RegValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Ok(value) = self.as_u32() {
+            return write!(f, "dword:{}", value);
+        }
+        if let Ok(value) = self.as_u64() {
+            return write!(f, "qword:{}", value);
+        }
+        match self.vtype {
+            RegType::REG_SZ => return write!(f, "sz:{}", self.as_string().unwrap_or_default()),
+            RegType::REG_EXPAND_SZ => return write!(f, "expand_sz:{}", self.as_string().unwrap_or_default()),
+            RegType::REG_MULTI_SZ => {
+                return write!(f, "multi_sz:{}", self.as_multi_string().unwrap_or_default().join("|"))
+            }
+            _ => (),
+        }
+        let hex: Vec<String> = self.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        match self.vtype {
+            RegType::REG_BINARY => write!(f, "hex:{}", hex.join(",")),
+            vtype => write!(f, "hex({}):{}", vtype as u32, hex.join(",")),
+        }
+    }
+}
+
+pub trait RegistryBackend {
+    type Key;
+    type Transaction;
+
+    fn predef(&self, hive: Hive) -> Self::Key;
+    fn open_subkey(&self, key: &Self::Key, path: &str) -> io::Result<Self::Key>;
+    fn create_subkey_transacted(
+        &self,
+        key: &Self::Key,
+        path: &str,
+        transaction: &Self::Transaction,
+    ) -> io::Result<Self::Key>;
+    // Everything done through the returned key is part of the transaction
+    fn open_subkey_transacted(
+        &self,
+        key: &Self::Key,
+        path: &str,
+        transaction: &Self::Transaction,
+    ) -> io::Result<Self::Key>;
+    // Removes an empty subkey, keys that still have subkeys are left alone
+    fn delete_subkey(&self, key: &Self::Key, path: &str) -> io::Result<()>;
+    fn delete_subkey_transacted(&self, key: &Self::Key, path: &str, transaction: &Self::Transaction) -> io::Result<()>;
+
+    fn get_raw_value(&self, key: &Self::Key, name: &str) -> io::Result<RegValue>;
+    fn set_raw_value(&self, key: &Self::Key, name: &str, value: &RegValue) -> io::Result<()>;
+    fn delete_value(&self, key: &Self::Key, name: &str) -> io::Result<()>;
+    fn enum_keys(&self, key: &Self::Key) -> io::Result<Vec<String>>;
+    fn enum_values(&self, key: &Self::Key) -> io::Result<Vec<(String, RegValue)>>;
+
+    fn transaction(&self) -> io::Result<Self::Transaction>;
+    fn commit(&self, transaction: &Self::Transaction) -> io::Result<()>;
+    fn rollback(&self, transaction: &Self::Transaction) -> io::Result<()>;
+
+    fn get_u32(&self, key: &Self::Key, name: &str) -> io::Result<u32> {
+        self.get_raw_value(key, name)?.as_u32()
+    }
+
+    fn get_string(&self, key: &Self::Key, name: &str) -> io::Result<String> {
+        self.get_raw_value(key, name)?.as_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_a_numbered_control_set_is_pinned() {
+        let path = |hive: Hive, subkey: &str| RegPath::new(hive, subkey);
+        assert_eq!(path(Hive::LocalMachine, "SYSTEM\\ControlSet001\\Control").pinned_control_set(), Some(1));
+        assert_eq!(path(Hive::LocalMachine, "system\\controlset003").pinned_control_set(), Some(3));
+        assert_eq!(path(Hive::LocalMachine, "SYSTEM\\CurrentControlSet\\Control").pinned_control_set(), None);
+        assert_eq!(path(Hive::LocalMachine, "SOFTWARE\\ControlSet001").pinned_control_set(), None);
+        assert_eq!(path(Hive::CurrentUser, "SYSTEM\\ControlSet001").pinned_control_set(), None);
+        assert_eq!(path(Hive::LocalMachine, "SYSTEM\\ControlSetX").pinned_control_set(), None);
+    }
+
+    #[test]
+    fn current_control_set_resolves_to_the_one_in_use() {
+        let current = RegPath::new(Hive::LocalMachine, "SYSTEM\\CurrentControlSet\\Control\\Test");
+        assert_eq!(
+            current.resolve_control_set(2),
+            RegPath::new(Hive::LocalMachine, "SYSTEM\\ControlSet002\\Control\\Test")
+        );
+        let pinned = RegPath::new(Hive::LocalMachine, "SYSTEM\\ControlSet001\\Control");
+        assert_eq!(pinned.resolve_control_set(2), pinned);
+    }
+}
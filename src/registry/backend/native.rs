@@ -0,0 +1,129 @@
+use super::{Hive, RegType, RegValue, RegistryBackend};
+
+use std::io;
+use winreg::enums::{self, HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, HKEY_USERS, KEY_ALL_ACCESS, KEY_READ};
+use winreg::transaction::Transaction;
+use winreg::RegKey;
+
+// The live registry through winreg
+pub struct NativeBackend;
+
+impl RegistryBackend for NativeBackend {
+    type Key = RegKey;
+    type Transaction = Transaction;
+
+    fn predef(&self, hive: Hive) -> RegKey {
+        RegKey::predef(match hive {
+            Hive::LocalMachine => HKEY_LOCAL_MACHINE,
+            Hive::CurrentUser => HKEY_CURRENT_USER,
+            Hive::Users => HKEY_USERS,
+        })
+    }
+
+    fn open_subkey(&self, key: &RegKey, path: &str) -> io::Result<RegKey> {
+        key.open_subkey_with_flags(path, KEY_READ)
+    }
+
+    fn create_subkey_transacted(
+        &self,
+        key: &RegKey,
+        path: &str,
+        transaction: &Transaction,
+    ) -> io::Result<RegKey> {
+        let (key, _) = key.create_subkey_transacted_with_flags(path, transaction, KEY_ALL_ACCESS)?;
+        Ok(key)
+    }
+
+    fn open_subkey_transacted(
+        &self,
+        key: &RegKey,
+        path: &str,
+        transaction: &Transaction,
+    ) -> io::Result<RegKey> {
+        key.open_subkey_transacted_with_flags(path, transaction, KEY_ALL_ACCESS)
+    }
+
+    fn delete_subkey(&self, key: &RegKey, path: &str) -> io::Result<()> {
+        key.delete_subkey(path)
+    }
+
+    fn delete_subkey_transacted(&self, key: &RegKey, path: &str, transaction: &Transaction) -> io::Result<()> {
+        key.delete_subkey_transacted(path, transaction)
+    }
+
+    fn get_raw_value(&self, key: &RegKey, name: &str) -> io::Result<RegValue> {
+        key.get_raw_value(name).map(from_winreg)
+    }
+
+    fn set_raw_value(&self, key: &RegKey, name: &str, value: &RegValue) -> io::Result<()> {
+        key.set_raw_value(name, &to_winreg(value))
+    }
+
+    fn delete_value(&self, key: &RegKey, name: &str) -> io::Result<()> {
+        key.delete_value(name)
+    }
+
+    fn enum_keys(&self, key: &RegKey) -> io::Result<Vec<String>> {
+        key.enum_keys().collect()
+    }
+
+    fn enum_values(&self, key: &RegKey) -> io::Result<Vec<(String, RegValue)>> {
+        key.enum_values()
+            .map(|r| r.map(|(name, value)| (name, from_winreg(value))))
+            .collect()
+    }
+
+    fn transaction(&self) -> io::Result<Transaction> {
+        Transaction::new()
+    }
+
+    fn commit(&self, transaction: &Transaction) -> io::Result<()> {
+        transaction.commit()
+    }
+
+    fn rollback(&self, transaction: &Transaction) -> io::Result<()> {
+        transaction.rollback()
+    }
+}
+
+fn from_winreg(value: winreg::RegValue) -> RegValue {
+    let vtype = match value.vtype {
+        enums::REG_NONE => RegType::REG_NONE,
+        enums::REG_SZ => RegType::REG_SZ,
+        enums::REG_EXPAND_SZ => RegType::REG_EXPAND_SZ,
+        enums::REG_BINARY => RegType::REG_BINARY,
+        enums::REG_DWORD => RegType::REG_DWORD,
+        enums::REG_DWORD_BIG_ENDIAN => RegType::REG_DWORD_BIG_ENDIAN,
+        enums::REG_LINK => RegType::REG_LINK,
+        enums::REG_MULTI_SZ => RegType::REG_MULTI_SZ,
+        enums::REG_RESOURCE_LIST => RegType::REG_RESOURCE_LIST,
+        enums::REG_FULL_RESOURCE_DESCRIPTOR => RegType::REG_FULL_RESOURCE_DESCRIPTOR,
+        enums::REG_RESOURCE_REQUIREMENTS_LIST => RegType::REG_RESOURCE_REQUIREMENTS_LIST,
+        enums::REG_QWORD => RegType::REG_QWORD,
+    };
+    RegValue {
+        bytes: value.bytes,
+        vtype,
+    }
+}
+
+fn to_winreg(value: &RegValue) -> winreg::RegValue {
+    let vtype = match value.vtype {
+        RegType::REG_NONE => enums::REG_NONE,
+        RegType::REG_SZ => enums::REG_SZ,
+        RegType::REG_EXPAND_SZ => enums::REG_EXPAND_SZ,
+        RegType::REG_BINARY => enums::REG_BINARY,
+        RegType::REG_DWORD => enums::REG_DWORD,
+        RegType::REG_DWORD_BIG_ENDIAN => enums::REG_DWORD_BIG_ENDIAN,
+        RegType::REG_LINK => enums::REG_LINK,
+        RegType::REG_MULTI_SZ => enums::REG_MULTI_SZ,
+        RegType::REG_RESOURCE_LIST => enums::REG_RESOURCE_LIST,
+        RegType::REG_FULL_RESOURCE_DESCRIPTOR => enums::REG_FULL_RESOURCE_DESCRIPTOR,
+        RegType::REG_RESOURCE_REQUIREMENTS_LIST => enums::REG_RESOURCE_REQUIREMENTS_LIST,
+        RegType::REG_QWORD => enums::REG_QWORD,
+    };
+    winreg::RegValue {
+        bytes: value.bytes.clone(),
+        vtype,
+    }
+}
@@ -0,0 +1,1141 @@
+// Registry hive files (the regf format of SYSTEM, SOFTWARE, NTUSER.DAT, ...)
+// read and written directly, without loading them into the registry. That way
+// an offline Windows image or the profile of another user can be checked and
+// tweaked, and the engine runs against real hives on any platform.
+//
+// A hive file is read into the in-memory tree at the key it is mounted at,
+// e.g. SOFTWARE at HKLM\SOFTWARE. A change outside a transaction writes the
+// hive file at once, a transaction writes every hive it touched on commit.
+// The file is written from scratch, compacted, with the security descriptors,
+// class names, flags and values of unknown types of every key kept.
+//
+// Layout: https://github.com/msuhanov/regf/blob/master/Windows%20registry%20file%20format%20specification.md
+
+use super::memory::{MemoryBackend, MemoryKey, MemoryTransaction, Node};
+use super::{current_control_set, Hive, RegPath, RegType, RegValue, RegistryBackend};
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const BASE_BLOCK: usize = 4096;
+const HBIN_HEADER: usize = 32;
+// Offsets of cells are relative to the first hbin, this one means none
+const NONE: u32 = 0xFFFF_FFFF;
+// Data up to this size fits one cell, longer data is split into a big data
+// record (hive version 1.4 and later)
+const BIG_DATA_SEGMENT: usize = 16344;
+// Subkey lists longer than this are split into an index root of leaves
+const MAX_LEAF: usize = 500;
+const MAX_DEPTH: usize = 512;
+
+const KEY_HIVE_ENTRY: u16 = 0x0004;
+const KEY_COMP_NAME: u16 = 0x0020;
+const VALUE_COMP_NAME: u16 = 0x0001;
+// Data of up to 4 bytes is stored in the data offset
+const DATA_RESIDENT: u32 = 0x8000_0000;
+
+// Everything of a key besides its name, values and subkeys
+#[derive(Clone, Default)]
+pub(super) struct KeyMeta {
+    flags: u16,
+    // FILETIME, 0 for keys created or whose values changed since the hive was read
+    last_written: u64,
+    access_bits: u32,
+    // The upper half of the largest subkey name field holds flags as well
+    user_flags: u16,
+    class: Option<Vec<u8>>,
+    // Index into the security descriptors of the hive, None inherits the parent's
+    security: Option<usize>,
+    // Values whose type `RegType` does not know, e.g. the device properties of
+    // the SYSTEM hive. The engine does not see them, they are written back as read.
+    opaque: Vec<(String, u32, Vec<u8>)>,
+}
+
+impl KeyMeta {
+    // The key is written with the time of the save
+    pub(super) fn touch(&mut self) {
+        self.last_written = 0;
+    }
+}
+
+// Where a hive file is placed in the registry, given as `HKLM\SOFTWARE=<file>`
+// or as the file alone when its name says it (SYSTEM, SOFTWARE, NTUSER.DAT, ...)
+#[derive(Clone, Debug)]
+pub struct HiveMount {
+    pub hive: Hive,
+    pub path: Vec<String>,
+    pub file: PathBuf,
+}
+
+impl HiveMount {
+    fn key(&self) -> String {
+        let mut key = self.hive.short_name().to_string();
+        for part in self.path.iter() {
+            key.push('\\');
+            key.push_str(part);
+        }
+        key
+    }
+
+    fn contains(&self, hive: Hive, path: &[String]) -> bool {
+        hive == self.hive
+            && path.len() >= self.path.len()
+            && self.path.iter().zip(path).all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+}
+
+impl fmt::Display for HiveMount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.key(), self.file.display())
+    }
+}
+
+impl FromStr for HiveMount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<HiveMount, String> {
+        let (key, file) = match s.split_once('=') {
+            Some((key, file)) => (key.to_string(), PathBuf::from(file)),
+            None => {
+                let file = PathBuf::from(s);
+                let name = file.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+                let key = match name.to_ascii_uppercase().as_str() {
+                    "SYSTEM" | "SOFTWARE" | "SAM" | "SECURITY" | "COMPONENTS" => format!("HKLM\\{}", name),
+                    "NTUSER.DAT" => "HKCU".to_string(),
+                    "DEFAULT" => "HKU\\.DEFAULT".to_string(),
+                    _ => {
+                        return Err(format!(
+                            "cannot tell where `{}` belongs, give the key as in HKLM\\SOFTWARE={}",
+                            s, s
+                        ))
+                    }
+                };
+                (key, file)
+            }
+        };
+        let mut parts = key.split('\\').filter(|p| !p.is_empty());
+        let hive = parts.next().unwrap_or_default().parse::<Hive>()?;
+        Ok(HiveMount {
+            hive,
+            path: parts.map(String::from).collect(),
+            file,
+        })
+    }
+}
+
+struct Loaded {
+    mount: HiveMount,
+    // The base block as read, written back with the new sequence numbers, root and size
+    base: Vec<u8>,
+    minor: u32,
+    root_name: String,
+    root_parent: u32,
+    security: Vec<Vec<u8>>,
+}
+
+pub struct HiveBackend {
+    memory: MemoryBackend,
+    hives: Vec<Loaded>,
+    // The hives each open transaction changed, by transaction id
+    touched: RefCell<HashMap<usize, BTreeSet<usize>>>,
+    // SYSTEM\Select\Current of a loaded SYSTEM hive
+    control_set: Option<u32>,
+}
+
+impl HiveBackend {
+    pub fn open(mounts: &[HiveMount]) -> io::Result<HiveBackend> {
+        let memory = MemoryBackend::new();
+        let mut hives = Vec::new();
+        for mount in mounts {
+            let overlaps = |h: &Loaded| h.mount.contains(mount.hive, &mount.path) || mount.contains(h.mount.hive, &h.mount.path);
+            if hives.iter().any(overlaps) {
+                return Err(invalid(format!("{} overlaps another hive file", mount.key())));
+            }
+            let bytes = fs::read(&mount.file).map_err(|e| with_file(&mount.file, e))?;
+            let (mut root, loaded) = read_hive(&bytes, mount).map_err(|e| with_file(&mount.file, e))?;
+            root.name = mount.path.last().cloned().unwrap_or_default();
+            memory.mount(mount.hive, &mount.path, root);
+            hives.push(loaded);
+        }
+        let control_set = current_control_set(&memory).ok();
+        Ok(HiveBackend {
+            memory,
+            hives,
+            touched: RefCell::new(HashMap::new()),
+            control_set,
+        })
+    }
+
+    // The SYSTEM hive has no CurrentControlSet, Windows links it at boot. A path
+    // through it goes to the control set the hive boots with instead.
+    fn resolve<'p>(&self, key: &MemoryKey, path: &'p str) -> Cow<'p, str> {
+        let current = match self.control_set {
+            Some(current) => current,
+            None => return Cow::Borrowed(path),
+        };
+        let full = RegPath::new(key.hive, &join(key, path).join("\\"));
+        let resolved = full.resolve_control_set(current);
+        if resolved.subkey == full.subkey {
+            return Cow::Borrowed(path);
+        }
+        let relative: Vec<&str> = resolved.subkey.split('\\').skip(key.path.len()).collect();
+        Cow::Owned(relative.join("\\"))
+    }
+
+    // The hive file a change of `path` goes to, a change outside of them would be lost
+    fn hive_of(&self, hive: Hive, path: &[String]) -> io::Result<usize> {
+        self.hives
+            .iter()
+            .position(|h| h.mount.contains(hive, path))
+            .ok_or_else(|| {
+                io::Error::other(format!(
+                    "{}\\{} is not part of a loaded hive file",
+                    hive.short_name(),
+                    path.join("\\")
+                ))
+            })
+    }
+
+    fn changed(&self, key: &MemoryKey, index: usize) -> io::Result<()> {
+        match key.transaction {
+            None => self.save(index),
+            Some(id) => {
+                self.touched.borrow_mut().entry(id).or_default().insert(index);
+                Ok(())
+            }
+        }
+    }
+
+    fn save(&self, index: usize) -> io::Result<()> {
+        let loaded = &self.hives[index];
+        let bytes = self
+            .memory
+            .with_node(loaded.mount.hive, &loaded.mount.path, |root| write_hive(root, loaded))
+            .ok_or_else(|| invalid(format!("{} is gone", loaded.mount.key())))?;
+        // Written next to the hive first, so a failed write leaves the old file
+        let file = &loaded.mount.file;
+        let mut temp = file.clone().into_os_string();
+        temp.push(".tmp");
+        fs::write(&temp, bytes)
+            .and_then(|_| fs::rename(&temp, file))
+            .map_err(|e| with_file(file, e))
+    }
+}
+
+impl RegistryBackend for HiveBackend {
+    type Key = MemoryKey;
+    type Transaction = MemoryTransaction;
+
+    fn predef(&self, hive: Hive) -> MemoryKey {
+        self.memory.predef(hive)
+    }
+
+    fn open_subkey(&self, key: &MemoryKey, path: &str) -> io::Result<MemoryKey> {
+        self.memory.open_subkey(key, &self.resolve(key, path))
+    }
+
+    fn create_subkey_transacted(
+        &self,
+        key: &MemoryKey,
+        path: &str,
+        transaction: &MemoryTransaction,
+    ) -> io::Result<MemoryKey> {
+        let path = &self.resolve(key, path);
+        let index = self.hive_of(key.hive, &join(key, path))?;
+        let sub = self.memory.create_subkey_transacted(key, path, transaction)?;
+        self.changed(&sub, index)?;
+        Ok(sub)
+    }
+
+    fn open_subkey_transacted(
+        &self,
+        key: &MemoryKey,
+        path: &str,
+        transaction: &MemoryTransaction,
+    ) -> io::Result<MemoryKey> {
+        self.memory.open_subkey_transacted(key, &self.resolve(key, path), transaction)
+    }
+
+    fn delete_subkey(&self, key: &MemoryKey, path: &str) -> io::Result<()> {
+        let path = &self.resolve(key, path);
+        let sub = join(key, path);
+        let index = self.hive_of(key.hive, &sub)?;
+        if sub.len() == self.hives[index].mount.path.len() {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the root of a hive cannot be deleted"));
+        }
+        self.memory.delete_subkey(key, path)?;
+        self.changed(key, index)
+    }
+
+    fn delete_subkey_transacted(&self, key: &MemoryKey, path: &str, transaction: &MemoryTransaction) -> io::Result<()> {
+        let mut key = key.clone();
+        key.transaction = Some(transaction.0);
+        self.delete_subkey(&key, path)
+    }
+
+    fn get_raw_value(&self, key: &MemoryKey, name: &str) -> io::Result<RegValue> {
+        self.memory.get_raw_value(key, name)
+    }
+
+    fn set_raw_value(&self, key: &MemoryKey, name: &str, value: &RegValue) -> io::Result<()> {
+        let index = self.hive_of(key.hive, &key.path)?;
+        self.memory.set_raw_value(key, name, value)?;
+        self.changed(key, index)
+    }
+
+    fn delete_value(&self, key: &MemoryKey, name: &str) -> io::Result<()> {
+        let index = self.hive_of(key.hive, &key.path)?;
+        self.memory.delete_value(key, name)?;
+        self.changed(key, index)
+    }
+
+    fn enum_keys(&self, key: &MemoryKey) -> io::Result<Vec<String>> {
+        self.memory.enum_keys(key)
+    }
+
+    fn enum_values(&self, key: &MemoryKey) -> io::Result<Vec<(String, RegValue)>> {
+        self.memory.enum_values(key)
+    }
+
+    fn transaction(&self) -> io::Result<MemoryTransaction> {
+        self.memory.transaction()
+    }
+
+    fn commit(&self, transaction: &MemoryTransaction) -> io::Result<()> {
+        self.memory.commit(transaction)?;
+        let touched = self.touched.borrow_mut().remove(&transaction.0).unwrap_or_default();
+        for index in touched {
+            self.save(index)?;
+        }
+        Ok(())
+    }
+
+    fn rollback(&self, transaction: &MemoryTransaction) -> io::Result<()> {
+        self.touched.borrow_mut().remove(&transaction.0);
+        self.memory.rollback(transaction)
+    }
+}
+
+fn join(key: &MemoryKey, path: &str) -> Vec<String> {
+    let mut joined = key.path.clone();
+    joined.extend(path.split('\\').filter(|p| !p.is_empty()).map(String::from));
+    joined
+}
+
+fn read_hive(bytes: &[u8], mount: &HiveMount) -> io::Result<(Node, Loaded)> {
+    if bytes.len() < BASE_BLOCK || &bytes[..4] != b"regf" {
+        return Err(invalid("not a registry hive file"));
+    }
+    let base = &bytes[..BASE_BLOCK];
+    if checksum(base) != u32_at(base, 0x1FC)? {
+        return Err(invalid("the checksum of the base block is wrong"));
+    }
+    if u32_at(base, 0x04)? != u32_at(base, 0x08)? {
+        return Err(invalid(
+            "the hive was not unloaded cleanly and its transaction logs are not applied, load and unload it once with reg.exe",
+        ));
+    }
+    let (major, minor) = (u32_at(base, 0x14)?, u32_at(base, 0x18)?);
+    if major != 1 || !(3..=6).contains(&minor) || u32_at(base, 0x1C)? != 0 {
+        return Err(invalid(format!("unsupported hive version {}.{}", major, minor)));
+    }
+    let size = u32_at(base, 0x28)? as usize;
+    let bins = bytes
+        .get(BASE_BLOCK..BASE_BLOCK + size)
+        .ok_or_else(|| invalid("the hive file is shorter than its base block says"))?;
+    if bins.get(..4) != Some(b"hbin".as_ref()) {
+        return Err(invalid("the first hive bin is missing"));
+    }
+
+    let mut reader = Reader {
+        bins,
+        big_data: minor >= 4,
+        security: Vec::new(),
+        seen: HashMap::new(),
+        visited: HashSet::new(),
+    };
+    let root_offset = u32_at(base, 0x24)?;
+    let root = reader.key(root_offset, 0)?;
+    let root_cell = reader.cell(root_offset)?;
+    let loaded = Loaded {
+        mount: mount.clone(),
+        base: base.to_vec(),
+        minor,
+        root_name: root.name.clone(),
+        root_parent: u32_at(root_cell, 0x10)?,
+        security: reader.security,
+    };
+    Ok((root, loaded))
+}
+
+struct Reader<'a> {
+    bins: &'a [u8],
+    big_data: bool,
+    security: Vec<Vec<u8>>,
+    // Offset of an sk cell to its index in `security`
+    seen: HashMap<u32, usize>,
+    // The nk offsets read so far, a broken subkey list may link a key twice or to its parent
+    visited: HashSet<u32>,
+}
+
+impl<'a> Reader<'a> {
+    // The data of the cell at `offset`, without its size
+    fn cell(&self, offset: u32) -> io::Result<&'a [u8]> {
+        let start = offset as usize;
+        let size = i32_at(self.bins, start)?.unsigned_abs() as usize;
+        if size < 4 {
+            return Err(invalid(format!("broken cell at 0x{:x}", offset)));
+        }
+        self.bins
+            .get(start + 4..start + size)
+            .ok_or_else(|| invalid(format!("cell at 0x{:x} is outside the hive", offset)))
+    }
+
+    fn key(&mut self, offset: u32, depth: usize) -> io::Result<Node> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("the keys of the hive are nested too deep"));
+        }
+        if !self.visited.insert(offset) {
+            return Err(invalid(format!("the key at 0x{:x} is linked more than once", offset)));
+        }
+        let cell = self.cell(offset)?;
+        signature(cell, b"nk", offset)?;
+        let flags = u16_at(cell, 0x02)?;
+        let name_length = u16_at(cell, 0x48)? as usize;
+        let name = bytes_at(cell, 0x4C, name_length)?;
+        let mut node = Node {
+            name: decode_name(name, flags & KEY_COMP_NAME != 0),
+            ..Node::default()
+        };
+        node.meta = KeyMeta {
+            flags,
+            last_written: u64_at(cell, 0x04)?,
+            access_bits: u32_at(cell, 0x0C)?,
+            user_flags: (u32_at(cell, 0x34)? >> 16) as u16,
+            class: match u32_at(cell, 0x30)? {
+                NONE => None,
+                class => Some(bytes_at(self.cell(class)?, 0, u16_at(cell, 0x4A)? as usize)?.to_vec()),
+            },
+            security: match u32_at(cell, 0x2C)? {
+                NONE => None,
+                sk => Some(self.security(sk)?),
+            },
+            opaque: Vec::new(),
+        };
+
+        let value_count = u32_at(cell, 0x24)? as usize;
+        if value_count > 0 {
+            let list = self.cell(u32_at(cell, 0x28)?)?;
+            for i in 0..value_count {
+                let (name, vtype, data) = self.value(u32_at(list, i * 4)?)?;
+                match RegType::from_u32(vtype) {
+                    Some(vtype) => node.values.push((name, RegValue { bytes: data, vtype })),
+                    None => node.meta.opaque.push((name, vtype, data)),
+                }
+            }
+        }
+
+        if u32_at(cell, 0x14)? > 0 {
+            let mut offsets = Vec::new();
+            self.subkeys(u32_at(cell, 0x1C)?, false, &mut offsets)?;
+            for sub in offsets {
+                let child = self.key(sub, depth + 1)?;
+                node.subkeys.insert(child.name.to_lowercase(), child);
+            }
+        }
+        Ok(node)
+    }
+
+    // The nk offsets of a subkey list, an index root holds further lists
+    fn subkeys(&self, offset: u32, nested: bool, offsets: &mut Vec<u32>) -> io::Result<()> {
+        let cell = self.cell(offset)?;
+        let count = u16_at(cell, 0x02)? as usize;
+        match cell.get(..2) {
+            Some(b"lf") | Some(b"lh") => {
+                for i in 0..count {
+                    offsets.push(u32_at(cell, 4 + i * 8)?);
+                }
+            }
+            Some(b"li") => {
+                for i in 0..count {
+                    offsets.push(u32_at(cell, 4 + i * 4)?);
+                }
+            }
+            Some(b"ri") if !nested => {
+                for i in 0..count {
+                    self.subkeys(u32_at(cell, 4 + i * 4)?, true, offsets)?;
+                }
+            }
+            _ => return Err(invalid(format!("broken subkey list at 0x{:x}", offset))),
+        }
+        Ok(())
+    }
+
+    fn value(&self, offset: u32) -> io::Result<(String, u32, Vec<u8>)> {
+        let cell = self.cell(offset)?;
+        signature(cell, b"vk", offset)?;
+        let name = bytes_at(cell, 0x14, u16_at(cell, 0x02)? as usize)?;
+        let name = decode_name(name, u16_at(cell, 0x10)? & VALUE_COMP_NAME != 0);
+        let size = u32_at(cell, 0x04)?;
+        let length = (size & !DATA_RESIDENT) as usize;
+        let data = if size & DATA_RESIDENT != 0 {
+            bytes_at(cell, 0x08, length.min(4))?.to_vec()
+        } else if length == 0 {
+            Vec::new()
+        } else {
+            let data = self.cell(u32_at(cell, 0x08)?)?;
+            if self.big_data && length > BIG_DATA_SEGMENT && data.get(..2) == Some(b"db".as_ref()) {
+                let segments = self.cell(u32_at(data, 0x04)?)?;
+                let mut joined = Vec::with_capacity(length);
+                for i in 0..u16_at(data, 0x02)? as usize {
+                    let segment = self.cell(u32_at(segments, i * 4)?)?;
+                    joined.extend_from_slice(&segment[..segment.len().min(BIG_DATA_SEGMENT)]);
+                }
+                joined.truncate(length);
+                joined
+            } else {
+                // Some writers store a size larger than the cell, the cell wins
+                data[..length.min(data.len())].to_vec()
+            }
+        };
+        Ok((name, u32_at(cell, 0x0C)?, data))
+    }
+
+    fn security(&mut self, offset: u32) -> io::Result<usize> {
+        if let Some(&index) = self.seen.get(&offset) {
+            return Ok(index);
+        }
+        let cell = self.cell(offset)?;
+        signature(cell, b"sk", offset)?;
+        let descriptor = bytes_at(cell, 0x14, u32_at(cell, 0x10)? as usize)?;
+        self.security.push(descriptor.to_vec());
+        self.seen.insert(offset, self.security.len() - 1);
+        Ok(self.security.len() - 1)
+    }
+}
+
+fn write_hive(root: &Node, loaded: &Loaded) -> Vec<u8> {
+    let mut writer = Writer::new(loaded.minor >= 4);
+
+    // Every descriptor in use gets one sk cell, linked in a ring and counting its keys
+    let mut references = BTreeMap::new();
+    count_security(root, None, &mut references);
+    let offsets: Vec<(usize, u32)> = references
+        .keys()
+        .map(|&index| (index, writer.alloc(0x14 + loaded.security[index].len())))
+        .collect();
+    for (i, &(index, offset)) in offsets.iter().enumerate() {
+        let next = offsets[(i + 1) % offsets.len()].1;
+        let previous = offsets[(i + offsets.len() - 1) % offsets.len()].1;
+        let descriptor = &loaded.security[index];
+        let mut cell = Vec::with_capacity(0x14 + descriptor.len());
+        cell.extend_from_slice(b"sk\0\0");
+        cell.extend_from_slice(&next.to_le_bytes());
+        cell.extend_from_slice(&previous.to_le_bytes());
+        cell.extend_from_slice(&references[&index].to_le_bytes());
+        cell.extend_from_slice(&(descriptor.len() as u32).to_le_bytes());
+        cell.extend_from_slice(descriptor);
+        writer.put(offset, &cell);
+    }
+    writer.security = offsets.into_iter().collect();
+
+    let root_offset = writer.key(root, &loaded.root_name, loaded.root_parent, true, None);
+    let bins = writer.finish();
+
+    let mut base = loaded.base.clone();
+    let sequence = u32_at(&base, 0x04).unwrap_or_default().wrapping_add(1);
+    base[0x04..0x08].copy_from_slice(&sequence.to_le_bytes());
+    base[0x08..0x0C].copy_from_slice(&sequence.to_le_bytes());
+    base[0x0C..0x14].copy_from_slice(&filetime_now().to_le_bytes());
+    base[0x24..0x28].copy_from_slice(&root_offset.to_le_bytes());
+    base[0x28..0x2C].copy_from_slice(&(bins.len() as u32).to_le_bytes());
+    let sum = checksum(&base);
+    base[0x1FC..0x200].copy_from_slice(&sum.to_le_bytes());
+    base.extend_from_slice(&bins);
+    base
+}
+
+fn count_security(node: &Node, inherited: Option<usize>, references: &mut BTreeMap<usize, u32>) {
+    let security = node.meta.security.or(inherited);
+    if let Some(index) = security {
+        *references.entry(index).or_default() += 1;
+    }
+    for child in node.subkeys.values() {
+        count_security(child, security, references);
+    }
+}
+
+struct Writer {
+    bins: Vec<u8>,
+    bin_end: usize,
+    big_data: bool,
+    // Index of a security descriptor to its sk cell
+    security: HashMap<usize, u32>,
+    now: u64,
+}
+
+impl Writer {
+    fn new(big_data: bool) -> Writer {
+        let mut writer = Writer {
+            bins: Vec::new(),
+            bin_end: 0,
+            big_data,
+            security: HashMap::new(),
+            now: filetime_now(),
+        };
+        writer.open_bin(0);
+        writer
+    }
+
+    fn open_bin(&mut self, cell: usize) {
+        let size = (cell + HBIN_HEADER).div_ceil(BASE_BLOCK) * BASE_BLOCK;
+        let start = self.bins.len();
+        self.bins.extend_from_slice(b"hbin");
+        self.bins.extend_from_slice(&(start as u32).to_le_bytes());
+        self.bins.extend_from_slice(&(size as u32).to_le_bytes());
+        self.bins.extend_from_slice(&[0; 8]);
+        self.bins.extend_from_slice(&self.now.to_le_bytes());
+        self.bins.extend_from_slice(&[0; 4]);
+        self.bin_end = start + size;
+    }
+
+    // The rest of the bin becomes one free cell
+    fn close_bin(&mut self) {
+        let rest = self.bin_end - self.bins.len();
+        if rest > 0 {
+            self.bins.extend_from_slice(&(rest as i32).to_le_bytes());
+            self.bins.resize(self.bin_end, 0);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.close_bin();
+        self.bins
+    }
+
+    // An allocated cell for `length` bytes, cells never cross the end of a bin
+    fn alloc(&mut self, length: usize) -> u32 {
+        let size = (length + 4).div_ceil(8) * 8;
+        if self.bins.len() + size > self.bin_end {
+            self.close_bin();
+            self.open_bin(size);
+        }
+        let offset = self.bins.len();
+        self.bins.extend_from_slice(&(-(size as i32)).to_le_bytes());
+        self.bins.resize(offset + size, 0);
+        offset as u32
+    }
+
+    fn put(&mut self, offset: u32, data: &[u8]) {
+        let start = offset as usize + 4;
+        self.bins[start..start + data.len()].copy_from_slice(data);
+    }
+
+    fn cell(&mut self, data: &[u8]) -> u32 {
+        let offset = self.alloc(data.len());
+        self.put(offset, data);
+        offset
+    }
+
+    fn key(&mut self, node: &Node, name: &str, parent: u32, root: bool, inherited: Option<usize>) -> u32 {
+        let (encoded, compressed) = encode_name(name);
+        let offset = self.alloc(0x4C + encoded.len());
+        let meta = &node.meta;
+        let security = meta.security.or(inherited);
+
+        // The engine's values first, then those of unknown types it did not replace
+        let mut values: Vec<(&str, u32, &[u8])> = node
+            .values
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.vtype as u32, value.bytes.as_slice()))
+            .collect();
+        for (name, vtype, data) in meta.opaque.iter() {
+            if !node.values.iter().any(|(n, _)| n.eq_ignore_ascii_case(name)) {
+                values.push((name.as_str(), *vtype, data.as_slice()));
+            }
+        }
+        let value_offsets: Vec<u32> = values.iter().map(|&(name, vtype, data)| self.value(name, vtype, data)).collect();
+        let value_list = match value_offsets.is_empty() {
+            true => NONE,
+            false => self.cell(&offsets_bytes(&value_offsets)),
+        };
+
+        // Windows looks subkeys up by binary search over the upper case names
+        let mut children: Vec<&Node> = node.subkeys.values().collect();
+        children.sort_by_cached_key(|child| upcase(&child.name));
+        let entries: Vec<(u32, u32)> = children
+            .iter()
+            .map(|child| (self.key(child, &child.name, offset, false, security), name_hash(&child.name)))
+            .collect();
+        let subkey_list = self.subkey_list(&entries);
+
+        let class = match &meta.class {
+            Some(class) => self.cell(class),
+            None => NONE,
+        };
+
+        let name_size = |name: &str| name.encode_utf16().count() as u32 * 2;
+        let max_subkey_name = children.iter().map(|c| name_size(&c.name)).max().unwrap_or(0);
+        let max_subkey_class = children
+            .iter()
+            .map(|c| c.meta.class.as_ref().map_or(0, |class| class.len() as u32))
+            .max()
+            .unwrap_or(0);
+        let max_value_name = values.iter().map(|(name, _, _)| name_size(name)).max().unwrap_or(0);
+        let max_value_data = values.iter().map(|(_, _, data)| data.len() as u32).max().unwrap_or(0);
+
+        let mut flags = meta.flags & !(KEY_COMP_NAME | KEY_HIVE_ENTRY);
+        if compressed {
+            flags |= KEY_COMP_NAME;
+        }
+        if root {
+            flags |= KEY_HIVE_ENTRY;
+        }
+        let last_written = match meta.last_written {
+            0 => self.now,
+            written => written,
+        };
+
+        let mut cell = Vec::with_capacity(0x4C + encoded.len());
+        cell.extend_from_slice(b"nk");
+        cell.extend_from_slice(&flags.to_le_bytes());
+        cell.extend_from_slice(&last_written.to_le_bytes());
+        cell.extend_from_slice(&meta.access_bits.to_le_bytes());
+        cell.extend_from_slice(&parent.to_le_bytes());
+        cell.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+        cell.extend_from_slice(&0u32.to_le_bytes());
+        cell.extend_from_slice(&subkey_list.to_le_bytes());
+        cell.extend_from_slice(&NONE.to_le_bytes());
+        cell.extend_from_slice(&(value_offsets.len() as u32).to_le_bytes());
+        cell.extend_from_slice(&value_list.to_le_bytes());
+        let sk = security.and_then(|index| self.security.get(&index)).copied().unwrap_or(NONE);
+        cell.extend_from_slice(&sk.to_le_bytes());
+        cell.extend_from_slice(&class.to_le_bytes());
+        cell.extend_from_slice(&(max_subkey_name & 0xFFFF | (meta.user_flags as u32) << 16).to_le_bytes());
+        cell.extend_from_slice(&max_subkey_class.to_le_bytes());
+        cell.extend_from_slice(&max_value_name.to_le_bytes());
+        cell.extend_from_slice(&max_value_data.to_le_bytes());
+        cell.extend_from_slice(&0u32.to_le_bytes());
+        cell.extend_from_slice(&(encoded.len() as u16).to_le_bytes());
+        let class_length = meta.class.as_ref().map_or(0, |class| class.len() as u16);
+        cell.extend_from_slice(&class_length.to_le_bytes());
+        cell.extend_from_slice(&encoded);
+        self.put(offset, &cell);
+        offset
+    }
+
+    // One hash leaf, or an index root of leaves for many subkeys
+    fn subkey_list(&mut self, entries: &[(u32, u32)]) -> u32 {
+        let leaf = |writer: &mut Writer, entries: &[(u32, u32)]| {
+            let mut cell = Vec::with_capacity(4 + entries.len() * 8);
+            cell.extend_from_slice(b"lh");
+            cell.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+            for (offset, hash) in entries {
+                cell.extend_from_slice(&offset.to_le_bytes());
+                cell.extend_from_slice(&hash.to_le_bytes());
+            }
+            writer.cell(&cell)
+        };
+        match entries.len() {
+            0 => NONE,
+            n if n <= MAX_LEAF => leaf(self, entries),
+            _ => {
+                let leaves: Vec<u32> = entries.chunks(MAX_LEAF).map(|chunk| leaf(self, chunk)).collect();
+                let mut cell = b"ri".to_vec();
+                cell.extend_from_slice(&(leaves.len() as u16).to_le_bytes());
+                cell.extend_from_slice(&offsets_bytes(&leaves));
+                self.cell(&cell)
+            }
+        }
+    }
+
+    fn value(&mut self, name: &str, vtype: u32, data: &[u8]) -> u32 {
+        let (encoded, compressed) = encode_name(name);
+        let (size, data_offset) = match data.len() {
+            0 => (0, NONE),
+            n if n <= 4 => {
+                let mut resident = [0; 4];
+                resident[..n].copy_from_slice(data);
+                (n as u32 | DATA_RESIDENT, u32::from_le_bytes(resident))
+            }
+            n if self.big_data && n > BIG_DATA_SEGMENT => {
+                let segments: Vec<u32> = data.chunks(BIG_DATA_SEGMENT).map(|segment| self.cell(segment)).collect();
+                let list = self.cell(&offsets_bytes(&segments));
+                let mut record = b"db".to_vec();
+                record.extend_from_slice(&(segments.len() as u16).to_le_bytes());
+                record.extend_from_slice(&list.to_le_bytes());
+                (n as u32, self.cell(&record))
+            }
+            n => (n as u32, self.cell(data)),
+        };
+        let mut cell = Vec::with_capacity(0x14 + encoded.len());
+        cell.extend_from_slice(b"vk");
+        cell.extend_from_slice(&(encoded.len() as u16).to_le_bytes());
+        cell.extend_from_slice(&size.to_le_bytes());
+        cell.extend_from_slice(&data_offset.to_le_bytes());
+        cell.extend_from_slice(&vtype.to_le_bytes());
+        let flags = if compressed { VALUE_COMP_NAME } else { 0 };
+        cell.extend_from_slice(&flags.to_le_bytes());
+        cell.extend_from_slice(&0u16.to_le_bytes());
+        cell.extend_from_slice(&encoded);
+        self.cell(&cell)
+    }
+}
+
+fn offsets_bytes(offsets: &[u32]) -> Vec<u8> {
+    offsets.iter().flat_map(|offset| offset.to_le_bytes()).collect()
+}
+
+// Names that fit Latin-1 are stored with one byte per character
+fn encode_name(name: &str) -> (Vec<u8>, bool) {
+    if name.chars().all(|c| (c as u32) < 0x100) {
+        (name.chars().map(|c| c as u8).collect(), true)
+    } else {
+        (name.encode_utf16().flat_map(u16::to_le_bytes).collect(), false)
+    }
+}
+
+fn decode_name(bytes: &[u8], compressed: bool) -> String {
+    if compressed {
+        bytes.iter().map(|&b| b as char).collect()
+    } else {
+        let units: Vec<u16> = bytes.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+        String::from_utf16_lossy(&units)
+    }
+}
+
+fn upcase(name: &str) -> Vec<u16> {
+    name.encode_utf16()
+        .map(|unit| {
+            let mut upper = char::from_u32(unit as u32).into_iter().flat_map(char::to_uppercase);
+            match (upper.next(), upper.next()) {
+                (Some(c), None) if (c as u32) < 0x10000 => c as u16,
+                _ => unit,
+            }
+        })
+        .collect()
+}
+
+fn name_hash(name: &str) -> u32 {
+    upcase(name)
+        .into_iter()
+        .fold(0u32, |hash, unit| hash.wrapping_mul(37).wrapping_add(unit as u32))
+}
+
+// XOR of the first 127 dwords, 0 and -1 are reserved
+fn checksum(base: &[u8]) -> u32 {
+    let sum = base[..0x1FC]
+        .chunks_exact(4)
+        .fold(0, |sum, dword| sum ^ u32::from_le_bytes([dword[0], dword[1], dword[2], dword[3]]));
+    match sum {
+        0 => 1,
+        0xFFFF_FFFF => 0xFFFF_FFFE,
+        sum => sum,
+    }
+}
+
+fn filetime_now() -> u64 {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    // 100ns intervals since 1601
+    116_444_736_000_000_000 + since_epoch.as_nanos() as u64 / 100
+}
+
+fn signature(cell: &[u8], expected: &[u8; 2], offset: u32) -> io::Result<()> {
+    if cell.get(..2) == Some(expected.as_ref()) {
+        Ok(())
+    } else {
+        Err(invalid(format!(
+            "expected a {} cell at 0x{:x}",
+            String::from_utf8_lossy(expected),
+            offset
+        )))
+    }
+}
+
+fn bytes_at(data: &[u8], at: usize, length: usize) -> io::Result<&[u8]> {
+    data.get(at..at + length)
+        .ok_or_else(|| invalid("a cell of the hive is cut short"))
+}
+
+fn u16_at(data: &[u8], at: usize) -> io::Result<u16> {
+    bytes_at(data, at, 2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn u32_at(data: &[u8], at: usize) -> io::Result<u32> {
+    bytes_at(data, at, 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn i32_at(data: &[u8], at: usize) -> io::Result<i32> {
+    u32_at(data, at).map(|v| v as i32)
+}
+
+fn u64_at(data: &[u8], at: usize) -> io::Result<u64> {
+    Ok(u32_at(data, at)? as u64 | (u32_at(data, at + 4)? as u64) << 32)
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn with_file(file: &Path, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", file.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Built by tests/fixtures/make_hives.py
+    const SOFTWARE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/software.hiv");
+    const SYSTEM: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/system.hiv");
+
+    fn mount(key: &str, file: &Path) -> HiveMount {
+        format!("{}={}", key, file.display()).parse().unwrap()
+    }
+
+    fn read(file: &str) -> (Node, Loaded) {
+        read_hive(&fs::read(file).unwrap(), &mount("HKLM\\SOFTWARE", Path::new(file))).unwrap()
+    }
+
+    // A copy of the fixture the test may write to
+    fn copy(file: &str, name: &str) -> PathBuf {
+        let copy = std::env::temp_dir().join(format!("gaming-optimizer-{}-{}.hiv", name, std::process::id()));
+        fs::copy(file, &copy).unwrap();
+        copy
+    }
+
+    fn find<'n>(node: &'n Node, path: &str) -> &'n Node {
+        path.split('\\')
+            .fold(node, |node, part| &node.subkeys[&part.to_lowercase()])
+    }
+
+    fn value<'n>(node: &'n Node, name: &str) -> &'n RegValue {
+        &node.values.iter().find(|(n, _)| n == name).unwrap().1
+    }
+
+    // Everything the writer has to keep, the security descriptors by content
+    fn assert_same(a: &Node, a_hive: &Loaded, b: &Node, b_hive: &Loaded, path: &str) {
+        assert_eq!(a.name, b.name, "{}", path);
+        assert_eq!(a.values, b.values, "{}", path);
+        assert_eq!(a.meta.opaque, b.meta.opaque, "{}", path);
+        assert_eq!(a.meta.class, b.meta.class, "{}", path);
+        assert_eq!(a.meta.last_written, b.meta.last_written, "{}", path);
+        assert_eq!(a.meta.flags, b.meta.flags, "{}", path);
+        let descriptor = |node: &Node, hive: &Loaded| node.meta.security.map(|index| hive.security[index].clone());
+        assert_eq!(descriptor(a, a_hive), descriptor(b, b_hive), "{}", path);
+        let names = |node: &Node| node.subkeys.keys().cloned().collect::<Vec<_>>();
+        assert_eq!(names(a), names(b), "{}", path);
+        for (name, child) in a.subkeys.iter() {
+            assert_same(child, a_hive, &b.subkeys[name], b_hive, &format!("{}\\{}", path, child.name));
+        }
+    }
+
+    #[test]
+    fn reads_the_fixture() {
+        let (root, loaded) = read(SOFTWARE);
+        assert_eq!(loaded.minor, 5);
+        assert_eq!(loaded.security.len(), 2);
+        let test = find(&root, "Vendor\\Test");
+        assert_eq!(value(test, "Dword").as_u32().unwrap(), 0xDEAD_BEEF);
+        assert_eq!(value(test, "Qword").as_u64().unwrap(), 0x0123_4567_89AB_CDEF);
+        assert_eq!(value(test, "String").as_string().unwrap(), "Gaming Optimizer");
+        assert_eq!(value(test, "Expand").vtype, RegType::REG_EXPAND_SZ);
+        assert_eq!(value(test, "Multi").as_multi_string().unwrap(), vec!["one", "two", "three"]);
+        assert_eq!(value(test, "Binary").bytes, (0..32).collect::<Vec<u8>>());
+        assert_eq!(value(test, "Short").bytes, vec![1, 2]);
+        assert!(value(test, "Empty").bytes.is_empty());
+        assert_eq!(value(test, "").as_string().unwrap(), "default value");
+        assert_eq!(test.meta.opaque.len(), 1);
+        assert_eq!(test.meta.opaque[0].1, 0xFFFF_0012);
+        assert!(test.meta.class.is_some());
+        assert_eq!(find(&root, "Vendor\\Ключ").values[0].0, "Значение");
+        let listed: Vec<&str> = find(&root, "Vendor\\Listed").subkeys.values().map(|k| k.name.as_str()).collect();
+        assert_eq!(listed, vec!["Alpha", "beta", "Gamma"]);
+    }
+
+    #[test]
+    fn big_data_values() {
+        let (root, loaded) = read(SOFTWARE);
+        let big = value(find(&root, "Vendor\\Test"), "Big");
+        let expected: Vec<u8> = (0..40000u32).map(|i| ((i * 7 + i / 251) & 0xFF) as u8).collect();
+        assert_eq!(big.bytes, expected);
+
+        let written = write_hive(&root, &loaded);
+        let (again, _) = read_hive(&written, &loaded.mount).unwrap();
+        assert_eq!(value(find(&again, "Vendor\\Test"), "Big").bytes, expected);
+        // Split into segments again, one cell could not hold it
+        assert!(written.windows(4).any(|w| w == b"db\x03\x00"));
+    }
+
+    #[test]
+    fn index_root_subkey_lists() {
+        let (root, loaded) = read(SOFTWARE);
+        let many = find(&root, "Vendor\\Many");
+        assert_eq!(many.subkeys.len(), 600);
+        assert_eq!(value(find(many, "Sub599"), "Index").as_u32().unwrap(), 599);
+
+        let written = write_hive(&root, &loaded);
+        assert!(written.windows(4).any(|w| w == b"ri\x02\x00"));
+        let (again, _) = read_hive(&written, &loaded.mount).unwrap();
+        let many = find(&again, "Vendor\\Many");
+        assert_eq!(many.subkeys.len(), 600);
+        assert_eq!(value(find(many, "Sub000"), "Index").as_u32().unwrap(), 0);
+    }
+
+    #[test]
+    fn read_write_read_is_equal() {
+        for file in [SOFTWARE, SYSTEM] {
+            let (root, loaded) = read(file);
+            let written = write_hive(&root, &loaded);
+            let (again, reloaded) = read_hive(&written, &loaded.mount).unwrap();
+            assert_same(&root, &loaded, &again, &reloaded, file);
+            assert_eq!(reloaded.root_name, loaded.root_name);
+            // A second write of the same tree is read back the same as well
+            let (third, rereloaded) = read_hive(&write_hive(&again, &reloaded), &loaded.mount).unwrap();
+            assert_same(&root, &loaded, &third, &rereloaded, file);
+        }
+    }
+
+    #[test]
+    fn changes_are_saved_to_the_file() {
+        let file = copy(SOFTWARE, "save");
+        let backend = HiveBackend::open(&[mount("HKLM\\SOFTWARE", &file)]).unwrap();
+        let hklm = backend.predef(Hive::LocalMachine);
+        let transaction = backend.transaction().unwrap();
+        let key = backend
+            .create_subkey_transacted(&hklm, "SOFTWARE\\Vendor\\New", &transaction)
+            .unwrap();
+        backend.set_raw_value(&key, "Added", &RegValue::dword(5)).unwrap();
+        backend.commit(&transaction).unwrap();
+
+        let reopened = HiveBackend::open(&[mount("HKLM\\SOFTWARE", &file)]).unwrap();
+        let key = reopened.open_subkey(&hklm, "SOFTWARE\\Vendor\\New").unwrap();
+        assert_eq!(reopened.get_u32(&key, "Added").unwrap(), 5);
+        let test = reopened.open_subkey(&hklm, "SOFTWARE\\Vendor\\Test").unwrap();
+        assert_eq!(reopened.get_raw_value(&test, "Big").unwrap().bytes.len(), 40000);
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn a_rolled_back_transaction_leaves_the_file() {
+        let file = copy(SOFTWARE, "rollback");
+        let before = fs::read(&file).unwrap();
+        let backend = HiveBackend::open(&[mount("HKLM\\SOFTWARE", &file)]).unwrap();
+        let transaction = backend.transaction().unwrap();
+        let key = backend
+            .create_subkey_transacted(&backend.predef(Hive::LocalMachine), "SOFTWARE\\Vendor\\Test", &transaction)
+            .unwrap();
+        backend.set_raw_value(&key, "Dword", &RegValue::dword(1)).unwrap();
+        backend.rollback(&transaction).unwrap();
+        assert_eq!(fs::read(&file).unwrap(), before);
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn current_control_set_follows_select_current() {
+        let file = copy(SYSTEM, "select");
+        let backend = HiveBackend::open(&[mount("HKLM\\SYSTEM", &file)]).unwrap();
+        let hklm = backend.predef(Hive::LocalMachine);
+        assert_eq!(current_control_set(&backend).unwrap(), 2);
+        let service = backend
+            .open_subkey(&hklm, "SYSTEM\\CurrentControlSet\\Services\\Test")
+            .unwrap();
+        assert_eq!(backend.get_u32(&service, "Start").unwrap(), 3);
+
+        let transaction = backend.transaction().unwrap();
+        let key = backend
+            .create_subkey_transacted(&hklm, "SYSTEM\\CurrentControlSet\\Services\\Test", &transaction)
+            .unwrap();
+        backend.set_raw_value(&key, "Start", &RegValue::dword(4)).unwrap();
+        backend.commit(&transaction).unwrap();
+
+        let reopened = HiveBackend::open(&[mount("HKLM\\SYSTEM", &file)]).unwrap();
+        let start = |set: &str| {
+            let key = reopened
+                .open_subkey(&hklm, &format!("SYSTEM\\{}\\Services\\Test", set))
+                .unwrap();
+            reopened.get_u32(&key, "Start").unwrap()
+        };
+        assert_eq!(start("ControlSet002"), 4);
+        assert_eq!(start("ControlSet001"), 1);
+        assert!(reopened.open_subkey(&hklm, "SYSTEM\\CurrentControlSet").is_ok());
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn hash_leaves_match_the_names() {
+        let bytes = fs::read(SOFTWARE).unwrap();
+        let reader = Reader {
+            bins: &bytes[BASE_BLOCK..],
+            big_data: true,
+            security: Vec::new(),
+            seen: HashMap::new(),
+            visited: HashSet::new(),
+        };
+        let subkeys = |nk: u32| u32_at(reader.cell(nk).unwrap(), 0x1C).unwrap();
+        let root = u32_at(&bytes, 0x24).unwrap();
+        let vendor = u32_at(reader.cell(subkeys(root)).unwrap(), 4).unwrap();
+        let leaf = reader.cell(subkeys(vendor)).unwrap();
+        assert_eq!(&leaf[..2], b"lh");
+        for i in 0..u16_at(leaf, 2).unwrap() as usize {
+            let nk = reader.cell(u32_at(leaf, 4 + i * 8).unwrap()).unwrap();
+            let name = decode_name(
+                bytes_at(nk, 0x4C, u16_at(nk, 0x48).unwrap() as usize).unwrap(),
+                u16_at(nk, 0x02).unwrap() & KEY_COMP_NAME != 0,
+            );
+            assert_eq!(name_hash(&name), u32_at(leaf, 8 + i * 8).unwrap(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn a_key_linked_twice_is_refused() {
+        let (root, loaded) = read(SOFTWARE);
+        let mut bytes = write_hive(&root, &loaded);
+        // The first subkey of the root points back at the root
+        let root_offset = u32_at(&bytes, 0x24).unwrap();
+        let nk = BASE_BLOCK + root_offset as usize + 4;
+        let list = BASE_BLOCK + u32_at(&bytes, nk + 0x1C).unwrap() as usize + 4;
+        bytes[list + 4..list + 8].copy_from_slice(&root_offset.to_le_bytes());
+        let error = read_hive(&bytes, &loaded.mount).err().unwrap();
+        assert!(error.to_string().contains("linked more than once"), "{}", error);
+    }
+
+    #[test]
+    fn a_changed_key_gets_the_time_of_the_save() {
+        let file = copy(SOFTWARE, "stamp");
+        let written = |backend: &HiveBackend, path: &str| {
+            let path: Vec<String> = path.split('\\').map(String::from).collect();
+            backend.memory.with_node(Hive::LocalMachine, &path, |node| node.meta.last_written).unwrap()
+        };
+        let backend = HiveBackend::open(&[mount("HKLM\\SOFTWARE", &file)]).unwrap();
+        let test = written(&backend, "SOFTWARE\\Vendor\\Test");
+        let listed = written(&backend, "SOFTWARE\\Vendor\\Listed");
+        let transaction = backend.transaction().unwrap();
+        let key = backend
+            .open_subkey_transacted(&backend.predef(Hive::LocalMachine), "SOFTWARE\\Vendor\\Test", &transaction)
+            .unwrap();
+        backend.set_raw_value(&key, "Dword", &RegValue::dword(1)).unwrap();
+        backend.delete_value(&key, "Short").unwrap();
+        backend.commit(&transaction).unwrap();
+
+        let reopened = HiveBackend::open(&[mount("HKLM\\SOFTWARE", &file)]).unwrap();
+        assert!(written(&reopened, "SOFTWARE\\Vendor\\Test") > test);
+        assert_eq!(written(&reopened, "SOFTWARE\\Vendor\\Listed"), listed);
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn a_broken_checksum_is_refused() {
+        let mut bytes = fs::read(SYSTEM).unwrap();
+        bytes[0x0C] ^= 1;
+        let error = read_hive(&bytes, &mount("HKLM\\SYSTEM", Path::new(SYSTEM))).err().unwrap();
+        assert!(error.to_string().contains("checksum"));
+    }
+}
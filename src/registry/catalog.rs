@@ -0,0 +1,649 @@
+// The tweak catalog is a versioned TOML (or JSON) document, the built-in one
+// lives in catalog/default.toml and is compiled into the binary.
+
+use super::backend::{Hive, RegPath};
+use super::{
+    Either, ExpandStringElement, MultiStringElement, Settings, StringElement, U32Element, U64Element,
+    VecElement,
+};
+use crate::cpu::CpuTopology;
+use crate::selection::{Category, Risk, TweakInfo};
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+const BUILTIN: &str = include_str!("../../catalog/default.toml");
+const VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TweakValue {
+    Dword(u32),
+    Qword(u64),
+    Sz(String),
+    ExpandSz(String),
+    MultiSz(Vec<String>),
+    Binary(Vec<u8>),
+}
+
+// RSS values that are kept off the E-cores of a hybrid CPU, `value` is the one
+// for every other CPU
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CpuValue {
+    RssBaseCpu,
+    MaxNumRssCpus,
+}
+
+impl std::str::FromStr for CpuValue {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CpuValue, String> {
+        match s {
+            "rss-base-cpu" => Ok(CpuValue::RssBaseCpu),
+            "max-num-rss-cpus" => Ok(CpuValue::MaxNumRssCpus),
+            other => Err(format!(
+                "value_by_cpu `{}` is unknown, expected rss-base-cpu or max-num-rss-cpus",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "RawTweak")]
+pub struct Tweak {
+    // Stable name for --only/--skip, lowercase letters, digits and dashes
+    pub id: String,
+    pub category: Category,
+    pub risk: Risk,
+    pub key: RegPath,
+    pub name: String,
+    pub value: TweakValue,
+    // Values that depend on the display scaling, `value` is used for any other DPI
+    pub value_by_dpi: BTreeMap<u32, TweakValue>,
+    // A dword that is fitted to the CPU, see CpuValue
+    pub value_by_cpu: Option<CpuValue>,
+    // The Windows default, None means the value does not exist on a fresh install
+    pub default: Option<TweakValue>,
+    pub description: Option<String>,
+    // Shown by `list`
+    pub sources: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "RawCatalog")]
+pub struct Catalog {
+    pub tweaks: Vec<Tweak>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawCatalog {
+    version: u32,
+    #[serde(default)]
+    tweak: Vec<Tweak>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTweak {
+    id: String,
+    category: String,
+    risk: Option<String>,
+    // Without it the path starts with the hive, `HKLM\\SYSTEM\\...`
+    hive: Option<String>,
+    path: String,
+    name: String,
+    #[serde(rename = "type")]
+    vtype: String,
+    value: RawData,
+    #[serde(default)]
+    value_by_dpi: BTreeMap<String, RawData>,
+    value_by_cpu: Option<String>,
+    default: Option<RawData>,
+    description: Option<String>,
+    #[serde(default)]
+    sources: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawData {
+    Number(i64),
+    Text(String),
+    List(Vec<String>),
+}
+
+impl TryFrom<RawCatalog> for Catalog {
+    type Error = String;
+
+    fn try_from(raw: RawCatalog) -> Result<Catalog, String> {
+        if raw.version != VERSION {
+            return Err(format!(
+                "unsupported catalog version {}, expected {}",
+                raw.version, VERSION
+            ));
+        }
+        for (i, tweak) in raw.tweak.iter().enumerate() {
+            let duplicate = raw.tweak[..i]
+                .iter()
+                .any(|t| t.key == tweak.key && t.name.eq_ignore_ascii_case(&tweak.name));
+            if duplicate {
+                return Err(format!("duplicate tweak {}\\{}", tweak.key, tweak.name));
+            }
+            if raw.tweak[..i].iter().any(|t| t.id == tweak.id) {
+                return Err(format!("duplicate tweak id `{}`", tweak.id));
+            }
+        }
+        Ok(Catalog { tweaks: raw.tweak })
+    }
+}
+
+impl TryFrom<RawTweak> for Tweak {
+    type Error = String;
+
+    fn try_from(raw: RawTweak) -> Result<Tweak, String> {
+        if !is_id(&raw.id) {
+            return Err(format!(
+                "tweak `{}`: id `{}` may only contain lowercase letters, digits and dashes",
+                raw.name, raw.id
+            ));
+        }
+        if raw.id.parse::<Category>().is_ok() {
+            return Err(format!("tweak `{}`: id `{}` is already a category", raw.name, raw.id));
+        }
+        let category = raw
+            .category
+            .parse::<Category>()
+            .map_err(|e| format!("tweak `{}`: {}", raw.name, e))?;
+        let risk = match &raw.risk {
+            Some(risk) => risk.parse::<Risk>().map_err(|e| format!("tweak `{}`: {}", raw.name, e))?,
+            None => Risk::Low,
+        };
+        let key = match &raw.hive {
+            Some(hive) => {
+                let hive = hive.parse::<Hive>()?;
+                let path = raw.path.trim_matches('\\');
+                if path.is_empty() {
+                    return Err(format!("tweak `{}`: path must not be empty", raw.name));
+                }
+                let first = path.split('\\').next().unwrap_or_default();
+                if first.parse::<Hive>().is_ok() {
+                    return Err(format!(
+                        "tweak `{}`: path must be relative to the hive, use `hive` for {}",
+                        raw.name, first
+                    ));
+                }
+                RegPath::new(hive, path)
+            }
+            None => raw
+                .path
+                .parse::<RegPath>()
+                .map_err(|e| format!("tweak `{}`: {}", raw.name, e))?,
+        };
+
+        let name = raw.name;
+        let vtype = raw.vtype;
+        let parse = |what: &str, data: RawData| {
+            parse_value(&vtype, data).map_err(|e| format!("tweak `{}`: {} {}", name, what, e))
+        };
+        let value = parse("value", raw.value)?;
+        let default = match raw.default {
+            Some(data) => Some(parse("default", data)?),
+            None => None,
+        };
+        let mut value_by_dpi = BTreeMap::new();
+        for (dpi, data) in raw.value_by_dpi {
+            let dpi = dpi
+                .parse::<u32>()
+                .map_err(|_| format!("tweak `{}`: value_by_dpi key `{}` is not a DPI", name, dpi))?;
+            value_by_dpi.insert(dpi, parse("value_by_dpi", data)?);
+        }
+        let value_by_cpu = match &raw.value_by_cpu {
+            Some(_) if vtype != "dword" => {
+                return Err(format!("tweak `{}`: value_by_cpu needs type dword", name));
+            }
+            Some(by_cpu) => Some(by_cpu.parse::<CpuValue>().map_err(|e| format!("tweak `{}`: {}", name, e))?),
+            None => None,
+        };
+
+        Ok(Tweak {
+            id: raw.id,
+            category,
+            risk,
+            key,
+            name,
+            value,
+            value_by_dpi,
+            value_by_cpu,
+            default,
+            description: raw.description,
+            sources: raw.sources,
+        })
+    }
+}
+
+// Lowercase letters, digits and dashes
+pub(super) fn is_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+fn parse_value(vtype: &str, data: RawData) -> Result<TweakValue, String> {
+    match (vtype, data) {
+        ("dword", RawData::Number(n)) => u32::try_from(n)
+            .map(TweakValue::Dword)
+            .map_err(|_| format!("{} does not fit into a dword", n)),
+        ("qword", RawData::Number(n)) => u64::try_from(n)
+            .map(TweakValue::Qword)
+            .map_err(|_| format!("{} does not fit into a qword", n)),
+        ("sz", RawData::Text(s)) => Ok(TweakValue::Sz(s)),
+        ("expand_sz", RawData::Text(s)) => Ok(TweakValue::ExpandSz(s)),
+        ("multi_sz", RawData::List(list)) => match list.iter().find(|s| s.is_empty() || s.contains('\u{0}')) {
+            Some(_) => Err(String::from("must not contain empty strings for type multi_sz")),
+            None => Ok(TweakValue::MultiSz(list)),
+        },
+        ("binary", RawData::Text(s)) => parse_hex(&s).map(TweakValue::Binary),
+        ("dword", _) => Err(String::from("must be a number for type dword")),
+        ("qword", _) => Err(String::from("must be a number for type qword")),
+        ("sz", _) => Err(String::from("must be a string for type sz")),
+        ("expand_sz", _) => Err(String::from("must be a string for type expand_sz")),
+        ("multi_sz", _) => Err(String::from("must be a list of strings for type multi_sz")),
+        ("binary", _) => Err(String::from("must be a hex string like \"00,ff\" for type binary")),
+        (other, _) => Err(format!(
+            "has unknown type `{}`, expected dword, qword, sz, expand_sz, multi_sz or binary",
+            other
+        )),
+    }
+}
+
+// "00,01,ff" with any whitespace or line breaks between the bytes
+pub(super) fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|b| !b.is_empty())
+        .map(|b| u8::from_str_radix(b, 16).map_err(|_| format!("has invalid hex byte `{}`", b)))
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct CatalogError {
+    pub file: String,
+    // 1-based line and column, when the parser knows them
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.position {
+            Some((line, col)) => write!(f, "{}:{}:{}: {}", self.file, line, col, self.message),
+            None => write!(f, "{}: {}", self.file, self.message),
+        }
+    }
+}
+
+// Both parsers append " at line X column Y", the position is printed up front instead
+pub(super) fn strip_position(message: String) -> String {
+    match message.rfind(" at line ") {
+        Some(index) => message[..index].to_string(),
+        None => message,
+    }
+}
+
+// Index of the first entry of the `table` array that does not parse on its own
+fn failing_entry<T: DeserializeOwned>(text: &str, table: &str) -> Option<usize> {
+    let document = text.parse::<toml::Value>().ok()?;
+    document
+        .get(table)?
+        .as_array()?
+        .iter()
+        .position(|entry| entry.clone().try_into::<T>().is_err())
+}
+
+fn entry_header(text: &str, table: &str, index: usize) -> Option<(usize, usize)> {
+    let header = format!("[[{}]]", table);
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| line.trim_start().starts_with(&header))
+        .nth(index)
+        .map(|(n, line)| (n + 1, line.len() - line.trim_start().len() + 1))
+}
+
+// Where a document fails whose entries all parse on their own: the entry that
+// clashes with an earlier one, or the version when it fails without entries
+fn conflict_position<D: DeserializeOwned>(text: &str, table: &str) -> Option<(usize, usize)> {
+    let document = text.parse::<toml::Value>().ok()?;
+    let entries = match document.get(table) {
+        Some(entries) => entries.as_array()?.clone(),
+        None => Vec::new(),
+    };
+    let fails = |count: usize| {
+        let mut prefix = document.clone();
+        if let Some(root) = prefix.as_table_mut() {
+            root.insert(table.to_string(), toml::Value::Array(entries[..count].to_vec()));
+        }
+        prefix.try_into::<D>().is_err()
+    };
+    match (0..=entries.len()).find(|&count| fails(count))? {
+        0 => key_line(text, "version"),
+        count => entry_header(text, table, count - 1),
+    }
+}
+
+// A key above the first table
+fn key_line(text: &str, key: &str) -> Option<(usize, usize)> {
+    text.lines()
+        .enumerate()
+        .take_while(|(_, line)| !line.trim_start().starts_with('['))
+        .find(|(_, line)| {
+            let line = line.trim_start();
+            line.strip_prefix(key).is_some_and(|rest| rest.trim_start().starts_with('='))
+        })
+        .map(|(n, line)| (n + 1, line.len() - line.trim_start().len() + 1))
+}
+
+// Parses a document made of `[[table]]` entries of type T
+pub(super) fn from_toml<D: DeserializeOwned, T: DeserializeOwned>(
+    file: &str,
+    text: &str,
+    table: &str,
+) -> Result<D, CatalogError> {
+    toml::from_str(text).map_err(|e| {
+        let mut position = e.line_col().map(|(line, col)| (line + 1, col + 1));
+        let mut message = strip_position(e.to_string());
+        // toml only knows where the array starts, point at the broken entry instead
+        if let Some(header) = failing_entry::<T>(text, table).and_then(|index| entry_header(text, table, index)) {
+            position = Some(header);
+            if let Some(index) = message.rfind(&format!(" for key `{}", table)) {
+                message.truncate(index);
+            }
+        } else if position.is_none() {
+            position = conflict_position::<D>(text, table);
+        }
+        CatalogError {
+            file: file.to_string(),
+            position,
+            message,
+        }
+    })
+}
+
+pub(super) fn from_json<D: DeserializeOwned>(file: &str, text: &str) -> Result<D, CatalogError> {
+    serde_json::from_str(text).map_err(|e| CatalogError {
+        file: file.to_string(),
+        position: Some((e.line(), e.column())).filter(|(line, _)| *line > 0),
+        message: strip_position(e.to_string()),
+    })
+}
+
+// TOML unless the file ends with .json
+pub(super) fn read_document<D: DeserializeOwned, T: DeserializeOwned>(path: &Path, table: &str) -> Result<D, CatalogError> {
+    let file = path.display().to_string();
+    let text = fs::read_to_string(path).map_err(|e| CatalogError {
+        file: file.clone(),
+        position: None,
+        message: e.to_string(),
+    })?;
+    if is_json(path) {
+        from_json(&file, &text)
+    } else {
+        from_toml::<D, T>(&file, &text, table)
+    }
+}
+
+pub(super) fn is_json(path: &Path) -> bool {
+    matches!(path.extension().and_then(|e| e.to_str()), Some(ext) if ext.eq_ignore_ascii_case("json"))
+}
+
+impl Catalog {
+    pub fn builtin() -> Catalog {
+        Catalog::from_toml("catalog/default.toml", BUILTIN).expect("embedded catalog is invalid")
+    }
+
+    pub fn from_file(path: &Path) -> Result<Catalog, CatalogError> {
+        read_document::<Catalog, Tweak>(path, "tweak")
+    }
+
+    pub fn from_toml(file: &str, text: &str) -> Result<Catalog, CatalogError> {
+        from_toml::<Catalog, Tweak>(file, text, "tweak")
+    }
+
+    // Resolves the DPI and CPU dependent values and groups the kept tweaks by key
+    pub fn settings(&self, dpi: u32, cpu: Option<&CpuTopology>, keep: impl Fn(&Tweak) -> bool) -> Settings {
+        let rss = cpu.map(|cpu| {
+            // The Windows defaults stand in for a value the catalog does not have
+            let base = self.dword_by_cpu(CpuValue::RssBaseCpu).unwrap_or(0);
+            let count = self.dword_by_cpu(CpuValue::MaxNumRssCpus).unwrap_or(u32::MAX);
+            cpu.rss_cpus(base, count)
+        });
+        group_tweaks(self.tweaks.iter().filter(|t| keep(t)), dpi, rss)
+    }
+
+    fn dword_by_cpu(&self, by_cpu: CpuValue) -> Option<u32> {
+        match self.tweaks.iter().find(|t| t.value_by_cpu == Some(by_cpu))?.value {
+            TweakValue::Dword(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+// `rss` is the RSS base CPU and count for this CPU, None keeps the values of the tweaks
+pub(super) fn group_tweaks<'a>(tweaks: impl Iterator<Item = &'a Tweak>, dpi: u32, rss: Option<(u32, u32)>) -> Settings {
+    let mut settings = Settings::default();
+    for tweak in tweaks {
+        settings.push(tweak.key.clone(), tweak.element(dpi, rss));
+    }
+    settings
+}
+
+impl Tweak {
+    pub fn info(&self) -> TweakInfo {
+        let description = match self.description.as_deref().and_then(|d| d.lines().next()) {
+            Some(line) if !line.trim().is_empty() => line.trim().to_string(),
+            _ => format!("{}\\{}", self.key, self.name),
+        };
+        TweakInfo {
+            id: self.id.clone(),
+            category: Some(self.category),
+            risk: self.risk,
+            description,
+        }
+    }
+
+    // A pinned ControlSetNNN is not followed when Windows boots another control set
+    pub fn warnings(&self) -> Vec<String> {
+        match self.key.pinned_control_set() {
+            Some(set) => vec![format!(
+                "tweak `{}` pins SYSTEM\\ControlSet{:03}, CurrentControlSet is the one Windows runs with",
+                self.id, set
+            )],
+            None => Vec::new(),
+        }
+    }
+
+    // A tweak with values by DPI falls back to `value` on any other display scaling
+    pub fn dpi_warning(&self, dpi: u32) -> Option<String> {
+        match self.value_by_dpi.is_empty() || self.value_by_dpi.contains_key(&dpi) {
+            true => None,
+            false => Some(format!("tweak `{}` has no value for {} DPI, `value` is written", self.id, dpi)),
+        }
+    }
+
+    fn element(&self, dpi: u32, rss: Option<(u32, u32)>) -> Either {
+        let by_cpu = match (self.value_by_cpu, rss) {
+            (Some(CpuValue::RssBaseCpu), Some((base, _))) => Some(TweakValue::Dword(base)),
+            (Some(CpuValue::MaxNumRssCpus), Some((_, count))) => Some(TweakValue::Dword(count)),
+            _ => None,
+        };
+        let value = self.value_by_dpi.get(&dpi).or(by_cpu.as_ref()).unwrap_or(&self.value);
+        let key = self.name.clone();
+        match (value, &self.default) {
+            (TweakValue::Dword(value), default) => Either::U32Element(U32Element {
+                key,
+                value: *value,
+                default: match default {
+                    Some(TweakValue::Dword(d)) => Some(*d),
+                    _ => None,
+                },
+            }),
+            (TweakValue::Qword(value), default) => Either::U64Element(U64Element {
+                key,
+                value: *value,
+                default: match default {
+                    Some(TweakValue::Qword(d)) => Some(*d),
+                    _ => None,
+                },
+            }),
+            (TweakValue::Sz(value), default) => Either::StringElement(StringElement {
+                key,
+                value: value.clone(),
+                default: match default {
+                    Some(TweakValue::Sz(d)) => Some(d.clone()),
+                    _ => None,
+                },
+            }),
+            (TweakValue::ExpandSz(value), default) => Either::ExpandStringElement(ExpandStringElement {
+                key,
+                value: value.clone(),
+                default: match default {
+                    Some(TweakValue::ExpandSz(d)) => Some(d.clone()),
+                    _ => None,
+                },
+            }),
+            (TweakValue::MultiSz(value), default) => Either::MultiStringElement(MultiStringElement {
+                key,
+                value: value.clone(),
+                default: match default {
+                    Some(TweakValue::MultiSz(d)) => Some(d.clone()),
+                    _ => None,
+                },
+            }),
+            (TweakValue::Binary(value), default) => Either::VecElement(VecElement {
+                key,
+                value: value.clone(),
+                default: match default {
+                    Some(TweakValue::Binary(d)) => Some(d.clone()),
+                    _ => None,
+                },
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIRST: &str = r#"[[tweak]]
+id = "first"
+category = "network"
+path = 'HKLM\SOFTWARE\Test'
+name = "First"
+type = "dword"
+value = 1
+"#;
+
+    fn error(text: &str) -> CatalogError {
+        Catalog::from_toml("test.toml", text).err().unwrap()
+    }
+
+    #[test]
+    fn a_catalog_file_in_toml_or_json() {
+        let dir = std::env::temp_dir();
+        let toml = dir.join(format!("gaming-optimizer-catalog-{}.toml", std::process::id()));
+        let json = toml.with_extension("json");
+        let described = "risk = \"high\"\ndescription = \"\"\"\nFaster\nand longer\"\"\"\n\
+            sources = [\"https://example.com\"]\n";
+        fs::write(&toml, format!("version = 1\n\n{}{}", FIRST, described)).unwrap();
+        fs::write(
+            &json,
+            r#"{"version": 1, "tweak": [{"id": "first", "category": "network", "path": "HKLM\\SOFTWARE\\Test",
+                "name": "First", "type": "dword", "value": 1}]}"#,
+        )
+        .unwrap();
+        let (from_toml, from_json) = (Catalog::from_file(&toml), Catalog::from_file(&json));
+        fs::remove_file(&toml).unwrap();
+        fs::remove_file(&json).unwrap();
+
+        let tweak = &from_toml.unwrap().tweaks[0];
+        assert_eq!(tweak.sources, vec!["https://example.com"]);
+        let info = tweak.info();
+        assert_eq!((info.id.as_str(), info.risk, info.description.as_str()), ("first", Risk::High, "Faster"));
+        // Without a description the value is shown
+        let info = from_json.unwrap().tweaks[0].info();
+        assert_eq!((info.risk, info.category), (Risk::Low, Some(Category::Network)));
+        assert_eq!(info.description, "HKEY_LOCAL_MACHINE\\SOFTWARE\\Test\\First");
+        assert!(Catalog::from_file(&toml).err().unwrap().position.is_none());
+    }
+
+    #[test]
+    fn a_bad_type_points_at_its_entry() {
+        let second = FIRST.replace("first", "second").replace("First", "Second").replace("dword", "word");
+        let e = error(&format!("version = 1\n\n{}\n{}", FIRST, second));
+        assert_eq!(e.position, Some((11, 1)));
+        assert_eq!(
+            e.message,
+            "tweak `Second`: value has unknown type `word`, expected dword, qword, sz, expand_sz, multi_sz or binary"
+        );
+    }
+
+    #[test]
+    fn a_duplicate_points_at_the_second_entry() {
+        let e = error(&format!("version = 1\n\n{}\n{}", FIRST, FIRST.replace("First", "Other")));
+        assert_eq!(e.position, Some((11, 1)));
+        assert_eq!(e.message, "duplicate tweak id `first`");
+        // The same value under another id, indented
+        let third = FIRST.replace("first", "third").replace("First", "Third");
+        let other = FIRST.replace("first", "other").replace("[[", "  [[");
+        let e = error(&format!("version = 1\n\n{}\n{}\n{}", FIRST, third, other));
+        assert_eq!(e.position, Some((19, 3)));
+        assert_eq!(e.message, "duplicate tweak HKEY_LOCAL_MACHINE\\SOFTWARE\\Test\\First");
+    }
+
+    #[test]
+    fn an_unsupported_version_points_at_the_version() {
+        let e = error(&format!("# A catalog of a newer release\n\nversion = 2\n\n{}", FIRST));
+        assert_eq!(e.position, Some((3, 1)));
+        assert_eq!(e.message, "unsupported catalog version 2, expected 1");
+        assert_eq!(error("version = 3\n").position, Some((1, 1)));
+    }
+
+    #[test]
+    fn a_missing_dpi_is_a_warning() {
+        let text = format!("version = 1\n\n{}value_by_dpi = {{ 96 = 2, 144 = 3 }}\n", FIRST);
+        let catalog = Catalog::from_toml("test.toml", &text).unwrap();
+        let tweak = &catalog.tweaks[0];
+        assert_eq!(tweak.dpi_warning(144), None);
+        assert_eq!(
+            tweak.dpi_warning(120).as_deref(),
+            Some("tweak `first` has no value for 120 DPI, `value` is written")
+        );
+        let plain = Catalog::from_toml("test.toml", &format!("version = 1\n\n{}", FIRST)).unwrap();
+        assert_eq!(plain.tweaks[0].dpi_warning(120), None);
+    }
+
+    #[test]
+    fn the_builtin_tweaks_follow_the_running_control_set() {
+        let catalog = Catalog::builtin();
+        assert!(catalog.tweaks.iter().all(|t| t.warnings().is_empty()));
+        let preemption = catalog.tweaks.iter().find(|t| t.id == "gpu-enable-preemption").unwrap();
+        assert_eq!(
+            preemption.key,
+            RegPath::new(Hive::LocalMachine, "SYSTEM\\CurrentControlSet\\Control\\GraphicsDrivers\\Scheduler")
+        );
+    }
+
+    #[test]
+    fn a_pinned_control_set_is_a_warning() {
+        let pinned = FIRST.replace("SOFTWARE\\Test", "System\\controlset002\\Control\\Test");
+        let catalog = Catalog::from_toml("test.toml", &format!("version = 1\n\n{}", pinned)).unwrap();
+        assert_eq!(
+            catalog.tweaks[0].warnings(),
+            vec!["tweak `first` pins SYSTEM\\ControlSet002, CurrentControlSet is the one Windows runs with"]
+        );
+    }
+}
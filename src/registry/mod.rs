@@ -1,1519 +1,1256 @@
+pub mod backend;
+pub mod catalog;
+pub mod perf_options;
+pub mod profile;
+pub mod regfile;
+pub mod report;
 mod set;
-mod powerplan;
+pub mod snapshot;
+pub mod users;
+mod power_settings;
+pub mod powerplan;
 
+use backend::{Hive, RegPath, RegValue, RegistryBackend};
 use set::*;
+pub use set::delete_tree_transacted;
 
-use winreg::enums::{
-    HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, HKEY_USERS, KEY_ALL_ACCESS, KEY_READ, REG_DWORD,
-};
-use winreg::transaction::Transaction;
-use winreg::RegKey;
-use winreg::RegValue;
-
-// println!("\x1b[0;92m INFO \x1b[0m");
-// println!("\x1b[0;93m WARN \x1b[0m");
-// println!("\x1b[0;91m ERR \x1b[0m");
+use report::{CheckOutcome, CheckResult, Report};
+use crate::error::{self, Error};
+use crate::selection::{Category, Risk, Selection, TweakInfo};
 
+#[derive(Clone)]
 struct U32Element {
     key: String,
     value: u32,
     default: Option<u32>,
 }
 
+#[derive(Clone)]
+struct U64Element {
+    key: String,
+    value: u64,
+    default: Option<u64>,
+}
+
+#[derive(Clone)]
 struct StringElement {
     key: String,
     value: String,
     default: Option<String>,
 }
 
+// A REG_EXPAND_SZ, Windows expands the %variables% when the value is read
+#[derive(Clone)]
+struct ExpandStringElement {
+    key: String,
+    value: String,
+    default: Option<String>,
+}
+
+// A REG_MULTI_SZ, e.g. a list of services
+#[derive(Clone)]
+struct MultiStringElement {
+    key: String,
+    value: Vec<String>,
+    default: Option<Vec<String>>,
+}
+
+#[derive(Clone)]
 struct VecElement {
     key: String,
     value: Vec<u8>,
     default: Option<Vec<u8>>,
 }
 
+// Any value type from the hex(n) data of a .reg file
+#[derive(Clone)]
+struct RawElement {
+    key: String,
+    value: RegValue,
+    default: Option<RegValue>,
+}
+
+// A value that has to be absent
+#[derive(Clone)]
+struct DeleteElement {
+    key: String,
+}
+
+#[derive(Clone)]
 struct RegTweaks {
-    path: String,
+    path: RegPath,
+    // The whole key has to be absent, `data` is empty then
+    remove: bool,
     data: Vec<Either>,
 }
 
+#[derive(Clone)]
+#[allow(clippy::enum_variant_names)]
 enum Either {
-    #[allow(dead_code)]
     StringElement(StringElement),
+    ExpandStringElement(ExpandStringElement),
+    MultiStringElement(MultiStringElement),
     U32Element(U32Element),
+    U64Element(U64Element),
     VecElement(VecElement),
+    RawElement(RawElement),
+    DeleteElement(DeleteElement),
 }
 
+impl Either {
+    fn name(&self) -> &str {
+        match self {
+            Either::StringElement(ele) => &ele.key,
+            Either::ExpandStringElement(ele) => &ele.key,
+            Either::MultiStringElement(ele) => &ele.key,
+            Either::U32Element(ele) => &ele.key,
+            Either::U64Element(ele) => &ele.key,
+            Either::VecElement(ele) => &ele.key,
+            Either::RawElement(ele) => &ele.key,
+            Either::DeleteElement(ele) => &ele.key,
+        }
+    }
+
+    // None when the value has to be absent
+    fn value(&self) -> Option<RegValue> {
+        match self {
+            Either::StringElement(ele) => Some(RegValue::sz(&ele.value)),
+            Either::ExpandStringElement(ele) => Some(RegValue::expand_sz(&ele.value)),
+            Either::MultiStringElement(ele) => Some(RegValue::multi_sz(&ele.value)),
+            Either::U32Element(ele) => Some(RegValue::dword(ele.value)),
+            Either::U64Element(ele) => Some(RegValue::qword(ele.value)),
+            Either::VecElement(ele) => Some(RegValue::binary(ele.value.clone())),
+            Either::RawElement(ele) => Some(ele.value.clone()),
+            Either::DeleteElement(_) => None,
+        }
+    }
+
+    // The Windows default, None when the value does not exist on a fresh install
+    fn default_value(&self) -> Option<RegValue> {
+        match self {
+            Either::StringElement(ele) => ele.default.as_deref().map(RegValue::sz),
+            Either::ExpandStringElement(ele) => ele.default.as_deref().map(RegValue::expand_sz),
+            Either::MultiStringElement(ele) => ele.default.as_deref().map(RegValue::multi_sz),
+            Either::U32Element(ele) => ele.default.map(RegValue::dword),
+            Either::U64Element(ele) => ele.default.map(RegValue::qword),
+            Either::VecElement(ele) => ele.default.clone().map(RegValue::binary),
+            Either::RawElement(ele) => ele.default.clone(),
+            Either::DeleteElement(_) => None,
+        }
+    }
+}
+
+// The registry tweaks grouped by key, in the order of the catalog or .reg file
+#[derive(Clone, Default)]
 pub struct Settings {
-    local_machine: Vec<RegTweaks>,
-    current_user: Vec<RegTweaks>,
-    users: Vec<RegTweaks>,
+    keys: Vec<RegTweaks>,
 }
 
+// The indices a setting gets when running on AC power and on battery
 struct SettingGuid {
     path: String,
-    data: u32,
-
+    // PROCTHROTTLEMIN, ..., from catalog/power.toml
+    alias: String,
+    // Windows hides it in the control panel, see unhide_power_settings
+    hidden: bool,
+    ac: u32,
+    dc: u32,
 }
 struct SubGuid {
     path: String,
     data: Vec<SettingGuid>,
 }
 
+// The settings of the gaming scheme, which starts as a copy of `base`
 pub struct PowerPlan {
-    scheme: Option<String>,
+    base: String,
     data: Vec<SubGuid>,
 }
 
-pub fn factory_settings(dpi: &u32) -> Settings {
-    let local_machine = vec![
-        RegTweaks {
-            path: String::from(
-                "SOFTWARE\\Policies\\Microsoft\\Windows\\GameDVR",
-            ),
-            data: vec![
-                // Windows Game Recording and Broadcasting.
-                // This setting enables or disables the Windows Game Recording and Broadcasting features. If you disable this setting, Windows Game Recording will not be allowed.
-                // If the setting is enabled or not configured, then Recording and Broadcasting (streaming) will be allowed.
-                // https://admx.help/?Category=Windows_10_2016&Policy=Microsoft.Policies.GameDVR::AllowGameDVR
-                Either::U32Element(U32Element {
-                    key: String::from("AllowGameDVR"),
-                    value: 0u32,
-                    default: None,
-                }),
-            ],
-        },
-        RegTweaks { // macht wohl nur mit QoS Sinn
-            path: String::from(
-                "SOFTWARE\\Policies\\Microsoft\\Windows\\Psched",
-            ),
-            data: vec![
-                // https://www.overclock.net/threads/network-stack-packet-scheduler-timer-resolution.1744292/
-                // http://systemmanager.ru/win2k_regestry.en/94171.htm
-                Either::U32Element(U32Element {
-                    key: String::from("TimerResolution"),
-                    value: 1u32,
-                    default: None, // TODO: 
-                }),
-                // https://thomasknoefel.de/tag/nonbesteffortlimit/
-                Either::U32Element(U32Element {
-                    key: String::from("NonBestEffortLimit"),
-                    value: 0u32,
-                    default: None, // TODO: 
-                }),
-            ],
-        },
-        RegTweaks {
-            path: String::from(
-                "SOFTWARE\\Microsoft\\MSMQ\\Parameters",
-            ),
-            data: vec![
-                Either::U32Element(U32Element {
-                    key: String::from("TcpNoDelay"),
-                    value: 1u32,
-                    default: None,
-                }),
-
-            ],
-        },
-        RegTweaks {
-            path: String::from(
-                "SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion\\Multimedia\\SystemProfile",
-            ),
-            data: vec![
-                // https://github.com/djdallmann/GamingPCSetup/blob/master/CONTENT/DOCS/NETWORK/README.md#operating-system-specific-configuration
-                // https://github.com/djdallmann/GamingPCSetup/blob/master/CONTENT/RESEARCH/NETWORK/README.md#networkthrottlingindex
-                Either::U32Element(U32Element {
-                    key: String::from("NetworkThrottlingIndex"),
-                    // value: 0xffff_ffff_u32,
-                    value: 20_u32,
-                    default: Some(10u32)
-                }),
-                Either::U32Element(U32Element {
-                    key: String::from("SystemResponsiveness"),
-                    value: 0u32, // value of 0 is also treated as 10 // https://docs.microsoft.com/en-us/windows/win32/procthread/multimedia-class-scheduler-service
-                    default: Some(20u32)
-                }),
-            ],
-        },
-        // https://docs.microsoft.com/en-us/windows/win32/procthread/multimedia-class-scheduler-service
-        RegTweaks {
-            path: String::from(
-                "SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion\\Multimedia\\SystemProfile\\Tasks\\Games",
-            ),
-            data: vec![
-                // NoLazyMode https://github.com/djdallmann/GamingPCSetup/blob/master/CONTENT/RESEARCH/WINSERVICES/README.md#q-what-the-heck-is-nolazymode-is-it-real-what-does-it-do
-
-                Either::U32Element(U32Element {
-                    key: String::from("Priority"), // The task priority. The range of values is 1 (low) to 8 (high).For tasks with a Scheduling Category of High, this value is always treated as 2.
-                    value: 8u32,
-                    default: Some(2u32),
-                }),
-            ],
-        },
-
-        // https://www.overclock.net/threads/research-on-multimedia-class-scheduler-service-mmcss.1774590/
-        // The most commonly requested task is Audio, this will occur naturally when Windows applications make requests to Microsofts High Level Apis for Audio playback.
-        RegTweaks {
-            path: String::from(
-                "SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion\\Multimedia\\SystemProfile\\Tasks\\Pro Audio",
-            ),
-            data: vec![
-                Either::U32Element(U32Element {
-                    key: String::from("Priority"),
-                    value: 8u32,
-                    default: Some(2u32),
-                }),
-                Either::StringElement(StringElement {
-                    key: String::from("Scheduling Category"),
-                    value: String::from("Medium"),
-                    default: Some(String::from("High")),
-                }),
-            ],
-        },
-        RegTweaks {
-            path: String::from(
-                "SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion\\Multimedia\\SystemProfile\\Tasks\\Audio",
-            ),
-            data: vec![
-                Either::U32Element(U32Element {
-                    key: String::from("Priority"),
-                    value: 8u32,
-                    default: Some(2u32),
-                }),
-            ],
-        },
-
-        // https://www.overclock.net/threads/gaming-and-mouse-response-bios-optimization-guide-for-modern-pc-hardware.1433882/page-213#post-28561474
-        RegTweaks {
-            path: String::from(
-                "SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion\\Windows",
-            ),
-            data: vec![
-                Either::U32Element(U32Element {
-                    key: String::from("DwmInputUsesIoCompletionPort "),
-                    value: 0_u32,
-                    default: Some(1u32)
-                }),
-                Either::U32Element(U32Element {
-                    key: String::from("EnableDwmInputProcessing"),
-                    value: 0u32,
-                    default: Some(7u32)
-                }),
-            ],
-        },
+impl PowerPlan {
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+}
 
-        // Turn On Windows Hardware Accelerated GPU Scheduling
-        RegTweaks {
-            path: String::from(
-                "SYSTEM\\CurrentControlSet\\Control\\GraphicsDrivers",
-            ),
-            data: vec![
-                Either::U32Element(U32Element {
-                    key: String::from("HwSchMode"),
-                    value: 2u32,
-                    default: None, // TODO:
-                }),
-            ],
-        },
+// `hybrid` CPUs also get the settings of their E-cores
+#[cfg(windows)]
+pub fn factory_powerplan(hybrid: bool) -> PowerPlan {
+    PowerCatalog::builtin().plan(hybrid)
+}
 
-        RegTweaks {
-            path: String::from(
-                "SYSTEM\\CurrentControlSet\\Control\\Nsi\\{eb004a03-9b1a-11d4-9123-0050047759bc}\\0",
-            ), 
-            data: vec![
-                Either::VecElement(VecElement {
-                    key: String::from("0200"),
-                    value: vec![
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x1e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
-                    ],
-                    default: None, // TODO:
-                }),
-                Either::VecElement(VecElement {
-                    key: String::from("1700"),
-                    value: vec![
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
-                    ],
-                    default: None, // TODO:
-                }),
-            ],
-        },
+pub const POWER_SCHEMES: &str = "SYSTEM\\CurrentControlSet\\Control\\Power\\User\\PowerSchemes";
+// The definitions of the settings, their Attributes apply to every scheme
+pub const POWER_SETTINGS: &str = "SYSTEM\\CurrentControlSet\\Control\\Power\\PowerSettings";
 
-        // https://www.speedguide.net/articles/windows-10-manual-tcpip-registry-tweaks-7507
-        // https://core.ac.uk/download/pdf/288500221.pdf
-        // https://www.sciencedirect.com/topics/computer-science/congestion-indicator
-        RegTweaks {
-            path: String::from(
-                "SYSTEM\\CurrentControlSet\\Control\\Nsi\\{eb004a03-9b1a-11d4-9123-0050047759bc}\\26",
-            ),
-            data: vec![
-                // "00000000" - Internet (this template is default in later Windows 10 builds)
-                // "04000000" - InternetCustom (this template was used in earlier versions of Windows 8/10, possibly with CTCP as the default CongestionProvider)
-                Either::VecElement(VecElement {
-                    key: String::from("00000000"),
-                    // 00 - none
-                    // 01 - NEWRENO
-                    // 02 - CTCP
-                    // 03 - DCTCP
-                    // 04 - LEDBAT (where available)
-                    // 05 - CUBIC
-                    value: vec![
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                        0x00,
-                    ],
-                    default: None,
-                }),
-            ],
-        },
+pub const POWERPLAN: &str = "powerplan-high-performance";
 
-        // https://docs.google.com/document/d/1c2-lUJq74wuYK1WrA_bIvgb89dUN0sj8-hO3vqmrau4/edit#
-        // Open regedit and go to:
-        // [HKEY_LOCAL_MACHINE\SYSTEM\ControlSet001\Control\PriorityControl]
-        // Add together the decimal values you want and enter that as a decimal to the Win32PrioritySeparation key. Example: 32+4+2. (You cannot use the third column unless you use variable quantum. If you are using fixed quantum, ignore the third column.)
-        // Decimal 40 theoretically would provide the most responsive input at the expense of smoothness and FPS (short, fixed, no boost). Decimal 22 should provide the smoothest gameplay. Dec 37 is a mix between 40 and 38. There is no set answer here, so feel free to try out lots of options. There is no restart required so you can leave regedit open and keep trying different values while having your game open.
-        // Possible options: decimal 21, 22, 24, 37, 38, 40
-        RegTweaks {
-            path: String::from(
-                "SYSTEM\\CurrentControlSet\\Control\\PriorityControl",
-            ),
-            data: vec![
-                Either::U32Element(U32Element {
-                    key: String::from("Win32PrioritySeparation"),
-                    value: 22u32, // 01 01 10 Longer intervals, Variable-length intervals, 3 : 1. The threads of foreground processes three times as much processor time than the threads of background.
-                    default: Some(2u32),
-                }),
-            ],
-        },
+#[cfg(windows)]
+pub use power_settings::PowerCatalog;
+pub use powerplan::{PowerConfig, PowerSource, Powercfg, GAMING_SCHEME, GAMING_SCHEME_NAME};
 
-        // RegTweaks {
-        //     path: String::from(
-        //         "SYSTEM\\CurrentControlSet\\Control\\Session Manager\\Memory Management",
-        //     ),
-        //     data: vec![
-        //         Either::U32Element(U32Element {
-        //             key: String::from("LargeSystemCache"),
-        //             value: 0u32, // 0 is default
-        //         }),
-        //     ],
-        // },
-
-//         RegTweaks {
-//             path: String::from(
-//                 "SYSTEM\\CurrentControlSet\\Services\\AFD\\parameters", // uninstall delete keys
-//             ),
-//             data: vec![
-//                 // https://answers.sap.com/questions/75047/do-you-configure-datagram-size-to-1500-according-t.html
-//                 Either::U32Element(U32Element {
-//                     key: String::from("FastSendDatagramThreshold"),
-//                     // value: 1024u32,
-//                     value: 16384u32,
-//                     // value: *mtu,
-//                     default: None,
-//                 }),
-
-//                 // When an application posts a receive with a buffer that is smaller than the current packet being buffered by Winsock, AFD can either make an additional copy of the packet and then copy data to the application buffers directly (two-stage copy because application buffers cannot be accessed directly under the lock), or it can lock and map application buffers and copy data once. This value represents a compromise between extra code execution for data copying, and extra code execution in the I/O subsystem and memory manager.
-//                 Either::U32Element(U32Element {
-//                     key: String::from("FastCopyReceiveThreshold"),
-//                     // value: 1024u32,
-//                     value: 16384u32,
-//                     // value: *mtu,
-//                     default: None,
-//                 }),
-
-// // https://github.com/danskee/AutoTweakingUtility/blob/b421397414d204a804e51a6d3d7b9a8413a56364/NetworkTweaksDialog.cs
-// // https://github.com/sale1977/WindowsUnity/blob/main/SetupComplete.cmd
-// // https://github.com/ArtanisInc/Post-Tweaks/blob/7bce01d1ebe9b5f61f7909139d067da03365dc25/PostTweaks.bat
-// // https://github.com/SmurfsCC/FPS-Booster-/blob/8ce6c086407ffcd1aac26b17a560cdb9b6310618/11%20-%20Regedit%20FPS/11%20-%20DesktopWin10.reg
-//                 // Either::U32Element(U32Element {
-//                 //     key: String::from("DisableRawSecurity"),
-//                 //     value: 1u32,
-//                 //     default: None,
-//                 // }),
-//                 // Either::U32Element(U32Element {
-//                 //     key: String::from("DynamicSendBufferDisable"),
-//                 //     value: 0u32,
-//                 //     default: None,
-//                 // }),
-//                 // Either::U32Element(U32Element {
-//                 //     key: String::from("IrpStackSize"),
-//                 //     value: 50u32,
-//                 //     default: None,
-//                 // }),
-//                 // Either::U32Element(U32Element {
-//                 //     key: String::from("PriorityBoost"),
-//                 //     value: 0u32,
-//                 //     default: None,
-//                 // }),
-//                 // Either::U32Element(U32Element {
-//                 //     key: String::from("DoNotHoldNicBuffers"),
-//                 //     value: 1u32,
-//                 //     default: None,
-//                 // }),
-//             ],
-//         },
-
-        // The LanmanServer service allows your computer to share files and printers with other devices on your network.
-        // RegTweaks {
-        //     path: String::from(
-        //         "SYSTEM\\CurrentControlSet\\Services\\LanmanServer\\parameters",
-        //     ),
-        //     data: vec![
-        //         // https://docs.mellanox.com/display/winof2/Performance+Tuning
-        //         // In order to improve live migration over SMB direct performance, please set the following registry key to 0 and reboot the machine:
-        //         Either::U32Element(U32Element {
-        //             key: String::from("RequireSecuritySignature"),
-        //             value: 0u32,
-        //         }),
-
-        //         // Specifies the size of the request buffers that the Server service uses.
-        //         // Small buffers use less memory, but large buffers can improve performance.
-        //         // For computers running Windows Server 2003 and with 512 MB or more of physical memory, the default size of the request buffers is 16,644 bytes; for servers with less physical memory, the default size is 4,356 bytes. If this entry is present in the registry, its value overrides the default value.
-        //         // https://docs.microsoft.com/en-us/previous-versions/windows/it-pro/windows-server-2003/cc740106(v=ws.10)
-        //         // Either::U32Element(U32Element {
-        //         //     key: String::from("RequireSecuritySignature"),
-        //         //     value: 16384u32,
-        //         // }),
-        //     ],
-        // },
-
-        // RegTweaks { // https://docs.microsoft.com/en-us/windows-server/administration/performance-tuning/role/file-server/#client-tuning-example
-        //     path: String::from(
-        //         "SYSTEM\\CurrentControlSet\\Services\\lanmanworkstation\\parameters",
-        //     ),
-        //     data: vec![
-        //         Either::U32Element(U32Element {
-        //             key: String::from("DisableBandwidthThrottling"),
-        //             value: 1u32,
-        //         }),
-        //         Either::U32Element(U32Element {
-        //             key: String::from("FileInfoCacheEntriesMax"),
-        //             value: 32768u32,
-        //         }),
-        //         Either::U32Element(U32Element {
-        //             key: String::from("DirectoryCacheEntriesMax"),
-        //             value: 4096u32,
-        //         }),
-        //         Either::U32Element(U32Element {
-        //             key: String::from("FileNotFoundCacheEntriesMax"),
-        //             value: 32768u32,
-        //         }),
-        //         Either::U32Element(U32Element {
-        //             key: String::from("MaxCmds"),
-        //             value: 32768u32,
-        //         }),
-        //     ],
-        // },
-
-        RegTweaks {
-            path: String::from(
-                "SYSTEM\\CurrentControlSet\\Services\\Ndis\\Parameters",
-            ),
-            data: vec![
-                // The RSS base CPU number is the CPU number of the first CPU that RSS can use. RSS cannot use the CPUs that are numbered below the base CPU number. For example, on a quad-core system with hyper-threading turned off, if base CPU number is set to 1, processors 1, 2, and 3 can be used for RSS.
-                // https://docs.microsoft.com/de-de/windows-hardware/drivers/network/reserving-processors-for-applications
-                Either::U32Element(U32Element {
-                    key: String::from("RssBaseCpu"),
-                    value: 2u32,
-                    default: None,
-                }),
-
-                Either::U32Element(U32Element {
-                    key: String::from("MaxNumRssCpus"),
-                    value: 2u32,
-                    default: None, // TODO: 
-                }),
-                // TODO: MaxNumRssCpus
-                // https://docs.microsoft.com/en-us/windows-hardware/drivers/network/setting-the-number-of-rss-processors
-
-            ],
-        },
-
-        // RegTweaks {
-        //     path: String::from(
-        //         "SYSTEM\\CurrentControlSet\\Services\\nvlddmkm",
-        //     ),
-        //     data: vec![
-        //         // The RSS base CPU number is the CPU number of the first CPU that RSS can use. RSS cannot use the CPUs that are numbered below the base CPU number. For example, on a quad-core system with hyper-threading turned off, if base CPU number is set to 1, processors 1, 2, and 3 can be used for RSS.
-        //         // https://forums.blurbusters.com/viewtopic.php?t=7323
-        //         Either::U32Element(U32Element {
-        //             key: String::from("DisableWriteCombining"),
-        //             value: 1u32,
-        //             default: None,
-        //         }),
-
-        //     ],
-        // },
-
-        RegTweaks { // https://www.speedguide.net/articles/host-resolution-priority-tweak-1130
-            path: String::from(
-                "SYSTEM\\CurrentControlSet\\Services\\Tcpip\\Parameters",
-            ),
-            data: vec![
-                // https://docs.microsoft.com/de-de/troubleshoot/windows-client/networking/tcpip-and-nbt-configuration-parameters+
-                // Description: This parameter determines the number of times that TCP retransmits a connect request (SYN) before aborting the attempt. The retransmission timeout is doubled with each successive retransmission in a particular connect attempt. The initial timeout value is three seconds.
-                Either::U32Element(U32Element {
-                    key: String::from("TcpMaxConnectRetransmissions"),
-                    value: 1u32,
-                    default: Some(2u32),
-                }),
-
-                // MSS: (TcpMaxDataRetransmissions) How many times unacknowledged data is retransmitted (3 recommended, 5 is default)
-                // https://admx.help/?Category=SecurityBaseline&Policy=Microsoft.Policies.MSS::Pol_MSS_TcpMaxDataRetransmissions
-                // Description: This parameter determines the number of times that TCP retransmits a connect request (SYN) before aborting the attempt. The retransmission timeout is doubled with each successive retransmission in a particular connect attempt. The initial timeout value is three seconds.
-                // https://docs.microsoft.com/de-de/troubleshoot/windows-client/networking/tcpip-and-nbt-configuration-parameters+
-                Either::U32Element(U32Element {
-                    key: String::from("TcpMaxDataRetransmissions"),
-                    value: 1u32,
-                    default: None,
-                }),
-
-                // https://docs.microsoft.com/en-us/previous-versions/windows/it-pro/windows-server-2003/cc757802(v=ws.10)
-                // https://social.technet.microsoft.com/Forums/en-US/2343ad57-062e-48ed-b1ce-4b17f138d3c9/tcpwindowsize-change?forum=winservergen
-
-                // https://docs.microsoft.com/en-us/previous-versions/technet-magazine/cc162519(v=msdn.10)?redirectedfrom=MSDN
-                Either::U32Element(U32Element {
-                    key: String::from("TcpWindowSize"),
-                    value: 65535u32,
-                    default: Some(14674u32),
-                }),
-
-                // Disable TCP selective acks option for better CPU utilization:
-                // https://docs.mellanox.com/display/winof2/Performance+Tuning
-                Either::U32Element(U32Element {
-                    key: String::from("SackOpts"),
-                    value: 0u32,
-                    default: Some(1u32),
-                }),
-
-                // Tcp1323Opts is a necessary setting in order to enable Large TCP Window support as described in RFC 1323. Without this parameter, the TCP Window is limited to 64K.
-                // https://www.speedguide.net/articles/windows-2kxp-registry-tweaks-157
-                // https://docs.microsoft.com/en-us/previous-versions/windows/it-pro/windows-server-2003/cc757402(v=ws.10)
-                Either::U32Element(U32Element {
-                    key: String::from("Tcp1323Opts"), // Window scaling is enabled.
-                    value: 1u32,
-                    default: Some(1u32),
-                }),
-
-                // https://docs.microsoft.com/en-us/windows-hardware/drivers/network/using-registry-values-to-enable-and-disable-task-offloading
-                // http://systemmanager.ru/win2k_regestry.en/94176.htm
-                Either::U32Element(U32Element {
-                    key: String::from("DisableTaskOffload"),
-                    value: 0u32, // TODO: Default ist 0 also... könnte das auch weg
-                    default: None,
-                }),
-
-                Either::U32Element(U32Element {
-                    key: String::from("DefaultTTL"),
-                    value: 64u32,
-                    default: None,
-                }),
-
-                // https://docs.microsoft.com/en-us/biztalk/technical-guides/settings-that-can-be-modified-to-improve-network-performance
-                Either::U32Element(U32Element {
-                    key: String::from("TcpTimedWaitDelay"),
-                    value: 30u32,
-                    default: None,
-                }),
-
-                // Enable fast datagram sending for UDP traffic:
-                // https://docs.mellanox.com/display/winof2/Performance+Tuning
-                // Either::U32Element(U32Element {
-                //     key: String::from("FastSendDatagramThreshold"),
-                //     value: 64000u32,
-                // }),
-
-                // Set RSS parameters:
-                // https://docs.mellanox.com/display/winof2/Performance+Tuning
-                // Either::U32Element(U32Element {
-                //     key: String::from("RssBaseCpu"),
-                //     value: 1u32,
-                // }),
-            ],
-        },
+pub fn powerplan_tweaks() -> Vec<TweakInfo> {
+    vec![TweakInfo {
+        id: POWERPLAN.to_string(),
+        category: Some(Category::Power),
+        risk: Risk::Medium,
+        description: format!("{} power plan from High performance, without core parking", GAMING_SCHEME_NAME),
+    }]
+}
 
-        // What we're aiming to do is increase the priority of the last 4 settings, while keeping their order.
-        // The valid range is from -32768 to +32767 and lower numbers mean higher priority compared to other services. What we're aiming at is lower numbers without going to
-        // RegTweaks { // https://www.speedguide.net/articles/host-resolution-priority-tweak-1130
-        //     path: String::from(
-        //         "SYSTEM\\CurrentControlSet\\Services\\Tcpip\\ServiceProvider",
-        //     ),
-        //     data: vec![
-        //         Either::U32Element(U32Element {
-        //             key: String::from("LocalPriority"),
-        //             value: 4u32,
-        //             default: Some(499u32),
-        //         }),
-        //         Either::U32Element(U32Element {
-        //             key: String::from("HostsPriority"),
-        //             value: 5u32,
-        //             default: Some(500u32),
-        //         }),
-        //         Either::U32Element(U32Element {
-        //             key: String::from("DnsPriority"),
-        //             value: 6u32,
-        //             default: Some(2000u32),
-        //         }),
-        //         Either::U32Element(U32Element {
-        //             key: String::from("NetbtPriority"),
-        //             value: 7u32,
-        //             default: Some(2001u32),
-        //         }),
-        //     ],
-        // },
-
-        // https://docs.microsoft.com/en-us/windows-hardware/drivers/display/changing-the-behavior-of-the-gpu-scheduler-for-debugging
-        RegTweaks {
-            path: String::from(
-                "SYSTEM\\ControlSet001\\Control\\GraphicsDrivers\\Scheduler",
-            ),
-            data: vec![
-                Either::U32Element(U32Element {
-                    key: String::from("EnablePreemption"),
-                    value: 0u32,
-                    default: None,
-                }),
-            ],
-        },
-    ];
+// Compares the gaming scheme with the plan: that it exists, its AC and DC
+// indices and that it is active. The planner turns the differences into
+// powercfg calls. Before the scheme exists its indices are the ones of the
+// base scheme it is copied from. powercfg also knows the indices of settings
+// that are at their default, which the registry has no key for.
+pub fn check_powerplan<P: PowerConfig>(power: &P, powerplan: &PowerPlan) -> Report {
+    let mut report = Report::default();
+    let failed = |path: String, e: std::io::Error| CheckResult {
+        hive: Hive::LocalMachine,
+        path,
+        name: String::new(),
+        value: None,
+        outcome: CheckOutcome::Failed(e.into()),
+    };
 
-    let current_user = vec![
-        RegTweaks {
-            path: String::from("Control Panel\\Mouse"),
-            data: vec![
-                Either::U32Element(U32Element {
-                    key: String::from("MouseSensitivity"), // @6-of-11
-                    value: 10u32,
-                    default: None,
-                }),
-                Either::VecElement(VecElement {
-                    key: String::from("SmoothMouseXCurve"),
-                    value: get_smooth_mouse_x_curve(*dpi),
-                    default: Some(vec![
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x15, 0x6e, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
-                        0x29, 0xdc, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x28, 0x00,
-                        0x00, 0x00, 0x00, 0x00,
-                    ]),
-                }),
-                Either::VecElement(VecElement {
-                    key: String::from("SmoothMouseYCurve"),
-                    value: get_smooth_mouse_y_curve(*dpi),
-                    default: Some(vec![
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xfd, 0x11, 0x01, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x24, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00,
-                        0x00, 0xfc, 0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, 0xbb, 0x01,
-                        0x00, 0x00, 0x00, 0x00,
-                    ]),
-                }),
-            ],
-        },
-        RegTweaks {
-            path: String::from("Software\\Microsoft\\GameBar"),
-            data: vec![
-                Either::U32Element(U32Element {
-                    key: String::from("AllowAutoGameMode"),
-                    value: 0u32,
-                    default: None,
-                }),
-                Either::U32Element(U32Element {
-                    key: String::from("ShowStartupPanel"),
-                    value: 0u32,
-                    default: None,
-                }),
-                Either::U32Element(U32Element {
-                    key: String::from("GamePanelStartupTipIndex"),
-                    value: 3u32,
-                    default: None,
-                }),
-                Either::U32Element(U32Element {
-                    key: String::from("UseNexusForGameBarEnabled"),
-                    value: 0u32,
-                    default: None,
-                }),
-            ],
-        },
+    let schemes = match power.list() {
+        Ok(schemes) => schemes,
+        Err(e) => {
+            report.push(failed(POWER_SCHEMES.to_string(), e));
+            return report;
+        }
+    };
 
-        RegTweaks {
-            path: String::from("Software\\Microsoft\\Windows\\CurrentVersion\\Explorer\\Serialize"),
-            data: vec![
-                Either::U32Element(U32Element {
-                    key: String::from("StartupDelayInMSec"),
-                    value: 0u32,
-                    default: None,
-                }),
-            ],
-        },
+    let scheme_path = format!("{}\\{}", POWER_SCHEMES, GAMING_SCHEME);
+    let queried = if schemes.iter().any(|s| s.guid.eq_ignore_ascii_case(GAMING_SCHEME)) {
+        GAMING_SCHEME
+    } else {
+        report.push(CheckResult {
+            hive: Hive::LocalMachine,
+            path: scheme_path.clone(),
+            name: String::new(),
+            value: None,
+            outcome: CheckOutcome::Missing,
+        });
+        &powerplan.base
+    };
+    let scheme = match power.query(queried) {
+        Ok(scheme) => scheme,
+        Err(e) => {
+            report.push(failed(format!("{}\\{}", POWER_SCHEMES, queried), e));
+            return report;
+        }
+    };
 
-        // RegTweaks {
-        //     path: String::from(
-        //         "Software\\Microsoft\\GameBar",
-        //     ),
-        //     data: vec![
-        //         Either::U32Element(U32Element {
-        //             key: String::from("ShowStartupPanel"),
-        //             value: 0u32,
-        //             default: None, // TODO: 
-        //         }),
-        //         Either::U32Element(U32Element {
-        //             key: String::from("GamePanelStartupTipIndex"),
-        //             value: 3u32,
-        //             default: None, // TODO: 
-        //         }),
-        //         Either::U32Element(U32Element {
-        //             key: String::from("AllowAutoGameMode"),
-        //             value: 0u32,
-        //             default: None, // TODO: 
-        //         }),
-        //         Either::U32Element(U32Element {
-        //             key: String::from("UseNexusForGameBarEnabled"),
-        //             value: 0u32,
-        //             default: None, // TODO: 
-        //         }),
-        //     ],
-        // },
-
-        RegTweaks {
-            path: String::from(
-                "Software\\Microsoft\\Windows\\CurrentVersion\\GameDVR",
-            ),
-            data: vec![
-                Either::U32Element(U32Element {
-                    key: String::from("AppCaptureEnabled"),
-                    value: 0u32,
-                    default: None, // TODO: 
-                }),
-            ],
-        },
+    for sub_guid in powerplan.data.iter() {
+        for setting_guid in sub_guid.data.iter() {
+            let setting = scheme.setting(&sub_guid.path, &setting_guid.path);
+            for (source, data) in [(PowerSource::Ac, setting_guid.ac), (PowerSource::Dc, setting_guid.dc)] {
+                let desired = RegValue::dword(data);
+                let outcome = match setting.map(|s| (s, s.index(source))) {
+                    // Windows would refuse to write it
+                    Some((setting, _)) if !setting.values.contains(data) => CheckOutcome::Failed(Error::OutOfRange {
+                        setting: setting_guid.alias.clone(),
+                        index: data,
+                    }),
+                    Some((_, Some(value))) if value == data => CheckOutcome::Correct,
+                    Some((_, Some(value))) => CheckOutcome::Mismatch {
+                        current: RegValue::dword(value),
+                        desired: desired.clone(),
+                    },
+                    Some((_, None)) => CheckOutcome::Missing,
+                    // Not a power setting of this system
+                    None => CheckOutcome::Failed(Error::Missing),
+                };
+                report.push(CheckResult {
+                    hive: Hive::LocalMachine,
+                    path: format!("{}\\{}\\{}", scheme_path, sub_guid.path, setting_guid.path),
+                    name: source.value_name().to_string(),
+                    value: Some(desired),
+                    outcome,
+                });
+            }
+        }
+    }
 
-        // The Road to Fullscreen Optimizations
-        // https://devblogs.microsoft.com/directx/demystifying-full-screen-optimizations/
-        RegTweaks {
-            path: String::from(
-                "SYSTEM\\GameConfigStore",
-            ),
-            data: vec![
-                // Disable Xbox Features
-                Either::U32Element(U32Element {
-                    key: String::from("GameDVR_Enabled"),
-                    value: 0u32,
-                    default: None, // TODO: 
-                }),
-                // Disable Fullscreen optimizations
-                Either::U32Element(U32Element {
-                    key: String::from("GameDVR_DSEBehavior"),
-                    value: 2u32,
-                    default: None, // TODO: 
-                }),
-                // Disable Fullscreen optimizations
-                Either::U32Element(U32Element {
-                    key: String::from("GameDVR_FSEBehaviorMode"),
-                    value: 2u32,
-                    default: None, // TODO: 
-                }),
-                // Disable Fullscreen optimizations
-                Either::U32Element(U32Element {
-                    key: String::from("GameDVR_FSEBehavior"),
-                    value: 2u32,
-                    default: None, // TODO: 
-                }),
-                // Disable Fullscreen optimizations
-                Either::U32Element(U32Element {
-                    key: String::from("GameDVR_HonorUserFSEBehaviorMode"),
-                    value: 1u32,
-                    default: None, // TODO: 
-                }),
-                // Disable Fullscreen optimizations
-                Either::U32Element(U32Element {
-                    key: String::from("GameDVR_DXGIHonorFSEWindowsCompatible"),
-                    value: 1u32,
-                    default: None, // TODO: 
-                }),
-                // Disable Fullscreen optimizations
-                Either::U32Element(U32Element {
-                    key: String::from("GameDVR_EFSEFeatureFlags"),
-                    value: 0u32,
-                    default: None, // TODO: 
-                }),
-            ],
+    let desired = RegValue::sz(GAMING_SCHEME);
+    report.push(CheckResult {
+        hive: Hive::LocalMachine,
+        path: POWER_SCHEMES.to_string(),
+        name: String::from("ActivePowerScheme"),
+        outcome: match schemes.iter().find(|s| s.active) {
+            Some(active) if active.guid.eq_ignore_ascii_case(GAMING_SCHEME) => CheckOutcome::Correct,
+            Some(active) => CheckOutcome::Mismatch {
+                current: RegValue::sz(&active.guid),
+                desired: desired.clone(),
+            },
+            None => CheckOutcome::Missing,
         },
-    ];
-    // TODO let users https://github.com/spddl/apex-optimizer/blob/master/src/registry/mousefix.rs#L50
-    // [HKEY_USERS\.DEFAULT\Control Panel\Mouse]
-
-    let users = vec![RegTweaks {
-        path: String::from(".DEFAULT\\Control Panel\\Mouse"),
-        data: vec![
-            Either::U32Element(U32Element {
-                key: String::from("MouseSpeed"),
-                value: 0u32,
-                default: None,
-            }),
-            Either::U32Element(U32Element {
-                key: String::from("MouseThreshold1"),
-                value: 0u32,
-                default: None,
-            }),
-            Either::U32Element(U32Element {
-                key: String::from("MouseThreshold2"),
-                value: 0u32,
-                default: None,
-            }),
-        ],
-    }];
+        value: Some(desired),
+    });
+    report
+}
 
-    Settings {
-        local_machine,
-        current_user,
-        users,
+// Whether Windows hides the settings of the plan it hides by default, see
+// catalog/power.toml. Only asked for with --unhide-power-settings, the planner
+// shows them with powercfg -attributes.
+pub fn check_power_attributes<B: RegistryBackend>(backend: &B, powerplan: &PowerPlan) -> Report {
+    let mut report = Report::default();
+    for sub_guid in powerplan.data.iter() {
+        for setting_guid in sub_guid.data.iter().filter(|s| s.hidden) {
+            let path = format!("{}\\{}\\{}", POWER_SETTINGS, sub_guid.path, setting_guid.path);
+            let (value, outcome) = match power_attributes(backend, &sub_guid.path, &setting_guid.path) {
+                Ok(Some(current)) if current & powerplan::ATTRIB_HIDE != 0 => {
+                    let desired = RegValue::dword(current & !powerplan::ATTRIB_HIDE);
+                    let outcome = CheckOutcome::Mismatch {
+                        current: RegValue::dword(current),
+                        desired: desired.clone(),
+                    };
+                    (desired, outcome)
+                }
+                Ok(current) => (RegValue::dword(current.unwrap_or(0)), CheckOutcome::Correct),
+                Err(e) => (RegValue::dword(0), CheckOutcome::Failed(e)),
+            };
+            report.push(CheckResult {
+                hive: Hive::LocalMachine,
+                path,
+                name: String::from("Attributes"),
+                value: Some(value),
+                outcome,
+            });
+        }
     }
+    report
 }
 
-#[allow(dead_code)]
-pub fn factory_powerplan() -> PowerPlan {
-// Computer\HKEY_LOCAL_MACHINE\SYSTEM\ControlSet001\Control\Power\User\PowerSchemes\2cd4d4f0-d578-48f4-be43-d145b9b71cbe\54533251-82be-4824-96c1-47b60b740d00\0cc5b647-c1df-4637-891a-dec35c318583
-// ACSettingIndex 0x64 (100)
-
-// Computer\HKEY_LOCAL_MACHINE\SYSTEM\ControlSet001\Control\Power\User\PowerSchemes\2cd4d4f0-d578-48f4-be43-d145b9b71cbe\54533251-82be-4824-96c1-47b60b740d00\45bcc044-d885-43e2-8605-ee0ec6e96b59
-// ACSettingIndex 0x64 (100)
-
-// Computer\HKEY_LOCAL_MACHINE\SYSTEM\ControlSet001\Control\Power\User\PowerSchemes\2cd4d4f0-d578-48f4-be43-d145b9b71cbe\54533251-82be-4824-96c1-47b60b740d00\893dee8e-2bef-41e0-89c6-b55d0929964c
-// ACSettingIndex 0x64 (100)
-
-// https://docs.microsoft.com/en-us/windows/win32/api/powrprof/nf-powrprof-powercreatesetting
-    PowerPlan{ // https://bitsum.com/known-windows-power-guids/
-        // scheme: None,
-        scheme: Some(String::from("8c5e7fda-e8bf-4a96-9a85-a6e23a8c635c")), // High performance
-        data: vec![
-            SubGuid{
-                path: String::from("54533251-82be-4824-96c1-47b60b740d00"), // SUB_PROCESSOR
-                data: vec![
-                    // Makes maximum CPU speeds available, by default they're not
-                    SettingGuid{
-                        path: String::from("be337238-0d82-4146-a960-4f3749d470c7"), // PERFBOOSTMODE
-                        data: 0u32,
-                    },
-                    SettingGuid{
-                        path: String::from("06cadf0e-64ed-448a-8927-ce7bf90eb35d"), // PERFINCTHRESHOLD (Processor performance increase threshold)
-                        data: 1u32,
-                    },
-                    SettingGuid{
-                        path: String::from("06cadf0e-64ed-448a-8927-ce7bf90eb35e"), // PERFINCTHRESHOLD1 (Processor performance increase threshold for Processor Power Efficiency Class 1)
-                        data: 1u32,
-                    },
-                    SettingGuid{
-                        path: String::from("984cf492-3bed-4488-a8f9-4286c97bf5aa"), // PERFINCTIME (Processor performance increase time)
-                        data: 1u32,
-                    },
-                    SettingGuid{
-                        path: String::from("984cf492-3bed-4488-a8f9-4286c97bf5ab"), // PERFINCTIME1 (Processor performance increase time for Processor Power Efficiency Class 1)
-                        data: 1u32,
-                    },
-                    SettingGuid{
-                        path: String::from("12a0ab44-fe28-4fa9-b3bd-4b64f44960a6"), // PERFDECTHRESHOLD (Processor performance decrease threshold)
-                        data: 100u32,
-                    },
-                    SettingGuid{
-                        path: String::from("12a0ab44-fe28-4fa9-b3bd-4b64f44960a7"), // PERFDECTHRESHOLD1 (Processor performance decrease threshold for Processor Power Efficiency Class 1)
-                        data: 100u32,
-                    },
-                    SettingGuid{
-                        path: String::from("619b7505-003b-4e82-b7a6-4dd29c300971"), // LATENCYHINTPERF (Latency sensitivity hint processor performance)
-                        data: 0u32,
-                    },
-                   SettingGuid{
-                        path: String::from("619b7505-003b-4e82-b7a6-4dd29c300972"), // LATENCYHINTPERF1 (Latency sensitivity hint processor performance for Processor Power Efficiency Class 1)
-                        data: 0u32,
-                    },
-                    SettingGuid{
-                        path: String::from("8baa4a8a-14c6-4451-8e8b-14bdbd197537"), // PERFAUTONOMOUS (Processor performance autonomous mode)
-                        data: 0u32,
-                    },
-                    SettingGuid{
-                        path: String::from("4e4450b3-6179-4e91-b8f1-5bb9938f81a1"), // PERFDUTYCYCLING (Processor duty cycling)
-                        data: 0u32,
-                    },
-
-                    // Sets overall throttles to maximum
-                    SettingGuid{
-                        path: String::from("bc5038f7-23e0-4960-96da-33abaf5935ec"), // PROCTHROTTLEMAX (Maximum processor state)
-                        data: 100u32,
-                    },
-                    SettingGuid{
-                        path: String::from("bc5038f7-23e0-4960-96da-33abaf5935ed"), // PROCTHROTTLEMAX1 (Maximum processor state for Processor Power Efficiency Class 1)
-                        data: 100u32,
-                    },
-                    SettingGuid{
-                        path: String::from("893dee8e-2bef-41e0-89c6-b55d0929964c"), // PROCTHROTTLEMIN (Minimum processor state)
-                        data: 100u32,
-                    },
-                    SettingGuid{
-                        path: String::from("893dee8e-2bef-41e0-89c6-b55d0929964d"), // PROCTHROTTLEMIN1 (Minimum processor state for Processor Power Efficiency Class 1)
-                        data: 100u32,
-                    },
-                    SettingGuid{
-                        path: String::from("1facfc65-a930-4bc5-9f38-504ec097bbc0"), // HETEROCLASS1INITIALPERF (Initial performance for Processor Power Efficiency Class 1 when unparked)
-                        data: 100u32,
-                    },
-                    SettingGuid{
-                        path: String::from("fddc842b-8364-4edc-94cf-c17f60de1c80"), // HETEROCLASS0FLOORPERF (A floor performance for Processor Power Efficiency Class 0 when there are Processor Power Efficiency Class 1 processors unparked)
-                        data: 100u32,
-                    },
-
-                    // Turns off CPU core controls, tells OS to just use them all.
-                    SettingGuid{
-                        path: String::from("ea062031-0e34-4ff1-9b6d-eb1059334028"), // CPMAXCORES (Processor performance core parking max cores)
-                        data: 100u32,
-                    },
-                    SettingGuid{
-                        path: String::from("ea062031-0e34-4ff1-9b6d-eb1059334029"), // CPMAXCORES1 (Processor performance core parking max cores for Processor Power Efficiency Class 1)
-                        data: 100u32,
-                    },
-                    SettingGuid{
-                        path: String::from("0cc5b647-c1df-4637-891a-dec35c318583"), // CPMINCORES (Processor performance core parking min cores)
-                        data: 100u32,
-                    },
-                    SettingGuid{
-                        path: String::from("0cc5b647-c1df-4637-891a-dec35c318584"), // CPMINCORES1 (Processor performance core parking min cores for Processor Power Efficiency Class 1)
-                        data: 100u32,
-                    },
-                    SettingGuid{
-                        path: String::from("e0007330-f589-42ed-a401-5ddb10e785d3"), // DISTRIBUTEUTIL (Processor performance core parking utility distribution)
-                        data: 0u32,
-                    },
-                    SettingGuid{
-                        path: String::from("4bdaf4e9-d103-46d7-a5f0-6280121616ef"), // CPDISTRIBUTION (Processor performance core parking distribution threshold)
-                        data: 1u32,
-                    },
+// The Attributes of a setting, None when it has none and is shown
+pub fn power_attributes<B: RegistryBackend>(backend: &B, sub: &str, setting: &str) -> error::Result<Option<u32>> {
+    let root = backend.predef(Hive::LocalMachine);
+    let key = backend.open_subkey(&root, &format!("{}\\{}\\{}", POWER_SETTINGS, sub, setting))?;
+    match backend.get_u32(&key, "Attributes") {
+        Ok(attributes) => Ok(Some(attributes)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
 
-                    // Minimizes CPU spinup time, and maximizes spindown time, just in case
-                    SettingGuid{
-                        path: String::from("2ddd5a84-5a71-437e-912a-db0b8c788732"), // CPINCREASETIME (Processor performance core parking increase time)
-                        data: 1u32,
-                    },
-                    SettingGuid{
-                        path: String::from("dfd10d17-d5eb-45dd-877a-9a34ddd15c82"), // CPDECREASETIME (Processor performance core parking decrease time)
-                        data: 100u32,
-                    },
-                    SettingGuid{
-                        path: String::from("f735a673-2066-4f80-a0c5-ddee0cf1bf5d"), // CPHEADROOM (Processor performance core parking concurrency headroom threshold)
-                        data: 1u32,
-                    },
-                    SettingGuid{
-                        path: String::from("2430ab6f-a520-44a2-9601-f7f23b5134b1"), // CPCONCURRENCY (Processor performance core parking concurrency threshold)
-                        data: 1u32,
-                    },
-                    SettingGuid{
-                        path: String::from("616cdaa5-695e-4545-97ad-97dc2d1bdd88"), // LATENCYHINTUNPARK (Latency sensitivity hint min unparked cores/packages)
-                        data: 1u32,
-                    },
-                    SettingGuid{
-                        path: String::from("616cdaa5-695e-4545-97ad-97dc2d1bdd89"), // LATENCYHINTUNPARK1 (Latency sensitivity hint min unparked cores/packages for Processor Power Efficiency Class 1)
-                        data: 1u32,
-                    },
+pub fn set_power_hidden(sub: &str, setting: &str, hidden: bool) -> error::Result<()> {
+    Ok(powerplan::set_hidden(sub, setting, hidden)?)
+}
 
-                    // Sets energy savings preference to zero
-                    SettingGuid{
-                        path: String::from("36687f9e-e3a5-4dbf-b1dc-15eb381c6863"), // PERFEPP (Processor energy performance preference policy)
-                        data: 0u32,
-                    },
+// Hides the settings of the plan that Windows hides by default again
+#[cfg(windows)]
+pub fn hide_power_settings<B: RegistryBackend>(backend: &B, powerplan: &PowerPlan) -> Report {
+    let mut report = Report::default();
+    for sub_guid in powerplan.data.iter() {
+        for setting_guid in sub_guid.data.iter().filter(|s| s.hidden) {
+            let (value, outcome) = match power_attributes(backend, &sub_guid.path, &setting_guid.path) {
+                Ok(Some(current)) if current & powerplan::ATTRIB_HIDE != 0 => (current, CheckOutcome::Correct),
+                Ok(current) => {
+                    let current = current.unwrap_or(0);
+                    match set_power_hidden(&sub_guid.path, &setting_guid.path, true) {
+                        Ok(()) => (current | powerplan::ATTRIB_HIDE, CheckOutcome::Written),
+                        Err(e) => (current, CheckOutcome::Failed(e)),
+                    }
+                }
+                Err(e) => (0, CheckOutcome::Failed(e)),
+            };
+            report.push(CheckResult {
+                hive: Hive::LocalMachine,
+                path: format!("{}\\{}\\{}", POWER_SETTINGS, sub_guid.path, setting_guid.path),
+                name: String::from("Attributes"),
+                value: Some(RegValue::dword(value)),
+                outcome,
+            });
+        }
+    }
+    report
+}
 
-                    // SettingGuid{
-                    //     path: String::from("45bcc044-d885-43e2-8605-ee0ec6e96b59"), // PERFBOOSTPOL (Processor performance boost policy)
-                    //     data: 100u32,
-                    // },
-                    // SettingGuid{
-                    //     path: String::from("893dee8e-2bef-41e0-89c6-b55d0929964c"), // PROCTHROTTLEMIN (Minimum processor state)
-                    //     data: 100u32,
-                    // },
-                    // SettingGuid{
-                    //     path: String::from("06cadf0e-64ed-448a-8927-ce7bf90eb35d"), // PERFINCTHRESHOLD (Processor performance increase threshold)
-                    //     data: 1u32,
-                    // },
-                    // SettingGuid{
-                    //     path: String::from("06cadf0e-64ed-448a-8927-ce7bf90eb35e"), // PERFINCTHRESHOLD1 (Processor performance increase threshold for Processor Power Efficiency Class 1)
-                    //     data: 1u32,
-                    // },
-                    // SettingGuid{
-                    //     path: String::from("2ddd5a84-5a71-437e-912a-db0b8c788732"), // CPINCREASETIME (Processor performance core parking increase time)
-                    //     data: 100u32,
-                    // },
-                 ],
-            },
-        ],
+// One index of a setting of a scheme, None when the setting has none
+pub fn power_index<P: PowerConfig>(
+    power: &P,
+    scheme: &str,
+    sub: &str,
+    setting: &str,
+    source: PowerSource,
+) -> error::Result<Option<u32>> {
+    match power.query_setting(scheme, sub, setting)? {
+        Some(setting) => Ok(setting.index(source)),
+        None => Err(Error::Missing),
     }
 }
 
-// pub fn default_powerplan() {
-//     powerplan::default();
-// }
+pub fn set_power_index<P: PowerConfig>(
+    power: &P,
+    scheme: &str,
+    sub: &str,
+    setting: &str,
+    source: PowerSource,
+    value: u32,
+) -> error::Result<()> {
+    Ok(power.set(scheme, sub, setting, source, value)?)
+}
 
-#[allow(dead_code)]
-pub fn check_powerplan(powerplan: &PowerPlan, write_settings: bool) {
-    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
+pub fn power_scheme_exists<P: PowerConfig>(power: &P, scheme: &str) -> error::Result<bool> {
+    Ok(power.list()?.iter().any(|s| s.guid.eq_ignore_ascii_case(scheme)))
+}
 
-    // Computer\HKEY_LOCAL_MACHINE\SYSTEM\CurrentControlSet\Control\Power\User\PowerSchemes
-    // Computer\HKEY_LOCAL_MACHINE\SYSTEM\ControlSet001\Control\Power\User\PowerSchemes
-    // ActivePowerScheme
-    let power_scheme: RegKey= hklm.open_subkey("SYSTEM\\CurrentControlSet\\Control\\Power\\User\\PowerSchemes").unwrap();
-    let scheme_guid: String;
+pub fn active_power_scheme<P: PowerConfig>(power: &P) -> error::Result<Option<String>> {
+    Ok(power.list()?.into_iter().find(|s| s.active).map(|s| s.guid))
+}
 
+pub fn create_power_scheme<P: PowerConfig>(power: &P, base: &str, scheme: &str, name: &str) -> error::Result<()> {
+    Ok(power.duplicate(base, scheme, name)?)
+}
 
-    match &powerplan.scheme {
-        None => {
-            scheme_guid = power_scheme.get_value("ActivePowerScheme").unwrap();
-            // println!("active_power_scheme = {}", scheme_guid);
+pub fn activate_power_scheme<P: PowerConfig>(power: &P, scheme: &str) -> error::Result<()> {
+    Ok(power.activate(scheme)?)
+}
 
-            let power_scheme_name = power_scheme.open_subkey(&scheme_guid).unwrap();
-            let scheme_name: String = power_scheme_name.get_value("FriendlyName").unwrap();
-            println!("Active Powerplan = {} {}", scheme_name, scheme_guid); // 	8c5e7fda-e8bf-4a96-9a85-a6e23a8c635c
-        },
-        Some(scheme) => {
-            scheme_guid = scheme.to_string();
-            println!("Active Powerplan = {}", scheme_guid); // 	8c5e7fda-e8bf-4a96-9a85-a6e23a8c635c
-        },
-    }
-    // if powerplan.scheme.is_none() {
-    //     scheme_guid = power_scheme.get_value("ActivePowerScheme").unwrap();
-    //     // println!("active_power_scheme = {}", scheme_guid);
-
-    //     let power_scheme_name = power_scheme.open_subkey(&scheme_guid).unwrap();
-    //     let scheme_name: String = power_scheme_name.get_value("FriendlyName").unwrap();
-    //     println!("Active Powerplan = {} {}", scheme_name, scheme_guid); // 8c5e7fda-e8bf-4a96-9a85-a6e23a8c635c
-    // } else {
-    //     scheme_guid = powerplan.scheme.unwrap();
-    // }
-
-    let scheme_guid_reg = power_scheme.open_subkey(&scheme_guid);
-    if scheme_guid_reg.is_err() {
-        println!("scheme_guidis_err");
+// Switches to `previous` (Balanced when it is not known) if the gaming scheme
+// is active and deletes it, false when there was no gaming scheme
+pub fn remove_gaming_scheme<P: PowerConfig>(power: &P, previous: Option<&str>) -> error::Result<bool> {
+    let schemes = power.list()?;
+    let gaming = |s: &&powerplan::SchemeEntry| s.guid.eq_ignore_ascii_case(GAMING_SCHEME);
+    match schemes.iter().find(gaming) {
+        None => return Ok(false),
+        Some(scheme) if scheme.active => power.activate(previous.unwrap_or(powerplan::BALANCED))?,
+        Some(_) => {}
     }
-    let scheme_guid_reg = scheme_guid_reg.unwrap();
-
-    //  scheme_GUID
-    //   Specifies a power scheme GUID. A power scheme GUID is returned by running powercfg /list.
-
-    //  sub_GUID
-    //   Specifies a power setting subgroup GUID. Running powercfg /query returns a power setting subgroup GUID.
+    power.delete(GAMING_SCHEME)?;
+    Ok(true)
+}
 
-    //  setting_GUID
-    //   Specifies a power setting GUID. A power setting GUID is returned by running powercfg /query.
-    
-    //  setting_index
-    //   Specifies which possible value this setting is set to. A list of possible values is returned by running powercfg /query.
+// Compares the values of a key, or writes them
+fn apply_section<B: RegistryBackend>(backend: &B, reg: &B::Key, section: &RegTweaks, write_settings: bool) -> Report {
+    let mut report = Report::default();
+    for element in section.data.iter() {
+        report.push(match element.value() {
+            Some(value) => set_raw_reg(backend, reg, &section.path, element.name(), value, write_settings),
+            None => remove_value_reg(backend, reg, &section.path, element.name(), write_settings),
+        });
+    }
+    report
+}
 
-    // Computer\HKEY_LOCAL_MACHINE\SYSTEM\CurrentControlSet\Control\Power\User\PowerSchemes\2cd4d4f0-d578-48f4-be43-d145b9b71cbe\54533251-82be-4824-96c1-47b60b740d00\0cc5b647-c1df-4637-891a-dec35c318583
-    // ACSettingIndex 0x64 (100)
+// Writes the Windows defaults of a key, values without one are deleted
+fn restore_section<B: RegistryBackend>(backend: &B, reg: &B::Key, section: &RegTweaks) -> Report {
+    let mut report = Report::default();
+    for element in section.data.iter() {
+        report.push(match (element.value(), element.default_value()) {
+            // Nothing known about what was there before
+            (None, _) => continue,
+            (Some(_), Some(default)) => set_raw_reg(backend, reg, &section.path, element.name(), default, true),
+            // A value that is already gone is the default
+            (Some(_), None) => remove_value_reg(backend, reg, &section.path, element.name(), true),
+        });
+    }
+    report
+}
 
-    for sub_guid in powerplan.data.iter() {
-        let sub_guid_path = scheme_guid_reg.open_subkey(sub_guid.path.clone());
-        if sub_guid_path.is_err() {
-            // println!("setting missing: \x1b[0;93m{}\x1b[0m", format!("HKEY_LOCAL_MACHINE\\SYSTEM\\CurrentControlSet\\Control\\Power\\User\\PowerSchemes\\{}\\{}", scheme_guid, sub_guid.path));
+// Every value of a key that does not exist is missing, values that have to be absent are fine
+fn missing_section(section: &RegTweaks) -> Report {
+    let mut report = Report::default();
+    for element in section.data.iter() {
+        let value = element.value();
+        report.push(CheckResult {
+            hive: section.path.hive,
+            path: section.path.subkey.clone(),
+            name: element.name().to_string(),
+            outcome: if value.is_some() {
+                CheckOutcome::Missing
+            } else {
+                CheckOutcome::Correct
+            },
+            value,
+        });
+    }
+    report
+}
 
-            for setting_guid in sub_guid.data.iter() {
-                println!("setting missing: \x1b[0;93m{}\x1b[0m", format!("HKEY_LOCAL_MACHINE\\SYSTEM\\CurrentControlSet\\Control\\Power\\User\\PowerSchemes\\{}\\{}\\{}", scheme_guid, sub_guid.path, setting_guid.path));
+impl Settings {
+    // Adds a value, next to the other values of its key
+    fn push(&mut self, path: RegPath, element: Either) {
+        match self.keys.iter_mut().find(|section| section.path == path && !section.remove) {
+            Some(section) => section.data.push(element),
+            None => self.keys.push(RegTweaks {
+                path,
+                remove: false,
+                data: vec![element],
+            }),
+        }
+    }
 
-                if write_settings {
-                    powerplan::set(&scheme_guid, &sub_guid.path, &setting_guid.path, &setting_guid.data);
-                }
+    // Values imported from a .reg file have no catalog entry, their full path is the id
+    pub fn tweaks(&self) -> Vec<TweakInfo> {
+        let mut tweaks = Vec::new();
+        for section in self.keys.iter() {
+            if section.remove {
+                tweaks.push(imported(section.path.to_string(), String::from("remove the key")));
             }
-        } else {
-            let sub_guid_path = sub_guid_path.unwrap();
-            for setting_guid in sub_guid.data.iter() {
-                let setting_guid_path = sub_guid_path.open_subkey(&setting_guid.path);
-                if setting_guid_path.is_err() {
-                    println!("setting missing: \x1b[0;93m{}\x1b[0m", format!("HKEY_LOCAL_MACHINE\\SYSTEM\\CurrentControlSet\\Control\\Power\\User\\PowerSchemes\\{}\\{}\\{}", scheme_guid, sub_guid.path, setting_guid.path));
-                    if write_settings {
-                        powerplan::set(&scheme_guid, &sub_guid.path, &setting_guid.path, &setting_guid.data);
-                    }
-                    continue
-                }
-
-                match setting_guid_path.unwrap().get_value("ACSettingIndex") {
-                    Err(e) => {
-                        println!("setting missing (ACSettingIndex): \x1b[0;93m{}\x1b[0m",e);
-                    }
-                    Ok(reg_val) => {
-                        let value: u32 = reg_val;
-                        if value == setting_guid.data {
-                            println!("correct setting: {}", format!("HKEY_LOCAL_MACHINE\\SYSTEM\\CurrentControlSet\\Control\\Power\\User\\PowerSchemes\\{}\\{}\\{}\\ACSettingIndex = dword:{}", scheme_guid, sub_guid.path, setting_guid.path, setting_guid.data));
-                        } else {
-                            println!(
-                                "wrong setting: \x1b[0;93m{} = dword:{}\x1b[0m (your value: {})",
-                                format!("HKEY_LOCAL_MACHINE\\SYSTEM\\CurrentControlSet\\Control\\Power\\User\\PowerSchemes\\{}\\{}\\{}\\ACSettingIndex", scheme_guid, sub_guid.path, setting_guid.path)
-                                , setting_guid.data, value
-                            );
-                            if write_settings {
-                                powerplan::set(&scheme_guid, &sub_guid.path, &setting_guid.path, &setting_guid.data);
-                            }
-                        }
-                    }
-                }
+            for element in section.data.iter() {
+                let description = match element.value() {
+                    Some(value) => format!("= {}", value),
+                    None => String::from("remove the value"),
+                };
+                tweaks.push(imported(format!("{}\\{}", section.path, element.name()), description));
             }
         }
+        tweaks
     }
-}
 
-pub fn apply_reg_tweaks(reg_settings: &Settings, write_settings: bool) {
-    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
-    for section in reg_settings.local_machine.iter() {
-        let reg_t = Transaction::new().unwrap();
-        let (reg, _) =
-            hklm.create_subkey_transacted_with_flags(&section.path, &reg_t, KEY_ALL_ACCESS).unwrap();
-        let reg_path = format!("HKEY_LOCAL_MACHINE\\{}", section.path);
-
-        for data in section.data.iter() {
-            match data {
-                Either::StringElement(ele) => {
-                    set_str_reg(&reg, &ele.key, &ele.value, &reg_path, write_settings)
-                }
-                Either::U32Element(ele) => {
-                    set_u32_reg(&reg, &ele.key, &ele.value, &reg_path, write_settings)
-                }
-                Either::VecElement(ele) => {
-                    set_vac_reg(&reg, &ele.key, ele.value.clone(), &reg_path, write_settings)
-                }
+    // The HKCU values written to HKU\<SID> of each user instead of the account
+    // running the tool, so every user gets results of their own
+    pub fn for_users(&self, sids: &[String]) -> Settings {
+        let mut keys = Vec::new();
+        for section in self.keys.iter() {
+            if section.path.hive != Hive::CurrentUser {
+                keys.push(section.clone());
+                continue;
+            }
+            for sid in sids {
+                keys.push(RegTweaks {
+                    path: RegPath::new(Hive::Users, sid).join(&section.path.subkey),
+                    ..section.clone()
+                });
             }
         }
-        if write_settings {
-            reg_t.commit().unwrap();
-        }
+        Settings { keys }
     }
 
-    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-    for section in reg_settings.current_user.iter() {
-        let reg_t = Transaction::new().unwrap();
-        let (reg, _) =
-            hkcu.create_subkey_transacted_with_flags(&section.path, &reg_t, KEY_ALL_ACCESS).unwrap();
-        let reg_path = format!("HKEY_CURRENT_USER\\{}", section.path);
-
-        for data in section.data.iter() {
-            match data {
-                Either::StringElement(ele) => {
-                    set_str_reg(&reg, &ele.key, &ele.value, &reg_path, write_settings)
-                }
-                Either::U32Element(ele) => {
-                    set_u32_reg(&reg, &ele.key, &ele.value, &reg_path, write_settings)
-                }
-                Either::VecElement(ele) => {
-                    set_vac_reg(&reg, &ele.key, ele.value.clone(), &reg_path, write_settings)
-                }
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        self.keys.retain_mut(|section| {
+            if section.remove {
+                return keep(&section.path.to_string());
             }
-        }
+            let path = section.path.to_string();
+            section
+                .data
+                .retain(|element| keep(&format!("{}\\{}", path, element.name())));
+            !section.data.is_empty()
+        });
+    }
+}
 
-        if write_settings {
-            reg_t.commit().unwrap();
-        }
+fn imported(id: String, description: String) -> TweakInfo {
+    TweakInfo {
+        id,
+        category: None,
+        risk: Risk::Low,
+        description,
     }
+}
 
-    let hkus = RegKey::predef(HKEY_USERS);
-    for section in reg_settings.users.iter() {
-        let reg_t = Transaction::new().unwrap();
-        let (reg, _) =
-            hkus.create_subkey_transacted_with_flags(&section.path, &reg_t, KEY_ALL_ACCESS).unwrap();
-        let reg_path = format!("HKEY_USERS\\{}", section.path);
-
-        for data in section.data.iter() {
-            match data {
-                Either::StringElement(ele) => {
-                    set_str_reg(&reg, &ele.key, &ele.value, &reg_path, write_settings)
-                }
-                Either::U32Element(ele) => {
-                    set_u32_reg(&reg, &ele.key, &ele.value, &reg_path, write_settings)
-                }
-                Either::VecElement(ele) => {
-                    set_vac_reg(&reg, &ele.key, ele.value.clone(), &reg_path, write_settings)
-                }
-            }
+// Compares the values with the machine, keys that do not exist are not created
+pub fn check_reg_tweaks<B: RegistryBackend>(backend: &B, reg_settings: &Settings) -> Report {
+    let mut report = Report::default();
+    for section in reg_settings.keys.iter() {
+        let root = backend.predef(section.path.hive);
+        if section.remove {
+            report.push(remove_key_reg(backend, &root, &section.path, false));
+            continue;
         }
-
-        if write_settings {
-            reg_t.commit().unwrap();
+        match backend.open_subkey(&root, &section.path.subkey) {
+            Ok(reg) => report.extend(apply_section(backend, &reg, section, false)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => report.extend(missing_section(section)),
+            Err(e) => report.push(CheckResult {
+                hive: section.path.hive,
+                path: section.path.subkey.clone(),
+                name: String::new(),
+                value: None,
+                outcome: CheckOutcome::Failed(e.into()),
+            }),
         }
     }
+    report
 }
 
-pub fn apply_tcp_tweaks(
-    mtu: &u32,
-    write_settings: bool,
-    default_settings: bool,
-) {
-    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
-    let nics = hklm.open_subkey_with_flags(
-        "SYSTEM\\CurrentControlSet\\Services\\Tcpip\\Parameters\\Interfaces",
-        KEY_READ,
-    ).unwrap();
-
-    let addr_type_value = RegValue {
-        bytes: vec![0u8, 0, 0, 0],
-        vtype: REG_DWORD,
-    };
+pub const TCP_ACK_FREQUENCY: &str = "tcp-ack-frequency";
+pub const TCP_MTU: &str = "tcp-mtu";
 
-    let mut nic_key = None;
-    for maybe_nic in nics.enum_keys() {
-        let nic_id = maybe_nic.unwrap();
-        let nic = nics.open_subkey_with_flags(nic_id.clone(), KEY_READ).unwrap();
-        if nic
-            .enum_values()
-            .filter(|r| match r {
-                Ok((k, v)) => k == "AddressType" && *v == addr_type_value,
-                _ => false,
-            })
-            .count()
-            > 0
-        {
-            nic_key = Some(nic_id);
-            break;
-        }
-    }
+pub fn tcp_tweaks() -> Vec<TweakInfo> {
+    vec![
+        TweakInfo {
+            id: TCP_ACK_FREQUENCY.to_string(),
+            category: Some(Category::Network),
+            risk: Risk::Low,
+            description: String::from("Acknowledge every TCP packet immediately"),
+        },
+        TweakInfo {
+            id: TCP_MTU.to_string(),
+            category: Some(Category::Network),
+            risk: Risk::Medium,
+            description: String::from("Set the MTU of the active interface to the measured one"),
+        },
+    ]
+}
 
-    if let Some(nic_id) = nic_key {
-        let nic_t = Transaction::new().unwrap();
-        let (nic, _) =
-            nics.create_subkey_transacted_with_flags(nic_id.clone(), &nic_t, KEY_ALL_ACCESS).unwrap();
-        let reg_path= format!("HKEY_LOCAL_MACHINE\\SYSTEM\\CurrentControlSet\\Services\\Tcpip\\Parameters\\Interfaces\\{}", &nic_id);
-
-        // Subkey: HKEY_LOCAL_MACHINE\SYSTEM\CurrentControlSet\Services\Tcpip\Parameters\Interfaces\<Interface GUID>
-        // Entry: TcpAckFrequency
-        // Value Type: REG_DWORD, number
-        // Valid Range: 0-255
-        // Default: 2
-        // Description: Specifies the number of ACKs that will be outstanding before the delayed ACK timer is ignored. Microsoft does not recommend changing the default value without careful study of the environment.
-        // If you set the value to 1, every packet is acknowledged immediately because there's only one outstanding TCP ACK as a segment is just received. The value of 0 (zero) isn't valid and is treated as the default, 2. The only time the ACK number is 0 when a segment isn't received and the host isn't going to acknowledge the data.
-        // https://docs.microsoft.com/en-us/troubleshoot/windows-server/networking/registry-entry-control-tcp-acknowledgment-behavior
-        if default_settings {
-            del_key_reg(&nic, "TcpAckFrequency", &reg_path);
-        } else {
-            set_u32_reg(&nic, "TcpAckFrequency", &1u32, &reg_path, write_settings);
-        }
+const INTERFACES: &str = "SYSTEM\\CurrentControlSet\\Services\\Tcpip\\Parameters\\Interfaces";
 
-        // https://support.microsoft.com/en-us/topic/fix-tcp-ip-nagle-algorithm-for-microsoft-message-queue-server-can-be-disabled-74ba2f6a-e558-d1df-1c60-57b0fab68ccc
-        // set_u32_reg(&nic, "TCPNoDelay", &1u32, &nic_id, fixes, write_settings);
-
-        // Key: Tcpip\Parameters\Interfaces\ID for Adapter
-        // Value type: REG_DWORD Number
-        // Valid range: 68 - the MTU of the underlying network
-        // Default: 0xFFFFFFFF
-        // Description: This parameter overrides the default Maximum Transmission Unit (MTU) for a network interface. The MTU is the maximum packet size in bytes that the transport transmits over the underlying network. The size includes the transport header. An IP datagram can span multiple packets. Values larger than the default value for the underlying network cause the transport to use the network default MTU. Values smaller than 68 cause the transport to use an MTU of 68.
-
-        if default_settings {
-            // let default_mtu = 1374u32;
-            // set_u32_reg(&nic, "MTU", &default_mtu, &reg_path, write_settings);
-            del_key_reg(&nic, "MTU", &reg_path);
-        } else {
-            set_u32_reg(&nic, "MTU", &mtu, &reg_path, write_settings);
-        }
+// The interface with AddressType 0, None when there is none. An interface
+// that cannot be read is not taken for the active one.
+fn find_nic<B: RegistryBackend>(backend: &B) -> std::io::Result<Option<String>> {
+    let hklm = backend.predef(Hive::LocalMachine);
+    let nics = backend.open_subkey(&hklm, INTERFACES)?;
 
-        // TCPNoDelay and set it also to 1 to disable “nagling”
-        // TcpDelAckTicks and set it to 0
+    let addr_type_value = RegValue::dword(0);
 
-        if write_settings {
-            nic_t.commit().unwrap();
-        }
-    } else {
-        println!("\x1b[0;93mCould not find your current network interface!\x1b[0m");
+    Ok(backend.enum_keys(&nics)?.into_iter().find(|nic_id| {
+        backend
+            .open_subkey(&nics, nic_id)
+            .and_then(|nic| backend.enum_values(&nic))
+            .is_ok_and(|values| values.iter().any(|(k, v)| k == "AddressType" && *v == addr_type_value))
+    }))
+}
+
+// The active interface, or one failed result for every selected TCP value
+fn active_nic<B: RegistryBackend>(backend: &B, selection: &Selection) -> Result<String, Report> {
+    match find_nic(backend) {
+        Ok(Some(nic_id)) => Ok(nic_id),
+        Ok(None) => Err(nic_failed(selection, INTERFACES, || Error::NoNetworkInterface)),
+        Err(e) => Err(nic_failed(selection, INTERFACES, || io_error(&e))),
     }
 }
 
-pub fn restore_default_reg(reg_settings: &Settings) -> std::io::Result<()> {
-    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
-    for section in reg_settings.local_machine.iter() {
-        let reg_t = Transaction::new()?;
-        let (reg, _) =
-            hklm.create_subkey_transacted_with_flags(&section.path, &reg_t, KEY_ALL_ACCESS)?;
-        let reg_path = format!("HKEY_LOCAL_MACHINE\\{}", section.path);
-
-        for data in section.data.iter() {
-            match data {
-                Either::StringElement(ele) => match &ele.default {
-                    None => del_key_reg(&reg, &ele.key, &reg_path),
-                    Some(val) => set_str_reg(&reg, &ele.key, &val, &reg_path, true),
-                },
-                Either::U32Element(ele) => match &ele.default {
-                    None => del_key_reg(&reg, &ele.key, &reg_path),
-                    Some(val) => set_u32_reg(&reg, &ele.key, &val, &reg_path, true),
-                },
-                Either::VecElement(ele) => match &ele.default {
-                    None => del_key_reg(&reg, &ele.key, &reg_path),
-                    Some(val) => set_vac_reg(&reg, &ele.key, val.clone(), &reg_path, true),
-                },
-            }
+// One failed result for every selected TCP value when the interface cannot be read
+fn nic_failed(selection: &Selection, path: &str, error: impl Fn() -> Error) -> Report {
+    let mut report = Report::default();
+    for (id, name) in [(TCP_ACK_FREQUENCY, "TcpAckFrequency"), (TCP_MTU, "MTU")] {
+        if selection.includes(id, Some(Category::Network)) {
+            report.push(CheckResult {
+                hive: Hive::LocalMachine,
+                path: path.to_string(),
+                name: name.to_string(),
+                value: None,
+                outcome: CheckOutcome::Failed(error()),
+            });
         }
-        reg_t.commit()?;
     }
+    report
+}
 
-    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-    for section in reg_settings.current_user.iter() {
-        let reg_t = Transaction::new()?;
-        let (reg, _) =
-            hkcu.create_subkey_transacted_with_flags(&section.path, &reg_t, KEY_ALL_ACCESS)?;
-        let reg_path = format!("HKEY_CURRENT_USER\\{}", section.path);
-
-        for data in section.data.iter() {
-            match data {
-                Either::StringElement(ele) => {
-                    set_str_reg(&reg, &ele.key, &ele.value, &reg_path, true)
-                }
-                Either::U32Element(ele) => set_u32_reg(&reg, &ele.key, &ele.value, &reg_path, true),
-                Either::VecElement(ele) => {
-                    set_vac_reg(&reg, &ele.key, ele.value.clone(), &reg_path, true)
-                }
-            }
-        }
+// io::Error is not Clone, a copy keeps the kind and the message
+fn io_error(e: &std::io::Error) -> Error {
+    std::io::Error::new(e.kind(), e.to_string()).into()
+}
 
-        reg_t.commit()?;
+// The MTU is only used when TCP_MTU is selected, a failed measurement fails only that value
+pub fn check_tcp_tweaks<B: RegistryBackend>(backend: &B, selection: &Selection, mtu: error::Result<u32>) -> Report {
+    let mut report = Report::default();
+    let nic_id = match active_nic(backend, selection) {
+        Ok(nic_id) => nic_id,
+        Err(failed) => return failed,
+    };
+    let reg_path = format!("{}\\{}", INTERFACES, &nic_id);
+    let nic = match backend.open_subkey(&backend.predef(Hive::LocalMachine), &reg_path) {
+        Ok(nic) => nic,
+        Err(e) => return nic_failed(selection, &reg_path, || io_error(&e)),
+    };
+    let location = &RegPath::new(Hive::LocalMachine, &reg_path);
+
+    // Subkey: HKEY_LOCAL_MACHINE\SYSTEM\CurrentControlSet\Services\Tcpip\Parameters\Interfaces\<Interface GUID>
+    // Entry: TcpAckFrequency
+    // Value Type: REG_DWORD, number
+    // Valid Range: 0-255
+    // Default: 2
+    // Description: Specifies the number of ACKs that will be outstanding before the delayed ACK timer is ignored. Microsoft does not recommend changing the default value without careful study of the environment.
+    // If you set the value to 1, every packet is acknowledged immediately because there's only one outstanding TCP ACK as a segment is just received. The value of 0 (zero) isn't valid and is treated as the default, 2. The only time the ACK number is 0 when a segment isn't received and the host isn't going to acknowledge the data.
+    // https://docs.microsoft.com/en-us/troubleshoot/windows-server/networking/registry-entry-control-tcp-acknowledgment-behavior
+    if selection.includes(TCP_ACK_FREQUENCY, Some(Category::Network)) {
+        report.push(set_u32_reg(backend, &nic, location, "TcpAckFrequency", &1u32, false));
     }
 
-    let hkus = RegKey::predef(HKEY_USERS);
-    for section in reg_settings.users.iter() {
-        let reg_t = Transaction::new()?;
-        let (reg, _) =
-            hkus.create_subkey_transacted_with_flags(&section.path, &reg_t, KEY_ALL_ACCESS)?;
-        let reg_path = format!("HKEY_USERS\\{}", section.path);
-
-        for data in section.data.iter() {
-            match data {
-                Either::StringElement(ele) => {
-                    set_str_reg(&reg, &ele.key, &ele.value, &reg_path, true)
-                }
-                Either::U32Element(ele) => set_u32_reg(&reg, &ele.key, &ele.value, &reg_path, true),
-                Either::VecElement(ele) => {
-                    set_vac_reg(&reg, &ele.key, ele.value.clone(), &reg_path, true)
-                }
-            }
-        }
-
-        reg_t.commit()?;
+    // https://support.microsoft.com/en-us/topic/fix-tcp-ip-nagle-algorithm-for-microsoft-message-queue-server-can-be-disabled-74ba2f6a-e558-d1df-1c60-57b0fab68ccc
+    // set_u32_reg(&nic, "TCPNoDelay", &1u32, &nic_id, fixes, write_settings);
+
+    // Key: Tcpip\Parameters\Interfaces\ID for Adapter
+    // Value type: REG_DWORD Number
+    // Valid range: 68 - the MTU of the underlying network
+    // Default: 0xFFFFFFFF
+    // Description: This parameter overrides the default Maximum Transmission Unit (MTU) for a network interface. The MTU is the maximum packet size in bytes that the transport transmits over the underlying network. The size includes the transport header. An IP datagram can span multiple packets. Values larger than the default value for the underlying network cause the transport to use the network default MTU. Values smaller than 68 cause the transport to use an MTU of 68.
+    if selection.includes(TCP_MTU, Some(Category::Network)) {
+        report.push(match mtu {
+            Ok(mtu) => set_u32_reg(backend, &nic, location, "MTU", &mtu, false),
+            Err(e) => CheckResult {
+                hive: Hive::LocalMachine,
+                path: reg_path.clone(),
+                name: String::from("MTU"),
+                value: None,
+                outcome: CheckOutcome::Failed(e),
+            },
+        });
     }
 
-    Ok(())
+    // TCPNoDelay and set it also to 1 to disable “nagling”
+    // TcpDelAckTicks and set it to 0
+    report
 }
 
-// TODO: control mmsys.cpl
-// Computer\HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\Windows\CurrentVersion\MMDevices\Audio\Render\{d75eb062-247f-4dd3-b4d5-55fea9c4cee2}\Properties
-// https://github.com/romanadamski/ChangeSoundDeviceTray/blob/268fc877622442d9f991ec670e785ebcabc51aeb/AudioSwitcher.AudioApi.CoreAudio/Internal/PropertyKeys.cs
-// https://github.com/MSDN-WhiteKnight/answers/blob/7e87ccf0edd6978802964503aa4ff8efda5ece62/tools/data/ru.stackoverflow.com/posts/A743709.md
-// https://stackoverflow.com/questions/52954849/enabling-recording-devices-programmatically
-// https://www.pinvoke.net/default.aspx/Constants/PROPERTYKEY.html
-// https://github.com/AutomatedLab/DeviceManagement/blob/master/DeviceManagementLib/Device.cs
-// https://windowsreport.com/audio-device-disabled-windows-10/
-// https://codemachine.com/articles/how_windows_sets_default_audio_device.html
-// https://social.technet.microsoft.com/Forums/en-US/590fd01d-f27b-48db-bad4-9497474ff185/setting-playback-and-communication-device-via-registry?forum=win10itprosetup
-
-#[allow(dead_code)]
-pub fn apply_audio_settings(_write_settings: bool) -> std::io::Result<()> {
-    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
-    let nics = hklm.open_subkey_with_flags(
-        "SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\MMDevices\\Audio\\Render",
-        KEY_READ,
-    )?;
-
-    let addr_type_value = RegValue {
-        bytes: vec![1u8, 0, 0, 0],
-        vtype: REG_DWORD,
+// Deleting the values gives the Windows defaults back
+pub fn restore_tcp_defaults<B: RegistryBackend>(backend: &B, selection: &Selection) -> Report {
+    let mut report = Report::default();
+    let nic_id = match active_nic(backend, selection) {
+        Ok(nic_id) => nic_id,
+        Err(failed) => return failed,
+    };
+    let hklm = backend.predef(Hive::LocalMachine);
+    let reg_path = format!("{}\\{}", INTERFACES, &nic_id);
+    let location = &RegPath::new(Hive::LocalMachine, &reg_path);
+    let nic_t = match backend.transaction() {
+        Ok(nic_t) => nic_t,
+        Err(e) => return nic_failed(selection, &reg_path, || io_error(&e)),
+    };
+    let nic = match backend.create_subkey_transacted(&hklm, &reg_path, &nic_t) {
+        Ok(nic) => nic,
+        Err(e) => return nic_failed(selection, &reg_path, || io_error(&e)),
     };
 
-    let mut soundcard_guid = Vec::new();
+    if selection.includes(TCP_ACK_FREQUENCY, Some(Category::Network)) {
+        report.push(remove_value_reg(backend, &nic, location, "TcpAckFrequency", true));
+    }
+    if selection.includes(TCP_MTU, Some(Category::Network)) {
+        // let default_mtu = 1374u32;
+        // set_u32_reg(&nic, "MTU", &default_mtu, &reg_path, write_settings);
+        report.push(remove_value_reg(backend, &nic, location, "MTU", true));
+    }
 
-    for maybe_nic in nics.enum_keys() {
-        let nic_id = maybe_nic?;
-        let nic = nics.open_subkey_with_flags(nic_id.clone(), KEY_READ)?;
+    // Nothing was deleted when the commit fails
+    match backend.commit(&nic_t) {
+        Ok(()) => report,
+        Err(e) => nic_failed(selection, &reg_path, || io_error(&e)),
+    }
+}
 
-        for n in nic.enum_values() {
-            match n {
-                Err(e) => panic!("{:?}", e),
-                Ok(ratio) => {
-                    if ratio.0 == "DeviceState" && ratio.1 == addr_type_value {
-                        soundcard_guid.push(nic_id.clone());
-                    }
-                }
-            }
+// The same for every hive: each key gets the Windows defaults in a transaction
+// of its own, a key that fails is reported and the next one goes on
+pub fn restore_default_reg<B: RegistryBackend>(backend: &B, reg_settings: &Settings) -> Report {
+    let mut report = Report::default();
+    for section in reg_settings.keys.iter().filter(|s| !s.remove) {
+        let root = backend.predef(section.path.hive);
+        let restored = backend.transaction().and_then(|reg_t| {
+            let reg = match backend.open_subkey_transacted(&root, &section.path.subkey, &reg_t) {
+                Ok(reg) => reg,
+                // The key is not created only to hold the defaults
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(unrestored_section(section)),
+                Err(e) => return Err(e),
+            };
+            let restored = restore_section(backend, &reg, section);
+            backend.commit(&reg_t)?;
+            Ok(restored)
+        });
+        match restored {
+            Ok(restored) => report.extend(restored),
+            // An uncommitted transaction changes nothing
+            Err(e) => report.extend(failed_section(section, &e)),
         }
     }
+    report
+}
 
-    for guid in soundcard_guid.iter() {
-        println!("soundcard_guid HKEY_LOCAL_MACHINE\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\MMDevices\\Audio\\Render\\{}\\Properties", guid);
-
-        let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
-        let key = hklm.open_subkey(format!(
-            "SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\MMDevices\\Audio\\Render\\{}\\Properties",
-            guid
-        ))?;
-
-        let bus: String = key.get_value("{b3f8fa53-0004-438e-9003-51a46e139bfc},2")?;
-        let name: String = key.get_value("{b3f8fa53-0004-438e-9003-51a46e139bfc},6")?;
-        // let deviceNameKey: String = key.get_value("{a45c254e-df1c-4efd-8020-67d146a850e0},2")?; // getOutputDevice(guid):
-
-        println!("Name: {}, Bus: {}", name, bus)
+// The values restore_section would have written when their key does not exist:
+// defaults are missing, values that have to be deleted are already gone
+fn unrestored_section(section: &RegTweaks) -> Report {
+    let mut report = Report::default();
+    for element in section.data.iter().filter(|element| element.value().is_some()) {
+        let value = element.default_value();
+        report.push(CheckResult {
+            hive: section.path.hive,
+            path: section.path.subkey.clone(),
+            name: element.name().to_string(),
+            outcome: if value.is_some() {
+                CheckOutcome::Missing
+            } else {
+                CheckOutcome::Correct
+            },
+            value,
+        });
     }
+    report
+}
 
-    Ok(())
+// Every value restore_section would have written fails with the key
+fn failed_section(section: &RegTweaks, e: &std::io::Error) -> Report {
+    let mut report = Report::default();
+    for element in section.data.iter().filter(|element| element.value().is_some()) {
+        let value = element.default_value();
+        report.push(CheckResult {
+            hive: section.path.hive,
+            path: section.path.subkey.clone(),
+            name: element.name().to_string(),
+            value,
+            outcome: CheckOutcome::Failed(io_error(e)),
+        });
+    }
+    report
 }
 
-pub fn apply_get_dpi() -> u32 {
-    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-    let key = hkcu.open_subkey("Control Panel\\Desktop");
+pub fn apply_get_dpi<B: RegistryBackend>(backend: &B) -> u32 {
+    let hkcu = backend.predef(Hive::CurrentUser);
 
-    if key.is_ok() {
-        let regkey = key.unwrap();
-        let win8_dpi_scaling: u32 = regkey.get_value("Win8DpiScaling").unwrap();
-        if win8_dpi_scaling == 0u32 {
-            96u32
-        } else {
+    // Without the values Windows uses 96
+    if let Ok(regkey) = backend.open_subkey(&hkcu, "Control Panel\\Desktop") {
+        match backend.get_u32(&regkey, "Win8DpiScaling") {
+            Ok(0) | Err(_) => 96u32,
             // DPI	Scale factor // https://docs.microsoft.com/en-us/windows-hardware/manufacture/desktop/dpi-related-apis-and-registry-settings
             // 96	100
             // 120	125
             // 144	150
             // 192	200
-            let log_pixels: u32 = regkey.get_value("LogPixels").unwrap();
-            log_pixels
+            Ok(_) => backend.get_u32(&regkey, "LogPixels").unwrap_or(96u32),
         }
     } else {
         96u32
     }
 }
 
-pub struct CpuPriority {
-    pub process: String,
-    pub cpu_priority_class: Option<u32>,
-    pub io_priority: Option<u32>,
-    pub page_priority: Option<u32>,
-    pub working_set_limit_in_kb: Option<u32>,
-}
+#[cfg(test)]
+mod tests {
+    use super::backend::{Hive, MemoryBackend, RegValue, RegistryBackend};
+    use super::catalog::Catalog;
+    use super::report::{CheckOutcome, Report};
+    use super::*;
+    use std::cell::RefCell;
+
+    // A tweak with a Windows default and one without, below `root`
+    fn catalog(root: &str) -> Catalog {
+        let text = format!(
+            r#"
+version = 1
+
+[[tweak]]
+id = "with-default"
+category = "input"
+path = '{root}\Software\Test'
+name = "Speed"
+type = "dword"
+value = 1
+default = 10
+
+[[tweak]]
+id = "without-default"
+category = "input"
+path = '{root}\Software\Test'
+name = "Extra"
+type = "sz"
+value = "on"
+"#,
+            root = root
+        );
+        Catalog::from_toml("test.toml", &text).unwrap()
+    }
+
+    fn settings(root: &str) -> Settings {
+        catalog(root).settings(96, None, |_| true)
+    }
 
-pub fn set_cpu_priority(data: CpuPriority, write_settings: bool) {
-    let ifeo = RegKey::predef(HKEY_LOCAL_MACHINE).open_subkey(
-        "SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion\\Image File Execution Options",
-    ).unwrap();
+    fn write(backend: &MemoryBackend, hive: Hive, path: &str, name: &str, value: &RegValue) {
+        let transaction = backend.transaction().unwrap();
+        let key = backend
+            .create_subkey_transacted(&backend.predef(hive), path, &transaction)
+            .unwrap();
+        backend.set_raw_value(&key, name, value).unwrap();
+        backend.commit(&transaction).unwrap();
+    }
 
-    let (perf_options, _) = ifeo.create_subkey(format!("{}\\PerfOptions", data.process)).unwrap();
-    if data.cpu_priority_class.is_some() {
-        set_u32_reg(&perf_options, &"CpuPriorityClass", &data.cpu_priority_class.unwrap(), &format!("{}\\PerfOptions", data.process), write_settings);
+    fn outcomes(report: &Report) -> Vec<(String, &'static str)> {
+        report
+            .results
+            .iter()
+            .map(|r| {
+                let outcome = match r.outcome {
+                    CheckOutcome::Correct => "correct",
+                    CheckOutcome::Mismatch { .. } => "mismatch",
+                    CheckOutcome::WrongType { .. } => "wrong type",
+                    CheckOutcome::Missing => "missing",
+                    CheckOutcome::Present => "present",
+                    CheckOutcome::Written => "written",
+                    CheckOutcome::Retyped { .. } => "retyped",
+                    CheckOutcome::Deleted => "deleted",
+                    CheckOutcome::DeletedKey => "deleted key",
+                    CheckOutcome::Failed(_) => "failed",
+                };
+                (r.name.clone(), outcome)
+            })
+            .collect()
     }
-    if data.io_priority.is_some() {
-        if write_settings {
-            perf_options.set_value("IoPriority", &data.io_priority.unwrap()).unwrap();
+
+    // The hive, the root of the catalog paths and the key below the hive they name
+    const ROOTS: [(Hive, &str, &str); 3] = [
+        (Hive::LocalMachine, "HKLM", ""),
+        (Hive::CurrentUser, "HKCU", ""),
+        (Hive::Users, "HKU\\S-1-5-21-1000", "S-1-5-21-1000\\"),
+    ];
+
+    #[test]
+    fn restore_on_a_clean_machine_does_not_fail() {
+        for (hive, root, sid) in ROOTS {
+            let backend = MemoryBackend::new();
+            let settings = settings(root);
+            let report = restore_default_reg(&backend, &settings);
+            assert_eq!(
+                outcomes(&report),
+                vec![(String::from("Speed"), "missing"), (String::from("Extra"), "correct")],
+                "{}",
+                root
+            );
+            assert_eq!(report.failed(), 0);
+            let path = format!("{}Software\\Test", sid);
+            assert!(backend.open_subkey(&backend.predef(hive), &path).is_err(), "{}", root);
         }
     }
-    if data.page_priority.is_some() {
-        if write_settings {
-            perf_options.set_value("PagePriority", &data.page_priority.unwrap()).unwrap();
+
+    #[test]
+    fn restore_is_the_same_in_every_hive() {
+        let mut all = Vec::new();
+        for (hive, root, sid) in ROOTS {
+            let backend = MemoryBackend::new();
+            let path = format!("{}Software\\Test", sid);
+            write(&backend, hive, &path, "Speed", &RegValue::dword(1));
+            write(&backend, hive, &path, "Extra", &RegValue::sz("on"));
+            let settings = settings(root);
+
+            let first = restore_default_reg(&backend, &settings);
+            let key = backend.open_subkey(&backend.predef(hive), &path).unwrap();
+            assert_eq!(backend.get_u32(&key, "Speed").unwrap(), 10);
+            assert!(backend.get_raw_value(&key, "Extra").is_err());
+
+            // A second restore finds everything at the default
+            let second = restore_default_reg(&backend, &settings);
+            all.push((outcomes(&first), outcomes(&second)));
         }
+        assert_eq!(
+            all[0].0,
+            vec![(String::from("Speed"), "written"), (String::from("Extra"), "deleted")]
+        );
+        assert_eq!(
+            all[0].1,
+            vec![(String::from("Speed"), "correct"), (String::from("Extra"), "correct")]
+        );
+        assert!(all.iter().all(|outcomes| *outcomes == all[0]));
+    }
+
+    #[test]
+    fn a_key_that_cannot_be_written_does_not_stop_the_restore() {
+        let backend = MemoryBackend::new();
+        write(&backend, Hive::LocalMachine, "Software\\Test", "Speed", &RegValue::dword(1));
+        write(&backend, Hive::CurrentUser, "Software\\Test", "Speed", &RegValue::dword(1));
+        backend.deny(Hive::LocalMachine, "Software");
+        let mut both = catalog("HKLM");
+        both.tweaks.extend(catalog("HKCU").tweaks);
+
+        let report = restore_default_reg(&backend, &both.settings(96, None, |_| true));
+        assert_eq!(
+            outcomes(&report),
+            vec![
+                (String::from("Speed"), "failed"),
+                (String::from("Extra"), "correct"),
+                (String::from("Speed"), "written"),
+                (String::from("Extra"), "correct"),
+            ]
+        );
+        assert!(matches!(report.results[0].outcome, CheckOutcome::Failed(Error::AccessDenied)));
+        let key = backend.open_subkey(&backend.predef(Hive::CurrentUser), "Software\\Test").unwrap();
+        assert_eq!(backend.get_u32(&key, "Speed").unwrap(), 10);
+    }
+
+    #[test]
+    fn restore_tcp_defaults_without_the_values() {
+        let backend = MemoryBackend::new();
+        let nic = format!("{}\\{{nic}}", INTERFACES);
+        write(&backend, Hive::LocalMachine, &nic, "AddressType", &RegValue::dword(0));
+        write(&backend, Hive::LocalMachine, &nic, "MTU", &RegValue::dword(1400));
+
+        let report = restore_tcp_defaults(&backend, &Selection::default());
+        assert_eq!(
+            outcomes(&report),
+            vec![(String::from("TcpAckFrequency"), "correct"), (String::from("MTU"), "deleted")]
+        );
+        let report = restore_tcp_defaults(&backend, &Selection::default());
+        assert_eq!(report.failed(), 0);
+    }
+
+    #[test]
+    fn no_active_interface_fails_the_tcp_values() {
+        let backend = MemoryBackend::new();
+        let nic = format!("{}\\{{nic}}", INTERFACES);
+        write(&backend, Hive::LocalMachine, &nic, "AddressType", &RegValue::dword(1));
+
+        let report = check_tcp_tweaks(&backend, &Selection::default(), Ok(1400));
+        assert_eq!(
+            outcomes(&report),
+            vec![(String::from("TcpAckFrequency"), "failed"), (String::from("MTU"), "failed")]
+        );
+        assert!(matches!(report.results[0].outcome, CheckOutcome::Failed(Error::NoNetworkInterface)));
+        let report = restore_tcp_defaults(&backend, &Selection::default());
+        assert_eq!(report.failed(), 2);
+    }
+
+    #[test]
+    fn restore_leaves_the_tweaks_outside_the_selection_alone() {
+        let backend = MemoryBackend::new();
+        write(&backend, Hive::LocalMachine, "Software\\Test", "Speed", &RegValue::dword(1));
+        write(&backend, Hive::LocalMachine, "Software\\Test", "Extra", &RegValue::sz("on"));
+        let selection = Selection::new(Vec::new(), vec![String::from("with-default")]);
+        let settings = catalog("HKLM").settings(96, None, |t| selection.includes(&t.id, Some(t.category)));
+
+        assert_eq!(outcomes(&check_reg_tweaks(&backend, &settings)), vec![(String::from("Extra"), "correct")]);
+        let report = restore_default_reg(&backend, &settings);
+        assert_eq!(outcomes(&report), vec![(String::from("Extra"), "deleted")]);
+        let key = backend.open_subkey(&backend.predef(Hive::LocalMachine), "Software\\Test").unwrap();
+        assert_eq!(backend.get_u32(&key, "Speed").unwrap(), 1);
+        assert!(backend.get_raw_value(&key, "Extra").is_err());
+    }
+
+    // The captured powercfg output of a system without the gaming scheme. Every
+    // scheme has the indices of the capture, the writes are recorded.
+    struct FakePowercfg {
+        schemes: RefCell<Vec<powerplan::SchemeEntry>>,
+        calls: RefCell<Vec<String>>,
     }
-    if data.working_set_limit_in_kb.is_some() {
-        if write_settings {
-            perf_options.set_value("WorkingSetLimitInKB", &data.working_set_limit_in_kb.unwrap()).unwrap();
+
+    fn fixture(name: &str) -> String {
+        std::fs::read_to_string(format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+    }
+
+    impl FakePowercfg {
+        fn new() -> FakePowercfg {
+            FakePowercfg {
+                schemes: RefCell::new(powerplan::parse_list(&fixture("powercfg-list-de.txt"))),
+                calls: RefCell::new(Vec::new()),
+            }
+        }
+
+        fn call(&self, call: String) {
+            self.calls.borrow_mut().push(call);
         }
     }
 
-    // "CpuPriorityClass"=dword:00000001
-    // 00000001 = Idle
-    // 00000002 = Normal (def 2)
-    // 00000003 = High
-    // 00000004 = RealTime (n.a.)
-    // 00000005 = Below Normal
-    // 00000006 = Above Normal
-
-    // "IoPriority"=dword:00000000
-    // 00000000 = Very Low
-    // 00000001 = Low
-    // 00000002 = Normal (def 2)
-    // 00000003 = High
-
-    // "PagePriority"=dword:00000001
-    // 00000000 = 0 Idle
-    // 00000001 = 1 Very Low
-    // 00000002 = 2 Low
-    // 00000003 = 3 Background
-    // 00000004 = 4 Background
-    // 00000005 = 5 Normal (def 5)
-
-    // "WorkingSetLimitInKB"
-    // (def 1382)
-}
+    impl PowerConfig for FakePowercfg {
+        fn list(&self) -> std::io::Result<Vec<powerplan::SchemeEntry>> {
+            Ok(self.schemes.borrow().clone())
+        }
 
-fn get_smooth_mouse_x_curve(dpi: u32) -> Vec<u8> {
-    return match dpi {
-        // DPI 100%
-        96 => vec![
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xC0, 0xCC, 0x0C, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x80, 0x99, 0x19, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x66, 0x26, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x33, 0x33, 0x00, 0x00, 0x00, 0x00, 0x00,
-        ],
-        // DPI 125%
-        120 => vec![
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x30, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00,
-        ],
-        // DPI 150%
-        144 => vec![
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x30, 0x33, 0x13, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x60, 0x66, 0x26, 0x00, 0x00, 0x00, 0x00, 0x00, 0x90, 0x99, 0x39, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0xC0, 0xCC, 0x4C, 0x00, 0x00, 0x00, 0x00, 0x00,
-        ],
-        // DPI 200%
-        192 => vec![
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x90, 0x99, 0x19, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x20, 0x33, 0x33, 0x00, 0x00, 0x00, 0x00, 0x00, 0xB0, 0xCC, 0x4C, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x40, 0x66, 0x66, 0x00, 0x00, 0x00, 0x00, 0x00,
-        ],
-        // DPI 100%
-        _ => {
-            println!(
-                "SmoothMouse X Curve for this DPI ({}) Setting not found",
-                dpi
-            );
+        fn query(&self, scheme: &str) -> std::io::Result<powerplan::PowerScheme> {
+            assert!(self.schemes.borrow().iter().any(|s| s.guid == scheme), "{} does not exist", scheme);
+            Ok(powerplan::parse_query(&fixture("powercfg-qh-en.txt")).unwrap())
+        }
+
+        fn query_setting(&self, scheme: &str, sub: &str, s: &str) -> std::io::Result<Option<powerplan::PowerSetting>> {
+            Ok(self.query(scheme)?.setting(sub, s).cloned())
+        }
+
+        fn duplicate(&self, base: &str, scheme: &str, name: &str) -> std::io::Result<()> {
+            self.call(format!("duplicate {} {} {}", base, scheme, name));
+            self.schemes.borrow_mut().push(powerplan::SchemeEntry {
+                guid: scheme.to_string(),
+                active: false,
+            });
+            Ok(())
+        }
+
+        fn set(&self, scheme: &str, sub: &str, setting: &str, source: PowerSource, value: u32) -> std::io::Result<()> {
+            self.call(format!("set {} {} {} {} {}", scheme, sub, setting, source.name(), value));
+            Ok(())
+        }
+
+        fn activate(&self, scheme: &str) -> std::io::Result<()> {
+            self.call(format!("activate {}", scheme));
+            for entry in self.schemes.borrow_mut().iter_mut() {
+                entry.active = entry.guid == scheme;
+            }
+            Ok(())
+        }
+
+        fn delete(&self, scheme: &str) -> std::io::Result<()> {
+            let mut schemes = self.schemes.borrow_mut();
+            if schemes.iter().any(|s| s.guid == scheme && s.active) {
+                return Err(std::io::Error::other("the active scheme cannot be deleted"));
+            }
+            self.call(format!("delete {}", scheme));
+            schemes.retain(|s| s.guid != scheme);
+            Ok(())
+        }
+    }
+
+    const HIGH_PERFORMANCE: &str = "8c5e7fda-e8bf-4a96-9a85-a6e23a8c635c";
+    const SUB_PROCESSOR: &str = "54533251-82be-4824-96c1-47b60b740d00";
+    const PROCTHROTTLEMIN: &str = "893dee8e-2bef-41e0-89c6-b55d0929964c";
+
+    fn setting(path: &str, alias: &str, ac: u32, dc: u32) -> SettingGuid {
+        SettingGuid {
+            path: path.to_string(),
+            alias: alias.to_string(),
+            hidden: false,
+            ac,
+            dc,
+        }
+    }
+
+    #[test]
+    fn power_settings_windows_does_not_know_fail_with_their_reason() {
+        let plan = PowerPlan {
+            base: powerplan::BALANCED.to_string(),
+            data: vec![SubGuid {
+                path: "54533251-82be-4824-96c1-47b60b740d00".to_string(),
+                data: vec![
+                    setting("893dee8e-2bef-41e0-89c6-b55d0929964c", "PROCTHROTTLEMIN", 100, 5),
+                    setting("be337238-0d82-4146-a960-4f3749d470c7", "PERFBOOSTMODE", 9, 2),
+                    setting("00000000-0000-0000-0000-000000000000", "NOSUCHSETTING", 1, 1),
+                ],
+            }],
+        };
+        let report = check_powerplan(&FakePowercfg::new(), &plan);
+        let outcomes: Vec<&str> = outcomes(&report).into_iter().map(|(_, outcome)| outcome).collect();
+        assert_eq!(
+            outcomes,
             vec![
-                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xC0, 0xCC, 0x0C, 0x00, 0x00, 0x00,
-                0x00, 0x00, 0x80, 0x99, 0x19, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x66, 0x26, 0x00,
-                0x00, 0x00, 0x00, 0x00, 0x00, 0x33, 0x33, 0x00, 0x00, 0x00, 0x00, 0x00,
+                "missing", "mismatch", "correct", "failed", "mismatch", "failed", "failed", "mismatch"
             ]
-        }
-    };
-}
+        );
+        let errors: Vec<String> = report
+            .results
+            .iter()
+            .filter_map(|r| match &r.outcome {
+                CheckOutcome::Failed(e) => Some(e.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "9 is not a possible index of PERFBOOSTMODE on this system",
+                "does not exist",
+                "does not exist",
+            ]
+        );
+        assert!(matches!(report.results[3].outcome, CheckOutcome::Failed(Error::OutOfRange { index: 9, .. })));
+        assert!(matches!(report.results[6].outcome, CheckOutcome::Failed(Error::Missing)));
+    }
 
-fn get_smooth_mouse_y_curve(dpi: u32) -> Vec<u8> {
-    return match dpi {
-        // DPI 100%
-        96 => vec![
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x38, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x70, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xA8, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xE0, 0x00, 0x00, 0x00, 0x00, 0x00,
-        ],
-        // DPI 125%
-        120 => vec![
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x38, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x70, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xA8, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xE0, 0x00, 0x00, 0x00, 0x00, 0x00,
-        ],
-        // DPI 150%
-        144 => vec![
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x38, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x70, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xA8, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xE0, 0x00, 0x00, 0x00, 0x00, 0x00,
-        ],
-        // DPI 200%
-        192 => vec![
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x38, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x70, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xA8, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xE0, 0x00, 0x00, 0x00, 0x00, 0x00,
-        ],
-        // DPI 100%
-        _ => {
-            println!(
-                "SmoothMouse Y Curve for this DPI ({}) Setting not found",
-                dpi
-            );
+    #[test]
+    fn the_gaming_scheme_is_a_copy_written_before_its_indices() {
+        let power = FakePowercfg::new();
+        let powerplan = PowerPlan {
+            base: HIGH_PERFORMANCE.to_string(),
+            data: vec![SubGuid {
+                path: SUB_PROCESSOR.to_string(),
+                data: vec![setting(PROCTHROTTLEMIN, "PROCTHROTTLEMIN", 100, 50)],
+            }],
+        };
+        let mut plan = crate::plan::ChangePlan::new();
+        plan.add_powerplan(&powerplan, &check_powerplan(&power, &powerplan));
+        let execution = crate::plan::execute_with(&MemoryBackend::new(), &power, &crate::bcd::Bcdedit, &plan, None);
+        assert!(!execution.failed());
+
+        let gaming = GAMING_SCHEME;
+        assert_eq!(
+            *power.calls.borrow(),
             vec![
-                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x38, 0x00, 0x00, 0x00,
-                0x00, 0x00, 0x00, 0x00, 0x70, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xA8, 0x00,
-                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xE0, 0x00, 0x00, 0x00, 0x00, 0x00,
+                format!("duplicate {} {} {}", HIGH_PERFORMANCE, gaming, GAMING_SCHEME_NAME),
+                format!("set {} {} {} AC 100", gaming, SUB_PROCESSOR, PROCTHROTTLEMIN),
+                format!("set {} {} {} DC 50", gaming, SUB_PROCESSOR, PROCTHROTTLEMIN),
+                format!("activate {}", gaming),
             ]
+        );
+        // High performance is only read from
+        assert!(power.calls.borrow()[1..].iter().all(|call| !call.contains(HIGH_PERFORMANCE)));
+    }
+
+    #[test]
+    fn removing_the_gaming_scheme_switches_back_first() {
+        let power = FakePowercfg::new();
+        create_power_scheme(&power, HIGH_PERFORMANCE, GAMING_SCHEME, GAMING_SCHEME_NAME).unwrap();
+        activate_power_scheme(&power, GAMING_SCHEME).unwrap();
+        assert_eq!(active_power_scheme(&power).unwrap().as_deref(), Some(GAMING_SCHEME));
+        power.calls.borrow_mut().clear();
+
+        assert!(remove_gaming_scheme(&power, None).unwrap());
+        assert_eq!(
+            *power.calls.borrow(),
+            vec![format!("activate {}", powerplan::BALANCED), format!("delete {}", GAMING_SCHEME)]
+        );
+        assert!(!power_scheme_exists(&power, GAMING_SCHEME).unwrap());
+        assert!(power_scheme_exists(&power, HIGH_PERFORMANCE).unwrap());
+
+        // Nothing to do the second time
+        assert!(!remove_gaming_scheme(&power, None).unwrap());
+        assert_eq!(power.calls.borrow().len(), 2);
+    }
+
+    #[test]
+    fn an_inactive_gaming_scheme_is_only_deleted() {
+        let power = FakePowercfg::new();
+        create_power_scheme(&power, HIGH_PERFORMANCE, GAMING_SCHEME, GAMING_SCHEME_NAME).unwrap();
+        power.calls.borrow_mut().clear();
+
+        assert!(remove_gaming_scheme(&power, Some(HIGH_PERFORMANCE)).unwrap());
+        assert_eq!(*power.calls.borrow(), vec![format!("delete {}", GAMING_SCHEME)]);
+        assert_eq!(active_power_scheme(&power).unwrap().as_deref(), Some(powerplan::BALANCED));
+    }
+
+    #[test]
+    fn every_tweak_has_an_id_of_its_own() {
+        let mut tweaks: Vec<TweakInfo> = Catalog::builtin().tweaks.iter().map(|t| t.info()).collect();
+        tweaks.extend(crate::bcd::bcd_tweaks());
+        tweaks.extend(powerplan_tweaks());
+        tweaks.extend(tcp_tweaks());
+        tweaks.extend(profile::Profiles::builtin().profiles.iter().map(|p| p.info()));
+        for (i, tweak) in tweaks.iter().enumerate() {
+            assert!(tweaks[..i].iter().all(|t| t.id != tweak.id), "{} is there twice", tweak.id);
+            assert!(!tweak.description.is_empty(), "{}", tweak.id);
+            assert!(tweak.category.is_some(), "{}", tweak.id);
         }
-    };
+        // bcdedit and the power scheme are not undone by deleting a value
+        let risk = |id: &str| tweaks.iter().find(|t| t.id == id).unwrap().risk;
+        assert_eq!(risk("bcd-useplatformclock"), Risk::Medium);
+        assert_eq!(risk(POWERPLAN), Risk::Medium);
+        assert_eq!(risk(TCP_ACK_FREQUENCY), Risk::Low);
+    }
+
+    #[test]
+    fn reg_file_values_are_tweaks_of_their_own() {
+        let text = "Windows Registry Editor Version 5.00\r\n\r\n\
+            [HKEY_CURRENT_USER\\Software\\Test]\r\n\
+            \"Speed\"=dword:00000001\r\n\
+            \"Old\"=-\r\n\r\n\
+            [-HKEY_LOCAL_MACHINE\\SOFTWARE\\Test\\Gone]\r\n";
+        let mut settings = regfile::parse_reg("test.reg", text.as_bytes()).unwrap();
+        let tweaks: Vec<(String, String)> = settings.tweaks().into_iter().map(|t| (t.id, t.description)).collect();
+        assert_eq!(
+            tweaks,
+            vec![
+                (String::from("HKEY_CURRENT_USER\\Software\\Test\\Speed"), String::from("= dword:1")),
+                (String::from("HKEY_CURRENT_USER\\Software\\Test\\Old"), String::from("remove the value")),
+                (String::from("HKEY_LOCAL_MACHINE\\SOFTWARE\\Test\\Gone"), String::from("remove the key")),
+            ]
+        );
+
+        // Each user gets the HKCU values under HKU\<SID>, HKLM stays as it is
+        let users = settings.for_users(&[String::from("S-1-5-21-1001"), String::from("S-1-5-21-1002")]);
+        let paths: Vec<String> = users.keys.iter().map(|k| k.path.to_string()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "HKEY_USERS\\S-1-5-21-1001\\Software\\Test",
+                "HKEY_USERS\\S-1-5-21-1002\\Software\\Test",
+                "HKEY_LOCAL_MACHINE\\SOFTWARE\\Test\\Gone",
+            ]
+        );
+
+        settings.retain(|id| id.ends_with("\\Speed"));
+        let kept: Vec<String> = settings.tweaks().into_iter().map(|t| t.id).collect();
+        assert_eq!(kept, vec!["HKEY_CURRENT_USER\\Software\\Test\\Speed"]);
+    }
+
+    #[test]
+    fn the_dpi_comes_from_the_display_scaling() {
+        let backend = MemoryBackend::new();
+        assert_eq!(apply_get_dpi(&backend), 96);
+        write(&backend, Hive::CurrentUser, "Control Panel\\Desktop", "LogPixels", &RegValue::dword(144));
+        // LogPixels only counts with the scaling turned on
+        assert_eq!(apply_get_dpi(&backend), 96);
+        write(&backend, Hive::CurrentUser, "Control Panel\\Desktop", "Win8DpiScaling", &RegValue::dword(1));
+        assert_eq!(apply_get_dpi(&backend), 144);
+    }
 }
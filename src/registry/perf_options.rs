@@ -0,0 +1,521 @@
+// The PerfOptions of the Image File Execution Options, which Windows applies
+// whenever the executable starts. Other tools write them too, so removing only
+// touches the values a profile sets.
+
+use super::backend::{Hive, RegPath, RegValue, RegistryBackend};
+use super::report::{CheckOutcome, CheckResult, Report};
+use super::set::{remove_key_reg, remove_value_reg, set_u32_reg};
+use crate::error;
+
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+pub const IFEO: &str = "SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion\\Image File Execution Options";
+
+pub const CPU_PRIORITY_CLASS: &str = "CpuPriorityClass";
+pub const IO_PRIORITY: &str = "IoPriority";
+pub const PAGE_PRIORITY: &str = "PagePriority";
+pub const WORKING_SET_LIMIT: &str = "WorkingSetLimitInKB";
+
+// The priority class Windows starts the process with. RealTime (4) is left out,
+// Windows ignores it here and it would starve the rest of the system anyway.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "RawLevel")]
+pub enum CpuPriorityClass {
+    Idle = 1,
+    Normal = 2,
+    High = 3,
+    BelowNormal = 5,
+    AboveNormal = 6,
+}
+
+// Critical (4) is reserved for the memory manager
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "RawLevel")]
+pub enum IoPriority {
+    VeryLow = 0,
+    Low = 1,
+    Normal = 2,
+    High = 3,
+}
+
+// The MEMORY_PRIORITY_* levels of SetProcessInformation
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "RawLevel")]
+pub enum PagePriority {
+    Lowest = 0,
+    VeryLow = 1,
+    Low = 2,
+    Medium = 3,
+    BelowNormal = 4,
+    Normal = 5,
+}
+
+// Profiles may give the number from the registry or the name, e.g. "below normal"
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawLevel {
+    Number(u32),
+    Name(String),
+}
+
+macro_rules! priority_level {
+    ($level:ident, $what:expr, [$($variant:ident => $name:expr),*], [$($rejected:expr => $reason:expr),*]) => {
+        impl $level {
+            pub const ALL: &'static [$level] = &[$($level::$variant),*];
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $($level::$variant => $name),*
+                }
+            }
+        }
+
+        impl TryFrom<u32> for $level {
+            type Error = String;
+
+            fn try_from(value: u32) -> Result<$level, String> {
+                if let Some(level) = $level::ALL.iter().find(|level| **level as u32 == value) {
+                    return Ok(*level);
+                }
+                Err(match value {
+                    $($rejected => format!("{} {} is not supported, {}", $what, value, $reason),)*
+                    _ => format!("{} {} is out of range, expected {}", $what, value, $level::expected()),
+                })
+            }
+        }
+
+        impl TryFrom<RawLevel> for $level {
+            type Error = String;
+
+            fn try_from(raw: RawLevel) -> Result<$level, String> {
+                match raw {
+                    RawLevel::Number(value) => $level::try_from(value),
+                    RawLevel::Name(name) => $level::from_str(&name),
+                }
+            }
+        }
+
+        impl FromStr for $level {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<$level, String> {
+                let wanted = s.replace(|c: char| c == ' ' || c == '-' || c == '_', "");
+                $level::ALL
+                    .iter()
+                    .copied()
+                    .find(|level| level.name().replace(' ', "").eq_ignore_ascii_case(&wanted))
+                    .ok_or_else(|| format!("unknown {} `{}`, expected {}", $what, s, $level::expected()))
+            }
+        }
+
+        impl $level {
+            fn expected() -> String {
+                let names: Vec<String> = $level::ALL
+                    .iter()
+                    .map(|level| format!("{} ({})", *level as u32, level.name()))
+                    .collect();
+                names.join(", ")
+            }
+        }
+
+        impl fmt::Display for $level {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(self.name())
+            }
+        }
+    };
+}
+
+priority_level!(
+    CpuPriorityClass,
+    "cpu priority class",
+    [
+        Idle => "Idle",
+        Normal => "Normal",
+        High => "High",
+        BelowNormal => "Below Normal",
+        AboveNormal => "Above Normal"
+    ],
+    [4 => "RealTime is ignored by Windows and would starve the system"]
+);
+
+priority_level!(
+    IoPriority,
+    "io priority",
+    [
+        VeryLow => "Very Low",
+        Low => "Low",
+        Normal => "Normal",
+        High => "High"
+    ],
+    [4 => "Critical is reserved for the memory manager"]
+);
+
+priority_level!(
+    PagePriority,
+    "page priority",
+    [
+        Lowest => "Lowest",
+        VeryLow => "Very Low",
+        Low => "Low",
+        Medium => "Medium",
+        BelowNormal => "Below Normal",
+        Normal => "Normal"
+    ],
+    []
+);
+
+pub struct CpuPriority {
+    pub process: String,
+    pub cpu_priority_class: Option<CpuPriorityClass>,
+    pub io_priority: Option<IoPriority>,
+    pub page_priority: Option<PagePriority>,
+    pub working_set_limit_in_kb: Option<u32>,
+}
+
+impl CpuPriority {
+    // The values this entry sets, in registry order
+    pub fn values(&self) -> Vec<(&'static str, u32)> {
+        [
+            (CPU_PRIORITY_CLASS, self.cpu_priority_class.map(|c| c as u32)),
+            (IO_PRIORITY, self.io_priority.map(|p| p as u32)),
+            (PAGE_PRIORITY, self.page_priority.map(|p| p as u32)),
+            (WORKING_SET_LIMIT, self.working_set_limit_in_kb),
+        ]
+        .iter()
+        .filter_map(|(name, value)| value.map(|value| (*name, value)))
+        .collect()
+    }
+}
+
+// What a value on the machine means, also for levels a profile may not use
+pub fn label(name: &str, value: u32) -> Option<String> {
+    match name {
+        CPU_PRIORITY_CLASS if value == 4 => Some(String::from("RealTime")),
+        CPU_PRIORITY_CLASS => CpuPriorityClass::try_from(value).ok().map(|c| c.to_string()),
+        IO_PRIORITY if value == 4 => Some(String::from("Critical")),
+        IO_PRIORITY => IoPriority::try_from(value).ok().map(|p| p.to_string()),
+        PAGE_PRIORITY => PagePriority::try_from(value).ok().map(|p| p.to_string()),
+        _ => None,
+    }
+}
+
+// "dword:3 (High)", other values as usual
+pub fn describe(name: &str, value: &RegValue) -> String {
+    match value.as_u32().ok().and_then(|v| label(name, v)) {
+        Some(label) => format!("{} ({})", value, label),
+        None => value.to_string(),
+    }
+}
+
+pub fn is_perf_options(path: &str) -> bool {
+    let (start, end) = (IFEO.len(), path.len().saturating_sub("\\PerfOptions".len()));
+    start < end
+        && path.get(..start).is_some_and(|s| s.eq_ignore_ascii_case(IFEO))
+        && path.get(end..).is_some_and(|s| s.eq_ignore_ascii_case("\\PerfOptions"))
+}
+
+pub fn perf_options_path(process: &str) -> String {
+    format!("{}\\{}\\PerfOptions", IFEO, process)
+}
+
+// Compares every value of the entry without creating the key
+pub fn check_cpu_priority<B: RegistryBackend>(backend: &B, data: CpuPriority) -> Report {
+    let mut report = Report::default();
+    let hklm = backend.predef(Hive::LocalMachine);
+    let path = perf_options_path(&data.process);
+    let location = RegPath::new(Hive::LocalMachine, &path);
+    let key = backend.open_subkey(&hklm, &path);
+    for (name, value) in data.values() {
+        let result = match &key {
+            Ok(key) => set_u32_reg(backend, key, &location, name, &value, false),
+            Err(e) => CheckResult {
+                hive: Hive::LocalMachine,
+                path: path.clone(),
+                name: name.to_string(),
+                value: Some(RegValue::dword(value)),
+                outcome: match e.kind() {
+                    io::ErrorKind::NotFound => CheckOutcome::Missing,
+                    kind => CheckOutcome::Failed(io::Error::new(kind, e.to_string()).into()),
+                },
+            },
+        };
+        report.push(result);
+    }
+    report
+}
+
+// Deletes the values the entry sets, then the PerfOptions and executable keys
+// once nothing of another tool is left in them
+pub fn remove_cpu_priority<B: RegistryBackend>(backend: &B, data: &CpuPriority) -> Report {
+    let mut report = Report::default();
+    let hklm = backend.predef(Hive::LocalMachine);
+    let path = perf_options_path(&data.process);
+    let location = RegPath::new(Hive::LocalMachine, &path);
+    let failed_values = |e: &io::Error| {
+        let mut report = Report::default();
+        for (name, _) in data.values() {
+            report.push(failed(&location, name, e));
+        }
+        report
+    };
+    let transaction = match backend.transaction() {
+        Ok(transaction) => transaction,
+        Err(e) => return failed_values(&e),
+    };
+    let key = match backend.open_subkey_transacted(&hklm, &path, &transaction) {
+        Ok(key) => key,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            report.push(remove_key_reg(backend, &hklm, &location, false));
+            return report;
+        }
+        Err(e) => return failed_values(&e),
+    };
+    for (name, _) in data.values() {
+        report.push(remove_value_reg(backend, &key, &location, name, true));
+    }
+    if let Err(e) = backend.commit(&transaction) {
+        return failed_values(&e);
+    }
+
+    match backend.open_subkey(&hklm, &path).and_then(|key| is_empty(backend, &key)) {
+        Ok(true) => report.push(remove_key_reg(backend, &hklm, &location, true)),
+        Ok(false) => return report,
+        Err(e) => {
+            report.push(failed(&location, "", &e));
+            return report;
+        }
+    }
+    let exe = RegPath::new(Hive::LocalMachine, &format!("{}\\{}", IFEO, data.process));
+    match backend.open_subkey(&hklm, &exe.subkey).and_then(|key| is_empty(backend, &key)) {
+        Ok(true) => report.push(remove_key_reg(backend, &hklm, &exe, true)),
+        Ok(false) => {}
+        Err(e) => report.push(failed(&exe, "", &e)),
+    }
+    report
+}
+
+// A value, or the key itself when `name` is empty, that could not be read
+fn failed(path: &RegPath, name: &str, e: &io::Error) -> CheckResult {
+    CheckResult {
+        hive: path.hive,
+        path: path.subkey.clone(),
+        name: name.to_string(),
+        value: None,
+        outcome: CheckOutcome::Failed(io::Error::new(e.kind(), e.to_string()).into()),
+    }
+}
+
+fn is_empty<B: RegistryBackend>(backend: &B, key: &B::Key) -> io::Result<bool> {
+    Ok(backend.enum_keys(key)?.is_empty() && backend.enum_values(key)?.is_empty())
+}
+
+// A PerfOptions key found on the machine
+pub struct PerfOptionsEntry {
+    pub process: String,
+    pub values: Vec<(String, RegValue)>,
+}
+
+// Every executable with PerfOptions, whoever wrote them
+pub fn scan<B: RegistryBackend>(backend: &B) -> error::Result<Vec<PerfOptionsEntry>> {
+    let ifeo = backend.open_subkey(&backend.predef(Hive::LocalMachine), IFEO)?;
+    let mut entries = Vec::new();
+    for process in backend.enum_keys(&ifeo)? {
+        let key = match backend.open_subkey(&ifeo, &format!("{}\\PerfOptions", process)) {
+            Ok(key) => key,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        entries.push(PerfOptionsEntry {
+            process,
+            values: backend.enum_values(&key)?,
+        });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::backend::MemoryBackend;
+
+    fn write(backend: &MemoryBackend, process: &str, values: &[(&str, u32)]) {
+        let transaction = backend.transaction().unwrap();
+        let key = backend
+            .create_subkey_transacted(&backend.predef(Hive::LocalMachine), &perf_options_path(process), &transaction)
+            .unwrap();
+        for (name, value) in values {
+            backend.set_raw_value(&key, name, &RegValue::dword(*value)).unwrap();
+        }
+        backend.commit(&transaction).unwrap();
+    }
+
+    fn high(process: &str) -> CpuPriority {
+        CpuPriority {
+            process: process.to_string(),
+            cpu_priority_class: Some(CpuPriorityClass::High),
+            io_priority: Some(IoPriority::High),
+            page_priority: None,
+            working_set_limit_in_kb: None,
+        }
+    }
+
+    fn exists(backend: &MemoryBackend, path: &str) -> bool {
+        backend.open_subkey(&backend.predef(Hive::LocalMachine), path).is_ok()
+    }
+
+    #[test]
+    fn numbers_from_the_registry() {
+        assert_eq!(CpuPriorityClass::try_from(3), Ok(CpuPriorityClass::High));
+        assert_eq!(CpuPriorityClass::try_from(5), Ok(CpuPriorityClass::BelowNormal));
+        assert_eq!(
+            CpuPriorityClass::try_from(4),
+            Err(String::from(
+                "cpu priority class 4 is not supported, RealTime is ignored by Windows and would starve the system"
+            ))
+        );
+        assert_eq!(
+            IoPriority::try_from(4),
+            Err(String::from("io priority 4 is not supported, Critical is reserved for the memory manager"))
+        );
+        assert_eq!(
+            CpuPriorityClass::try_from(0),
+            Err(String::from(
+                "cpu priority class 0 is out of range, expected 1 (Idle), 2 (Normal), 3 (High), 5 (Below Normal), 6 (Above Normal)"
+            ))
+        );
+        assert_eq!(PagePriority::try_from(4), Ok(PagePriority::BelowNormal));
+        assert_eq!(
+            PagePriority::try_from(6),
+            Err(String::from(
+                "page priority 6 is out of range, expected 0 (Lowest), 1 (Very Low), 2 (Low), 3 (Medium), 4 (Below Normal), 5 (Normal)"
+            ))
+        );
+    }
+
+    #[test]
+    fn names_with_spaces_dashes_or_neither() {
+        for name in ["below normal", "Below-Normal", "BELOW_NORMAL", "belownormal"] {
+            assert_eq!(name.parse::<CpuPriorityClass>(), Ok(CpuPriorityClass::BelowNormal), "{}", name);
+        }
+        assert_eq!("very low".parse::<IoPriority>(), Ok(IoPriority::VeryLow));
+        assert_eq!("Medium".parse::<PagePriority>(), Ok(PagePriority::Medium));
+        assert_eq!(
+            "realtime".parse::<CpuPriorityClass>(),
+            Err(String::from(
+                "unknown cpu priority class `realtime`, expected 1 (Idle), 2 (Normal), 3 (High), 5 (Below Normal), 6 (Above Normal)"
+            ))
+        );
+        for level in CpuPriorityClass::ALL.iter() {
+            assert_eq!(level.to_string().parse::<CpuPriorityClass>().as_ref(), Ok(level));
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct Levels {
+        cpu: CpuPriorityClass,
+        io: Option<IoPriority>,
+    }
+
+    #[test]
+    fn a_profile_gives_the_number_or_the_name() {
+        let levels: Levels = toml::from_str("cpu = 6\nio = \"very-low\"").unwrap();
+        assert_eq!((levels.cpu, levels.io), (CpuPriorityClass::AboveNormal, Some(IoPriority::VeryLow)));
+        let levels: Levels = toml::from_str("cpu = \"Above Normal\"").unwrap();
+        assert_eq!((levels.cpu, levels.io), (CpuPriorityClass::AboveNormal, None));
+        let error = toml::from_str::<Levels>("cpu = 4").err().unwrap().to_string();
+        assert!(error.contains("cpu priority class 4 is not supported"), "{}", error);
+    }
+
+    #[test]
+    fn perf_options_paths_in_any_case() {
+        assert!(is_perf_options(&perf_options_path("game.exe")));
+        assert!(is_perf_options(&perf_options_path("game.exe").to_uppercase()));
+        assert!(is_perf_options(&format!("{}\\game.exe\\perfoptions", IFEO.to_lowercase())));
+        assert!(!is_perf_options(&format!("{}\\PerfOptions", IFEO)));
+        assert!(!is_perf_options(&format!("{}\\game.exe", IFEO)));
+        assert!(!is_perf_options("SOFTWARE\\Vendor\\game.exe\\PerfOptions"));
+    }
+
+    #[test]
+    fn a_check_compares_without_creating_the_key() {
+        let backend = MemoryBackend::new();
+        let outcomes = |report: Report| -> Vec<(String, &str)> {
+            report
+                .results
+                .into_iter()
+                .map(|r| {
+                    let outcome = match r.outcome {
+                        CheckOutcome::Correct => "correct",
+                        CheckOutcome::Mismatch { .. } => "mismatch",
+                        CheckOutcome::Missing => "missing",
+                        _ => "other",
+                    };
+                    (r.name, outcome)
+                })
+                .collect()
+        };
+        assert_eq!(
+            outcomes(check_cpu_priority(&backend, high("game.exe"))),
+            vec![(String::from(CPU_PRIORITY_CLASS), "missing"), (String::from(IO_PRIORITY), "missing")]
+        );
+        assert!(!exists(&backend, &perf_options_path("game.exe")));
+
+        write(&backend, "game.exe", &[(CPU_PRIORITY_CLASS, 3), (IO_PRIORITY, 2)]);
+        assert_eq!(
+            outcomes(check_cpu_priority(&backend, high("game.exe"))),
+            vec![(String::from(CPU_PRIORITY_CLASS), "correct"), (String::from(IO_PRIORITY), "mismatch")]
+        );
+    }
+
+    #[test]
+    fn another_tools_entry_is_listed_and_left_alone() {
+        let backend = MemoryBackend::new();
+        write(&backend, "game.exe", &[(CPU_PRIORITY_CLASS, 3), (IO_PRIORITY, 3), (PAGE_PRIORITY, 5)]);
+        write(&backend, "other.exe", &[(CPU_PRIORITY_CLASS, 1)]);
+
+        let scanned: Vec<(String, usize)> = scan(&backend)
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.process, entry.values.len()))
+            .collect();
+        assert_eq!(scanned, vec![(String::from("game.exe"), 3), (String::from("other.exe"), 1)]);
+
+        let report = remove_cpu_priority(&backend, &high("game.exe"));
+        let removed: Vec<&str> = report.results.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(removed, vec![CPU_PRIORITY_CLASS, IO_PRIORITY]);
+        assert!(report.results.iter().all(|r| matches!(r.outcome, CheckOutcome::Deleted)));
+        // The page priority someone else set keeps the key
+        let entries = scan(&backend).unwrap();
+        assert_eq!(entries[0].values, vec![(String::from(PAGE_PRIORITY), RegValue::dword(5))]);
+        assert_eq!(entries[1].process, "other.exe");
+        assert_eq!(entries[1].values, vec![(String::from(CPU_PRIORITY_CLASS), RegValue::dword(1))]);
+    }
+
+    #[test]
+    fn an_emptied_entry_is_deleted_up_to_the_executable() {
+        let backend = MemoryBackend::new();
+        write(&backend, "game.exe", &[(CPU_PRIORITY_CLASS, 3), (IO_PRIORITY, 3)]);
+        write(&backend, "other.exe", &[(CPU_PRIORITY_CLASS, 1)]);
+
+        let report = remove_cpu_priority(&backend, &high("game.exe"));
+        let paths: Vec<(&str, &str)> = report
+            .results
+            .iter()
+            .filter(|r| matches!(r.outcome, CheckOutcome::DeletedKey))
+            .map(|r| (r.path.as_str(), r.name.as_str()))
+            .collect();
+        let exe = format!("{}\\game.exe", IFEO);
+        assert_eq!(paths, vec![(perf_options_path("game.exe").as_str(), ""), (exe.as_str(), "")]);
+        assert!(!exists(&backend, &exe));
+        assert!(exists(&backend, &perf_options_path("other.exe")));
+
+        // Nothing left to remove the second time
+        let again = remove_cpu_priority(&backend, &high("game.exe"));
+        assert!(again.results.iter().all(|r| matches!(r.outcome, CheckOutcome::Correct)));
+    }
+}
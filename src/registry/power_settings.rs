@@ -0,0 +1,387 @@
+// The power settings the tool knows by their alias (PROCTHROTTLEMIN, CPMINCORES,
+// ...) with their range and whether Windows hides them. The gaming scheme in
+// catalog/power.toml refers to them by alias, its indices are checked against
+// the range when the file is read.
+
+use super::catalog::{self, CatalogError};
+use super::powerplan::is_guid;
+use super::{PowerPlan, SettingGuid, SubGuid};
+
+use serde::Deserialize;
+use std::convert::TryFrom;
+
+const BUILTIN: &str = include_str!("../../catalog/power.toml");
+const VERSION: u32 = 1;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Subgroup {
+    pub alias: String,
+    pub guid: String,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SettingRange {
+    Range { min: u32, max: u32, units: String },
+    // The names of the indices 0, 1, ...
+    Values(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "RawSetting")]
+pub struct PowerSettingInfo {
+    pub alias: String,
+    // The alias of the subgroup
+    pub subgroup: String,
+    pub guid: String,
+    pub name: String,
+    pub range: SettingRange,
+    // Attributes = 1, the power options of the control panel do not show it
+    pub hidden: bool,
+    // Only has an effect on CPUs with more than one efficiency class
+    pub hybrid: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSetting {
+    alias: String,
+    subgroup: String,
+    guid: String,
+    name: String,
+    min: Option<u32>,
+    max: Option<u32>,
+    units: Option<String>,
+    values: Option<Vec<String>>,
+    #[serde(default)]
+    hidden: bool,
+    #[serde(default)]
+    hybrid: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawIndex {
+    setting: String,
+    ac: u32,
+    dc: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawPlan {
+    base: String,
+    #[serde(default)]
+    index: Vec<RawIndex>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawPowerCatalog {
+    version: u32,
+    #[serde(default)]
+    subgroup: Vec<Subgroup>,
+    #[serde(default)]
+    setting: Vec<PowerSettingInfo>,
+    plan: RawPlan,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "RawPowerCatalog")]
+pub struct PowerCatalog {
+    pub subgroups: Vec<Subgroup>,
+    pub settings: Vec<PowerSettingInfo>,
+    // The plan with the aliases resolved to GUIDs
+    base: String,
+    indices: Vec<(usize, u32, u32)>,
+}
+
+impl TryFrom<RawSetting> for PowerSettingInfo {
+    type Error = String;
+
+    fn try_from(raw: RawSetting) -> Result<PowerSettingInfo, String> {
+        if !is_guid(&raw.guid) {
+            return Err(format!("setting `{}`: `{}` is not a GUID", raw.alias, raw.guid));
+        }
+        let range = match (raw.min, raw.max, raw.units, raw.values) {
+            (Some(min), Some(max), units, None) if min <= max => SettingRange::Range {
+                min,
+                max,
+                units: units.unwrap_or_default(),
+            },
+            (Some(min), Some(max), _, None) => {
+                return Err(format!("setting `{}`: min {} is above max {}", raw.alias, min, max));
+            }
+            (None, None, None, Some(values)) if !values.is_empty() => SettingRange::Values(values),
+            _ => {
+                return Err(format!(
+                    "setting `{}`: needs either min and max or a list of values",
+                    raw.alias
+                ));
+            }
+        };
+        Ok(PowerSettingInfo {
+            alias: raw.alias,
+            subgroup: raw.subgroup,
+            guid: raw.guid.to_lowercase(),
+            name: raw.name,
+            range,
+            hidden: raw.hidden,
+            hybrid: raw.hybrid,
+        })
+    }
+}
+
+impl TryFrom<RawPowerCatalog> for PowerCatalog {
+    type Error = String;
+
+    fn try_from(raw: RawPowerCatalog) -> Result<PowerCatalog, String> {
+        if raw.version != VERSION {
+            return Err(format!(
+                "unsupported power settings version {}, expected {}",
+                raw.version, VERSION
+            ));
+        }
+        for (i, subgroup) in raw.subgroup.iter().enumerate() {
+            if !is_guid(&subgroup.guid) {
+                return Err(format!("subgroup `{}`: `{}` is not a GUID", subgroup.alias, subgroup.guid));
+            }
+            if raw.subgroup[..i].iter().any(|s| s.alias.eq_ignore_ascii_case(&subgroup.alias)) {
+                return Err(format!("duplicate subgroup `{}`", subgroup.alias));
+            }
+        }
+        for (i, setting) in raw.setting.iter().enumerate() {
+            if raw.setting[..i].iter().any(|s| s.alias.eq_ignore_ascii_case(&setting.alias)) {
+                return Err(format!("duplicate setting `{}`", setting.alias));
+            }
+            if !raw.subgroup.iter().any(|s| s.alias.eq_ignore_ascii_case(&setting.subgroup)) {
+                return Err(format!(
+                    "setting `{}`: unknown subgroup `{}`",
+                    setting.alias, setting.subgroup
+                ));
+            }
+        }
+
+        if !is_guid(&raw.plan.base) {
+            return Err(format!("plan: base `{}` is not a GUID", raw.plan.base));
+        }
+        let mut indices = Vec::new();
+        for index in raw.plan.index.iter() {
+            let position = raw
+                .setting
+                .iter()
+                .position(|s| s.alias.eq_ignore_ascii_case(&index.setting))
+                .ok_or_else(|| format!("plan: unknown setting `{}`", index.setting))?;
+            if indices.iter().any(|(p, _, _)| *p == position) {
+                return Err(format!("plan: setting `{}` is listed twice", index.setting));
+            }
+            let setting = &raw.setting[position];
+            setting.check("ac", index.ac).map_err(|e| format!("plan: {}", e))?;
+            setting.check("dc", index.dc).map_err(|e| format!("plan: {}", e))?;
+            indices.push((position, index.ac, index.dc));
+        }
+
+        Ok(PowerCatalog {
+            subgroups: raw.subgroup,
+            settings: raw.setting,
+            base: raw.plan.base.to_lowercase(),
+            indices,
+        })
+    }
+}
+
+impl PowerSettingInfo {
+    // `what` names the index in the message, ac or dc
+    pub fn check(&self, what: &str, value: u32) -> Result<(), String> {
+        match &self.range {
+            SettingRange::Range { min, max, units } if value < *min || value > *max => Err(format!(
+                "{} {} of `{}` is outside {}..{}{}",
+                what,
+                value,
+                self.alias,
+                min,
+                max,
+                match units.is_empty() {
+                    true => String::new(),
+                    false => format!(" {}", units),
+                }
+            )),
+            SettingRange::Values(values) if value as usize >= values.len() => Err(format!(
+                "{} {} of `{}` is not one of 0..{} ({})",
+                what,
+                value,
+                self.alias,
+                values.len() - 1,
+                values.join(", ")
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl PowerCatalog {
+    pub fn builtin() -> PowerCatalog {
+        PowerCatalog::from_toml("catalog/power.toml", BUILTIN).expect("embedded power settings are invalid")
+    }
+
+    pub fn from_toml(file: &str, text: &str) -> Result<PowerCatalog, CatalogError> {
+        catalog::from_toml::<PowerCatalog, PowerSettingInfo>(file, text, "setting")
+    }
+
+    pub fn subgroup(&self, alias: &str) -> Option<&Subgroup> {
+        self.subgroups.iter().find(|s| s.alias.eq_ignore_ascii_case(alias))
+    }
+
+    // The settings of the gaming scheme with their AC and DC index in the order
+    // of the file. The settings of efficiency class 1 are left out unless `hybrid`.
+    fn indices(&self, hybrid: bool) -> impl Iterator<Item = (&Subgroup, &PowerSettingInfo, u32, u32)> {
+        self.indices.iter().filter_map(move |&(position, ac, dc)| {
+            let setting = &self.settings[position];
+            if setting.hybrid && !hybrid {
+                return None;
+            }
+            // Checked when the file was read
+            Some((self.subgroup(&setting.subgroup)?, setting, ac, dc))
+        })
+    }
+
+    // The gaming scheme, its settings grouped by subgroup
+    pub fn plan(&self, hybrid: bool) -> PowerPlan {
+        let mut data: Vec<SubGuid> = Vec::new();
+        for (subgroup, setting, ac, dc) in self.indices(hybrid) {
+            let subgroup = subgroup.guid.to_lowercase();
+            let guid = SettingGuid {
+                path: setting.guid.clone(),
+                alias: setting.alias.clone(),
+                hidden: setting.hidden,
+                ac,
+                dc,
+            };
+            match data.iter_mut().find(|s| s.path == subgroup) {
+                Some(sub) => sub.data.push(guid),
+                None => data.push(SubGuid {
+                    path: subgroup,
+                    data: vec![guid],
+                }),
+            }
+        }
+        PowerPlan {
+            base: self.base.clone(),
+            data,
+        }
+    }
+
+    // Shown by `list` under the power tweak, with the names powercfg shows
+    pub fn describe_plan(&self, hybrid: bool) -> Vec<String> {
+        self.indices(hybrid)
+            .map(|(subgroup, setting, ac, dc)| {
+                format!("{}: {} ({}) AC {}, DC {}", subgroup.name, setting.name, setting.alias, ac, dc)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::{Change, ChangePlan};
+    use crate::registry::backend::{Hive, MemoryBackend, RegValue, RegistryBackend};
+    use crate::registry::{check_power_attributes, POWER_SETTINGS};
+
+    const SUB_PROCESSOR: &str = "54533251-82be-4824-96c1-47b60b740d00";
+    const PROCTHROTTLEMIN: &str = "893dee8e-2bef-41e0-89c6-b55d0929964c";
+
+    // One hidden setting with a range, `index` is its line in the plan
+    fn catalog(index: &str) -> Result<PowerCatalog, CatalogError> {
+        let text = format!(
+            r#"
+version = 1
+
+[[subgroup]]
+alias = "SUB_PROCESSOR"
+guid = "{}"
+name = "Processor power management"
+
+[[setting]]
+alias = "PROCTHROTTLEMIN"
+subgroup = "SUB_PROCESSOR"
+guid = "{}"
+name = "Minimum processor state"
+min = 0
+max = 100
+units = "%"
+hidden = true
+
+[plan]
+base = "8c5e7fda-e8bf-4a96-9a85-a6e23a8c635c"
+
+[[plan.index]]
+setting = "PROCTHROTTLEMIN"
+{}
+"#,
+            SUB_PROCESSOR, PROCTHROTTLEMIN, index
+        );
+        PowerCatalog::from_toml("power.toml", &text)
+    }
+
+    #[test]
+    fn the_builtin_settings_parse() {
+        let builtin = PowerCatalog::builtin();
+        assert_eq!(builtin.plan(false).base(), "8c5e7fda-e8bf-4a96-9a85-a6e23a8c635c");
+        let described = builtin.describe_plan(false);
+        assert_eq!(
+            described[0],
+            "Processor power management: Processor performance boost mode (PERFBOOSTMODE) AC 0, DC 0"
+        );
+        // The E-core settings come on top
+        assert!(builtin.describe_plan(true).len() > described.len());
+    }
+
+    #[test]
+    fn an_index_outside_the_range_is_refused() {
+        assert!(catalog("ac = 100\ndc = 0").is_ok());
+        let error = catalog("ac = 101\ndc = 0").unwrap_err();
+        assert_eq!(error.message, "plan: ac 101 of `PROCTHROTTLEMIN` is outside 0..100 %");
+    }
+
+    #[test]
+    fn a_hidden_setting_is_planned_to_be_shown() {
+        let powerplan = catalog("ac = 5\ndc = 5").unwrap().plan(false);
+        let backend = MemoryBackend::new();
+        let transaction = backend.transaction().unwrap();
+        let path = format!("{}\\{}\\{}", POWER_SETTINGS, SUB_PROCESSOR, PROCTHROTTLEMIN);
+        let key = backend
+            .create_subkey_transacted(&backend.predef(Hive::LocalMachine), &path, &transaction)
+            .unwrap();
+        backend.set_raw_value(&key, "Attributes", &RegValue::dword(3)).unwrap();
+        backend.commit(&transaction).unwrap();
+
+        let mut plan = ChangePlan::new();
+        plan.add_powerplan(&powerplan, &check_power_attributes(&backend, &powerplan));
+        match &plan.steps[..] {
+            [step] => match &step.change {
+                Change::ShowPowerSetting {
+                    subgroup,
+                    setting,
+                    current,
+                } => {
+                    assert_eq!((subgroup.as_str(), setting.as_str(), *current), (SUB_PROCESSOR, PROCTHROTTLEMIN, 3));
+                }
+                _ => panic!("expected the setting to be shown"),
+            },
+            _ => panic!("expected one step"),
+        }
+
+        // Nothing to do once it is shown
+        let transaction = backend.transaction().unwrap();
+        let key = backend
+            .open_subkey_transacted(&backend.predef(Hive::LocalMachine), &path, &transaction)
+            .unwrap();
+        backend.set_raw_value(&key, "Attributes", &RegValue::dword(2)).unwrap();
+        backend.commit(&transaction).unwrap();
+        let mut plan = ChangePlan::new();
+        plan.add_powerplan(&powerplan, &check_power_attributes(&backend, &powerplan));
+        assert!(plan.is_empty());
+    }
+}
@@ -1,69 +1,504 @@
+use serde::{Deserialize, Serialize};
+use std::io;
 use std::process::Command;
 
-// https://github.com/nsmryan/luster/blob/master/src/main.rs
-// fn find_guid(search_string: &str, query: &str, skip: usize) -> String {
-//     let mut guid = None;
-//     for line in search_string.lines() {
-//         if line.contains(query) {
-//             guid = line.trim().split(" ").skip(skip).next();
-//             break;
-//         }
-//     }
-//     let guid = guid.expect("Could not find GUID");
-
-//     return guid.to_string();
-// }
-
-// pub fn check() {
-//     // powercfg /SETACVALUEINDEX fb5220ff-7e1a-47aa-9a42-50ffbf45c673 7516b95f-f776-4464-8c53-06167f40cc99 3c0bc021-c8a8-4e07-a973-6b14cbcb2b7e 600
-//     let power_query_output = Command::new("powercfg")
-//         .args(&["/q"])
-//         .output()
-//         .expect("failed to run powercfg query");
-
-//     // println!("power_query_output: {}", &power_query_output.stdout);
-//     let power_query =
-//         std::str::from_utf8(&power_query_output.stdout).expect("Invalid output from powercfg");
-
-//     let scheme_guid = find_guid(power_query, "Power Scheme GUID", 3);
-//     // let subgroup_guid = find_guid(power_query, "(Display)", 2);
-//     // let setting_guid = find_guid(power_query, "(Display brightness)", 3);
-//     println!("scheme_guid: {}", scheme_guid);
-//     println!("power_query: {}", power_query);
-//     // Command::new("powercfg")
-//     //     .args(&[
-//     //         "-SetDcValueIndex",
-//     //         &scheme_guid,
-//     //         &subgroup_guid,
-//     //         &setting_guid,
-//     //         &value,
-//     //     ])
-//     //     .output()
-//     //     .expect("Failure setting AC brigtness value");
-// }
-
-pub fn set(
-    scheme_guid: &String,
-    sub_guid_path: &String,
-    setting_guid_path: &String,
-    setting_guid_data: &u32,
-) {
-    Command::new("powercfg")
-        .args(&[
-            "-SetAcValueIndex",
-            &scheme_guid,
-            &sub_guid_path,
-            &setting_guid_path,
-            &setting_guid_data.to_string(),
-        ])
-        .output()
-        .expect("Failure setting Powerplan Setting");
-}
-
-#[allow(dead_code)]
-pub fn default() {
-    Command::new("powercfg")
-        .args(&[ "-restoredefaultschemes" ])
-        .output()
-        .expect("Failure reset Powerplan Setting");
+// The scheme the tool writes to, a copy of a built-in one. The built-in schemes
+// stay as Windows ships them, so a restore only has to switch back and delete it.
+pub const GAMING_SCHEME: &str = "5d2a9c8e-7b31-4f06-a4e5-3c9b1f0d6e72";
+pub const GAMING_SCHEME_NAME: &str = "Gaming Optimizer";
+// Active on a fresh install, the fallback when the scheme active before is not known
+pub const BALANCED: &str = "381b4222-f694-41f0-9685-ff5bb260df2e";
+
+// Every power setting has an index for running on AC power and one for battery
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerSource {
+    // Plans saved before DC indices were written only have AC steps
+    #[default]
+    Ac,
+    Dc,
+}
+
+impl PowerSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            PowerSource::Ac => "AC",
+            PowerSource::Dc => "DC",
+        }
+    }
+
+    // The value a scheme stores the index in
+    pub fn value_name(self) -> &'static str {
+        match self {
+            PowerSource::Ac => "ACSettingIndex",
+            PowerSource::Dc => "DCSettingIndex",
+        }
+    }
+}
+
+
+// The bit of the Attributes value that hides a setting in the control panel
+pub const ATTRIB_HIDE: u32 = 1;
+
+// Shows or hides the setting in the power options of the control panel, for every scheme
+pub fn set_hidden(sub_guid: &str, setting_guid: &str, hidden: bool) -> io::Result<()> {
+    let flag = if hidden { "+ATTRIB_HIDE" } else { "-ATTRIB_HIDE" };
+    powercfg(&["-attributes", sub_guid, setting_guid, flag])
+}
+
+// What powercfg knows about the power schemes, a trait so the checker runs without Windows
+pub trait PowerConfig {
+    // powercfg /list
+    fn list(&self) -> io::Result<Vec<SchemeEntry>>;
+    // powercfg /qh <scheme>, every subgroup and setting including the hidden ones
+    fn query(&self, scheme: &str) -> io::Result<PowerScheme>;
+    // powercfg /qh <scheme> <subgroup> <setting>, None when the setting does not exist
+    fn query_setting(&self, scheme: &str, subgroup: &str, setting: &str) -> io::Result<Option<PowerSetting>>;
+    // A copy of `base` with all of its indices under the GUID `scheme`
+    fn duplicate(&self, base: &str, scheme: &str, name: &str) -> io::Result<()>;
+    fn set(&self, scheme: &str, subgroup: &str, setting: &str, source: PowerSource, value: u32) -> io::Result<()>;
+    fn activate(&self, scheme: &str) -> io::Result<()>;
+    // Windows refuses to delete the active scheme
+    fn delete(&self, scheme: &str) -> io::Result<()>;
+}
+
+pub struct Powercfg;
+
+impl PowerConfig for Powercfg {
+    fn list(&self) -> io::Result<Vec<SchemeEntry>> {
+        Ok(parse_list(&powercfg_output(&["/list"])?))
+    }
+
+    fn query(&self, scheme: &str) -> io::Result<PowerScheme> {
+        parse_query(&powercfg_output(&["/qh", scheme])?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn query_setting(&self, scheme: &str, subgroup: &str, setting: &str) -> io::Result<Option<PowerSetting>> {
+        let text = powercfg_output(&["/qh", scheme, subgroup, setting])?;
+        let mut scheme = parse_query(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(scheme
+            .subgroups
+            .pop()
+            .and_then(|mut subgroup| subgroup.settings.pop()))
+    }
+
+    fn duplicate(&self, base: &str, scheme: &str, name: &str) -> io::Result<()> {
+        powercfg(&["-DuplicateScheme", base, scheme])?;
+        powercfg(&["-ChangeName", scheme, name])
+    }
+
+    fn set(&self, scheme: &str, subgroup: &str, setting: &str, source: PowerSource, value: u32) -> io::Result<()> {
+        let option = match source {
+            PowerSource::Ac => "-SetAcValueIndex",
+            PowerSource::Dc => "-SetDcValueIndex",
+        };
+        powercfg(&[option, scheme, subgroup, setting, &value.to_string()])
+    }
+
+    fn activate(&self, scheme: &str) -> io::Result<()> {
+        powercfg(&["-SetActive", scheme])
+    }
+
+    fn delete(&self, scheme: &str) -> io::Result<()> {
+        powercfg(&["-Delete", scheme])
+    }
+}
+
+// One line of powercfg /list. The friendly names are localized and left out,
+// schemes and settings are known by GUID.
+#[derive(Clone, Debug)]
+pub struct SchemeEntry {
+    pub guid: String,
+    pub active: bool,
+}
+
+// The scheme powercfg was asked for
+#[derive(Clone, Debug)]
+pub struct PowerScheme {
+    pub subgroups: Vec<PowerSubgroup>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PowerSubgroup {
+    pub guid: String,
+    // SUB_PROCESSOR, SUB_DISK, ...
+    pub alias: Option<String>,
+    pub settings: Vec<PowerSetting>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PowerSetting {
+    pub guid: String,
+    // PROCTHROTTLEMIN, CPMINCORES, ...
+    pub alias: Option<String>,
+    pub values: PossibleValues,
+    // The indices of the scheme, a setting at its default has them as well
+    pub ac: Option<u32>,
+    pub dc: Option<u32>,
+}
+
+// What an index of a setting may be: one of a list or a number in a range
+#[derive(Clone, Debug, PartialEq)]
+pub enum PossibleValues {
+    Options(Vec<(u32, String)>),
+    Range {
+        min: u32,
+        max: u32,
+        increment: u32,
+        units: String,
+    },
+    Unknown,
+}
+
+impl PossibleValues {
+    // Unknown lets every index through, powercfg did not say
+    pub fn contains(&self, value: u32) -> bool {
+        match self {
+            PossibleValues::Options(options) => options.iter().any(|(index, _)| *index == value),
+            PossibleValues::Range { min, max, .. } => (*min..=*max).contains(&value),
+            PossibleValues::Unknown => true,
+        }
+    }
+}
+
+impl PowerScheme {
+    pub fn setting(&self, subgroup: &str, setting: &str) -> Option<&PowerSetting> {
+        self.subgroups
+            .iter()
+            .find(|s| s.guid.eq_ignore_ascii_case(subgroup))?
+            .settings
+            .iter()
+            .find(|s| s.guid.eq_ignore_ascii_case(setting))
+    }
+}
+
+impl PowerSetting {
+    pub fn index(&self, source: PowerSource) -> Option<u32> {
+        match source {
+            PowerSource::Ac => self.ac,
+            PowerSource::Dc => self.dc,
+        }
+    }
+}
+
+// Existing Power Schemes (* Active)
+// -----------------------------------
+// Power Scheme GUID: 381b4222-f694-41f0-9685-ff5bb260df2e  (Balanced) *
+pub fn parse_list(text: &str) -> Vec<SchemeEntry> {
+    text.lines()
+        .filter_map(|line| line.split_once(':'))
+        .filter_map(|(_, value)| guid_of(value.trim()))
+        .map(|(guid, active)| SchemeEntry { guid, active })
+        .collect()
+}
+
+// powercfg /query and /qh. The labels are translated with Windows, so a line is
+// known by its indentation and the shape of its value instead:
+//
+// Power Scheme GUID: 381b4222-f694-41f0-9685-ff5bb260df2e  (Balanced)
+//   GUID Alias: SCHEME_BALANCED
+//   Subgroup GUID: 54533251-82be-4824-96c1-47b60b740d00  (Processor power management)
+//     GUID Alias: SUB_PROCESSOR
+//     Power Setting GUID: 893dee8e-2bef-41e0-89c6-b55d0929964c  (Minimum processor state)
+//       GUID Alias: PROCTHROTTLEMIN
+//       Minimum Possible Setting: 0x00000000
+//       Maximum Possible Setting: 0x00000064
+//       Possible Settings increment: 0x00000001
+//       Possible Settings units: %
+//     Current AC Power Setting Index: 0x00000005
+//     Current DC Power Setting Index: 0x00000005
+//
+// A setting with a list of values has `Possible Setting Index: 000` and
+// `Possible Setting Friendly Name: ...` pairs instead of the range.
+pub fn parse_query(text: &str) -> Result<PowerScheme, String> {
+    let mut scheme: Option<PowerScheme> = None;
+    // The alias belongs to the GUID line right before it
+    let mut after_guid = false;
+    // A possible setting index waiting for its friendly name
+    let mut option: Option<u32> = None;
+    let mut range: Vec<u32> = Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let error = |message: &str| format!("line {}: {}", n + 1, message);
+        let indent = line.len() - line.trim_start().len();
+        let value = match line.split_once(':') {
+            Some((_, value)) => value.trim(),
+            None => continue,
+        };
+
+        if let Some((guid, _)) = guid_of(value) {
+            after_guid = true;
+            if indent == 0 {
+                if scheme.is_some() {
+                    return Err(error("a second power scheme"));
+                }
+                scheme = Some(PowerScheme { subgroups: Vec::new() });
+                continue;
+            }
+            let scheme = scheme.as_mut().ok_or_else(|| error("a GUID before the power scheme"))?;
+            if indent <= 2 {
+                scheme.subgroups.push(PowerSubgroup {
+                    guid,
+                    alias: None,
+                    settings: Vec::new(),
+                });
+                continue;
+            }
+            let subgroup = scheme
+                .subgroups
+                .last_mut()
+                .ok_or_else(|| error("a power setting outside of a subgroup"))?;
+            subgroup.settings.push(PowerSetting {
+                guid,
+                alias: None,
+                values: PossibleValues::Unknown,
+                ac: None,
+                dc: None,
+            });
+            option = None;
+            range.clear();
+            continue;
+        }
+
+        let scheme = match scheme.as_mut() {
+            Some(scheme) => scheme,
+            None => continue,
+        };
+        if after_guid && is_alias(value) {
+            after_guid = false;
+            let alias = Some(value.to_string());
+            match (indent, scheme.subgroups.last_mut()) {
+                // SCHEME_BALANCED and the other built-in schemes
+                (0..=2, _) => {}
+                (3..=4, Some(subgroup)) if subgroup.settings.is_empty() => subgroup.alias = alias,
+                (3..=4, Some(_)) => return Err(error("an alias after the settings of a subgroup")),
+                (_, Some(subgroup)) => match subgroup.settings.last_mut() {
+                    Some(setting) => setting.alias = alias,
+                    None => return Err(error("an alias outside of a power setting")),
+                },
+                (_, None) => return Err(error("an alias outside of a subgroup")),
+            }
+            continue;
+        }
+        after_guid = false;
+
+        let setting = match scheme.subgroups.last_mut().and_then(|s| s.settings.last_mut()) {
+            Some(setting) => setting,
+            None => continue,
+        };
+        // Current AC Power Setting Index, then the DC one
+        if indent <= 4 {
+            let index = hex(value).ok_or_else(|| error("expected a power setting index"))?;
+            match setting.ac {
+                None => setting.ac = Some(index),
+                Some(_) => setting.dc = Some(index),
+            }
+            continue;
+        }
+        if let Some(index) = option.take() {
+            if let PossibleValues::Unknown = setting.values {
+                setting.values = PossibleValues::Options(Vec::new());
+            }
+            if let PossibleValues::Options(options) = &mut setting.values {
+                options.push((index, value.to_string()));
+            }
+        } else if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+            option = value.parse().ok();
+        } else if let (Some(number), true) = (hex(value), range.len() < 3) {
+            range.push(number);
+        } else if let [min, max, increment] = range[..] {
+            setting.values = PossibleValues::Range {
+                min,
+                max,
+                increment,
+                units: value.to_string(),
+            };
+        } else {
+            return Err(error("unexpected line in a power setting"));
+        }
+    }
+    scheme.ok_or_else(|| String::from("no power scheme in the output"))
+}
+
+// `<guid>  (<name>)` with a trailing `*` for the active scheme in /list
+fn guid_of(value: &str) -> Option<(String, bool)> {
+    let guid = value.get(..36)?;
+    if !is_guid(guid) {
+        return None;
+    }
+    Some((guid.to_lowercase(), value[36..].trim().ends_with('*')))
+}
+
+pub(super) fn is_guid(s: &str) -> bool {
+    s.len() == 36
+        && s.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+fn is_alias(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_uppercase()) && s.bytes().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || b == b'_')
+}
+
+fn hex(s: &str) -> Option<u32> {
+    u32::from_str_radix(s.strip_prefix("0x")?, 16).ok()
+}
+
+fn powercfg_output(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("powercfg").args(args).output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(io::Error::other(format!(
+            "powercfg {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stdout).trim()
+        )))
+    }
+}
+
+fn powercfg(args: &[&str]) -> io::Result<()> {
+    let output = Command::new("powercfg").args(args).output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "powercfg {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stdout).trim()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Captured from powercfg, the German ones with CRLF line ends
+    fn fixture(name: &str) -> String {
+        let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    const SUB_PROCESSOR: &str = "54533251-82be-4824-96c1-47b60b740d00";
+    const USB: &str = "2a737441-1930-4402-8d77-b2bebba308a3";
+
+    fn range(min: u32, max: u32, units: &str) -> PossibleValues {
+        PossibleValues::Range {
+            min,
+            max,
+            increment: 1,
+            units: units.to_string(),
+        }
+    }
+
+    fn options(names: &[&str]) -> PossibleValues {
+        PossibleValues::Options(names.iter().enumerate().map(|(i, n)| (i as u32, n.to_string())).collect())
+    }
+
+    #[test]
+    fn list_in_english_and_german() {
+        let english = parse_list(&fixture("powercfg-list-en.txt"));
+        let guids: Vec<(&str, bool)> = english.iter().map(|s| (s.guid.as_str(), s.active)).collect();
+        assert_eq!(
+            guids,
+            vec![
+                (BALANCED, false),
+                (GAMING_SCHEME, true),
+                ("8c5e7fda-e8bf-4a96-9a85-a6e23a8c635c", false),
+                ("a1841308-3541-4fab-bc81-f71556f20b4a", false),
+            ]
+        );
+
+        let german = parse_list(&fixture("powercfg-list-de.txt"));
+        assert_eq!(german.len(), 3);
+        assert!(german[0].active && german[0].guid == BALANCED);
+        assert!(!german[1].active && !german[2].active);
+    }
+
+    // Both captures are of the same scheme, only the labels and names differ
+    fn assert_balanced(scheme: &PowerScheme) {
+        let subgroups: Vec<(&str, Option<&str>, usize)> = scheme
+            .subgroups
+            .iter()
+            .map(|s| (s.guid.as_str(), s.alias.as_deref(), s.settings.len()))
+            .collect();
+        assert_eq!(
+            subgroups,
+            vec![
+                ("fea3413e-7e05-4911-9a71-700331f1c294", Some("SUB_NONE"), 1),
+                (USB, None, 1),
+                (SUB_PROCESSOR, Some("SUB_PROCESSOR"), 4),
+            ]
+        );
+
+        type Row<'a> = (&'a str, Option<&'a str>, Option<u32>, Option<u32>);
+        let settings: Vec<Row> = scheme
+            .subgroups
+            .iter()
+            .flat_map(|s| s.settings.iter())
+            .map(|s| (s.guid.as_str(), s.alias.as_deref(), s.ac, s.dc))
+            .collect();
+        assert_eq!(
+            settings,
+            vec![
+                ("0e796bdb-100d-47d6-a2d5-f7d2daa51f51", Some("CONSOLELOCK"), Some(1), Some(1)),
+                ("48e6b7a6-50f5-4782-a5d4-53bb8f07e226", None, Some(1), Some(1)),
+                ("893dee8e-2bef-41e0-89c6-b55d0929964c", Some("PROCTHROTTLEMIN"), Some(5), Some(5)),
+                ("be337238-0d82-4146-a960-4f3749d470c7", Some("PERFBOOSTMODE"), Some(2), Some(1)),
+                ("0cc5b647-c1df-4637-891a-dec35c318583", Some("CPMINCORES"), Some(100), Some(10)),
+                ("4d2b0152-7d5c-498b-88e2-34345392a2c5", Some("PERFCHECK"), Some(15), Some(30)),
+            ]
+        );
+
+        let min = scheme.setting(SUB_PROCESSOR, "893DEE8E-2BEF-41E0-89C6-B55D0929964C").unwrap();
+        assert_eq!(min.values, range(0, 100, "%"));
+        assert_eq!(min.index(PowerSource::Ac), Some(5));
+        assert!(min.values.contains(100) && !min.values.contains(101));
+        let check = scheme.setting(SUB_PROCESSOR, "4d2b0152-7d5c-498b-88e2-34345392a2c5").unwrap();
+        assert!(matches!(check.values, PossibleValues::Range { min: 1, max: 5000, increment: 1, .. }));
+        let boost = scheme.setting(SUB_PROCESSOR, "be337238-0d82-4146-a960-4f3749d470c7").unwrap();
+        assert_eq!(boost.index(PowerSource::Dc), Some(1));
+        assert!(boost.values.contains(2) && !boost.values.contains(3));
+        assert!(scheme.setting(USB, "893dee8e-2bef-41e0-89c6-b55d0929964c").is_none());
+    }
+
+    #[test]
+    fn query_in_english() {
+        let scheme = parse_query(&fixture("powercfg-qh-en.txt")).unwrap();
+        assert_balanced(&scheme);
+        let boost = scheme.setting(SUB_PROCESSOR, "be337238-0d82-4146-a960-4f3749d470c7").unwrap();
+        assert_eq!(boost.values, options(&["Disabled", "Enabled", "Aggressive"]));
+        let check = scheme.setting(SUB_PROCESSOR, "4d2b0152-7d5c-498b-88e2-34345392a2c5").unwrap();
+        assert_eq!(check.values, range(1, 5000, "Milliseconds"));
+    }
+
+    #[test]
+    fn query_in_german() {
+        let scheme = parse_query(&fixture("powercfg-qh-de.txt")).unwrap();
+        assert_balanced(&scheme);
+        let boost = scheme.setting(SUB_PROCESSOR, "be337238-0d82-4146-a960-4f3749d470c7").unwrap();
+        assert_eq!(boost.values, options(&["Deaktiviert", "Aktiviert", "Aggressiv"]));
+        let lock = scheme.setting("fea3413e-7e05-4911-9a71-700331f1c294", "0e796bdb-100d-47d6-a2d5-f7d2daa51f51").unwrap();
+        assert_eq!(lock.values, options(&["Nein", "Ja"]));
+    }
+
+    #[test]
+    fn query_of_one_setting() {
+        let scheme = parse_query(&fixture("powercfg-qh-setting-en.txt")).unwrap();
+        let setting = scheme.setting(SUB_PROCESSOR, "0cc5b647-c1df-4637-891a-dec35c318583").unwrap();
+        assert_eq!(setting.alias.as_deref(), Some("CPMINCORES"));
+        assert_eq!((setting.ac, setting.dc), (Some(100), Some(100)));
+    }
+
+    #[test]
+    fn broken_output_names_the_line() {
+        let text = fixture("powercfg-qh-en.txt").replace("0x00000005", "five");
+        assert_eq!(parse_query(&text).unwrap_err(), "line 31: expected a power setting index");
+        // The range is one number short when the units come
+        let text = fixture("powercfg-qh-en.txt").replace("0x00001388", "5000");
+        assert_eq!(parse_query(&text).unwrap_err(), "line 56: unexpected line in a power setting");
+        assert!(parse_query("").is_err());
+    }
 }
@@ -0,0 +1,482 @@
+// Process profiles are groups of executables that get the same Image File
+// Execution Options, optionally with extra registry values. The built-in ones
+// live in catalog/profiles.toml, `profile add`/`profile remove` edit a copy of it.
+
+use super::backend::RegistryBackend;
+use super::catalog::{self, CatalogError, Tweak};
+use super::report::Report;
+use super::perf_options::{self, CpuPriority, CpuPriorityClass, IoPriority, PagePriority};
+use super::Settings;
+use crate::selection::{Category, Risk, TweakInfo};
+
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+#[cfg(windows)]
+use std::path::PathBuf;
+
+const BUILTIN: &str = include_str!("../../catalog/profiles.toml");
+const VERSION: u32 = 1;
+
+// Raising these can starve everything else or hang the machine
+const SYSTEM_PROCESSES: &[&str] = &[
+    "csrss.exe",
+    "lsass.exe",
+    "ntoskrnl.exe",
+    "services.exe",
+    "smss.exe",
+    "wininit.exe",
+    "winlogon.exe",
+];
+
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "RawProfile")]
+pub struct Profile {
+    pub name: String,
+    pub description: Option<String>,
+    pub risk: Risk,
+    pub processes: Vec<String>,
+    pub cpu_priority_class: Option<CpuPriorityClass>,
+    pub io_priority: Option<IoPriority>,
+    pub page_priority: Option<PagePriority>,
+    pub working_set_limit_in_kb: Option<u32>,
+    // Extra registry values, e.g. the fullscreen optimizations of a game
+    pub tweaks: Vec<Tweak>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "RawProfiles")]
+pub struct Profiles {
+    pub profiles: Vec<Profile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawProfiles {
+    version: u32,
+    #[serde(default)]
+    profile: Vec<Profile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawProfile {
+    name: String,
+    description: Option<String>,
+    risk: Option<String>,
+    #[serde(default)]
+    processes: Vec<String>,
+    cpu_priority_class: Option<CpuPriorityClass>,
+    io_priority: Option<IoPriority>,
+    page_priority: Option<PagePriority>,
+    working_set_limit_in_kb: Option<u32>,
+    #[serde(default)]
+    tweak: Vec<Tweak>,
+}
+
+impl TryFrom<RawProfiles> for Profiles {
+    type Error = String;
+
+    fn try_from(raw: RawProfiles) -> Result<Profiles, String> {
+        if raw.version != VERSION {
+            return Err(format!(
+                "unsupported profiles version {}, expected {}",
+                raw.version, VERSION
+            ));
+        }
+        for (i, profile) in raw.profile.iter().enumerate() {
+            let earlier = &raw.profile[..i];
+            if earlier.iter().any(|p| p.name == profile.name) {
+                return Err(format!("duplicate profile `{}`", profile.name));
+            }
+            // Two profiles would fight over the same PerfOptions key
+            for process in profile.processes.iter() {
+                if let Some(other) = earlier.iter().find(|p| p.owns(process)) {
+                    return Err(format!(
+                        "profile `{}`: {} is already in profile `{}`",
+                        profile.name, process, other.name
+                    ));
+                }
+            }
+        }
+        Ok(Profiles { profiles: raw.profile })
+    }
+}
+
+impl TryFrom<RawProfile> for Profile {
+    type Error = String;
+
+    fn try_from(raw: RawProfile) -> Result<Profile, String> {
+        if !catalog::is_id(&raw.name) {
+            return Err(format!(
+                "profile `{}`: the name may only contain lowercase letters, digits and dashes",
+                raw.name
+            ));
+        }
+        let risk = match &raw.risk {
+            Some(risk) => risk.parse::<Risk>().map_err(|e| format!("profile `{}`: {}", raw.name, e))?,
+            None => Risk::Low,
+        };
+        for (i, process) in raw.processes.iter().enumerate() {
+            check_process(process).map_err(|e| format!("profile `{}`: {}", raw.name, e))?;
+            if raw.processes[..i].iter().any(|p| p.eq_ignore_ascii_case(process)) {
+                return Err(format!("profile `{}`: duplicate process {}", raw.name, process));
+            }
+        }
+        Ok(Profile {
+            name: raw.name,
+            description: raw.description,
+            risk,
+            processes: raw.processes,
+            cpu_priority_class: raw.cpu_priority_class,
+            io_priority: raw.io_priority,
+            page_priority: raw.page_priority,
+            working_set_limit_in_kb: raw.working_set_limit_in_kb,
+            tweaks: raw.tweak,
+        })
+    }
+}
+
+// IFEO keys are named after the bare executable
+pub fn check_process(process: &str) -> Result<(), String> {
+    let bare = !process.contains(['\\', '/', ':']);
+    let exe = process.len() > 4 && process[process.len() - 4..].eq_ignore_ascii_case(".exe");
+    if bare && exe {
+        Ok(())
+    } else {
+        Err(format!("`{}` is not an executable name like game.exe", process))
+    }
+}
+
+impl Profile {
+    // The tweak id for --only/--skip
+    pub fn id(&self) -> String {
+        format!("profile-{}", self.name)
+    }
+
+    pub fn info(&self) -> TweakInfo {
+        TweakInfo {
+            id: self.id(),
+            category: Some(Category::Scheduler),
+            risk: self.risk,
+            description: match &self.description {
+                Some(description) => description.clone(),
+                None => self.processes.join(", "),
+            },
+        }
+    }
+
+    // One line per system process the profile gives a higher priority and per
+    // questionable tweak of the profile
+    pub fn warnings(&self) -> Vec<String> {
+        let raises = matches!(
+            self.cpu_priority_class,
+            Some(CpuPriorityClass::High) | Some(CpuPriorityClass::AboveNormal)
+        ) || self.io_priority == Some(IoPriority::High);
+        let mut warnings: Vec<String> = self
+            .processes
+            .iter()
+            .filter(|p| raises && SYSTEM_PROCESSES.iter().any(|s| s.eq_ignore_ascii_case(p)))
+            .map(|p| format!("profile `{}` raises the priority of the system process {}", self.name, p))
+            .collect();
+        warnings.extend(self.tweaks.iter().flat_map(Tweak::warnings));
+        warnings
+    }
+
+    pub fn owns(&self, process: &str) -> bool {
+        self.processes.iter().any(|p| p.eq_ignore_ascii_case(process))
+    }
+
+    pub fn cpu_priorities(&self) -> Vec<CpuPriority> {
+        self.processes
+            .iter()
+            .map(|process| CpuPriority {
+                process: process.clone(),
+                cpu_priority_class: self.cpu_priority_class,
+                io_priority: self.io_priority,
+                page_priority: self.page_priority,
+                working_set_limit_in_kb: self.working_set_limit_in_kb,
+            })
+            .collect()
+    }
+
+    pub fn settings(&self, dpi: u32) -> Settings {
+        catalog::group_tweaks(self.tweaks.iter(), dpi, None)
+    }
+}
+
+impl Profiles {
+    pub fn builtin() -> Profiles {
+        catalog::from_toml::<Profiles, Profile>("catalog/profiles.toml", BUILTIN, "profile")
+            .expect("embedded profiles are invalid")
+    }
+
+    pub fn from_file(path: &Path) -> Result<Profiles, CatalogError> {
+        catalog::read_document::<Profiles, Profile>(path, "profile")
+    }
+
+    // The given file, else the user's copy, else the built-in profiles
+    #[cfg(windows)]
+    pub fn load(path: Option<&Path>) -> Result<Profiles, CatalogError> {
+        match path {
+            Some(path) => Profiles::from_file(path),
+            None if profiles_file().exists() => Profiles::from_file(&profiles_file()),
+            None => Ok(Profiles::builtin()),
+        }
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|p| p.name.eq_ignore_ascii_case(name))
+    }
+}
+
+#[cfg(windows)]
+pub fn profiles_file() -> PathBuf {
+    match env::var_os("LOCALAPPDATA") {
+        Some(dir) => PathBuf::from(dir).join("gaming-optimizer").join("profiles.toml"),
+        None => PathBuf::from("profiles.toml"),
+    }
+}
+
+pub enum ProfileEdit<'a> {
+    Add { process: &'a str, profile: &'a str },
+    Remove { process: &'a str },
+}
+
+// The profile `edit_profiles` changed
+pub struct ProfileEdited {
+    pub profile: String,
+    // The file had comments, the rewritten one has none
+    pub dropped_comments: bool,
+}
+
+// Rewrites the profiles file with one process added or removed. A missing file
+// starts from the built-in profiles, comments are not kept.
+pub fn edit_profiles(path: &Path, edit: ProfileEdit) -> Result<ProfileEdited, CatalogError> {
+    let file = path.display().to_string();
+    let error = |message: String| CatalogError {
+        file: file.clone(),
+        position: None,
+        message,
+    };
+    let (text, json, existed) = match fs::read_to_string(path) {
+        Ok(text) => (text, catalog::is_json(path), true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (BUILTIN.to_string(), false, false),
+        Err(e) => return Err(error(e.to_string())),
+    };
+    let dropped_comments = existed && !json && text.lines().any(|line| line.trim_start().starts_with('#'));
+    let profiles: Profiles = if json {
+        catalog::from_json(&file, &text)?
+    } else {
+        catalog::from_toml::<Profiles, Profile>(&file, &text, "profile")?
+    };
+    // Edited as a plain document so the extra tweaks are written back as they were
+    let mut document: serde_json::Value = if json {
+        serde_json::from_str(&text).map_err(|e| error(e.to_string()))?
+    } else {
+        let document: toml::Value = toml::from_str(&text).map_err(|e| error(e.to_string()))?;
+        serde_json::to_value(document).map_err(|e| error(e.to_string()))?
+    };
+
+    let (process, name) = match edit {
+        ProfileEdit::Add { process, profile } => {
+            check_process(process).map_err(error)?;
+            if let Some(owner) = profiles.profiles.iter().find(|p| p.owns(process)) {
+                return Err(error(format!("{} is already in profile `{}`", process, owner.name)));
+            }
+            match profiles.find(profile) {
+                Some(profile) => (process, profile.name.clone()),
+                None => return Err(error(format!("unknown profile `{}`", profile))),
+            }
+        }
+        ProfileEdit::Remove { process } => match profiles.profiles.iter().find(|p| p.owns(process)) {
+            Some(owner) => (process, owner.name.clone()),
+            None => return Err(error(format!("{} is in no profile", process))),
+        },
+    };
+    let entry = document
+        .get_mut("profile")
+        .and_then(|profiles| profiles.as_array_mut())
+        .and_then(|profiles| profiles.iter_mut().find(|p| p["name"] == name.as_str()))
+        .and_then(|profile| profile.as_object_mut())
+        .ok_or_else(|| error(format!("unknown profile `{}`", name)))?;
+    let processes = entry
+        .entry("processes")
+        .or_insert_with(|| serde_json::Value::Array(Vec::new()));
+    if let Some(processes) = processes.as_array_mut() {
+        match edit {
+            ProfileEdit::Add { .. } => processes.push(serde_json::Value::from(process)),
+            ProfileEdit::Remove { .. } => {
+                processes.retain(|p| !p.as_str().is_some_and(|p| p.eq_ignore_ascii_case(process)))
+            }
+        }
+    }
+
+    serde_json::from_value::<Profiles>(document.clone()).map_err(|e| error(e.to_string()))?;
+    let text = if json {
+        serde_json::to_string_pretty(&document).map(|text| text + "\n").map_err(|e| error(e.to_string()))?
+    } else {
+        toml::Value::try_from(&document)
+            .and_then(|document| toml::to_string(&document))
+            .map_err(|e| error(e.to_string()))?
+    };
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| error(e.to_string()))?;
+    }
+    fs::write(path, text).map_err(|e| error(e.to_string()))?;
+    Ok(ProfileEdited {
+        profile: name,
+        dropped_comments,
+    })
+}
+
+// Compares the IFEO entries and the extra values of a profile
+pub fn check_profile<B: RegistryBackend>(backend: &B, profile: &Profile, dpi: u32) -> Report {
+    let mut report = Report::default();
+    for priority in profile.cpu_priorities() {
+        report.extend(perf_options::check_cpu_priority(backend, priority));
+    }
+    report.extend(super::check_reg_tweaks(backend, &profile.settings(dpi)));
+    report
+}
+
+// Deletes the PerfOptions values of every process and puts the Windows defaults
+// of the extra values back
+pub fn remove_profile<B: RegistryBackend>(backend: &B, profile: &Profile, dpi: u32) -> Report {
+    let mut report = Report::default();
+    for priority in profile.cpu_priorities() {
+        report.extend(perf_options::remove_cpu_priority(backend, &priority));
+    }
+    report.extend(super::restore_default_reg(backend, &profile.settings(dpi)));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profiles(text: &str) -> Profiles {
+        catalog::from_toml::<Profiles, Profile>("profiles.toml", text, "profile").unwrap()
+    }
+
+    #[test]
+    fn raising_a_system_process_is_a_warning() {
+        let profiles = profiles(
+            r#"
+version = 1
+
+[[profile]]
+name = "system"
+processes = ["game.exe", "CSRSS.EXE"]
+cpu_priority_class = "high"
+
+[[profile]]
+name = "quiet"
+processes = ["lsass.exe"]
+cpu_priority_class = "below normal"
+io_priority = "low"
+"#,
+        );
+        assert_eq!(
+            profiles.profiles[0].warnings(),
+            vec!["profile `system` raises the priority of the system process CSRSS.EXE"]
+        );
+        // A lower priority is only a bad idea
+        assert!(profiles.profiles[1].warnings().is_empty());
+    }
+
+    #[test]
+    fn the_builtin_system_profile_warns() {
+        let profiles = Profiles::builtin();
+        let warnings = profiles.find("system-high").unwrap().warnings();
+        assert!(warnings.iter().any(|w| w.ends_with("system process csrss.exe")), "{:?}", warnings);
+        assert!(warnings.iter().any(|w| w.ends_with("system process ntoskrnl.exe")), "{:?}", warnings);
+    }
+
+    #[test]
+    fn add_and_remove_leave_the_profiles_as_they_were() {
+        let path = env::temp_dir().join(format!("gaming-optimizer-profiles-{}.toml", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let builtin = Profiles::builtin();
+        let name = builtin.profiles[0].name.clone();
+
+        // The first edit starts from the built-in profiles
+        let add = ProfileEdit::Add {
+            process: "testgame.exe",
+            profile: &name,
+        };
+        let added = edit_profiles(&path, add).unwrap();
+        assert_eq!(added.profile, name);
+        assert!(!added.dropped_comments);
+        assert!(Profiles::from_file(&path).unwrap().find(&name).unwrap().owns("TestGame.exe"));
+
+        let removed = edit_profiles(&path, ProfileEdit::Remove { process: "TESTGAME.EXE" }).unwrap();
+        assert_eq!(removed.profile, name);
+        let after = Profiles::from_file(&path).unwrap();
+        assert_eq!(format!("{:?}", after.profiles), format!("{:?}", builtin.profiles));
+
+        // Comments of the user's file are reported
+        let text = fs::read_to_string(&path).unwrap();
+        fs::write(&path, format!("# my games\n{}", text)).unwrap();
+        let add = ProfileEdit::Add {
+            process: "testgame.exe",
+            profile: &name,
+        };
+        assert!(edit_profiles(&path, add).unwrap().dropped_comments);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn check_and_remove_a_profile() {
+        use crate::registry::backend::{Hive, MemoryBackend, RegValue};
+        use crate::registry::report::CheckOutcome;
+
+        let profiles = profiles(
+            r#"
+version = 1
+
+[[profile]]
+name = "games"
+description = "Competitive games"
+risk = "medium"
+processes = ["game.exe", "other.exe"]
+page_priority = "normal"
+working_set_limit_in_kb = 4096
+
+[[profile.tweak]]
+id = "game-fullscreen"
+category = "scheduler"
+path = 'HKCU\Software\Test'
+name = "Fullscreen"
+type = "dword"
+value = 1
+"#,
+        );
+        let profile = profiles.find("GAMES").unwrap();
+        let info = profile.info();
+        assert_eq!(info.id, "profile-games");
+        assert_eq!((info.risk, info.description.as_str()), (Risk::Medium, "Competitive games"));
+        let values: Vec<Vec<(&str, u32)>> = profile.cpu_priorities().iter().map(|p| p.values()).collect();
+        assert_eq!(values[1], vec![(perf_options::PAGE_PRIORITY, 5), (perf_options::WORKING_SET_LIMIT, 4096)]);
+
+        let backend = MemoryBackend::new();
+        // Both values of both processes and the extra value
+        let report = check_profile(&backend, profile, 96);
+        assert_eq!(report.results.len(), 5);
+        assert!(report.results.iter().all(|r| matches!(r.outcome, CheckOutcome::Missing)));
+
+        let transaction = backend.transaction().unwrap();
+        let hklm = backend.predef(Hive::LocalMachine);
+        let key = backend
+            .create_subkey_transacted(&hklm, &perf_options::perf_options_path("game.exe"), &transaction)
+            .unwrap();
+        backend.set_raw_value(&key, perf_options::PAGE_PRIORITY, &RegValue::dword(5)).unwrap();
+        backend.commit(&transaction).unwrap();
+        let report = remove_profile(&backend, profile, 96);
+        assert_eq!(report.failed(), 0);
+        assert!(backend.open_subkey(&hklm, &perf_options::perf_options_path("game.exe")).is_err());
+    }
+}
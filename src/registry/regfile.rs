@@ -0,0 +1,539 @@
+// Windows Registry Editor 5.00 files, so a change can be reviewed in regedit
+// format before anything is written.
+
+use super::backend::{RegPath, RegType, RegValue, RegistryBackend};
+use super::catalog::{parse_hex, CatalogError};
+use super::{DeleteElement, Either, RawElement, RegTweaks, Settings, StringElement, U32Element, VecElement};
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const HEADER: &str = "Windows Registry Editor Version 5.00";
+// regedit wraps hex data before this column
+const LINE_WIDTH: usize = 80;
+
+// The values the tweaks would write
+pub fn export_desired(settings: &Settings) -> String {
+    let mut out = header();
+    for section in settings.keys.iter() {
+        if section.remove {
+            out.push_str(&format!("[-{}]\r\n\r\n", section.path));
+            continue;
+        }
+        out.push_str(&format!("[{}]\r\n", section.path));
+        for element in section.data.iter() {
+            out.push_str(&format_line(element.name(), element.value().as_ref()));
+        }
+        out.push_str("\r\n");
+    }
+    out
+}
+
+// The values a restore would write, `-` deletes values without a Windows default.
+// Deletions have no known previous value and are left out.
+pub fn export_defaults(settings: &Settings) -> String {
+    let mut out = header();
+    for section in settings.keys.iter().filter(|s| !s.remove) {
+        out.push_str(&format!("[{}]\r\n", section.path));
+        for element in section.data.iter() {
+            if let Either::DeleteElement(_) = element {
+                continue;
+            }
+            out.push_str(&format_line(element.name(), element.default_value().as_ref()));
+        }
+        out.push_str("\r\n");
+    }
+    out
+}
+
+// What the machine has right now for every value the tweaks cover
+pub fn export_current<B: RegistryBackend>(backend: &B, settings: &Settings) -> String {
+    let mut out = header();
+    for section in settings.keys.iter() {
+        let root = backend.predef(section.path.hive);
+        let key = match backend.open_subkey(&root, &section.path.subkey) {
+            Ok(key) => key,
+            Err(_) => {
+                out.push_str(&format!("; [{}] does not exist\r\n\r\n", section.path));
+                continue;
+            }
+        };
+        if section.remove {
+            out.push_str(&format!("; [{}] exists\r\n\r\n", section.path));
+            continue;
+        }
+        out.push_str(&format!("[{}]\r\n", section.path));
+        for element in section.data.iter() {
+            let name = element.name();
+            let value = backend.get_raw_value(&key, name).ok();
+            out.push_str(&format_line(name, value.as_ref()));
+        }
+        out.push_str("\r\n");
+    }
+    out
+}
+
+// regedit reads UTF-16LE with a byte order mark for version 5.00 files
+pub fn write_reg_file(path: &Path, content: &str) -> io::Result<()> {
+    let mut bytes = vec![0xff, 0xfe];
+    for unit in content.encode_utf16() {
+        bytes.extend_from_slice(&unit.to_le_bytes());
+    }
+    fs::write(path, bytes)
+}
+
+fn header() -> String {
+    format!("{}\r\n\r\n", HEADER)
+}
+
+// One `"name"=data` entry including the line break, None deletes the value
+fn format_line(name: &str, value: Option<&RegValue>) -> String {
+    let name = if name.is_empty() {
+        String::from("@")
+    } else {
+        format!("\"{}\"", escape(name))
+    };
+    let value = match value {
+        None => return format!("{}=-\r\n", name),
+        Some(value) => value,
+    };
+    match value.vtype {
+        RegType::REG_DWORD if value.bytes.len() == 4 => {
+            format!("{}=dword:{:08x}\r\n", name, value.as_u32().unwrap_or_default())
+        }
+        RegType::REG_SZ if value.as_string().is_ok_and(|s| !s.contains(['\r', '\n'])) => {
+            format!("{}=\"{}\"\r\n", name, escape(&value.as_string().unwrap_or_default()))
+        }
+        RegType::REG_BINARY => format_hex(&format!("{}=hex:", name), &value.bytes),
+        vtype => format_hex(&format!("{}=hex({:x}):", name, vtype as u32), &value.bytes),
+    }
+}
+
+// Wraps like regedit: two spaces of indent and a trailing backslash
+fn format_hex(prefix: &str, bytes: &[u8]) -> String {
+    let mut out = String::from(prefix);
+    let mut line = prefix.len();
+    for (i, byte) in bytes.iter().enumerate() {
+        out.push_str(&format!("{:02x}", byte));
+        line += 2;
+        if i + 1 < bytes.len() {
+            out.push(',');
+            line += 1;
+            if line + 3 > LINE_WIDTH - 2 {
+                out.push_str("\\\r\n  ");
+                line = 2;
+            }
+        }
+    }
+    out.push_str("\r\n");
+    out
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Reads a REGEDIT4 or Registry Editor 5.00 file into tweaks, so a .reg file
+// from the net can be checked against the machine before it is applied.
+pub fn import_reg_file(path: &Path) -> Result<Settings, CatalogError> {
+    let file = path.display().to_string();
+    let bytes = fs::read(path).map_err(|e| CatalogError {
+        file: file.clone(),
+        position: None,
+        message: e.to_string(),
+    })?;
+    parse_reg(&file, &bytes)
+}
+
+pub fn parse_reg(file: &str, bytes: &[u8]) -> Result<Settings, CatalogError> {
+    let error = |line: usize, message: String| CatalogError {
+        file: file.to_string(),
+        position: Some((line, 1)),
+        message,
+    };
+
+    let text = decode(bytes);
+    let lines = logical_lines(&text);
+    let mut lines = lines.iter().filter(|(_, l)| !l.is_empty() && !l.starts_with(';'));
+
+    let ansi = match lines.next() {
+        Some((_, l)) if l == HEADER => false,
+        Some((_, l)) if l == "REGEDIT4" => true,
+        Some((n, _)) => return Err(error(*n, format!("expected `{}` or `REGEDIT4`", HEADER))),
+        None => return Err(error(1, String::from("empty file"))),
+    };
+
+    let mut settings = Settings::default();
+
+    for (n, line) in lines {
+        if let Some(key) = line.strip_prefix('[') {
+            let key = key
+                .strip_suffix(']')
+                .ok_or_else(|| error(*n, String::from("missing `]` after the key")))?;
+            let (remove, key) = match key.strip_prefix('-') {
+                Some(key) => (true, key),
+                None => (false, key),
+            };
+            let path = key.parse::<RegPath>().map_err(|e| error(*n, e))?;
+
+            let same_key = matches!(settings.keys.last(), Some(last) if last.path == path && last.remove == remove);
+            if !same_key {
+                settings.keys.push(RegTweaks {
+                    path,
+                    remove,
+                    data: Vec::new(),
+                });
+            }
+            continue;
+        }
+
+        let section = settings
+            .keys
+            .last_mut()
+            .ok_or_else(|| error(*n, String::from("value before the first key")))?;
+        if section.remove {
+            return Err(error(*n, format!("value below the removed key [-{}]", section.path)));
+        }
+        let element = parse_value_line(line, ansi).map_err(|e| error(*n, e))?;
+        section.data.push(element);
+    }
+
+    Ok(settings)
+}
+
+// regedit writes UTF-16LE, older tools and hand written files are ANSI or UTF-8
+fn decode(bytes: &[u8]) -> String {
+    if let Some(rest) = bytes.strip_prefix(&[0xff, 0xfe]) {
+        let units: Vec<u16> = rest.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+        return String::from_utf16_lossy(&units);
+    }
+    if let Some(rest) = bytes.strip_prefix(&[0xef, 0xbb, 0xbf]) {
+        return String::from_utf8_lossy(rest).into_owned();
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|b| cp1252(*b)).collect(),
+    }
+}
+
+// Windows-1252, which only differs from Latin-1 in 0x80..0x9f
+fn cp1252(byte: u8) -> char {
+    const HIGH: [char; 32] = [
+        '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+        '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+    ];
+    match byte {
+        0x80..=0x9f => HIGH[(byte - 0x80) as usize],
+        _ => byte as char,
+    }
+}
+
+// Joins lines ending in a backslash, keeps the number of the first line
+fn logical_lines(text: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    let mut continued = false;
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let (line, next) = match line.strip_suffix('\\') {
+            Some(rest) => (rest, true),
+            None => (line, false),
+        };
+        if continued {
+            lines.last_mut().unwrap().1.push_str(line);
+        } else {
+            lines.push((n + 1, line.to_string()));
+        }
+        continued = next;
+    }
+    lines
+}
+
+fn parse_value_line(line: &str, ansi: bool) -> Result<Either, String> {
+    let (key, rest) = match line.strip_prefix('@') {
+        Some(rest) => (String::new(), rest),
+        None => parse_quoted(line)?,
+    };
+    let data = rest
+        .trim_start()
+        .strip_prefix('=')
+        .ok_or_else(|| format!("expected `=` after `{}`", key))?
+        .trim();
+
+    if data == "-" {
+        return Ok(Either::DeleteElement(DeleteElement { key }));
+    }
+    if data.starts_with('"') {
+        let (value, rest) = parse_quoted(data)?;
+        if !rest.trim().is_empty() {
+            return Err(format!("unexpected `{}` after the string of `{}`", rest.trim(), key));
+        }
+        return Ok(Either::StringElement(StringElement {
+            key,
+            value,
+            default: None,
+        }));
+    }
+    if let Some(hex) = data.strip_prefix("dword:") {
+        let value = u32::from_str_radix(hex.trim(), 16)
+            .map_err(|_| format!("`{}` of `{}` is not a dword", hex.trim(), key))?;
+        return Ok(Either::U32Element(U32Element {
+            key,
+            value,
+            default: None,
+        }));
+    }
+    if let Some(hex) = data.strip_prefix("hex:") {
+        let value = parse_hex(hex).map_err(|e| format!("`{}` {}", key, e))?;
+        return Ok(Either::VecElement(VecElement {
+            key,
+            value,
+            default: None,
+        }));
+    }
+    if let Some(rest) = data.strip_prefix("hex(") {
+        let (vtype, hex) = rest
+            .split_once("):")
+            .ok_or_else(|| format!("expected `hex(type):` for `{}`", key))?;
+        let vtype = u32::from_str_radix(vtype, 16)
+            .ok()
+            .and_then(RegType::from_u32)
+            .ok_or_else(|| format!("unknown value type hex({}) for `{}`", vtype, key))?;
+        let mut bytes = parse_hex(hex).map_err(|e| format!("`{}` {}", key, e))?;
+        // REGEDIT4 stores strings as ANSI, the registry wants UTF-16LE
+        if ansi && matches!(vtype, RegType::REG_SZ | RegType::REG_EXPAND_SZ | RegType::REG_MULTI_SZ) {
+            bytes = bytes
+                .iter()
+                .map(|b| cp1252(*b))
+                .collect::<String>()
+                .encode_utf16()
+                .flat_map(|u| u.to_le_bytes())
+                .collect();
+        }
+        return Ok(Either::RawElement(RawElement {
+            key,
+            value: RegValue { bytes, vtype },
+            default: None,
+        }));
+    }
+    Err(format!("unknown data `{}` for `{}`", data, key))
+}
+
+// A "quoted" string with \\ and \" escapes, returns the string and the rest of the line
+fn parse_quoted(s: &str) -> Result<(String, &str), String> {
+    let body = s
+        .strip_prefix('"')
+        .ok_or_else(|| format!("expected a quoted name or `@`, found `{}`", s))?;
+    let mut value = String::new();
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &body[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, c)) => value.push(c),
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+    Err(format!("missing closing quote in `{}`", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::backend::Hive;
+
+    // As regedit saves it
+    fn utf16(text: &str) -> Vec<u8> {
+        let mut bytes = vec![0xff, 0xfe];
+        bytes.extend(text.encode_utf16().flat_map(|u| u.to_le_bytes()));
+        bytes
+    }
+
+    fn values(settings: &Settings) -> Vec<(String, String, Option<RegValue>)> {
+        settings
+            .keys
+            .iter()
+            .flat_map(|s| s.data.iter().map(move |e| (s.path.to_string(), e.name().to_string(), e.value())))
+            .collect()
+    }
+
+    fn utf16_value(vtype: RegType, text: &str) -> RegValue {
+        RegValue {
+            bytes: text.encode_utf16().flat_map(|u| u.to_le_bytes()).collect(),
+            vtype,
+        }
+    }
+
+    const EXPORT: &str = "Windows Registry Editor Version 5.00\r\n\
+        \r\n\
+        ; exported by hand\r\n\
+        [HKEY_CURRENT_USER\\Software\\Test]\r\n\
+        @=\"default\"\r\n\
+        \"Quoted \\\"name\\\"\"=\"C:\\\\Games\\\\Ünïcode\"\r\n\
+        \"Speed\"=dword:0000000a\r\n\
+        \"Path\"=hex(2):25,00,53,00,79,00,73,00,74,00,65,00,6d,00,52,00,6f,00,6f,00,74,\\\r\n\
+        \x20 00,25,00,00,00\r\n\
+        \"List\"=hex(7):61,00,00,00,62,00,00,00,00,00\r\n\
+        \"Big\"=hex(b):00,00,00,00,01,00,00,00\r\n\
+        \"Mask\"=hex:01,02,\\\r\n\
+        \x20 03\r\n\
+        \"Old\"=-\r\n\
+        \r\n\
+        [-HKEY_LOCAL_MACHINE\\SOFTWARE\\Test\\Gone]\r\n";
+
+    #[test]
+    fn utf16_with_a_byte_order_mark() {
+        let settings = parse_reg("test.reg", &utf16(EXPORT)).unwrap();
+        assert_eq!(settings.keys.len(), 2);
+        assert_eq!(settings.keys[0].path, RegPath::new(Hive::CurrentUser, "Software\\Test"));
+        assert!(!settings.keys[0].remove);
+        let user = "HKEY_CURRENT_USER\\Software\\Test".to_string();
+        assert_eq!(
+            values(&settings),
+            vec![
+                (user.clone(), String::new(), Some(RegValue::sz("default"))),
+                (user.clone(), "Quoted \"name\"".to_string(), Some(RegValue::sz("C:\\Games\\Ünïcode"))),
+                (user.clone(), "Speed".to_string(), Some(RegValue::dword(10))),
+                (user.clone(), "Path".to_string(), Some(utf16_value(RegType::REG_EXPAND_SZ, "%SystemRoot%\0"))),
+                (user.clone(), "List".to_string(), Some(utf16_value(RegType::REG_MULTI_SZ, "a\0b\0\0"))),
+                (user.clone(), "Big".to_string(), Some(RegValue::qword(1 << 32))),
+                (user.clone(), "Mask".to_string(), Some(RegValue::binary(vec![1, 2, 3]))),
+                (user, "Old".to_string(), None),
+            ]
+        );
+        // The whole key goes, it has no values of its own
+        assert_eq!(settings.keys[1].path, RegPath::new(Hive::LocalMachine, "SOFTWARE\\Test\\Gone"));
+        assert!(settings.keys[1].remove && settings.keys[1].data.is_empty());
+    }
+
+    #[test]
+    fn utf8_without_a_byte_order_mark() {
+        let utf16 = parse_reg("test.reg", &utf16(EXPORT)).unwrap();
+        let utf8 = parse_reg("test.reg", EXPORT.as_bytes()).unwrap();
+        assert_eq!(values(&utf8), values(&utf16));
+    }
+
+    #[test]
+    fn ansi_regedit4() {
+        // Windows-1252, which is not valid UTF-8
+        let mut bytes = b"REGEDIT4\r\n\r\n[HKEY_CURRENT_USER\\Software\\Caf\xe9]\r\n".to_vec();
+        bytes.extend_from_slice(b"\"Price\"=\"5 \x80\"\r\n");
+        bytes.extend_from_slice(b"\"Where\"=hex(2):25,41,50,50,44,41,54,41,25,5c,e9,00\r\n");
+        bytes.extend_from_slice(b"\"Raw\"=hex:e9\r\n");
+        let settings = parse_reg("test.reg", &bytes).unwrap();
+        assert_eq!(settings.keys[0].path, RegPath::new(Hive::CurrentUser, "Software\\Café"));
+        let values: Vec<Option<RegValue>> = values(&settings).into_iter().map(|(_, _, v)| v).collect();
+        assert_eq!(
+            values,
+            vec![
+                Some(RegValue::sz("5 €")),
+                // The ANSI string data is converted to UTF-16LE, binary data is kept
+                Some(utf16_value(RegType::REG_EXPAND_SZ, "%APPDATA%\\é\0")),
+                Some(RegValue::binary(vec![0xe9])),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_continued_line_keeps_the_number_of_its_first_line() {
+        let text = "Windows Registry Editor Version 5.00\r\n\r\n[HKEY_CURRENT_USER\\Software\\Test]\r\n\
+            \"Mask\"=hex:01,\\\r\n  02,\\\r\n  zz\r\n";
+        let error = parse_reg("test.reg", text.as_bytes()).err().unwrap();
+        assert_eq!(error.position, Some((4, 1)));
+        assert!(error.message.contains("`Mask`"), "{}", error.message);
+    }
+
+    #[test]
+    fn broken_files_name_the_line() {
+        let cases = [
+            ("REGEDIT5\r\n", 1, "expected `Windows Registry Editor Version 5.00` or `REGEDIT4`"),
+            ("REGEDIT4\r\n\"Speed\"=dword:1\r\n", 2, "value before the first key"),
+            ("REGEDIT4\r\n[HKEY_CURRENT_USER\\Software\r\n", 2, "missing `]` after the key"),
+            (
+                "REGEDIT4\r\n[-HKEY_CURRENT_USER\\Software\\Test]\r\n\"Speed\"=dword:1\r\n",
+                3,
+                "value below the removed key [-HKEY_CURRENT_USER\\Software\\Test]",
+            ),
+            ("REGEDIT4\r\n[HKEY_CURRENT_USER\\Software\\Test]\r\n\"Speed\"=dword:x\r\n", 3, "`x` of `Speed` is not a dword"),
+            ("REGEDIT4\r\n[HKEY_CURRENT_USER\\Software\\Test]\r\n\"Odd\"=hex(99):00\r\n", 3, "unknown value type hex(99) for `Odd`"),
+        ];
+        for (text, line, message) in cases.iter() {
+            let error = parse_reg("test.reg", text.as_bytes()).err().unwrap();
+            assert_eq!((error.position, error.message.as_str()), (Some((*line, 1)), *message));
+        }
+    }
+
+    #[test]
+    fn an_export_reads_back_the_same() {
+        let settings = parse_reg("test.reg", &utf16(EXPORT)).unwrap();
+        let exported = export_desired(&settings);
+        let again = parse_reg("desired.reg", &utf16(&exported)).unwrap();
+        assert_eq!(values(&again), values(&settings));
+        assert!(again.keys[1].remove);
+        assert!(exported.contains("\"Speed\"=dword:0000000a\r\n"));
+        assert!(exported.contains("[-HKEY_LOCAL_MACHINE\\SOFTWARE\\Test\\Gone]\r\n"));
+    }
+
+    #[test]
+    fn the_defaults_and_the_values_on_the_machine() {
+        use crate::registry::backend::MemoryBackend;
+        use crate::registry::catalog::Catalog;
+
+        let catalog = r#"
+version = 1
+
+[[tweak]]
+id = "speed"
+category = "input"
+path = 'HKCU\Software\Test'
+name = "Speed"
+type = "dword"
+value = 1
+default = 10
+
+[[tweak]]
+id = "extra"
+category = "input"
+path = 'HKCU\Software\Test'
+name = "Extra"
+type = "sz"
+value = "on"
+"#;
+        let settings = Catalog::from_toml("test.toml", catalog).unwrap().settings(96, None, |_| true);
+        let key = "[HKEY_CURRENT_USER\\Software\\Test]\r\n";
+        assert_eq!(
+            export_defaults(&settings),
+            format!("{}\r\n\r\n{}\"Speed\"=dword:0000000a\r\n\"Extra\"=-\r\n\r\n", HEADER, key)
+        );
+
+        let backend = MemoryBackend::new();
+        let current = export_current(&backend, &settings);
+        assert!(current.ends_with("; [HKEY_CURRENT_USER\\Software\\Test] does not exist\r\n\r\n"), "{}", current);
+        let transaction = backend.transaction().unwrap();
+        let test = backend
+            .create_subkey_transacted(&backend.predef(Hive::CurrentUser), "Software\\Test", &transaction)
+            .unwrap();
+        backend.set_raw_value(&test, "Speed", &RegValue::dword(5)).unwrap();
+        backend.commit(&transaction).unwrap();
+        let current = export_current(&backend, &settings);
+        assert_eq!(current, format!("{}\r\n\r\n{}\"Speed\"=dword:00000005\r\n\"Extra\"=-\r\n\r\n", HEADER, key));
+
+        // Written as regedit writes it and read back
+        let path = std::env::temp_dir().join(format!("gaming-optimizer-current-{}.reg", std::process::id()));
+        write_reg_file(&path, &current).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let imported = import_reg_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(bytes, utf16(&current));
+        let imported = imported.unwrap();
+        assert_eq!(
+            values(&imported),
+            vec![
+                (String::from("HKEY_CURRENT_USER\\Software\\Test"), String::from("Speed"), Some(RegValue::dword(5))),
+                (String::from("HKEY_CURRENT_USER\\Software\\Test"), String::from("Extra"), None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use super::backend::{Hive, RegValue};
+use super::perf_options;
+use crate::error::Error;
+
+pub enum CheckOutcome {
+    Correct,
+    Mismatch { current: RegValue, desired: RegValue },
+    // The value exists with another registry type
+    WrongType { current: RegValue, desired: RegValue },
+    Missing,
+    // The value or key should not exist but does
+    Present,
+    Written,
+    // Written over a value of another registry type
+    Retyped { previous: RegValue },
+    Deleted,
+    // The key with everything below it
+    DeletedKey,
+    Failed(Error),
+}
+
+pub struct CheckResult {
+    pub hive: Hive,
+    pub path: String,
+    pub name: String,
+    // The value the check is aiming for, None when it removes the value
+    pub value: Option<RegValue>,
+    pub outcome: CheckOutcome,
+}
+
+impl CheckResult {
+    // Results about a whole key have no value name
+    pub fn full_path(&self) -> String {
+        if self.name.is_empty() {
+            format!("{}\\{}", self.hive, self.path)
+        } else {
+            format!("{}\\{}\\{}", self.hive, self.path, self.name)
+        }
+    }
+
+    // PerfOptions values get their meaning appended, e.g. "dword:3 (High)"
+    pub fn describe(&self, value: &RegValue) -> String {
+        if perf_options::is_perf_options(&self.path) {
+            perf_options::describe(&self.name, value)
+        } else {
+            value.to_string()
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Report {
+    pub results: Vec<CheckResult>,
+}
+
+impl Report {
+    pub fn push(&mut self, result: CheckResult) {
+        self.results.push(result);
+    }
+
+    pub fn extend(&mut self, other: Report) {
+        self.results.extend(other.results);
+    }
+
+    pub fn count(&self, filter: impl Fn(&CheckOutcome) -> bool) -> usize {
+        self.results.iter().filter(|r| filter(&r.outcome)).count()
+    }
+
+    // Settings that differ from the tweak and were not (yet) written
+    pub fn drift(&self) -> usize {
+        self.count(|o| {
+            matches!(
+                o,
+                CheckOutcome::Mismatch { .. } | CheckOutcome::WrongType { .. } | CheckOutcome::Missing | CheckOutcome::Present
+            )
+        })
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(|o| matches!(o, CheckOutcome::Failed(_)))
+    }
+}
+
+// println!("\x1b[0;92m INFO \x1b[0m");
+// println!("\x1b[0;93m WARN \x1b[0m");
+// println!("\x1b[0;91m ERR \x1b[0m");
+#[cfg(windows)]
+pub fn print_console(report: &Report) {
+    for result in report.results.iter() {
+        let path = result.full_path();
+        let value = match &result.value {
+            Some(value) => format!(" = {}", result.describe(value)),
+            None => String::new(),
+        };
+        match &result.outcome {
+            CheckOutcome::Correct => println!("correct setting: {}{}", path, value),
+            CheckOutcome::Mismatch { current, desired } => println!(
+                "wrong setting: \x1b[0;93m{} = {}\x1b[0m (your value: {})",
+                path,
+                result.describe(desired),
+                result.describe(current)
+            ),
+            CheckOutcome::WrongType { current, desired } => println!(
+                "wrong type: \x1b[0;93m{} = {}\x1b[0m (your value: {} as {:?})",
+                path,
+                result.describe(desired),
+                result.describe(current),
+                current.vtype
+            ),
+            CheckOutcome::Missing => println!("setting missing: \x1b[0;93m{}{}\x1b[0m", path, value),
+            CheckOutcome::Present => println!("should not exist: \x1b[0;93m{}\x1b[0m", path),
+            CheckOutcome::Written => println!("write reg key: \x1b[0;92m{}{}\x1b[0m", path, value),
+            CheckOutcome::Retyped { previous } => println!(
+                "write reg key: \x1b[0;92m{}{}\x1b[0m (was {} as {:?})",
+                path,
+                value,
+                result.describe(previous),
+                previous.vtype
+            ),
+            CheckOutcome::Deleted => println!("deleted value: \x1b[0;93m{}\x1b[0m", path),
+            CheckOutcome::DeletedKey => println!("deleted key: \x1b[0;93m{}\x1b[0m", path),
+            CheckOutcome::Failed(e) => println!("\x1b[0;91m{}: {}\x1b[0m", path, e),
+        }
+    }
+}
+
+#[cfg(windows)]
+pub fn print_summary(report: &Report) {
+    print_totals(&[report]);
+}
+
+// One summary line over the reports of several areas
+#[cfg(windows)]
+pub fn print_totals(reports: &[&Report]) {
+    let total = |count: &dyn Fn(&Report) -> usize| reports.iter().map(|r| count(r)).sum::<usize>();
+    let correct = total(&|r| r.count(|o| matches!(o, CheckOutcome::Correct)));
+    let written = total(&|r| r.count(|o| matches!(o, CheckOutcome::Written | CheckOutcome::Retyped { .. } | CheckOutcome::Deleted | CheckOutcome::DeletedKey)));
+    println!(
+        "\n{} correct, \x1b[0;93m{} to fix\x1b[0m, \x1b[0;92m{} written\x1b[0m, \x1b[0;91m{} failed\x1b[0m",
+        correct,
+        total(&|r| r.drift()),
+        written,
+        total(&|r| r.failed())
+    );
+}
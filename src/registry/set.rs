@@ -1,137 +1,229 @@
-use winreg::enums::RegType::REG_BINARY;
-use winreg::RegKey;
-use winreg::RegValue;
-
-pub fn set_u32_reg(reg: &RegKey, key: &str, val: &u32, reg_path: &str, write_settings: bool) {
-    match reg.get_value(key) {
-        Err(_) => {
-            if write_settings {
-                match reg.set_value(key, val) {
-                    Err(e) => println!("\x1b[0;91m{:?}\x1b[0m", e),
-                    Ok(()) => println!(
-                        "write reg key: \x1b[0;92m{}\\{} = dword:{}\x1b[0m",
-                        reg_path, key, val
-                    ),
-                }
-            } else {
-                println!(
-                    "setting missing: \x1b[0;93m{}\\{} = dword:{}\x1b[0m",
-                    reg_path, key, val
-                );
-            }
-        }
-        Ok(reg_val) => {
-            let value: u32 = reg_val;
-            if value != *val {
-                if write_settings {
-                    match reg.set_value(key, val) {
-                        Err(e) => println!("\x1b[0;91m{:?}\x1b[0m", e),
-                        Ok(()) => println!(
-                            "write reg key: \x1b[0;92m{}\\{} = dword:{}\x1b[0m",
-                            reg_path, key, val
-                        ),
-                    }
-                } else {
-                    println!(
-                        "wrong setting: \x1b[0;93m{}\\{} = dword:{}\x1b[0m (your value: {})",
-                        reg_path, key, val, value
-                    );
-                }
-            } else {
-                println!("correct setting: {}\\{} = dword:{}", reg_path, key, val);
-            }
-        }
+use super::backend::{RegPath, RegValue, RegistryBackend};
+use super::report::{CheckOutcome, CheckResult};
+
+use std::io;
+
+// Compares the current value with the desired one and writes it if asked to.
+// The value is read raw, so a value of another type is reported as such
+// instead of being taken for a missing one.
+fn reconcile<B: RegistryBackend>(
+    backend: &B,
+    reg: &B::Key,
+    path: &RegPath,
+    key: &str,
+    desired: RegValue,
+    write_settings: bool,
+) -> CheckResult {
+    let current = backend.get_raw_value(reg, key);
+    let outcome = match current {
+        Ok(ref current) if current.same_data(&desired) => CheckOutcome::Correct,
+        Err(e) if e.kind() != io::ErrorKind::NotFound => CheckOutcome::Failed(e.into()),
+        // The type change stays visible after the write
+        _ if write_settings => match (backend.set_raw_value(reg, key, &desired), current) {
+            (Ok(()), Ok(previous)) if previous.vtype != desired.vtype => CheckOutcome::Retyped { previous },
+            (Ok(()), _) => CheckOutcome::Written,
+            (Err(e), _) => CheckOutcome::Failed(e.into()),
+        },
+        Ok(current) if current.vtype != desired.vtype => CheckOutcome::WrongType {
+            current,
+            desired: desired.clone(),
+        },
+        Ok(current) => CheckOutcome::Mismatch {
+            current,
+            desired: desired.clone(),
+        },
+        Err(_) => CheckOutcome::Missing,
+    };
+    CheckResult {
+        hive: path.hive,
+        path: path.subkey.clone(),
+        name: key.to_string(),
+        value: Some(desired),
+        outcome,
     }
 }
 
-pub fn set_str_reg(reg: &RegKey, key: &str, val: &str, reg_path: &str, write_settings: bool) {
-    match reg.get_value(key) {
-        Err(_) => {
-            if write_settings {
-                match reg.set_value(key, &val) {
-                    Err(e) => println!("\x1b[0;91m{:?}\x1b[0m", e),
-                    Ok(()) => println!(
-                        "write reg key: \x1b[0;92m{}\\{} = sz:{}\x1b[0m",
-                        reg_path, key, val
-                    ),
-                }
-            } else {
-                println!(
-                    "setting missing: \x1b[0;93m{}\\{} = sz:{}\x1b[0m",
-                    reg_path, key, val
-                );
-            }
+pub fn set_u32_reg<B: RegistryBackend>(
+    backend: &B,
+    reg: &B::Key,
+    path: &RegPath,
+    key: &str,
+    val: &u32,
+    write_settings: bool,
+) -> CheckResult {
+    reconcile(backend, reg, path, key, RegValue::dword(*val), write_settings)
+}
+
+// Any value type
+pub fn set_raw_reg<B: RegistryBackend>(
+    backend: &B,
+    reg: &B::Key,
+    path: &RegPath,
+    key: &str,
+    val: RegValue,
+    write_settings: bool,
+) -> CheckResult {
+    reconcile(backend, reg, path, key, val, write_settings)
+}
+
+// The value has to be absent
+pub fn remove_value_reg<B: RegistryBackend>(
+    backend: &B,
+    reg: &B::Key,
+    path: &RegPath,
+    key: &str,
+    write_settings: bool,
+) -> CheckResult {
+    let outcome = match backend.get_raw_value(reg, key) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => CheckOutcome::Correct,
+        _ if write_settings => match backend.delete_value(reg, key) {
+            Ok(()) => CheckOutcome::Deleted,
+            Err(e) => CheckOutcome::Failed(e.into()),
+        },
+        _ => CheckOutcome::Present,
+    };
+    CheckResult {
+        hive: path.hive,
+        path: path.subkey.clone(),
+        name: key.to_string(),
+        value: None,
+        outcome,
+    }
+}
+
+// The key and everything below it has to be absent
+pub fn remove_key_reg<B: RegistryBackend>(
+    backend: &B,
+    root: &B::Key,
+    path: &RegPath,
+    write_settings: bool,
+) -> CheckResult {
+    let outcome = match backend.open_subkey(root, &path.subkey) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => CheckOutcome::Correct,
+        _ if write_settings => match delete_tree(backend, root, &path.subkey) {
+            Ok(()) => CheckOutcome::DeletedKey,
+            Err(e) => CheckOutcome::Failed(e.into()),
+        },
+        _ => CheckOutcome::Present,
+    };
+    CheckResult {
+        hive: path.hive,
+        path: path.subkey.clone(),
+        name: String::new(),
+        value: None,
+        outcome,
+    }
+}
+
+fn delete_tree<B: RegistryBackend>(backend: &B, root: &B::Key, path: &str) -> io::Result<()> {
+    let key = backend.open_subkey(root, path)?;
+    for sub in backend.enum_keys(&key)? {
+        delete_tree(backend, root, &format!("{}\\{}", path, sub))?;
+    }
+    backend.delete_subkey(root, path)
+}
+
+// Deletes the key with everything below it as part of the transaction
+pub fn delete_tree_transacted<B: RegistryBackend>(
+    backend: &B,
+    root: &B::Key,
+    path: &str,
+    transaction: &B::Transaction,
+) -> io::Result<()> {
+    let key = backend.open_subkey_transacted(root, path, transaction)?;
+    for sub in backend.enum_keys(&key)? {
+        delete_tree_transacted(backend, root, &format!("{}\\{}", path, sub), transaction)?;
+    }
+    backend.delete_subkey_transacted(root, path, transaction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::backend::{Hive, MemoryBackend, RegType};
+
+    const PATH: &str = "Software\\Test";
+
+    // A key of an open transaction, so the checks can write through it too
+    fn key_with(backend: &MemoryBackend, current: Option<&RegValue>) -> <MemoryBackend as RegistryBackend>::Key {
+        let transaction = backend.transaction().unwrap();
+        let key = backend
+            .create_subkey_transacted(&backend.predef(Hive::LocalMachine), PATH, &transaction)
+            .unwrap();
+        if let Some(current) = current {
+            backend.set_raw_value(&key, "Value", current).unwrap();
         }
-        Ok(reg_val) => {
-            let value: String = reg_val;
-            if value != val {
-                if write_settings {
-                    match reg.set_value(key, &val) {
-                        Err(e) => println!("\x1b[0;91m{:?}\x1b[0m", e),
-                        Ok(()) => println!(
-                            "write reg key: \x1b[0;92m{}\\{} = sz:{}\x1b[0m",
-                            reg_path, key, val
-                        ),
-                    }
-                } else {
-                    println!(
-                        "wrong setting: \x1b[0;93m{}\\{} = sz:{}\x1b[0m (your value: {})",
-                        reg_path, key, val, value
-                    );
-                }
-            } else {
-                println!("correct setting: {}\\{} = sz:{}", reg_path, key, val);
+        key
+    }
+
+    fn check(current: &RegValue, desired: RegValue, write_settings: bool) -> (CheckOutcome, RegValue) {
+        let backend = MemoryBackend::new();
+        let key = key_with(&backend, Some(current));
+        let path = RegPath::new(Hive::LocalMachine, PATH);
+        let result = set_raw_reg(&backend, &key, &path, "Value", desired, write_settings);
+        (result.outcome, backend.get_raw_value(&key, "Value").unwrap())
+    }
+
+    #[test]
+    fn a_string_where_a_dword_belongs_is_the_wrong_type() {
+        match check(&RegValue::sz("1"), RegValue::dword(1), false) {
+            (CheckOutcome::WrongType { current, desired }, _) => {
+                assert_eq!(current.vtype, RegType::REG_SZ);
+                assert_eq!(desired.vtype, RegType::REG_DWORD);
             }
+            _ => panic!("expected the wrong type"),
         }
     }
-}
 
-pub fn set_vac_reg(reg: &RegKey, key: &str, val: Vec<u8>, reg_path: &str, write_settings: bool) {
-    match reg.get_raw_value(key) {
-        Err(_) => {
-            if write_settings {
-                match reg.set_raw_value(
-                    key,
-                    &RegValue {
-                        vtype: REG_BINARY,
-                        bytes: val,
-                    },
-                ) {
-                    Err(e) => println!("\x1b[0;91m{:?}\x1b[0m", e),
-                    Ok(()) => println!("write reg key: \x1b[0;92m{}\\{}\x1b[0m", reg_path, key),
-                }
-            } else {
-                println!("setting missing: \x1b[0;93m{}\\{}\x1b[0m", reg_path, key);
-            }
+    #[test]
+    fn a_qword_is_not_a_dword_of_the_same_number() {
+        match check(&RegValue::qword(1), RegValue::dword(1), false).0 {
+            CheckOutcome::WrongType { current, .. } => assert_eq!(current.vtype, RegType::REG_QWORD),
+            _ => panic!("expected the wrong type"),
         }
-        Ok(reg_val) => {
-            let value: Vec<u8> = reg_val.bytes;
-            if value != val {
-                // *fixes += 1;
-                if write_settings {
-                    match reg.set_raw_value(
-                        key,
-                        &RegValue {
-                            vtype: REG_BINARY,
-                            bytes: val,
-                        },
-                    ) {
-                        Err(e) => println!("\x1b[0;91m{:?}\x1b[0m", e),
-                        Ok(()) => println!("write reg key: \x1b[0;92m{}\\{}\x1b[0m", reg_path, key),
-                    }
-                } else {
-                    println!("wrong setting: \x1b[0;93m{}\\{}\x1b[0m", reg_path, key);
-                }
-            } else {
-                println!("correct setting: {}\\{} = binary:{:?}", reg_path, key, val);
-            }
+        match check(&RegValue::dword(1), RegValue::qword(1), false).0 {
+            CheckOutcome::WrongType { current, .. } => assert_eq!(current.vtype, RegType::REG_DWORD),
+            _ => panic!("expected the wrong type"),
         }
     }
-}
 
-pub fn del_key_reg(reg: &RegKey, key: &str, reg_path: &str) {
-    match reg.delete_value(key) {
-        Err(e) => println!("\x1b[0;91m{:?}\x1b[0m", e),
-        Ok(()) => println!("deleted key: \x1b[0;93m{}\\{}\x1b[0m", reg_path, key),
+    #[test]
+    fn strings_compare_by_type_and_text() {
+        let expand = RegValue::expand_sz("%SystemRoot%\\x");
+        assert!(matches!(check(&expand, expand.clone(), false).0, CheckOutcome::Correct));
+        assert!(matches!(
+            check(&RegValue::sz("%SystemRoot%\\x"), expand.clone(), false).0,
+            CheckOutcome::WrongType { .. }
+        ));
+        assert!(matches!(
+            check(&RegValue::expand_sz("%SystemRoot%\\y"), expand, false).0,
+            CheckOutcome::Mismatch { .. }
+        ));
+
+        let list = |items: &[&str]| RegValue::multi_sz(&items.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+        assert!(matches!(check(&list(&["a", "b"]), list(&["a", "b"]), false).0, CheckOutcome::Correct));
+        assert!(matches!(check(&list(&["b", "a"]), list(&["a", "b"]), false).0, CheckOutcome::Mismatch { .. }));
+        assert!(matches!(check(&RegValue::sz("a"), list(&["a"]), false).0, CheckOutcome::WrongType { .. }));
+    }
+
+    #[test]
+    fn writing_over_another_type_reports_the_old_value() {
+        match check(&RegValue::sz("1"), RegValue::dword(1), true) {
+            (CheckOutcome::Retyped { previous }, written) => {
+                assert!(previous.same_data(&RegValue::sz("1")));
+                assert!(written.same_data(&RegValue::dword(1)));
+            }
+            _ => panic!("expected the old value"),
+        }
+        assert!(matches!(
+            check(&RegValue::dword(2), RegValue::dword(1), true).0,
+            CheckOutcome::Written
+        ));
+
+        let backend = MemoryBackend::new();
+        let key = key_with(&backend, None);
+        let path = RegPath::new(Hive::LocalMachine, PATH);
+        let result = set_u32_reg(&backend, &key, &path, "Value", &1, true);
+        assert!(matches!(result.outcome, CheckOutcome::Written));
     }
 }
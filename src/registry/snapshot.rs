@@ -0,0 +1,777 @@
+// Before anything is written the previous state of the value (or the fact that
+// it did not exist) is saved to a snapshot file, so a run can be undone exactly
+// instead of falling back to the Windows defaults from the catalog.
+
+use super::backend::{Hive, HiveMount, RegPath, RegType, RegValue, RegistryBackend};
+use super::catalog::parse_hex;
+use super::report::{CheckOutcome, CheckResult, Report};
+use super::set::{remove_value_reg, set_raw_reg};
+use crate::error::{self, Error};
+
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::env;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 2 records the target
+const VERSION: u32 = 2;
+
+// What a run wrote to, a snapshot is only restored to the same place
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotTarget {
+    // The hive files of --hive as KEY=file, empty for the live registry
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hives: Vec<String>,
+    // DOMAIN\user whose HKCU is the live one, None for hive files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    // SIDs of the users picked with --users
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<String>,
+}
+
+impl SnapshotTarget {
+    pub fn new(hives: &[HiveMount], users: &[String]) -> SnapshotTarget {
+        let mut mounted: Vec<String> = hives
+            .iter()
+            .map(|mount| {
+                let file = fs::canonicalize(&mount.file).unwrap_or_else(|_| mount.file.clone());
+                HiveMount { file, ..mount.clone() }.to_string()
+            })
+            .collect();
+        mounted.sort_by_key(|mount| mount.to_lowercase());
+        let account = match hives.is_empty() {
+            true => env::var("USERNAME").ok().map(|user| match env::var("USERDOMAIN") {
+                Ok(domain) => format!("{}\\{}", domain, user),
+                Err(_) => user,
+            }),
+            false => None,
+        };
+        let mut users: Vec<String> = users.iter().map(|sid| sid.to_uppercase()).collect();
+        users.sort();
+        SnapshotTarget {
+            hives: mounted,
+            account,
+            users,
+        }
+    }
+
+    // Windows paths, accounts and SIDs do not care about case
+    fn same(&self, other: &SnapshotTarget) -> bool {
+        let same_list = |a: &[String], b: &[String]| {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.eq_ignore_ascii_case(b))
+        };
+        same_list(&self.hives, &other.hives)
+            && same_list(&self.users, &other.users)
+            && match (&self.account, &other.account) {
+                (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+                (a, b) => a == b,
+            }
+    }
+}
+
+impl fmt::Display for SnapshotTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.hives.is_empty() {
+            true => f.write_str("the live registry")?,
+            false => write!(f, "the hives {}", self.hives.join(", "))?,
+        }
+        if let Some(account) = &self.account {
+            write!(f, " of {}", account)?;
+        }
+        if !self.users.is_empty() {
+            write!(f, " for the users {}", self.users.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SavedValue {
+    pub hive: String,
+    pub path: String,
+    pub name: String,
+    // None when the value did not exist
+    pub previous: Option<SavedData>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SavedData {
+    #[serde(rename = "type")]
+    pub vtype: u32,
+    pub data: String,
+}
+
+impl SavedData {
+    pub fn from_value(value: &RegValue) -> SavedData {
+        SavedData {
+            vtype: value.vtype as u32,
+            data: to_hex(&value.bytes),
+        }
+    }
+
+    pub fn to_value(&self) -> io::Result<RegValue> {
+        Ok(RegValue {
+            bytes: parse_hex(&self.data).map_err(invalid)?,
+            vtype: RegType::from_u32(self.vtype)
+                .ok_or_else(|| invalid(format!("unknown registry type {}", self.vtype)))?,
+        })
+    }
+}
+
+// A key that did not exist before and was created by a write
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SavedKey {
+    pub hive: String,
+    pub path: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SavedBcd {
+    pub setting: String,
+    // None when the element was not set
+    pub previous: Option<bool>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SavedPowerSetting {
+    pub subgroup: String,
+    pub setting: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile {
+    version: u32,
+    created: String,
+    target: SnapshotTarget,
+    #[serde(default)]
+    values: Vec<SavedValue>,
+    #[serde(default)]
+    keys: Vec<SavedKey>,
+    #[serde(default)]
+    bcd: Vec<SavedBcd>,
+    // The power scheme that was active before the gaming scheme
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    power_scheme: Option<String>,
+    // Power settings Windows hid before --unhide-power-settings showed them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    shown_power_settings: Vec<SavedPowerSetting>,
+}
+
+pub struct Snapshot {
+    path: RefCell<PathBuf>,
+    // Whether the file at `path` is ours, see claim()
+    claimed: Cell<bool>,
+    file: RefCell<SnapshotFile>,
+}
+
+impl Snapshot {
+    // Nothing is written to disk until the first value is recorded
+    pub fn create(dir: &Path, target: SnapshotTarget) -> Snapshot {
+        let (date, time, millis) = utc_now();
+        Snapshot {
+            path: RefCell::new(dir.join(format!("{}_{}.{:03}.json", date, time.replace(':', "-"), millis))),
+            claimed: Cell::new(false),
+            file: RefCell::new(SnapshotFile {
+                version: VERSION,
+                created: format!("{} {} UTC", date, time),
+                target,
+                values: Vec::new(),
+                keys: Vec::new(),
+                bcd: Vec::new(),
+                power_scheme: None,
+                shown_power_settings: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Snapshot> {
+        let text = fs::read_to_string(path)?;
+        let file: SnapshotFile = serde_json::from_str(&text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if file.version != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported snapshot version {}", file.version),
+            ));
+        }
+        Ok(Snapshot {
+            path: RefCell::new(path.to_path_buf()),
+            claimed: Cell::new(true),
+            file: RefCell::new(file),
+        })
+    }
+
+    // Newest first
+    pub fn list(dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut snapshots = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "json") {
+                snapshots.push(path);
+            }
+        }
+        snapshots.sort();
+        snapshots.reverse();
+        Ok(snapshots)
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.path.borrow().clone()
+    }
+
+    pub fn created(&self) -> String {
+        self.file.borrow().created.clone()
+    }
+
+    pub fn target(&self) -> SnapshotTarget {
+        self.file.borrow().target.clone()
+    }
+
+    // A snapshot of a hive file or of other users does not belong in this run
+    pub fn check_target(&self, target: &SnapshotTarget) -> error::Result<()> {
+        let file = self.file.borrow();
+        match file.target.same(target) {
+            true => Ok(()),
+            false => Err(Error::WrongTarget {
+                snapshot: file.target.to_string(),
+                run: target.to_string(),
+            }),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let file = self.file.borrow();
+        file.values.is_empty() && file.keys.is_empty() && file.bcd.is_empty()
+            && file.power_scheme.is_none()
+            && file.shown_power_settings.is_empty()
+    }
+
+    pub fn values(&self) -> Vec<SavedValue> {
+        self.file.borrow().values.clone()
+    }
+
+    pub fn keys(&self) -> Vec<SavedKey> {
+        self.file.borrow().keys.clone()
+    }
+
+    pub fn bcd(&self) -> Vec<SavedBcd> {
+        self.file.borrow().bcd.clone()
+    }
+
+    pub fn power_scheme(&self) -> Option<String> {
+        self.file.borrow().power_scheme.clone()
+    }
+
+    pub fn shown_power_settings(&self) -> Vec<SavedPowerSetting> {
+        self.file.borrow().shown_power_settings.clone()
+    }
+
+    // Only the first state of a value counts, later writes in the same run are ignored
+    pub fn record_value(&self, hive: Hive, path: &str, name: &str, previous: Option<&RegValue>) -> io::Result<()> {
+        let known = self.file.borrow().values.iter().any(|v| {
+            v.hive == hive.short_name() && v.path.eq_ignore_ascii_case(path) && v.name.eq_ignore_ascii_case(name)
+        });
+        if known {
+            return Ok(());
+        }
+        self.file.borrow_mut().values.push(SavedValue {
+            hive: hive.short_name().to_string(),
+            path: path.to_string(),
+            name: name.to_string(),
+            previous: previous.map(SavedData::from_value),
+        });
+        self.save()
+    }
+
+    pub fn record_key(&self, hive: Hive, path: &str) -> io::Result<()> {
+        let known = self
+            .file
+            .borrow()
+            .keys
+            .iter()
+            .any(|k| k.hive == hive.short_name() && k.path.eq_ignore_ascii_case(path));
+        if known {
+            return Ok(());
+        }
+        self.file.borrow_mut().keys.push(SavedKey {
+            hive: hive.short_name().to_string(),
+            path: path.to_string(),
+        });
+        self.save()
+    }
+
+    // The run changed nothing after all, e.g. its transaction was rolled back
+    pub fn discard(&self) -> io::Result<()> {
+        let mut file = self.file.borrow_mut();
+        file.values.clear();
+        file.keys.clear();
+        file.bcd.clear();
+        file.power_scheme = None;
+        file.shown_power_settings.clear();
+        self.claimed.set(false);
+        match fs::remove_file(&*self.path.borrow()) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn record_bcd(&self, setting: &str, previous: Option<bool>) -> io::Result<()> {
+        if self.file.borrow().bcd.iter().any(|b| b.setting == setting) {
+            return Ok(());
+        }
+        self.file.borrow_mut().bcd.push(SavedBcd {
+            setting: setting.to_string(),
+            previous,
+        });
+        self.save()
+    }
+
+    pub fn record_power_scheme(&self, previous: &str) -> io::Result<()> {
+        if self.file.borrow().power_scheme.is_some() {
+            return Ok(());
+        }
+        self.file.borrow_mut().power_scheme = Some(previous.to_string());
+        self.save()
+    }
+
+    pub fn record_shown_power_setting(&self, subgroup: &str, setting: &str) -> io::Result<()> {
+        let known = self.file.borrow().shown_power_settings.iter().any(|s| {
+            s.subgroup.eq_ignore_ascii_case(subgroup) && s.setting.eq_ignore_ascii_case(setting)
+        });
+        if known {
+            return Ok(());
+        }
+        self.file.borrow_mut().shown_power_settings.push(SavedPowerSetting {
+            subgroup: subgroup.to_string(),
+            setting: setting.to_string(),
+        });
+        self.save()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let text = serde_json::to_string_pretty(&*self.file.borrow())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if !self.claimed.get() {
+            self.claim()?;
+        }
+        fs::write(&*self.path.borrow(), text)
+    }
+
+    // Creates the file before the first save, so two runs never share one. A
+    // name that is taken gets a counter.
+    fn claim(&self) -> io::Result<()> {
+        let first = self.path.borrow().clone();
+        let dir = first.parent().unwrap_or_else(|| Path::new(""));
+        fs::create_dir_all(dir)?;
+        let stem = first.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        for n in 0.. {
+            let path = match n {
+                0 => first.clone(),
+                _ => dir.join(format!("{}_{:03}.json", stem, n)),
+            };
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => {
+                    *self.path.borrow_mut() = path;
+                    self.claimed.set(true);
+                    return Ok(());
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        unreachable!()
+    }
+}
+
+// The scheme that was active before the gaming scheme, from the newest snapshot that knows it
+pub fn last_power_scheme(dir: &Path) -> Option<String> {
+    Snapshot::list(dir)
+        .ok()?
+        .iter()
+        .filter_map(|path| Snapshot::load(path).ok())
+        .find_map(|snapshot| snapshot.power_scheme())
+}
+
+// %LOCALAPPDATA%\gaming-optimizer\snapshots, or next to the working directory
+#[cfg(windows)]
+pub fn snapshot_dir() -> PathBuf {
+    match env::var_os("LOCALAPPDATA") {
+        Some(dir) => PathBuf::from(dir).join("gaming-optimizer").join("snapshots"),
+        None => PathBuf::from("snapshots"),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(",")
+}
+
+// ("2021-06-01", "18:04:59", 123) without pulling in a date crate
+fn utc_now() -> (String, String, u32) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = now.as_secs();
+    let (days, rest) = ((secs / 86400) as i64, secs % 86400);
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (
+        format!("{:04}-{:02}-{:02}", year, month, day),
+        format!("{:02}:{:02}:{:02}", rest / 3600, rest % 3600 / 60, rest % 60),
+        now.subsec_millis(),
+    )
+}
+
+// Wraps a backend and records the previous state of every value before it is
+// written or deleted, and every key before it is created.
+pub struct SnapshotBackend<'a, B: RegistryBackend> {
+    inner: &'a B,
+    snapshot: &'a Snapshot,
+}
+
+pub struct TrackedKey<K> {
+    key: K,
+    hive: Hive,
+    path: String,
+}
+
+impl<'a, B: RegistryBackend> SnapshotBackend<'a, B> {
+    pub fn new(inner: &'a B, snapshot: &'a Snapshot) -> SnapshotBackend<'a, B> {
+        SnapshotBackend { inner, snapshot }
+    }
+
+    fn track(&self, parent: &TrackedKey<B::Key>, path: &str, key: B::Key) -> TrackedKey<B::Key> {
+        TrackedKey {
+            key,
+            hive: parent.hive,
+            path: join(&parent.path, path),
+        }
+    }
+
+    // Records every level of `path` below `key` that does not exist yet
+    fn record_missing_keys(&self, key: &TrackedKey<B::Key>, path: &str) -> io::Result<()> {
+        let mut sub = String::new();
+        for part in path.split('\\').filter(|p| !p.is_empty()) {
+            sub = join(&sub, part);
+            if self.inner.open_subkey(&key.key, &sub).is_err() {
+                self.snapshot.record_key(key.hive, &join(&key.path, &sub))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn record_value(&self, key: &TrackedKey<B::Key>, name: &str) -> io::Result<()> {
+        let previous = match self.inner.get_raw_value(&key.key, name) {
+            Ok(value) => Some(value),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        self.snapshot.record_value(key.hive, &key.path, name, previous.as_ref())
+    }
+
+    // Every value of the subkey `path` that is about to be deleted
+    fn record_values(&self, key: &TrackedKey<B::Key>, path: &str, sub: B::Key) -> io::Result<()> {
+        let sub_path = join(&key.path, path);
+        for (name, value) in self.inner.enum_values(&sub)? {
+            self.snapshot.record_value(key.hive, &sub_path, &name, Some(&value))?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, B: RegistryBackend> RegistryBackend for SnapshotBackend<'a, B> {
+    type Key = TrackedKey<B::Key>;
+    type Transaction = B::Transaction;
+
+    fn predef(&self, hive: Hive) -> TrackedKey<B::Key> {
+        TrackedKey {
+            key: self.inner.predef(hive),
+            hive,
+            path: String::new(),
+        }
+    }
+
+    fn open_subkey(&self, key: &TrackedKey<B::Key>, path: &str) -> io::Result<TrackedKey<B::Key>> {
+        let sub = self.inner.open_subkey(&key.key, path)?;
+        Ok(self.track(key, path, sub))
+    }
+
+    fn create_subkey_transacted(
+        &self,
+        key: &TrackedKey<B::Key>,
+        path: &str,
+        transaction: &B::Transaction,
+    ) -> io::Result<TrackedKey<B::Key>> {
+        self.record_missing_keys(key, path)?;
+        let sub = self.inner.create_subkey_transacted(&key.key, path, transaction)?;
+        Ok(self.track(key, path, sub))
+    }
+
+    fn open_subkey_transacted(
+        &self,
+        key: &TrackedKey<B::Key>,
+        path: &str,
+        transaction: &B::Transaction,
+    ) -> io::Result<TrackedKey<B::Key>> {
+        let sub = self.inner.open_subkey_transacted(&key.key, path, transaction)?;
+        Ok(self.track(key, path, sub))
+    }
+
+    fn delete_subkey(&self, key: &TrackedKey<B::Key>, path: &str) -> io::Result<()> {
+        self.record_values(key, path, self.inner.open_subkey(&key.key, path)?)?;
+        self.inner.delete_subkey(&key.key, path)
+    }
+
+    fn delete_subkey_transacted(
+        &self,
+        key: &TrackedKey<B::Key>,
+        path: &str,
+        transaction: &B::Transaction,
+    ) -> io::Result<()> {
+        let sub = self.inner.open_subkey_transacted(&key.key, path, transaction)?;
+        self.record_values(key, path, sub)?;
+        self.inner.delete_subkey_transacted(&key.key, path, transaction)
+    }
+
+    fn get_raw_value(&self, key: &TrackedKey<B::Key>, name: &str) -> io::Result<RegValue> {
+        self.inner.get_raw_value(&key.key, name)
+    }
+
+    fn set_raw_value(&self, key: &TrackedKey<B::Key>, name: &str, value: &RegValue) -> io::Result<()> {
+        self.record_value(key, name)?;
+        self.inner.set_raw_value(&key.key, name, value)
+    }
+
+    fn delete_value(&self, key: &TrackedKey<B::Key>, name: &str) -> io::Result<()> {
+        self.record_value(key, name)?;
+        self.inner.delete_value(&key.key, name)
+    }
+
+    fn enum_keys(&self, key: &TrackedKey<B::Key>) -> io::Result<Vec<String>> {
+        self.inner.enum_keys(&key.key)
+    }
+
+    fn enum_values(&self, key: &TrackedKey<B::Key>) -> io::Result<Vec<(String, RegValue)>> {
+        self.inner.enum_values(&key.key)
+    }
+
+    fn transaction(&self) -> io::Result<B::Transaction> {
+        self.inner.transaction()
+    }
+
+    fn commit(&self, transaction: &B::Transaction) -> io::Result<()> {
+        self.inner.commit(transaction)
+    }
+
+    fn rollback(&self, transaction: &B::Transaction) -> io::Result<()> {
+        self.inner.rollback(transaction)
+    }
+}
+
+fn join(parent: &str, path: &str) -> String {
+    let path = path.trim_matches('\\');
+    if parent.is_empty() {
+        path.to_string()
+    } else {
+        format!("{}\\{}", parent, path)
+    }
+}
+
+// Puts every recorded value back the way it was, values that did not exist are
+// deleted again and keys created by the run are removed once they are empty.
+// Nothing is touched when the run targets other hives or users than the snapshot.
+pub fn restore_snapshot<B: RegistryBackend>(
+    backend: &B,
+    snapshot: &Snapshot,
+    target: &SnapshotTarget,
+) -> error::Result<Report> {
+    snapshot.check_target(target)?;
+    let mut report = Report::default();
+    let transaction = backend.transaction()?;
+
+    for saved in snapshot.values().iter() {
+        let hive = saved.hive.parse::<Hive>().map_err(invalid)?;
+        let previous = match &saved.previous {
+            Some(data) => Some(data.to_value()?),
+            None => None,
+        };
+        let location = &RegPath::new(hive, &saved.path);
+
+        let key = match backend.create_subkey_transacted(&backend.predef(hive), &saved.path, &transaction) {
+            Ok(key) => key,
+            Err(e) => {
+                report.push(failed(location, &saved.name, e));
+                continue;
+            }
+        };
+        report.push(match previous {
+            Some(value) => set_raw_reg(backend, &key, location, &saved.name, value, true),
+            None => remove_value_reg(backend, &key, location, &saved.name, true),
+        });
+    }
+    backend.commit(&transaction)?;
+
+    // Deepest first, a key that got other content in the meantime is kept
+    let mut keys = snapshot.keys();
+    keys.sort_by_key(|k| std::cmp::Reverse(k.path.matches('\\').count()));
+    for saved in keys.iter() {
+        let hive = saved.hive.parse::<Hive>().map_err(invalid)?;
+        let root = backend.predef(hive);
+        let key = match backend.open_subkey(&root, &saved.path) {
+            Ok(key) => key,
+            Err(_) => continue,
+        };
+        if backend.enum_keys(&key)?.is_empty() && backend.enum_values(&key)?.is_empty() {
+            if let Err(e) = backend.delete_subkey(&root, &saved.path) {
+                println!("\x1b[0;93mcould not delete key {}\\{}: {}\x1b[0m", hive, saved.path, e);
+            }
+        } else {
+            println!("\x1b[0;93mkeeping key {}\\{}, it is not empty\x1b[0m", hive, saved.path);
+        }
+    }
+
+    Ok(report)
+}
+
+fn failed(path: &RegPath, name: &str, e: io::Error) -> CheckResult {
+    CheckResult {
+        hive: path.hive,
+        path: path.subkey.clone(),
+        name: name.to_string(),
+        value: None,
+        outcome: CheckOutcome::Failed(e.into()),
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::backend::MemoryBackend;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("gaming-optimizer-snapshots-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn hive_target(file: &str) -> SnapshotTarget {
+        SnapshotTarget::new(&[file.parse::<HiveMount>().unwrap()], &[])
+    }
+
+    fn speed(backend: &MemoryBackend) -> Option<u32> {
+        let key = backend.open_subkey(&backend.predef(Hive::LocalMachine), "SOFTWARE\\Test").ok()?;
+        backend.get_u32(&key, "Speed").ok()
+    }
+
+    // Writes Speed through a SnapshotBackend, so the snapshot has the old value
+    fn write_speed(backend: &MemoryBackend, snapshot: &Snapshot, value: u32) {
+        let tracked = SnapshotBackend::new(backend, snapshot);
+        let transaction = tracked.transaction().unwrap();
+        let key = tracked
+            .create_subkey_transacted(&tracked.predef(Hive::LocalMachine), "SOFTWARE\\Test", &transaction)
+            .unwrap();
+        tracked.set_raw_value(&key, "Speed", &RegValue::dword(value)).unwrap();
+        tracked.commit(&transaction).unwrap();
+    }
+
+    #[test]
+    fn snapshots_of_the_same_moment_get_their_own_file() {
+        let dir = temp_dir("unique");
+        let snapshots: Vec<Snapshot> = (0..3).map(|_| Snapshot::create(&dir, SnapshotTarget::default())).collect();
+        // The same name as long as nothing is saved
+        for snapshot in snapshots.iter() {
+            snapshot.record_bcd("useplatformclock", None).unwrap();
+        }
+        let mut paths: Vec<PathBuf> = snapshots.iter().map(Snapshot::path).collect();
+        paths.sort();
+        paths.dedup();
+        assert_eq!(paths.len(), 3);
+        assert_eq!(Snapshot::list(&dir).unwrap().len(), 3);
+        for path in paths.iter() {
+            assert_eq!(Snapshot::load(path).unwrap().bcd().len(), 1);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_target_is_saved_with_the_snapshot() {
+        let dir = temp_dir("target");
+        let target = SnapshotTarget::new(&[], &["s-1-5-21-1000".to_string()]);
+        let snapshot = Snapshot::create(&dir, target.clone());
+        snapshot.record_key(Hive::Users, "S-1-5-21-1000\\Software\\Test").unwrap();
+        let loaded = Snapshot::load(&snapshot.path()).unwrap();
+        assert_eq!(loaded.target(), target);
+        assert_eq!(loaded.target().users, vec!["S-1-5-21-1000"]);
+        assert!(loaded.check_target(&SnapshotTarget::new(&[], &["S-1-5-21-1000".to_string()])).is_ok());
+        assert!(loaded.check_target(&SnapshotTarget::new(&[], &[])).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_snapshot_is_only_restored_to_its_target() {
+        let dir = temp_dir("restore");
+        let backend = MemoryBackend::new();
+        let target = hive_target("HKLM\\SOFTWARE=C:\\images\\a\\SOFTWARE");
+        let snapshot = Snapshot::create(&dir, target.clone());
+        write_speed(&backend, &snapshot, 1);
+
+        for other in [
+            SnapshotTarget::default(),
+            hive_target("HKLM\\SOFTWARE=C:\\images\\b\\SOFTWARE"),
+            SnapshotTarget::new(&["HKLM\\SOFTWARE=C:\\images\\a\\SOFTWARE".parse().unwrap()], &["S-1-5-21-1000".to_string()]),
+        ] {
+            let refused = restore_snapshot(&backend, &snapshot, &other);
+            assert!(matches!(refused, Err(Error::WrongTarget { .. })), "{}", other);
+            assert_eq!(speed(&backend), Some(1));
+        }
+
+        let report = restore_snapshot(&backend, &snapshot, &hive_target("hklm\\software=c:\\IMAGES\\a\\software")).unwrap();
+        assert_eq!(report.failed(), 0);
+        assert_eq!(speed(&backend), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_power_scheme_comes_from_the_newest_snapshot_that_knows_it() {
+        let dir = temp_dir("power");
+        let older = Snapshot::create(&dir, SnapshotTarget::default());
+        assert!(older.is_empty());
+        older.record_power_scheme("381b4222-f694-41f0-9685-ff5bb260df2e").unwrap();
+        // Only the scheme active before the first switch counts
+        older.record_power_scheme("5d2a9c8e-7b31-4f06-a4e5-3c9b1f0d6e72").unwrap();
+        older.record_shown_power_setting("sub", "setting").unwrap();
+        older.record_shown_power_setting("SUB", "SETTING").unwrap();
+        assert!(!older.is_empty());
+        assert!(older.created().ends_with(" UTC"));
+        let loaded = Snapshot::load(&older.path()).unwrap();
+        assert_eq!(loaded.power_scheme().as_deref(), Some("381b4222-f694-41f0-9685-ff5bb260df2e"));
+        assert_eq!(loaded.shown_power_settings().len(), 1);
+
+        // A newer snapshot without a scheme does not hide it
+        let newer = Snapshot::create(&dir, SnapshotTarget::default());
+        newer.record_bcd("useplatformclock", Some(true)).unwrap();
+        assert_eq!(Snapshot::list(&dir).unwrap().len(), 2);
+        assert_eq!(last_power_scheme(&dir).as_deref(), Some("381b4222-f694-41f0-9685-ff5bb260df2e"));
+
+        // A discarded snapshot is gone
+        older.discard().unwrap();
+        assert!(older.is_empty());
+        assert_eq!(Snapshot::list(&dir).unwrap(), vec![newer.path()]);
+        assert_eq!(last_power_scheme(&dir), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
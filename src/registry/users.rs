@@ -0,0 +1,298 @@
+// The HKCU tweaks (mouse curves, GameBar, GameDVR, startup delay) only reach the
+// account running the tool, which is often an administrator and not the one
+// who plays. With --users they go to HKU\<SID> of the picked profiles instead,
+// the NTUSER.DAT of a user who is not signed in can be loaded for the run.
+
+use super::backend::{Hive, RegistryBackend};
+use crate::error;
+
+use std::env;
+use std::io;
+use std::path::PathBuf;
+
+const PROFILE_LIST: &str = "SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion\\ProfileList";
+
+pub struct UserProfile {
+    pub sid: String,
+    // The profile folder, usually the user name, the SID when there is none
+    pub name: String,
+    pub hive_file: Option<PathBuf>,
+    // The hive is in HKEY_USERS, the user is signed in or it was loaded
+    pub loaded: bool,
+}
+
+// Local and domain accounts, the service accounts (S-1-5-18 to 20) are left out
+fn is_user_sid(sid: &str) -> bool {
+    sid.starts_with("S-1-5-21-") && !sid.ends_with("_Classes")
+}
+
+// The profiles of the profile list and every user hive in HKEY_USERS, by name
+pub fn user_profiles<B: RegistryBackend>(backend: &B) -> error::Result<Vec<UserProfile>> {
+    let loaded: Vec<String> = backend
+        .enum_keys(&backend.predef(Hive::Users))?
+        .into_iter()
+        .filter(|sid| is_user_sid(sid))
+        .collect();
+    let is_loaded = |sid: &str| loaded.iter().any(|l| l.eq_ignore_ascii_case(sid));
+
+    let mut profiles = Vec::new();
+    match backend.open_subkey(&backend.predef(Hive::LocalMachine), PROFILE_LIST) {
+        Ok(list) => {
+            for sid in backend.enum_keys(&list)?.into_iter().filter(|sid| is_user_sid(sid)) {
+                // Split by hand, the path is a Windows one even when a hive file is read elsewhere
+                let image = backend
+                    .open_subkey(&list, &sid)
+                    .and_then(|key| backend.get_string(&key, "ProfileImagePath"))
+                    .ok()
+                    .map(|path| expand(&path));
+                profiles.push(UserProfile {
+                    name: image
+                        .as_deref()
+                        .and_then(|path| path.trim_end_matches('\\').rsplit('\\').next())
+                        .filter(|name| !name.is_empty())
+                        .map_or_else(|| sid.clone(), String::from),
+                    hive_file: image.map(|path| PathBuf::from(format!("{}\\NTUSER.DAT", path.trim_end_matches('\\')))),
+                    loaded: is_loaded(&sid),
+                    sid,
+                });
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    for sid in loaded.iter() {
+        if !profiles.iter().any(|p: &UserProfile| p.sid.eq_ignore_ascii_case(sid)) {
+            profiles.push(UserProfile {
+                sid: sid.clone(),
+                name: sid.clone(),
+                hive_file: None,
+                loaded: true,
+            });
+        }
+    }
+    profiles.sort_by_key(|p| p.name.to_lowercase());
+    Ok(profiles)
+}
+
+// `all` or user names and SIDs, an unknown one is an error
+pub fn pick_users(profiles: Vec<UserProfile>, names: &[String]) -> Result<Vec<UserProfile>, String> {
+    if names.iter().any(|n| n.eq_ignore_ascii_case("all")) {
+        return Ok(profiles);
+    }
+    let matches = |p: &UserProfile, name: &str| p.sid.eq_ignore_ascii_case(name) || p.name.eq_ignore_ascii_case(name);
+    if let Some(unknown) = names.iter().find(|name| !profiles.iter().any(|p| matches(p, name))) {
+        let known: Vec<&str> = profiles.iter().map(|p| p.name.as_str()).collect();
+        return Err(format!("unknown user `{}`, the profiles are {}", unknown, known.join(", ")));
+    }
+    Ok(profiles
+        .into_iter()
+        .filter(|p| names.iter().any(|name| matches(p, name)))
+        .collect())
+}
+
+// %SystemDrive%\Users\Player. A name is only a variable with a percent sign on
+// both sides, unknown ones are kept.
+fn expand(path: &str) -> String {
+    let parts: Vec<&str> = path.split('%').collect();
+    let mut expanded = parts[0].to_string();
+    let mut i = 1;
+    while i < parts.len() {
+        match env::var(parts[i]) {
+            Ok(value) if i + 1 < parts.len() => {
+                expanded.push_str(&value);
+                expanded.push_str(parts[i + 1]);
+                i += 2;
+            }
+            _ => {
+                expanded.push('%');
+                expanded.push_str(parts[i]);
+                i += 1;
+            }
+        }
+    }
+    expanded
+}
+
+// A user hive loaded into HKEY_USERS for the run. `unload` reports whether it
+// could be unloaded, one that is dropped without it is unloaded quietly.
+#[cfg(windows)]
+pub struct LoadedHive {
+    sid: Vec<u16>,
+    unloaded: bool,
+}
+
+#[cfg(windows)]
+impl LoadedHive {
+    // Needs administrator rights, the backup and restore privileges are enabled for it
+    pub fn load(profile: &UserProfile) -> io::Result<LoadedHive> {
+        use winapi::um::winreg::{RegLoadKeyW, HKEY_USERS};
+
+        let file = profile
+            .hive_file
+            .as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the profile has no NTUSER.DAT"))?;
+        enable_privilege("SeBackupPrivilege")?;
+        enable_privilege("SeRestorePrivilege")?;
+        let sid = wide(profile.sid.as_ref());
+        let status = unsafe { RegLoadKeyW(HKEY_USERS, sid.as_ptr(), wide(file.as_os_str()).as_ptr()) };
+        if status != 0 {
+            return Err(io::Error::from_raw_os_error(status));
+        }
+        Ok(LoadedHive { sid, unloaded: false })
+    }
+
+    pub fn unload(mut self) -> io::Result<()> {
+        use winapi::um::winreg::{RegUnLoadKeyW, HKEY_USERS};
+
+        self.unloaded = true;
+        let status = unsafe { RegUnLoadKeyW(HKEY_USERS, self.sid.as_ptr()) };
+        if status == 0 {
+            return Ok(());
+        }
+        let e = io::Error::from_raw_os_error(status);
+        let sid = String::from_utf16_lossy(&self.sid[..self.sid.len() - 1]);
+        Err(io::Error::new(e.kind(), format!("could not unload HKEY_USERS\\{}: {}", sid, e)))
+    }
+}
+
+#[cfg(windows)]
+impl Drop for LoadedHive {
+    fn drop(&mut self) {
+        use winapi::um::winreg::{RegUnLoadKeyW, HKEY_USERS};
+
+        if !self.unloaded {
+            unsafe { RegUnLoadKeyW(HKEY_USERS, self.sid.as_ptr()) };
+        }
+    }
+}
+
+#[cfg(windows)]
+fn wide(s: &std::ffi::OsStr) -> Vec<u16> {
+    use std::os::windows::ffi::OsStrExt;
+    s.encode_wide().chain(Some(0)).collect()
+}
+
+#[cfg(windows)]
+fn enable_privilege(name: &str) -> io::Result<()> {
+    use std::ptr;
+    use winapi::shared::winerror::ERROR_NOT_ALL_ASSIGNED;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcessToken};
+    use winapi::um::securitybaseapi::AdjustTokenPrivileges;
+    use winapi::um::winbase::LookupPrivilegeValueW;
+    use winapi::um::winnt::{SE_PRIVILEGE_ENABLED, TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES, TOKEN_QUERY};
+
+    unsafe {
+        let mut token = ptr::null_mut();
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY, &mut token) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut privileges: TOKEN_PRIVILEGES = std::mem::zeroed();
+        privileges.PrivilegeCount = 1;
+        privileges.Privileges[0].Attributes = SE_PRIVILEGE_ENABLED;
+        let name = wide(name.as_ref());
+        let result = if LookupPrivilegeValueW(ptr::null(), name.as_ptr(), &mut privileges.Privileges[0].Luid) == 0
+            || AdjustTokenPrivileges(token, 0, &mut privileges, 0, ptr::null_mut(), ptr::null_mut()) == 0
+        {
+            Err(io::Error::last_os_error())
+        } else {
+            // Succeeds without the privilege as well, the last error tells
+            match io::Error::last_os_error() {
+                e if e.raw_os_error() == Some(ERROR_NOT_ALL_ASSIGNED as i32) => {
+                    Err(io::Error::new(io::ErrorKind::PermissionDenied, e))
+                }
+                _ => Ok(()),
+            }
+        };
+        CloseHandle(token);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::backend::{MemoryBackend, RegValue};
+
+    const PLAYER: &str = "S-1-5-21-1-1001";
+    const NO_FOLDER: &str = "S-1-5-21-1-1002";
+    const SIGNED_IN: &str = "S-1-5-21-1-1003";
+
+    fn key(backend: &MemoryBackend, hive: Hive, path: &str, image: Option<&str>) {
+        let transaction = backend.transaction().unwrap();
+        let key = backend
+            .create_subkey_transacted(&backend.predef(hive), path, &transaction)
+            .unwrap();
+        if let Some(image) = image {
+            backend
+                .set_raw_value(&key, "ProfileImagePath", &RegValue::expand_sz(image))
+                .unwrap();
+        }
+        backend.commit(&transaction).unwrap();
+    }
+
+    // Two profiles of the profile list, one of them signed in, and a hive that
+    // was loaded without being in the list
+    fn machine() -> MemoryBackend {
+        let backend = MemoryBackend::new();
+        let profile = |sid: &str| format!("{}\\{}", PROFILE_LIST, sid);
+        key(&backend, Hive::LocalMachine, &profile("S-1-5-18"), Some("%systemroot%\\system32\\config\\systemprofile"));
+        key(&backend, Hive::LocalMachine, &profile(PLAYER), Some("C:\\Users\\Player\\"));
+        key(&backend, Hive::LocalMachine, &profile(NO_FOLDER), None);
+        for sid in [".DEFAULT", "S-1-5-18", PLAYER, &format!("{}_Classes", PLAYER), SIGNED_IN].iter() {
+            key(&backend, Hive::Users, sid, None);
+        }
+        backend
+    }
+
+    #[test]
+    fn profiles_of_the_list_and_of_hkey_users() {
+        let profiles = user_profiles(&machine()).unwrap();
+        let found: Vec<(&str, &str, bool)> = profiles
+            .iter()
+            .map(|p| (p.sid.as_str(), p.name.as_str(), p.loaded))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("S-1-5-21-1-1001", "Player", true),
+                (NO_FOLDER, NO_FOLDER, false),
+                (SIGNED_IN, SIGNED_IN, true),
+            ]
+        );
+        assert_eq!(profiles[0].hive_file, Some(PathBuf::from("C:\\Users\\Player\\NTUSER.DAT")));
+        assert_eq!(profiles[1].hive_file, None);
+        assert_eq!(profiles[2].hive_file, None);
+
+        // Without a profile list only the signed in users are known
+        let backend = MemoryBackend::new();
+        key(&backend, Hive::Users, SIGNED_IN, None);
+        let sids: Vec<String> = user_profiles(&backend).unwrap().into_iter().map(|p| p.sid).collect();
+        assert_eq!(sids, vec![SIGNED_IN]);
+    }
+
+    #[test]
+    fn users_are_picked_by_name_or_sid() {
+        let sids = |names: &[&str]| -> Result<Vec<String>, String> {
+            let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+            let picked = pick_users(user_profiles(&machine()).unwrap(), &names)?;
+            Ok(picked.into_iter().map(|p| p.sid).collect())
+        };
+        assert_eq!(sids(&["player", SIGNED_IN]).unwrap(), vec![PLAYER, SIGNED_IN]);
+        assert_eq!(sids(&["ALL"]).unwrap().len(), 3);
+        assert_eq!(
+            sids(&["Player", "guest"]).unwrap_err(),
+            format!("unknown user `guest`, the profiles are Player, {}, {}", NO_FOLDER, SIGNED_IN)
+        );
+    }
+
+    #[test]
+    fn variables_of_the_profile_path_are_expanded() {
+        env::set_var("GAMING_OPTIMIZER_TEST_DRIVE", "D:");
+        assert_eq!(expand("%GAMING_OPTIMIZER_TEST_DRIVE%\\Users\\Player"), "D:\\Users\\Player");
+        assert_eq!(expand("C:\\Users\\Player"), "C:\\Users\\Player");
+        // An unknown variable and a lone percent sign stay as they are
+        assert_eq!(expand("%GAMING_OPTIMIZER_NOT_SET%\\x"), "%GAMING_OPTIMIZER_NOT_SET%\\x");
+        assert_eq!(expand("C:\\100%\\x"), "C:\\100%\\x");
+    }
+}
@@ -0,0 +1,219 @@
+// Which tweaks a run touches. Every tweak has a stable id and a category, the
+// command line (--only/--skip) and the menu pick tweaks by either of them.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Network,
+    Input,
+    Scheduler,
+    Gamebar,
+    Gpu,
+    Audio,
+    Power,
+    Boot,
+}
+
+impl Category {
+    pub const ALL: [Category; 8] = [
+        Category::Network,
+        Category::Input,
+        Category::Scheduler,
+        Category::Gamebar,
+        Category::Gpu,
+        Category::Audio,
+        Category::Power,
+        Category::Boot,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Network => "network",
+            Category::Input => "input",
+            Category::Scheduler => "scheduler",
+            Category::Gamebar => "gamebar",
+            Category::Gpu => "gpu",
+            Category::Audio => "audio",
+            Category::Power => "power",
+            Category::Boot => "boot",
+        }
+    }
+}
+
+impl FromStr for Category {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Category, String> {
+        Category::ALL
+            .iter()
+            .copied()
+            .find(|c| c.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown category `{}`, expected {}", s, category_names()))
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+pub fn category_names() -> String {
+    let names: Vec<&str> = Category::ALL.iter().map(|c| c.name()).collect();
+    names.join(", ")
+}
+
+// How likely a tweak breaks something, high risk tweaks are not preselected in the menu
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Risk {
+    Low,
+    Medium,
+    High,
+}
+
+impl FromStr for Risk {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Risk, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "low" => Ok(Risk::Low),
+            "medium" => Ok(Risk::Medium),
+            "high" => Ok(Risk::High),
+            _ => Err(format!("unknown risk `{}`, expected low, medium or high", s)),
+        }
+    }
+}
+
+impl fmt::Display for Risk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Risk::Low => "low",
+            Risk::Medium => "medium",
+            Risk::High => "high",
+        })
+    }
+}
+
+// A tweak as `list` and the menu show it. Values imported from a .reg file have
+// no category, their id is the full registry path.
+pub struct TweakInfo {
+    pub id: String,
+    pub category: Option<Category>,
+    pub risk: Risk,
+    pub description: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Selection {
+    only: Vec<String>,
+    skip: Vec<String>,
+}
+
+impl Selection {
+    // Each name is a category or a tweak id
+    pub fn new(only: Vec<String>, skip: Vec<String>) -> Selection {
+        Selection { only, skip }
+    }
+
+    // Exactly these tweaks, e.g. what was picked in the menu
+    pub fn ids(ids: Vec<String>) -> Selection {
+        Selection {
+            only: ids,
+            skip: Vec::new(),
+        }
+    }
+
+    // The same selection with these names skipped as well
+    pub fn without(mut self, names: Vec<String>) -> Selection {
+        self.skip.extend(names);
+        self
+    }
+
+    pub fn includes(&self, id: &str, category: Option<Category>) -> bool {
+        let matches = |name: &String| {
+            name.eq_ignore_ascii_case(id) || category.is_some_and(|c| c.name().eq_ignore_ascii_case(name))
+        };
+        (self.only.is_empty() || self.only.iter().any(matches)) && !self.skip.iter().any(matches)
+    }
+
+    pub fn selects(&self, tweak: &TweakInfo) -> bool {
+        self.includes(&tweak.id, tweak.category)
+    }
+
+    // Names that are neither a category nor the id of one of the tweaks
+    pub fn unknown<'a>(&'a self, tweaks: &[TweakInfo]) -> Vec<&'a str> {
+        self.only
+            .iter()
+            .chain(self.skip.iter())
+            .filter(|name| name.parse::<Category>().is_err())
+            .filter(|name| !tweaks.iter().any(|t| t.id.eq_ignore_ascii_case(name)))
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn tweak(id: &str, category: Option<Category>) -> TweakInfo {
+        TweakInfo {
+            id: id.to_string(),
+            category,
+            risk: Risk::Low,
+            description: String::new(),
+        }
+    }
+
+    #[test]
+    fn tweaks_are_picked_by_id_or_category() {
+        let all = Selection::default();
+        assert!(all.includes("mouse-acceleration", Some(Category::Input)));
+        assert!(all.includes("HKEY_CURRENT_USER\\Software\\Test\\Speed", None));
+
+        let only = Selection::new(names(&["Input", "bcd-useplatformclock"]), Vec::new());
+        assert!(only.includes("mouse-acceleration", Some(Category::Input)));
+        assert!(only.includes("BCD-UsePlatformClock", Some(Category::Boot)));
+        assert!(!only.includes("bcd-disabledynamictick", Some(Category::Boot)));
+        // A value of a .reg file has no category to be picked by
+        assert!(!only.includes("HKEY_CURRENT_USER\\Software\\Test\\Speed", None));
+
+        let skip = Selection::new(Vec::new(), names(&["boot", "mouse-acceleration"]));
+        assert!(!skip.includes("mouse-acceleration", Some(Category::Input)));
+        assert!(!skip.includes("bcd-useplatformclock", Some(Category::Boot)));
+        assert!(skip.includes("keyboard-delay", Some(Category::Input)));
+    }
+
+    #[test]
+    fn a_skipped_tweak_stays_out_of_the_picked_ones() {
+        let picked = Selection::ids(names(&["tcp-ack-frequency", "tcp-mtu"]));
+        assert!(picked.includes("tcp-mtu", Some(Category::Network)));
+        assert!(!picked.includes("gamebar", Some(Category::Gamebar)));
+
+        let picked = picked.without(names(&["tcp-mtu"]));
+        assert!(picked.includes("tcp-ack-frequency", Some(Category::Network)));
+        assert!(!picked.includes("tcp-mtu", Some(Category::Network)));
+        assert!(picked.selects(&tweak("tcp-ack-frequency", Some(Category::Network))));
+        assert!(!picked.selects(&tweak("tcp-mtu", Some(Category::Network))));
+    }
+
+    #[test]
+    fn unknown_names_are_reported() {
+        let tweaks = vec![tweak("tcp-mtu", Some(Category::Network)), tweak("mouse-acceleration", Some(Category::Input))];
+        let selection = Selection::new(names(&["network", "TCP-MTU", "tcp-mut"]), names(&["GPU", "mouse"]));
+        assert_eq!(selection.unknown(&tweaks), vec!["tcp-mut", "mouse"]);
+        assert!(Selection::default().unknown(&tweaks).is_empty());
+        assert_eq!(
+            "mouse".parse::<Category>().unwrap_err(),
+            format!("unknown category `mouse`, expected {}", category_names())
+        );
+    }
+}
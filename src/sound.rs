@@ -7,6 +7,7 @@ extern crate winapi;
 
 use widestring::*;
 use winapi::{
+    shared::winerror::{DISP_E_TYPEMISMATCH, HRESULT, RPC_E_CHANGED_MODE},
     shared::wtypes::*,
     shared::*,
     um::{
@@ -16,231 +17,253 @@ use winapi::{
     },
     Interface,
 };
+use crate::error::{self, Error};
+use crate::selection::{Category, Risk, TweakInfo};
 use std::ptr;
 use wio::com::ComPtr;
 
-fn get_device_enumerator() -> *mut IMMDeviceEnumerator {
-    let cls_mm_device_enum: guiddef::GUID = CLSID_MMDeviceEnumerator;
-    let iid_imm_device_enumerator = IMMDeviceEnumerator::uuidof();
+pub const AUDIO_FULL_RANGE: &str = "audio-full-range-speakers";
 
-    let mut device_enumerator: *mut IMMDeviceEnumerator = unsafe { std::mem::zeroed() };
-
-    unsafe {
-        winapi::um::combaseapi::CoCreateInstance(
-            &cls_mm_device_enum,
-            std::ptr::null_mut(),
-            wtypesbase::CLSCTX_INPROC_SERVER,
-            &iid_imm_device_enumerator,
-            &mut device_enumerator as *mut *mut IMMDeviceEnumerator
-                as *mut *mut winapi::ctypes::c_void,
-        );
-    }
-    return device_enumerator;
+pub fn audio_tweaks() -> Vec<TweakInfo> {
+    vec![TweakInfo {
+        id: AUDIO_FULL_RANGE.to_string(),
+        category: Some(Category::Audio),
+        risk: Risk::Low,
+        description: String::from("Let the default speakers use the full bandwidth"),
+    }]
 }
 
-fn get_imm_device(device_enumerator: *mut IMMDeviceEnumerator) -> *mut IMMDevice {
-    let mut pp_device: *mut winapi::um::mmdeviceapi::IMMDevice = unsafe { std::mem::zeroed() };
-    unsafe {
-        (*device_enumerator).GetDefaultAudioEndpoint(
-            winapi::um::mmdeviceapi::eRender,
-            winapi::um::mmdeviceapi::eConsole,
-            &mut pp_device,
-        );
+// COM calls report a failure through a negative HRESULT
+fn com(call: &'static str, hresult: HRESULT) -> error::Result<()> {
+    if hresult < 0 {
+        Err(Error::Com { call, hresult })
+    } else {
+        Ok(())
     }
-    return pp_device;
 }
 
-fn get_device_friendly_name(property_store: ComPtr<IPropertyStore>) -> String {
-    let result: String;
-    unsafe {
-        let mut prop_variant = PROPVARIANT::default();
-        let hr = property_store.GetValue(&PKEY_Device_FriendlyName, &mut prop_variant);
-        if hr != 0 {
-            println!("IPropertyStore::GetValue failed: hr = 0x{:08x}", hr);
-        }
+fn get_device_enumerator() -> error::Result<ComPtr<IMMDeviceEnumerator>> {
+    let cls_mm_device_enum: guiddef::GUID = CLSID_MMDeviceEnumerator;
+    let iid_imm_device_enumerator = IMMDeviceEnumerator::uuidof();
 
-        if prop_variant.vt as u32 != VT_LPWSTR {
-            println!(
-                "PKEY_Device_FriendlyName variant type is {} - expected VT_LPWSTR",
-                prop_variant.vt
-            );
-        }
+    let mut device_enumerator: *mut IMMDeviceEnumerator = ptr::null_mut();
 
-        result = match U16CStr::from_ptr_str(*prop_variant.data.pwszVal()).to_string() {
-            Ok(file) => file,
-            Err(e) => {
-                println!("U16CStr::from_ptr_str Error {}", e);
-                return String::from("");
-            }
-        };
-
-        let hr = PropVariantClear(&mut prop_variant);
-        if hr != 0 {
-            println!("PropVariantClear failed: hr = 0x{:08x}", hr);
-        }
+    unsafe {
+        com(
+            "CoCreateInstance",
+            winapi::um::combaseapi::CoCreateInstance(
+                &cls_mm_device_enum,
+                std::ptr::null_mut(),
+                wtypesbase::CLSCTX_INPROC_SERVER,
+                &iid_imm_device_enumerator,
+                &mut device_enumerator as *mut *mut IMMDeviceEnumerator
+                    as *mut *mut winapi::ctypes::c_void,
+            ),
+        )?;
+        Ok(ComPtr::from_raw(device_enumerator))
     }
-
-    result
 }
 
-fn get_audio_endpoint_physical_speakers(
-    property_store: ComPtr<IPropertyStore>,
-    prop_variant: &mut PROPVARIANT,
-) -> i32 {
-    let result: i32;
+fn get_imm_device(device_enumerator: &ComPtr<IMMDeviceEnumerator>) -> error::Result<ComPtr<IMMDevice>> {
+    let mut pp_device: *mut IMMDevice = ptr::null_mut();
     unsafe {
-        let hr = property_store.GetValue(&PKEY_AudioEndpoint_PhysicalSpeakers, prop_variant);
-        if hr != 0 {
-            println!("IPropertyStore::GetValue failed: hr = 0x{:08x}", hr);
-        }
-
-        if prop_variant.vt as u32 != VT_UI4 {
-            println!(
-                "PKEY_AudioEndpoint_PhysicalSpeakers variant type is {} - expected VT_UI4",
-                prop_variant.vt
-            );
-        }
-
-        result = *prop_variant.data.intVal();
+        com(
+            "IMMDeviceEnumerator::GetDefaultAudioEndpoint",
+            device_enumerator.GetDefaultAudioEndpoint(
+                winapi::um::mmdeviceapi::eRender,
+                winapi::um::mmdeviceapi::eConsole,
+                &mut pp_device,
+            ),
+        )?;
+        Ok(ComPtr::from_raw(pp_device))
     }
-    result
 }
 
-fn get_audio_endpoint_full_range_speakers(property_store: ComPtr<IPropertyStore>) -> i32 {
-    let result: i32;
+// Reads one property, `read` returns None when the variant does not have the
+// type of the property
+fn get_property<T>(
+    property_store: &ComPtr<IPropertyStore>,
+    key: &PROPERTYKEY,
+    name: &'static str,
+    read: impl FnOnce(&PROPVARIANT) -> Option<T>,
+) -> error::Result<T> {
     unsafe {
         let mut prop_variant = PROPVARIANT::default();
-        let hr = property_store.GetValue(&PKEY_AudioEndpoint_FullRangeSpeakers, &mut prop_variant);
-        if hr != 0 {
-            println!("IPropertyStore::GetValue failed: hr = 0x{:08x}", hr);
-        }
+        com("IPropertyStore::GetValue", property_store.GetValue(key, &mut prop_variant))?;
+        let result = read(&prop_variant).ok_or(Error::Com {
+            call: name,
+            hresult: DISP_E_TYPEMISMATCH,
+        });
+        let cleared = com("PropVariantClear", PropVariantClear(&mut prop_variant));
+        let result = result?;
+        cleared.map(|_| result)
+    }
+}
 
-        if prop_variant.vt as u32 != VT_UI4 {
-            println!(
-                "PKEY_AudioEndpoint_FullRangeSpeakers variant type is {} - expected VT_UI4",
-                prop_variant.vt
-            );
+fn get_device_friendly_name(property_store: &ComPtr<IPropertyStore>) -> error::Result<String> {
+    get_property(property_store, &PKEY_Device_FriendlyName, "PKEY_Device_FriendlyName", |prop_variant| unsafe {
+        let name = *prop_variant.data.pwszVal();
+        if prop_variant.vt as u32 != VT_LPWSTR || name.is_null() {
+            return None;
         }
+        Some(U16CStr::from_ptr_str(name).to_string_lossy())
+    })
+}
 
-        result = *prop_variant.data.intVal();
-        let hr = PropVariantClear(&mut prop_variant);
-        if hr != 0 {
-            println!("PropVariantClear failed: hr = 0x{:08x}", hr);
-        }
-    }
+fn get_audio_endpoint_physical_speakers(property_store: &ComPtr<IPropertyStore>) -> error::Result<i32> {
+    get_property(
+        property_store,
+        &PKEY_AudioEndpoint_PhysicalSpeakers,
+        "PKEY_AudioEndpoint_PhysicalSpeakers",
+        |prop_variant| unsafe {
+            match prop_variant.vt as u32 {
+                VT_UI4 => Some(*prop_variant.data.intVal()),
+                _ => None,
+            }
+        },
+    )
+}
 
-    result
+// Drivers that never set the property leave it empty, which counts as no speakers
+fn get_audio_endpoint_full_range_speakers(property_store: &ComPtr<IPropertyStore>) -> error::Result<i32> {
+    get_property(
+        property_store,
+        &PKEY_AudioEndpoint_FullRangeSpeakers,
+        "PKEY_AudioEndpoint_FullRangeSpeakers",
+        |prop_variant| unsafe {
+            match prop_variant.vt as u32 {
+                VT_UI4 => Some(*prop_variant.data.intVal()),
+                VT_EMPTY => Some(0),
+                _ => None,
+            }
+        },
+    )
 }
 
 fn set_audio_endpoint_full_range_speakers(
-    property_store: ComPtr<IPropertyStore>,
-    physical_speakers: &mut PROPVARIANT,
-) {
+    property_store: &ComPtr<IPropertyStore>,
+    physical_speakers: i32,
+) -> error::Result<()> {
     unsafe {
-        let hr = property_store.SetValue(&PKEY_AudioEndpoint_FullRangeSpeakers, physical_speakers);
-        if hr != 0 {
-            println!("IPropertyStore::GetValue failed: hr = 0x{:08x}", hr);
-        }
-
-        property_store.Commit();
+        let mut prop_variant = PROPVARIANT {
+            vt: VT_UI4 as u16,
+            ..Default::default()
+        };
+        *prop_variant.data.intVal_mut() = physical_speakers;
+        com(
+            "IPropertyStore::SetValue",
+            property_store.SetValue(&PKEY_AudioEndpoint_FullRangeSpeakers, &prop_variant),
+        )?;
+        com("IPropertyStore::Commit", property_store.Commit())
     }
 }
 
-fn get_audio_engine_device_format(property_store: ComPtr<IPropertyStore>) {
-    unsafe {
-        let mut prop_variant = PROPVARIANT::default();
-        let hr = property_store.GetValue(&PKEY_AudioEngine_DeviceFormat, &mut prop_variant);
-        if hr != 0 {
-            println!("IPropertyStore::GetValue failed: hr = 0x{:08x}", hr);
-        }
+pub struct DeviceFormat {
+    pub channels: u16,
+    pub bits_per_sample: u16,
+    pub samples_per_sec: u32,
+    pub avg_bytes_per_sec: u32,
+}
 
-        if prop_variant.vt as u32 != VT_BLOB {
-            println!(
-                "PKEY_AudioEngine_DeviceFormat variant type is {} - expected VT_BLOB",
-                prop_variant.vt
-            );
-        }
+fn get_audio_engine_device_format(property_store: &ComPtr<IPropertyStore>) -> error::Result<DeviceFormat> {
+    get_property(
+        property_store,
+        &PKEY_AudioEngine_DeviceFormat,
+        "PKEY_AudioEngine_DeviceFormat",
+        |prop_variant| unsafe {
+            let blob = prop_variant.data.blob();
+            if prop_variant.vt as u32 != VT_BLOB
+                || blob.pBlobData.is_null()
+                || (blob.cbSize as usize) < std::mem::size_of::<mmreg::WAVEFORMATEX>()
+            {
+                return None;
+            }
+            let format = ptr::read_unaligned(blob.pBlobData as winapi::um::mmsystem::PWAVEFORMATEX);
+            Some(DeviceFormat {
+                channels: format.nChannels,
+                bits_per_sample: format.wBitsPerSample,
+                samples_per_sec: format.nSamplesPerSec,
+                avg_bytes_per_sec: format.nAvgBytesPerSec,
+            })
+        },
+    )
+}
 
-        let result = prop_variant.data.blob_mut().pBlobData as winapi::um::mmsystem::PWAVEFORMATEX;
-        let n_channels  = (*result).nChannels ;
-        let w_bits_per_sample = (*result).wBitsPerSample;
-        let n_samples_per_sec = (*result).nSamplesPerSec;
-        let n_avg_bytes_per_sec = (*result).nAvgBytesPerSec;
-        println!("Channels: \x1b[0;92m{}\x1b[0m", n_channels);
-        if w_bits_per_sample == 16 {
-            println!("\x1b[0;93mBits per sample: {} Bit\x1b[0m", w_bits_per_sample);
-        } else {
-            println!("Bits per sample: \x1b[0;92m{}\x1b[0m Bit", w_bits_per_sample);
-        }
-        println!("Samples per sec: \x1b[0;92m{}\x1b[0m kHz", n_samples_per_sec/1000);
-        println!("Average: \x1b[0;92m{}\x1b[0m bytes/s", n_avg_bytes_per_sec);
+// The default render endpoint as apply_audio_settings found it
+pub struct AudioEndpoint {
+    pub friendly_name: String,
+    pub physical_speakers: i32,
+    pub full_range_speakers: i32,
+    // full_range_speakers was set to physical_speakers
+    pub written: bool,
+    pub format: DeviceFormat,
+}
 
-        let hr = PropVariantClear(&mut prop_variant);
-        if hr != 0 {
-            println!("PropVariantClear failed: hr = 0x{:08x}", hr);
-        }
+impl AudioEndpoint {
+    pub fn in_sync(&self) -> bool {
+        self.physical_speakers == self.full_range_speakers
     }
 }
 
+pub fn print_audio_endpoint(endpoint: &AudioEndpoint) {
+    println!("Friendly Name: {}", endpoint.friendly_name);
+    if endpoint.in_sync() {
+        println!("correct setting: Speakers already use the full bandwidth");
+    } else if endpoint.written {
+        println!("write setting: \x1b[0;92mSpeakers now use the full bandwidth\x1b[0m");
+    } else {
+        println!("wrong setting: \x1b[0;93mSpeakers do not yet use the full bandwidth\x1b[0m");
+    }
 
-pub fn apply_audio_settings(write_settings: bool, _default_settings: bool) {
-    unsafe { CoInitialize(std::ptr::null_mut()) };
-    let device_enumerator: *mut IMMDeviceEnumerator = get_device_enumerator();
-    let pp_device: *mut IMMDevice = get_imm_device(device_enumerator);
+    let format = &endpoint.format;
+    println!("Channels: \x1b[0;92m{}\x1b[0m", format.channels);
+    if format.bits_per_sample == 16 {
+        println!("\x1b[0;93mBits per sample: {} Bit\x1b[0m", format.bits_per_sample);
+    } else {
+        println!("Bits per sample: \x1b[0;92m{}\x1b[0m Bit", format.bits_per_sample);
+    }
+    println!("Samples per sec: \x1b[0;92m{}\x1b[0m kHz", format.samples_per_sec / 1000);
+    println!("Average: \x1b[0;92m{}\x1b[0m bytes/s", format.avg_bytes_per_sec);
+}
 
+pub fn apply_audio_settings(write_settings: bool, _default_settings: bool) -> error::Result<AudioEndpoint> {
     unsafe {
-        let mut property_store_ptr: *mut IPropertyStore = ptr::null_mut();
-
-        let hr = (*pp_device).OpenPropertyStore(
-            if write_settings {
-                STGM_READWRITE
-            } else {
-                STGM_READ
-            },
-            &mut property_store_ptr as _,
-        );
-        if hr != 0 {
-            println!("IMMDevice::OpenPropertyStore failed: hr = 0x{:08x}", hr);
+        // A thread that already uses another apartment can still create the enumerator
+        match CoInitialize(ptr::null_mut()) {
+            RPC_E_CHANGED_MODE => (),
+            hr => com("CoInitialize", hr)?,
         }
+        let device = get_imm_device(&get_device_enumerator()?)?;
 
+        let mut property_store_ptr: *mut IPropertyStore = ptr::null_mut();
+        com(
+            "IMMDevice::OpenPropertyStore",
+            device.OpenPropertyStore(
+                if write_settings {
+                    STGM_READWRITE
+                } else {
+                    STGM_READ
+                },
+                &mut property_store_ptr as _,
+            ),
+        )?;
         let property_store = ComPtr::from_raw(property_store_ptr);
-        let friendly_name = get_device_friendly_name(property_store.clone());
-        println!("Friendly Name: {}", friendly_name);
-
-        let mut physical_speakers = PROPVARIANT::default();
-        let audio_endpoint_physical_speakers =
-            get_audio_endpoint_physical_speakers(property_store.clone(), &mut physical_speakers);
 
-        if !write_settings {
-            let hr = PropVariantClear(&mut physical_speakers);
-            if hr != 0 {
-                println!("PropVariantClear failed: hr = 0x{:08x}", hr);
-            }
-        }
+        let friendly_name = get_device_friendly_name(&property_store)?;
+        let audio_endpoint_physical_speakers = get_audio_endpoint_physical_speakers(&property_store)?;
+        let audio_endpoint_full_range_speakers = get_audio_endpoint_full_range_speakers(&property_store)?;
 
-        let audio_endpoint_full_range_speakers =
-            get_audio_endpoint_full_range_speakers(property_store.clone());
-
-        if audio_endpoint_physical_speakers == audio_endpoint_full_range_speakers {
-            println!("correct setting: Speakers already use the full bandwidth");
-        } else {
-            if write_settings {
-                set_audio_endpoint_full_range_speakers(
-                    property_store.clone(),
-                    &mut physical_speakers,
-                );
-                println!("write setting: \x1b[0;92mSpeakers now use the full bandwidth\x1b[0m");
-                let hr = PropVariantClear(&mut physical_speakers);
-                if hr != 0 {
-                    println!("PropVariantClear failed: hr = 0x{:08x}", hr);
-                }
-            } else {
-                println!(
-                    "wrong setting: \x1b[0;93mSpeakers do not yet use the full bandwidth\x1b[0m"
-                );
-            }
+        let written = audio_endpoint_physical_speakers != audio_endpoint_full_range_speakers && write_settings;
+        if written {
+            set_audio_endpoint_full_range_speakers(&property_store, audio_endpoint_physical_speakers)?;
         }
 
-        get_audio_engine_device_format(property_store.clone());
+        Ok(AudioEndpoint {
+            friendly_name,
+            physical_speakers: audio_endpoint_physical_speakers,
+            full_range_speakers: audio_endpoint_full_range_speakers,
+            written,
+            format: get_audio_engine_device_format(&property_store)?,
+        })
     }
 }
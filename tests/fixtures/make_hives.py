@@ -0,0 +1,236 @@
+#!/usr/bin/env python3
+# Builds the regf fixture hives from the format specification, independent of
+# the writer in src/registry/backend/regf.rs:
+# https://github.com/msuhanov/regf/blob/master/Windows%20registry%20file%20format%20specification.md
+#
+#   software.hiv  values of every type, a big data value (db), a class name,
+#                 two security descriptors, a UTF-16 key name, an li list and
+#                 a key with 600 subkeys in an ri index of an lf and an lh leaf
+#   system.hiv    Select\Current = 2 with ControlSet001 and ControlSet002
+#
+# Run it from the repository root: python3 tests/fixtures/make_hives.py
+
+import os
+import struct
+
+BLOCK = 4096
+NONE = 0xFFFFFFFF
+TIMESTAMP = 0x01D8_0000_0000_0000
+SEGMENT = 16344
+
+REG_SZ, REG_EXPAND_SZ, REG_BINARY, REG_DWORD, REG_MULTI_SZ, REG_QWORD = 1, 2, 3, 4, 7, 11
+
+
+class Bins:
+    def __init__(self):
+        self.data = bytearray()
+        self.end = 0
+        self.open(0)
+
+    def open(self, cell):
+        size = (cell + 32 + BLOCK - 1) // BLOCK * BLOCK
+        start = len(self.data)
+        self.data += b"hbin" + struct.pack("<III", start, size, 0) + struct.pack("<IQI", 0, TIMESTAMP, 0)
+        self.end = start + size
+
+    def close(self):
+        rest = self.end - len(self.data)
+        if rest:
+            self.data += struct.pack("<i", rest) + bytes(rest - 4)
+
+    def cell(self, payload):
+        size = (len(payload) + 4 + 7) // 8 * 8
+        if len(self.data) + size > self.end:
+            self.close()
+            self.open(size)
+        offset = len(self.data)
+        self.data += struct.pack("<i", -size) + payload + bytes(size - 4 - len(payload))
+        return offset
+
+    def patch(self, offset, at, payload):
+        start = offset + 4 + at
+        self.data[start:start + len(payload)] = payload
+
+
+def name_bytes(name):
+    try:
+        return name.encode("latin-1"), True
+    except UnicodeEncodeError:
+        return name.encode("utf-16-le"), False
+
+
+def name_hash(name):
+    # Upper case UTF-16 units, h = h * 37 + unit
+    h = 0
+    for (unit,) in struct.iter_unpack("<H", name.encode("utf-16-le")):
+        upper = chr(unit).upper()
+        h = (h * 37 + (ord(upper) if len(upper) == 1 else unit)) & 0xFFFFFFFF
+    return h
+
+
+def sz(text):
+    return text.encode("utf-16-le") + b"\0\0"
+
+
+def multi_sz(items):
+    return b"".join(sz(i) for i in items) + b"\0\0"
+
+
+def descriptor(owner_rid):
+    # Self-relative, owner S-1-5-<rid>, no DACL
+    sid = struct.pack("<BB", 1, 1) + b"\0\0\0\0\0\x05" + struct.pack("<I", owner_rid)
+    return struct.pack("<BBHIIII", 1, 0, 0x8000, 20, 0, 0, 0) + sid
+
+
+class Key:
+    def __init__(self, name, values=(), subkeys=(), cls=None, sk=0, index="lh"):
+        self.name = name
+        self.values = list(values)
+        self.subkeys = list(subkeys)
+        self.cls = cls
+        self.sk = sk
+        # lh, lf, li or ri (an index root of an lf and an lh leaf)
+        self.index = index
+
+
+def write_value(bins, name, vtype, data):
+    encoded, compressed = name_bytes(name)
+    if len(data) <= 4:
+        size, offset = len(data) | 0x80000000, struct.unpack("<I", data.ljust(4, b"\0"))[0]
+    elif len(data) > SEGMENT:
+        segments = [bins.cell(data[i:i + SEGMENT]) for i in range(0, len(data), SEGMENT)]
+        listing = bins.cell(struct.pack("<%dI" % len(segments), *segments))
+        size, offset = len(data), bins.cell(b"db" + struct.pack("<HI", len(segments), listing))
+    else:
+        size, offset = len(data), bins.cell(data)
+    vk = b"vk" + struct.pack("<HIIIHH", len(encoded), size, offset, vtype, 1 if compressed else 0, 0) + encoded
+    return bins.cell(vk)
+
+
+def leaf(kind, entries):
+    out = kind + struct.pack("<H", len(entries))
+    for offset, name in entries:
+        if kind == b"lf":
+            out += struct.pack("<I", offset) + name.encode("latin-1")[:4].ljust(4, b"\0")
+        elif kind == b"lh":
+            out += struct.pack("<II", offset, name_hash(name))
+        else:
+            out += struct.pack("<I", offset)
+    return out
+
+
+def write_key(bins, key, parent, security, root=False):
+    encoded, compressed = name_bytes(key.name)
+    flags = (0x20 if compressed else 0) | (0x0C if root else 0)
+    nk = bins.cell(bytes(0x4C + len(encoded)))
+
+    values = [write_value(bins, *v) for v in key.values]
+    value_list = bins.cell(struct.pack("<%dI" % len(values), *values)) if values else NONE
+
+    children = sorted(key.subkeys, key=lambda k: k.name.upper())
+    entries = [(write_key(bins, child, nk, security), child.name) for child in children]
+    if not entries:
+        subkey_list = NONE
+    elif key.index == "ri":
+        half = len(entries) // 2
+        leaves = [bins.cell(leaf(b"lf", entries[:half])), bins.cell(leaf(b"lh", entries[half:]))]
+        subkey_list = bins.cell(b"ri" + struct.pack("<H%dI" % len(leaves), len(leaves), *leaves))
+    else:
+        subkey_list = bins.cell(leaf(key.index.encode(), entries))
+
+    cls = bins.cell(key.cls) if key.cls else NONE
+    max_name = max([len(c.name.encode("utf-16-le")) for c in children], default=0)
+    max_value_name = max([len(v[0].encode("utf-16-le")) for v in key.values], default=0)
+    max_value_data = max([len(v[2]) for v in key.values], default=0)
+    cell = b"nk" + struct.pack(
+        "<HQIIIIIIIIIIIIIIIHH",
+        flags, TIMESTAMP, 0, parent, len(entries), 0, subkey_list, NONE,
+        len(values), value_list, security[key.sk], cls,
+        max_name, 0, max_value_name, max_value_data, 0,
+        len(encoded), len(key.cls) if key.cls else 0,
+    ) + encoded
+    bins.patch(nk, 0, cell)
+    return nk
+
+
+def hive(path, root, descriptors, file_name):
+    bins = Bins()
+    # The security descriptors in a ring, each used by every key pointing at it
+    users = [0] * len(descriptors)
+
+    def count(key):
+        users[key.sk] += 1
+        for child in key.subkeys:
+            count(child)
+
+    count(root)
+    security = [bins.cell(bytes(0x14 + len(d))) for d in descriptors]
+    for i, d in enumerate(descriptors):
+        flink, blink = security[(i + 1) % len(security)], security[i - 1]
+        bins.patch(security[i], 0, b"sk\0\0" + struct.pack("<IIII", flink, blink, users[i], len(d)) + d)
+
+    root_offset = write_key(bins, root, 0xFFFFFFFF, security, root=True)
+    bins.close()
+
+    base = bytearray(BLOCK)
+    base[0:4] = b"regf"
+    struct.pack_into("<IIQIIIIIII", base, 4, 7, 7, TIMESTAMP, 1, 5, 0, 1, root_offset, len(bins.data), 1)
+    encoded = file_name.encode("utf-16-le")[:64]
+    base[0x30:0x30 + len(encoded)] = encoded
+    checksum = 0
+    for (dword,) in struct.iter_unpack("<I", bytes(base[:0x1FC])):
+        checksum ^= dword
+    struct.pack_into("<I", base, 0x1FC, checksum)
+    with open(path, "wb") as f:
+        f.write(bytes(base) + bytes(bins.data))
+
+
+def big_data():
+    return bytes((i * 7 + i // 251) & 0xFF for i in range(40000))
+
+
+def software():
+    many = Key("Many", subkeys=[Key("Sub%03d" % i, values=[("Index", REG_DWORD, struct.pack("<I", i))]) for i in range(600)], index="ri")
+    listed = Key("Listed", subkeys=[Key("Alpha"), Key("beta"), Key("Gamma")], index="li")
+    test = Key(
+        "Test",
+        values=[
+            ("Dword", REG_DWORD, struct.pack("<I", 0xDEADBEEF)),
+            ("Qword", REG_QWORD, struct.pack("<Q", 0x0123456789ABCDEF)),
+            ("String", REG_SZ, sz("Gaming Optimizer")),
+            ("Expand", REG_EXPAND_SZ, sz("%SystemRoot%\\system32")),
+            ("Multi", REG_MULTI_SZ, multi_sz(["one", "two", "three"])),
+            ("Binary", REG_BINARY, bytes(range(32))),
+            ("Short", REG_BINARY, b"\x01\x02"),
+            ("Empty", REG_SZ, b""),
+            ("Big", REG_BINARY, big_data()),
+            ("DeviceProperty", 0xFFFF0012, sz("opaque")),
+            ("", REG_SZ, sz("default value")),
+        ],
+        cls=sz("TestClass")[:-2],
+        sk=1,
+    )
+    unicode = Key("Ключ", values=[("Значение", REG_DWORD, struct.pack("<I", 7))], sk=1)
+    root = Key("CMI-CreateHive{2A7FB991-7BBE-4F9D-B91E-7CB51D4737F5}", subkeys=[
+        Key("Vendor", subkeys=[test, unicode, listed, many]),
+    ])
+    return root
+
+
+def system():
+    def control_set(name, start):
+        return Key(name, subkeys=[Key("Services", subkeys=[Key("Test", values=[("Start", REG_DWORD, struct.pack("<I", start))])])])
+
+    select = Key("Select", values=[
+        ("Current", REG_DWORD, struct.pack("<I", 2)),
+        ("Default", REG_DWORD, struct.pack("<I", 2)),
+        ("Failed", REG_DWORD, struct.pack("<I", 0)),
+        ("LastKnownGood", REG_DWORD, struct.pack("<I", 1)),
+    ])
+    return Key("ROOT", subkeys=[control_set("ControlSet001", 1), control_set("ControlSet002", 3), select])
+
+
+if __name__ == "__main__":
+    here = os.path.dirname(os.path.abspath(__file__))
+    hive(os.path.join(here, "software.hiv"), software(), [descriptor(18), descriptor(32)], "\\SystemRoot\\System32\\Config\\SOFTWARE")
+    hive(os.path.join(here, "system.hiv"), system(), [descriptor(18)], "\\SystemRoot\\System32\\Config\\SYSTEM")